@@ -165,13 +165,16 @@ def _read_active() -> dict:
         pass
     lines = active_file.read_text(encoding="utf-8").strip().splitlines()
     app = lines[0] if lines else "none"
+    # Positional lines first, then key=value lines (version=N, ...)
+    extra = dict(l.split("=", 1) for l in lines[1:] if "=" in l)
     if app == "none" or not lines:
-        return {"snapped": False, "app": "none", "a11y": "", "snap": ""}
+        return {"snapped": False, "app": "none", "a11y": "", "snap": "", **extra}
     return {
         "snapped": True,
         "app": app,
         "a11y": lines[1] if len(lines) > 1 else "",
         "snap": lines[2] if len(lines) > 2 else "",
+        **extra,
     }
 
 
@@ -761,7 +764,7 @@ def _inject_action(action: str, text: str = "", target: str = "", app: Optional[
 
 
 def _wait_for_action(action_id: int, db_path: Path, timeout: float = 5.0, poll_interval: float = 0.05):
-    """Poll the inject table until the action is marked done=1.
+    """Poll the inject table until the action is finished (done=1, or done=2 = dead).
 
     Polls every 50ms (fast enough to feel instant, light enough to not spam).
     Times out after 5 seconds to prevent infinite hangs.
@@ -774,7 +777,7 @@ def _wait_for_action(action_id: int, db_path: Path, timeout: float = 5.0, poll_i
                 row = conn.execute(
                     "SELECT done FROM inject WHERE id=?", (action_id,)
                 ).fetchone()
-                if row and row[0] == 1:
+                if row and row[0] != 0:
                     # Action done — wait one more cycle for DS to write output files
                     time.sleep(0.15)
                     return
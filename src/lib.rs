@@ -0,0 +1,9204 @@
+// DirectShell — Universal Application Control Through the Accessibility Layer
+// Copyright (C) 2026  Martin Gehrken (IamLumae)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Core engine: overlay, snap, UIA dump, injection, event capture.
+//! `run()` is the full desktop app (the `directshell` binary is a thin wrapper);
+//! [`DirectShell`] embeds the engine headless — no overlay, no file polling.
+
+use std::borrow::Cow;
+use std::ffi::c_void;
+use std::fs;
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicIsize, AtomicU64, Ordering::SeqCst};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use rusqlite::{Connection, OptionalExtension, params};
+use windows::core::*;
+use windows::Win32::Foundation::*;
+use windows::Win32::Graphics::Gdi::*;
+use windows::Win32::System::Com::*;
+use windows::Win32::System::LibraryLoader::{GetModuleHandleW, GetModuleFileNameW};
+use windows::Win32::UI::Accessibility::*;
+use windows::Win32::Globalization::{NormalizeString, NormalizationC};
+use windows::Win32::System::Threading::{
+    OpenProcess, OpenProcessToken, GetCurrentProcess, QueryFullProcessImageNameW,
+    PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_NAME_FORMAT,
+};
+use windows::Win32::Security::{
+    GetSidSubAuthority, GetSidSubAuthorityCount, GetTokenInformation,
+    TokenElevation, TokenIntegrityLevel, TOKEN_ELEVATION, TOKEN_MANDATORY_LABEL, TOKEN_QUERY,
+};
+use windows::Win32::System::DataExchange::{
+    CloseClipboard, CountClipboardFormats, EmptyClipboard, EnumClipboardFormats, GetClipboardData,
+    GetClipboardSequenceNumber, OpenClipboard, SetClipboardData,
+};
+use windows::Win32::System::Memory::{GlobalAlloc, GlobalLock, GlobalUnlock, GMEM_MOVEABLE};
+use windows::Win32::System::StationsAndDesktops::{
+    CloseDesktop, GetUserObjectInformationW, OpenInputDesktop, DESKTOP_CONTROL_FLAGS, DESKTOP_READOBJECTS, UOI_NAME,
+};
+use windows::Win32::UI::Input::KeyboardAndMouse::*;
+use windows::Win32::UI::WindowsAndMessaging::*;
+
+// ── Farben (COLORREF = 0x00BBGGRR) ─────────────────
+const INVIS: COLORREF = COLORREF(0x00FF00FF);
+const TOP_CLR: COLORREF = COLORREF(0x00827873);
+const SIDE_CLR: COLORREF = COLORREF(0x00736964);
+const BOT_CLR: COLORREF = COLORREF(0x005F5550);
+const HL_CLR: COLORREF = COLORREF(0x00D7CDC8);
+const SH_CLR: COLORREF = COLORREF(0x00413732);
+const ICON_CLR: COLORREF = COLORREF(0x00D0D0D0);
+const TARGET_BOX_CLR: COLORREF = COLORREF(0x000000FF);  // Debug-Highlight: aufgelöstes Ziel (rot)
+const HOOK_CLR: COLORREF = COLORREF(0x0060C060);        // Overlay: Tastatur-Hook fängt gerade ab (grün)
+
+// ── Dimensionen ─────────────────────────────────────
+const DEFAULT_TOP_H: i32 = 20;    // Standard-Höhe wenn ungesnappt
+const SIDE_W: i32 = 4;
+const GRIP: i32 = 12;
+const CORNER_R: i32 = 8;
+const FALLBACK_BTN_X: i32 = 140;
+const ALPHA: u8 = 180;
+const SNAP_THRESH: f64 = 0.20;
+const SYNC_TIMER: usize = 1;
+const ANIM_TIMER: usize = 2;
+const TIMER_MS: u32 = 16;
+const ANIM_MS: u32 = 33;
+const LIGHT_PERIOD: f64 = 3000.0;
+const LIGHT_LEN: f64 = 120.0;     // etwas länger für weicheren Fade
+const LIGHT_STEPS: i32 = 24;      // Gradient-Auflösung
+const INIT_W: i32 = 500;          // Startgröße (Breite)
+const INIT_H: i32 = 350;          // Startgröße (Höhe)
+const TREE_TIMER: usize = 3;      // Accessibility Tree Dump
+const TREE_MS: u32 = 500;         // 2 Hz — genug Raum für ~200ms Dumps + Puffer
+const INJECT_TIMER: usize = 4;    // Action Queue Processing (eigener Timer)
+const INJECT_MS: u32 = 30;        // 33 Hz — schnelles Typing wie ein Mensch
+const ENUM_TIMER: usize = 5;      // Window Enumeration (Daemon Mode)
+const ENUM_MS: u32 = 2000;        // 2 Hz — alle offenen Fenster tracken
+const SNAP_REQ_TIMER: usize = 6;  // Snap Request Polling (AI-triggered)
+const SNAP_REQ_MS: u32 = 200;     // 5 Hz — schnelle Reaktion auf AI-Befehle
+const OBSERVE_TIMER: usize = 7;   // Observe-only Targets (Hintergrund-Dumps)
+const OBSERVE_MS: u32 = 1000;     // 1 Hz — Nebenziele, kein Overlay
+const FOLLOW_DEBOUNCE_MS: u64 = 600; // follow_focus: Fokus muss so lange stabil sein
+const MAX_DEPTH: i32 = i32::MAX;  // Primitivum. Kein Limit.
+const MAX_CHILDREN: i32 = i32::MAX; // Primitivum. Kein Limit.
+const STREAM_BATCH: i32 = 200;    // COMMIT alle 200 Elemente → progressive Verfügbarkeit
+const ACTIVE_FILE: &str = "is_active";                // Status für KI-Agents
+const LOG_FILE: &str = "directshell.log";             // Log neben den Profilen
+const WINDOWS_FILE: &str = "windows.json";            // Daemon: alle offenen Fenster
+const SNAP_REQUEST_FILE: &str = "snap_request";       // AI → DS: "snap to this app"
+const SNAP_RESULT_FILE: &str = "snap_result";         // DS → AI: result JSON
+const OVERLAY_MODE_FILE: &str = "overlay_mode";       // AI → DS: "agent" or "human"
+const CONFIG_FILE: &str = "config";                   // User: key=value Optionen
+const DUMP_NOW_FILE: &str = "dump_now";               // AI → DS: "dump right now"
+const DUMP_DONE_FILE: &str = "dump_done";             // DS → AI: completion marker JSON
+const EVENTS_SINCE_FILE: &str = "events_since";       // AI → DS: timestamp (ms)
+const EVENTS_DELTA_FILE: &str = "events_delta.json";  // DS → AI: events newer than that
+const SELFTEST_FILE: &str = "selftest";               // AI/User → DS: run diagnostics
+const SELFTEST_RESULT_FILE: &str = "selftest_result.json"; // DS → AI: diagnostics report
+const INJECT_RESULT_FILE: &str = "inject_result";       // DS → AI: result JSON of the last action
+const STATUS_FILE: &str = "status.json";              // DS → AI/User: keyboard hook state
+const REPLAY_FILE: &str = "replay";                   // AI/User → DS: [timed:]<history file>
+const REPLAY_RESULT_FILE: &str = "replay_result";     // DS → AI: result JSON
+const ELEMENT_DUMP_FILE: &str = "element_dump.json"; // DS → AI: alle Properties eines Elements (dump_element)
+const METRICS_FILE: &str = "metrics.csv";             // Dump-Telemetrie: timestamp,app,rows,ms
+const METRICS_MAX_BYTES: u64 = 1024 * 1024;           // Rotation → metrics.csv.1
+const ACTION_HISTORY_MAX_BYTES: u64 = 1024 * 1024;    // Rotation → <app>.actions.jsonl.1
+const ACTION_HISTORY_TEXT_MAX: usize = 500;           // Text-Vorschau in der Action-History (Zeichen)
+const SEARCH_MAX_RESULTS: i64 = 50;                   // search: max. search_result Events pro Anfrage
+const FUZZY_THRESHOLD_DEFAULT: f64 = 0.8;             // Fuzzy-Target: Mindest-Ähnlichkeit (config: fuzzy_threshold)
+const WAIT_IDLE_QUIET_MS: i64 = 300;                  // wait_idle: so lange keine Events = UI ruhig
+const WAIT_IDLE_MAX_MS: u64 = 5000;                   // wait_idle: spätestens dann aufgeben
+const WAIT_IDLE_POLL_MS: u64 = 50;
+const WAIT_MAX_MS: u64 = 300_000;                     // Obergrenze für vom Agent gesetzte Wartezeiten (@max, hover ms)
+const NAVIGATE_TIMEOUT_MS: u64 = 5000;                // navigate: max. Wartezeit auf content_loaded
+const NAVIGATE_POLL_MS: u64 = 100;
+const WAIT_PROGRESS_MAX_MS: u64 = 60000;              // wait_progress: Default-Timeout
+const WAIT_PROGRESS_POLL_MS: u64 = 500;
+const SCROLL_MAX_NOTCHES: i32 = 100;                  // scroll: größte Menge pro Action (smooth blockiert so lange)
+const INJECT_VERIFY_MS: u64 = 100;                    // text: Wartezeit bis zum Zurücklesen nach SendInput
+const CONFIRM_WAIT_MS: u64 = 5000;                    // confirm: so lange auf den Dialog warten
+const CONFIRM_POLL_MS: u64 = 250;
+const DISMISS_ESCAPE_MS: u64 = 500;                   // dismiss: Escape so lange wirken lassen, bevor ein Button gedrückt wird
+const DISMISS_WAIT_MS: u64 = 5000;                    // dismiss: ohne fertigen Dump nach Escape → Timeout
+const TOOLTIP_WAIT_MS: u64 = 2000;                    // hover: so lange auf den Tooltip warten
+const TOOLTIP_POLL_MS: u64 = 100;
+const CLIPBOARD_WAIT_MS: u64 = 1000;                  // read_field: so lange auf das Ctrl+C-Ergebnis warten
+const CATALOG_TTL_MS: i64 = 7 * 24 * 3600 * 1000;     // Element-Katalog: 7 Tage ungesehen → raus
+const WM_TRAYICON: u32 = 0x0400 + 50;  // WM_APP + 50 — custom tray callback
+const TRAY_ID: u32 = 1;
+const IDM_TOGGLE_MODE: u16 = 1001;
+const IDM_EXIT: u16 = 1002;
+const IDM_TOGGLE_QUIET: u16 = 1003;
+const IDM_TOGGLE_RECORD: u16 = 1004;
+
+// ── Profil-Verzeichnis (absolut, zur Laufzeit bestimmt) ──
+// Alle *_FILE Konstanten sind Dateinamen RELATIV dazu → ds_file(NAME).
+// Priorität: 1) DS_PROFILES env  2) profiles_dir=... in directshell.cfg neben der exe
+//            3) %LOCALAPPDATA%\DirectShell\ds_profiles  4) ds_profiles neben der exe
+// Nie vom Working Directory abhängig (Elevated Relaunch startet mit ".").
+const DIR_CONFIG_FILE: &str = "directshell.cfg";
+static PROFILES_DIR: OnceLock<String> = OnceLock::new();
+
+fn exe_dir() -> std::path::PathBuf {
+    std::env::current_exe().ok()
+        .and_then(|p| p.parent().map(|d| d.to_path_buf()))
+        .unwrap_or_else(|| std::path::PathBuf::from("."))
+}
+
+fn db_dir() -> &'static str {
+    PROFILES_DIR.get_or_init(|| {
+        // Kein log() hier — log() schreibt selbst nach db_dir()
+        let dir = std::env::var("DS_PROFILES").ok().filter(|v| !v.trim().is_empty())
+            .or_else(|| {
+                let cfg = fs::read_to_string(exe_dir().join(DIR_CONFIG_FILE)).ok()?;
+                cfg.lines()
+                    .filter_map(|l| l.trim().split_once('='))
+                    .find(|(k, _)| k.trim().eq_ignore_ascii_case("profiles_dir"))
+                    .map(|(_, v)| v.trim().to_string())
+                    .filter(|v| !v.is_empty())
+            })
+            .map(std::path::PathBuf::from)
+            .or_else(|| std::env::var("LOCALAPPDATA").ok()
+                .filter(|v| !v.is_empty())
+                .map(|v| std::path::Path::new(&v).join("DirectShell").join("ds_profiles")))
+            .unwrap_or_else(|| exe_dir().join("ds_profiles"));
+        // Relative Angaben (env/cfg) gelten relativ zur exe, nicht zum Working Dir
+        let dir = if dir.is_absolute() { dir } else { exe_dir().join(dir) };
+        let _ = fs::create_dir_all(&dir);
+        dir.to_string_lossy().trim_end_matches(['/', '\\']).to_string()
+    })
+}
+
+/// Absolute path of a file inside the profile directory.
+fn ds_file(name: &str) -> String {
+    format!("{}/{}", db_dir(), name)
+}
+
+/// App name from a DB path: ".../ds_profiles/claude.db" → "claude"
+fn app_from_db_path(db_path: &str) -> &str {
+    let file = db_path.rsplit(['/', '\\']).next().unwrap_or(db_path);
+    file.trim_end_matches(".db")
+}
+
+/// Breadcrumb for agents/MCP server: %LOCALAPPDATA%\DirectShell\profiles_path.txt
+fn write_profiles_breadcrumb() {
+    let Ok(local) = std::env::var("LOCALAPPDATA") else { return };
+    let dir = std::path::Path::new(&local).join("DirectShell");
+    let _ = fs::create_dir_all(&dir);
+    if let Err(e) = fs::write(dir.join("profiles_path.txt"), db_dir()) {
+        log(&format!("startup: breadcrumb write failed: {e}"));
+    }
+}
+
+// ── Logging (Ring-Buffer im RAM, Flush auf Disk) ────
+use std::collections::VecDeque;
+static LOG_BUF: Mutex<Option<VecDeque<String>>> = Mutex::new(None);
+const LOG_MAX: usize = 100;
+
+fn log(msg: &str) {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = ts.as_secs();
+    let millis = ts.subsec_millis();
+    let h = (secs / 3600) % 24;
+    let m = (secs / 60) % 60;
+    let s = secs % 60;
+    let line = format!("[{:02}:{:02}:{:02}.{:03}] {}", h, m, s, millis, msg);
+
+    let mut guard = LOG_BUF.lock().unwrap();
+    let buf = guard.get_or_insert_with(|| VecDeque::with_capacity(LOG_MAX + 1));
+    buf.push_back(line);
+    while buf.len() > LOG_MAX {
+        buf.pop_front();
+    }
+    // Flush to disk
+    let content: String = buf.iter().map(|l| l.as_str()).collect::<Vec<_>>().join("\n") + "\n";
+    drop(guard); // Release lock before IO
+    let _ = fs::write(ds_file(LOG_FILE), content);
+}
+
+// ── Disk-Fehler (read-only / voll) ──────────────────
+// Schreibfehler in den Hot Paths → EINE Warnung pro Intervall statt Stille.
+static LAST_WRITE_WARN: AtomicU64 = AtomicU64::new(0);
+const WRITE_WARN_INTERVAL_MS: u64 = 30_000;
+
+fn warn_write_failure(path: &str, err: &str) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+    let last = LAST_WRITE_WARN.load(SeqCst);
+    if last != 0 && now.saturating_sub(last) < WRITE_WARN_INTERVAL_MS { return; }
+    LAST_WRITE_WARN.store(now, SeqCst);
+    log(&format!("WARNING: write failed ({}): {} — is {} read-only or full?", path, err, db_dir()));
+}
+
+/// fs::write for agent-facing files: failures are reported (throttled) instead of swallowed.
+fn write_checked(path: &str, content: impl AsRef<[u8]>) -> bool {
+    match fs::write(path, content) {
+        Ok(()) => true,
+        Err(e) => { warn_write_failure(path, &e.to_string()); false }
+    }
+}
+
+/// Startup probe: can we create and write files in the profile directory at all?
+fn check_db_dir_writable() -> std::result::Result<(), String> {
+    fs::create_dir_all(db_dir()).map_err(|e| e.to_string())?;
+    let probe = ds_file(".write_probe");
+    fs::write(&probe, b"ok").map_err(|e| e.to_string())?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+// ── Config (ds_profiles/config, key=value pro Zeile) ──
+// Optional. Fehlende Datei / fehlender Key = Default-Verhalten.
+// Wird bei Änderung (mtime) automatisch neu geladen — kein Neustart nötig.
+use std::collections::HashMap;
+type ConfigCache = (Option<SystemTime>, HashMap<String, String>);
+static CONFIG: Mutex<Option<ConfigCache>> = Mutex::new(None);
+
+fn config_get(key: &str) -> Option<String> {
+    let mtime = fs::metadata(ds_file(CONFIG_FILE)).and_then(|m| m.modified()).ok();
+    let mut guard = CONFIG.lock().unwrap();
+    let stale = match &*guard {
+        Some((cached, _)) => *cached != mtime,
+        None => true,
+    };
+    if stale {
+        let mut map = HashMap::new();
+        for line in fs::read_to_string(ds_file(CONFIG_FILE)).unwrap_or_default().lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+            if let Some((k, v)) = line.split_once('=') {
+                map.insert(k.trim().to_lowercase(), v.trim().to_string());
+            }
+        }
+        *guard = Some((mtime, map));
+    }
+    guard.as_ref().and_then(|(_, map)| map.get(&key.to_lowercase()).cloned())
+}
+
+/// Boolean config key: 1/true/yes/on = an.
+fn config_flag(key: &str) -> bool {
+    matches!(config_get(key).as_deref().map(|v| v.to_lowercase()).as_deref(),
+        Some("1" | "true" | "yes" | "on"))
+}
+
+// ── Globaler State ──────────────────────────────────
+static TARGET_HW: AtomicIsize = AtomicIsize::new(0);
+static IS_SNAPPED: AtomicBool = AtomicBool::new(false);
+static TREE_BUSY: AtomicBool = AtomicBool::new(false);
+static DUMP_GEN: AtomicU64 = AtomicU64::new(0); // abgeschlossene Primary-Dumps (Tabelle komplett, nicht mitten im Stream)
+// Observe-only Targets: (hwnd, db_path) — eigene DB, kein Overlay, keine Injection
+static OBSERVERS: Mutex<Vec<(usize, String)>> = Mutex::new(Vec::new());
+static OBSERVE_BUSY: AtomicBool = AtomicBool::new(false);
+// observe:<app> — Primärziel ohne Overlay/Geometrie (read-only, kein do_sync, keine Injection)
+static OBSERVE_ONLY: AtomicBool = AtomicBool::new(false);
+static CURRENT_DB: Mutex<String> = Mutex::new(String::new());
+static CURRENT_FRAMEWORK: Mutex<String> = Mutex::new(String::new()); // UIA FrameworkId of the snapped root (last dump)
+static CURRENT_URL: Mutex<String> = Mutex::new(String::new());       // Browser: address bar value (last dump)
+static KB_HOOK: AtomicIsize = AtomicIsize::new(0);
+static MOUSE_HOOK: AtomicIsize = AtomicIsize::new(0);         // WH_MOUSE_LL, nur bei mouse_hook=1 oder laufender Aufnahme
+static EVENT_UIA_PTR: AtomicIsize = AtomicIsize::new(0);      // UIA instance for event handlers (cleanup on unsnap)
+static A11Y_UIA_PTR: AtomicIsize = AtomicIsize::new(0);       // UIA instance from activate_accessibility (reused across snaps, freed on WM_DESTROY)
+const A11Y_RELEASE_TIMEOUT_MS: u64 = 1000;
+static TEARDOWNS: AtomicI32 = AtomicI32::new(0);               // laufende RemoveAllEventHandlers-Threads
+static TEARDOWN_SINCE: AtomicU64 = AtomicU64::new(0);          // Start des ältesten laufenden Teardowns (ms)
+static DEFERRED_REGISTER: AtomicIsize = AtomicIsize::new(0);   // Ziel, dessen Handler auf das Teardown warten
+const TEARDOWN_TIMEOUT_MS: u64 = 15000;                        // danach: trotzdem neu registrieren
+const TEARDOWN_POLL_MS: u64 = 50;
+static LAST_EVENT_DUMP_MS: AtomicIsize = AtomicIsize::new(0);  // Debounce: last event-triggered dump timestamp
+static LAST_X: AtomicI32 = AtomicI32::new(0);
+static LAST_Y: AtomicI32 = AtomicI32::new(0);
+static LAST_W: AtomicI32 = AtomicI32::new(0);
+static LAST_H: AtomicI32 = AtomicI32::new(0);
+static BTN_OFF_X: AtomicI32 = AtomicI32::new(FALLBACK_BTN_X);
+static DYN_TOP_H: AtomicI32 = AtomicI32::new(DEFAULT_TOP_H);
+static START_TIME: OnceLock<Instant> = OnceLock::new();
+static DS_HWND: AtomicIsize = AtomicIsize::new(0);           // Daemon: eigenes Fenster-Handle
+static DAEMON_SNAP: AtomicBool = AtomicBool::new(false);     // Daemon: skip CDP popup
+static AGENT_MODE: AtomicBool = AtomicBool::new(false);      // Agent mode: overlay hidden
+static QUIET: AtomicBool = AtomicBool::new(false);           // Quiet mode: alles pausiert außer ENUM (aktiver Zustand)
+static QUIET_TRAY: AtomicBool = AtomicBool::new(false);      // Quiet mode per Tray eingeschaltet
+static HOOK_SUSPECT: AtomicBool = AtomicBool::new(false);    // Hook-Callback war zu langsam → Windows entfernt ihn evtl. still
+static MOUSE_HOOK_SUSPECT: AtomicBool = AtomicBool::new(false); // dito für MOUSE_HOOK
+static HOOK_STATE_SHOWN: AtomicI32 = AtomicI32::new(-1);   // zuletzt nach status.json geschriebener HookState
+static EVENTS_STATE_SHOWN: AtomicI32 = AtomicI32::new(-1); // dito: Teardown/Deferred-Bits der Event-Handler
+static LAST_CLICK_X: AtomicI32 = AtomicI32::new(-1);        // Auto-persist: last click X (absolute screen)
+static LAST_CLICK_Y: AtomicI32 = AtomicI32::new(-1);        // Auto-persist: last click Y (absolute screen)
+static DUMP_NOW_PENDING: AtomicBool = AtomicBool::new(false); // On-demand dump: write DUMP_DONE_FILE when finished
+static FOLLOW_CAND: AtomicIsize = AtomicIsize::new(0);       // follow_focus: zuletzt fokussiertes Top-Level-Fenster
+static FOLLOW_SINCE: AtomicU64 = AtomicU64::new(0);          // follow_focus: seit wann (ms seit Start)
+
+fn tgt() -> HWND { HWND(TARGET_HW.load(SeqCst) as *mut _) }
+fn snapped() -> bool { IS_SNAPPED.load(SeqCst) }
+fn top_h() -> i32 { DYN_TOP_H.load(SeqCst) }
+fn save(x: i32, y: i32, w: i32, h: i32) {
+    LAST_X.store(x, SeqCst); LAST_Y.store(y, SeqCst);
+    LAST_W.store(w, SeqCst); LAST_H.store(h, SeqCst);
+}
+fn saved() -> (i32, i32, i32, i32) {
+    (LAST_X.load(SeqCst), LAST_Y.load(SeqCst),
+     LAST_W.load(SeqCst), LAST_H.load(SeqCst))
+}
+
+// App-Name aus Fenstertitel extrahieren → sauberer DB-Filename
+// "Google Gemini – Opera" → "opera.db"
+// "GitHub Desktop" → "github_desktop.db"
+// "release – Datei-Explorer" → "datei_explorer.db"
+fn db_name_from_title(title: &str) -> String {
+    ds_file(&format!("{}.db", app_name_from_title(title)))
+}
+
+/// Pure part of db_name_from_title: title → sanitized app name ("unknown" if nothing is left).
+fn app_name_from_title(title: &str) -> String {
+    // Letztes nicht-leeres Segment nach " – " (en-dash), " — " (em-dash) oder " - " (hyphen).
+    // Nicht-leer: "Notes – " (trailing separator) must not collapse to "unknown".
+    let app = title
+        .rsplit(&['\u{2013}', '\u{2014}'][..]) // en-dash, em-dash
+        .map(str::trim)
+        .find(|s| !s.is_empty())
+        .unwrap_or("");
+    let app = app
+        .rsplit(" - ")
+        .map(str::trim)
+        .find(|s| !s.is_empty())
+        .unwrap_or(app);
+
+    // Sanitize: lowercase (Unicode-aware, "ÜBER" == "über"), nur alphanumerisch + underscore
+    let clean: String = app
+        .chars()
+        .flat_map(|c| if c.is_alphanumeric() { c.to_lowercase().collect::<Vec<_>>() } else { vec!['_'] })
+        .collect();
+    let clean = clean.trim_matches('_');
+
+    // Fallback
+    if clean.is_empty() { "unknown".to_string() } else { clean.to_string() }
+}
+
+fn get_db_path() -> String {
+    CURRENT_DB.lock().unwrap().clone()
+}
+
+fn set_db_path(path: &str) {
+    *CURRENT_DB.lock().unwrap() = path.to_string();
+}
+
+fn get_framework() -> String {
+    CURRENT_FRAMEWORK.lock().unwrap().clone()
+}
+
+fn set_framework(fw: &str) {
+    *CURRENT_FRAMEWORK.lock().unwrap() = fw.to_string();
+}
+
+fn get_url() -> String {
+    CURRENT_URL.lock().unwrap().clone()
+}
+
+fn set_url(url: &str) {
+    *CURRENT_URL.lock().unwrap() = url.to_string();
+}
+
+/// Format version of ds_profiles/is_active. Bump whenever a line/key changes.
+const ACTIVE_STATUS_VERSION: u32 = 8;
+
+/// Write is_active status file for AI agents.
+///
+/// Schema (line-based, positional lines stay stable for old consumers):
+/// ```text
+/// line 1: app name | "none"
+/// line 2: <base>.a11y path        (snapped only)
+/// line 3: <base>.snap path        (snapped only)
+/// then:   key=value lines, order not guaranteed
+///         version=<ACTIVE_STATUS_VERSION>
+///         framework=<UIA FrameworkId>  (snapped only: Win32, WPF, WinForm, XAML, DirectUI, Chrome, ...)
+///         observing=<app>,<app>        (observe-only background targets, may be empty)
+///         mode=snap|observe            (snapped only; observe = read-only, actions are not executed)
+///         url=<address bar>            (snapped only; browsers (Chrome framework), empty otherwise)
+///         quiet=0|1                    (1 = quiet mode: no dumps, no actions, no events)
+///         protected=<reason>           (empty | secure_desktop | exclude_from_capture | capture_monitor_only)
+///         operable=<n>                 (snapped only; entries in .a11y.snap of the last dump)
+///         suspect_empty=0|1            (snapped only; 1 = nothing operable in a near-empty tree —
+///                                       accessibility is probably off, not "nothing to do")
+/// ```
+fn write_active_status(db_path: &str) {
+    let observing = OBSERVERS.lock().unwrap().iter()
+        .map(|(_, db)| app_from_db_path(db).to_string())
+        .collect::<Vec<_>>().join(",");
+    let quiet = QUIET.load(SeqCst) as u8;
+    let protected = unsafe { window_protection(if db_path.is_empty() { HWND::default() } else { tgt() }) };
+    *PROTECTION_SHOWN.lock().unwrap() = protected;
+    let content = if db_path.is_empty() {
+        format!("none\nversion={}\nobserving={}\nquiet={}\nprotected={}\n", ACTIVE_STATUS_VERSION, observing, quiet, protected)
+    } else {
+        // <dir>/claude.db → base = <dir>/claude
+        let base = db_path.trim_end_matches(".db");
+        let app = app_from_db_path(db_path);
+        let mode = if OBSERVE_ONLY.load(SeqCst) { "observe" } else { "snap" };
+        format!("{}\n{}.a11y\n{}.snap\nversion={}\nframework={}\nobserving={}\nmode={}\nurl={}\nquiet={}\nprotected={}\noperable={}\nsuspect_empty={}\n",
+            app, base, base, ACTIVE_STATUS_VERSION, get_framework(), observing, mode, get_url(), quiet, protected,
+            OPERABLE.load(SeqCst), SUSPECT_EMPTY.load(SeqCst) as u8)
+    };
+    write_checked(&ds_file(ACTIVE_FILE), content);
+}
+
+fn anim_t() -> f64 {
+    let ms = START_TIME.get_or_init(Instant::now).elapsed().as_millis() as f64;
+    (ms % LIGHT_PERIOD) / LIGHT_PERIOD
+}
+
+/// Anteil von `a`, den `b` überdeckt (0.0–1.0). Zero-area `a` → 0.0.
+fn overlap(a: &RECT, b: &RECT) -> f64 {
+    let ox = (a.right.min(b.right) - a.left.max(b.left)).max(0) as f64;
+    let oy = (a.bottom.min(b.bottom) - a.top.max(b.top)).max(0) as f64;
+    let area = (a.right - a.left) as f64 * (a.bottom - a.top) as f64;
+    if area > 0.0 { ox * oy / area } else { 0.0 }
+}
+
+/// Snap-Entscheidung: Overlay (`me`) liegt zu mindestens SNAP_THRESH über dem Ziel.
+fn snap_hit(me: &RECT, target: &RECT) -> bool {
+    overlap(me, target) >= SNAP_THRESH
+}
+
+// Farbinterpolation für Gradient
+fn lerp_clr(a: COLORREF, b: COLORREF, t: f64) -> COLORREF {
+    let mix = |av: u32, bv: u32| -> u32 {
+        (av as f64 + (bv as f64 - av as f64) * t).round() as u32
+    };
+    COLORREF(
+        mix(a.0 & 0xFF, b.0 & 0xFF)
+        | (mix((a.0 >> 8) & 0xFF, (b.0 >> 8) & 0xFF) << 8)
+        | (mix((a.0 >> 16) & 0xFF, (b.0 >> 16) & 0xFF) << 16)
+    )
+}
+
+// ── Shell-Fenster erkennen ─────────────────────────
+unsafe fn is_shell(hwnd: HWND) -> bool {
+    if hwnd == GetDesktopWindow() { return true; }
+    let mut buf = [0u16; 64];
+    let len = GetClassNameW(hwnd, &mut buf);
+    if len == 0 { return false; }
+    let cls = String::from_utf16_lossy(&buf[..len as usize]);
+    matches!(cls.as_str(),
+        "Progman" | "WorkerW" | "Shell_TrayWnd" |
+        "Shell_SecondaryTrayWnd" | "SHELLDLL_DefView"
+    )
+}
+
+// ── Protected Windows (Display Affinity / Secure Desktop) ──
+// Zwei Fälle, in denen DS kaputt aussieht, es aber nicht ist:
+// - SetWindowDisplayAffinity (DRM-Video, Passwort-Manager): Screenshots zeigen Schwarz,
+//   UIA liest meist weiter — der Agent muss wissen, dass sein Bild nicht stimmt.
+// - Secure Desktop (UAC-Prompt, Ctrl+Alt+Del, Sperrbildschirm): der Eingabedesktop ist
+//   nicht "Default" — Eingaben gehen ins Leere, Dumps können leer sein.
+// Gemeldet als protected=<reason> in is_active und als meta 'protected' im Dump.
+static PROTECTION_SHOWN: Mutex<&'static str> = Mutex::new(""); // zuletzt nach is_active geschrieben
+
+/// Reason string for is_active / meta. Secure desktop wins: then nothing works at all.
+fn protection_reason(affinity: u32, secure_desktop: bool) -> &'static str {
+    if secure_desktop { return "secure_desktop"; }
+    match WINDOW_DISPLAY_AFFINITY(affinity) {
+        WDA_NONE => "",
+        WDA_EXCLUDEFROMCAPTURE => "exclude_from_capture",
+        _ => "capture_monitor_only", // WDA_MONITOR: nur auf dem Monitor sichtbar, Capture schwarz
+    }
+}
+
+/// Input desktop is not ours ("Default") — UAC prompt or lock screen in front.
+unsafe fn secure_desktop_active() -> bool {
+    // Secure desktop denies the open outright
+    let Ok(desk) = OpenInputDesktop(DESKTOP_CONTROL_FLAGS(0), FALSE, DESKTOP_READOBJECTS) else { return true };
+    let mut buf = [0u16; 64];
+    let ok = GetUserObjectInformationW(HANDLE(desk.0), UOI_NAME,
+        Some(buf.as_mut_ptr() as *mut c_void), (buf.len() * 2) as u32, None).is_ok();
+    let _ = CloseDesktop(desk);
+    let name = String::from_utf16_lossy(&buf[..buf.iter().position(|&c| c == 0).unwrap_or(buf.len())]);
+    ok && !name.eq_ignore_ascii_case("Default")
+}
+
+/// "" = readable + capturable. Null `target` → only the secure desktop check.
+unsafe fn window_protection(target: HWND) -> &'static str {
+    let mut affinity = 0u32;
+    if !target.0.is_null() {
+        let _ = GetWindowDisplayAffinity(target, &mut affinity);
+    }
+    protection_reason(affinity, secure_desktop_active())
+}
+
+/// ENUM tick: rewrite is_active when the protection state changes (UAC prompt comes/goes).
+unsafe fn check_protection() {
+    let current = window_protection(if snapped() { tgt() } else { HWND::default() });
+    if *PROTECTION_SHOWN.lock().unwrap() == current { return; }
+    log(&format!("protection: {}", if current.is_empty() { "none" } else { current }));
+    write_active_status(&get_db_path());
+}
+
+// ── UI Automation: Instanz ──────────────────────────
+// CUIAutomation8 (Win8+) bevorzugt, CUIAutomation als Fallback (ältere Systeme /
+// Klasse nicht registriert) — sonst gäbe es nur stille leere Dumps.
+// Neuere Interfaces (IUIAutomation6, ...) werden an den Aufrufstellen per cast() geprüft.
+static UIA_CLASS: Mutex<&'static str> = Mutex::new("");
+
+unsafe fn create_uia() -> windows::core::Result<IUIAutomation> {
+    let (uia, class) = match CoCreateInstance(&CUIAutomation8, None, CLSCTX_INPROC_SERVER) {
+        Ok(u) => (u, "CUIAutomation8"),
+        Err(e8) => match CoCreateInstance(&CUIAutomation, None, CLSCTX_INPROC_SERVER) {
+            Ok(u) => (u, "CUIAutomation"),
+            Err(_) => return Err(e8),
+        },
+    };
+    let mut last = UIA_CLASS.lock().unwrap();
+    if *last != class {
+        log(&format!("uia: using {}", class));
+        *last = class;
+    }
+    Ok(uia)
+}
+
+fn uia_class() -> &'static str {
+    *UIA_CLASS.lock().unwrap()
+}
+
+// ── UI Automation: TitleBar-Höhe + Button-Offset ───
+struct CaptionInfo {
+    btn_offset: i32,
+    bar_height: i32,
+}
+
+unsafe fn probe_caption(target: HWND) -> CaptionInfo {
+    log(&format!("probe_caption: target=0x{:X}", target.0 as usize));
+    let default = CaptionInfo { btn_offset: FALLBACK_BTN_X, bar_height: DEFAULT_TOP_H };
+
+    let uia: IUIAutomation = match create_uia() {
+        Ok(u) => u,
+        Err(e) => { log(&format!("probe_caption: CoCreateInstance FAILED: {e}")); return default; }
+    };
+
+    let elem = match uia.ElementFromHandle(target) {
+        Ok(e) => e,
+        Err(e) => { log(&format!("probe_caption: ElementFromHandle FAILED: {e}")); return default; }
+    };
+
+    let mut win_rc = RECT::default();
+    let _ = GetWindowRect(target, &mut win_rc);
+    let win_right = win_rc.right;
+    let win_top = win_rc.top;
+
+    // TitleBar finden (ControlType 50037)
+    let cond = match uia.CreatePropertyCondition(
+        UIA_ControlTypePropertyId, &VARIANT::from(50037i32),
+    ) {
+        Ok(c) => c,
+        Err(_) => return default,
+    };
+
+    let titlebar = match elem.FindFirst(TreeScope_Descendants, &cond) {
+        Ok(tb) => tb,
+        Err(_) => return default,
+    };
+
+    // TitleBar-Höhe aus BoundingRectangle
+    let bar_height = match titlebar.CurrentBoundingRectangle() {
+        Ok(r) => {
+            let h = r.bottom - r.top;
+            // Manche Apps: TitleBar beginnt NICHT am Fenster-Top (Schatten/Border)
+            // Also: Höhe = TitleBar.bottom - Window.top
+            let full_h = r.bottom - win_top;
+            full_h.max(h).max(DEFAULT_TOP_H).min(60)
+        }
+        Err(_) => DEFAULT_TOP_H,
+    };
+
+    // Buttons in der TitleBar finden (ControlType 50000)
+    let btn_cond = match uia.CreatePropertyCondition(
+        UIA_ControlTypePropertyId, &VARIANT::from(50000i32),
+    ) {
+        Ok(c) => c,
+        Err(_) => return CaptionInfo { btn_offset: FALLBACK_BTN_X, bar_height },
+    };
+
+    let buttons = match titlebar.FindAll(TreeScope_Children, &btn_cond) {
+        Ok(b) => b,
+        Err(_) => return CaptionInfo { btn_offset: FALLBACK_BTN_X, bar_height },
+    };
+
+    let count = buttons.Length().unwrap_or(0);
+    if count == 0 {
+        return CaptionInfo { btn_offset: FALLBACK_BTN_X, bar_height };
+    }
+
+    let mut leftmost_x = win_right;
+    for i in 0..count {
+        if let Ok(btn) = buttons.GetElement(i) {
+            if let Ok(rect) = btn.CurrentBoundingRectangle() {
+                if rect.left < leftmost_x {
+                    leftmost_x = rect.left;
+                }
+            }
+        }
+    }
+
+    let btn_offset = win_right - leftmost_x;
+    let result = CaptionInfo {
+        btn_offset: if btn_offset > 0 && btn_offset < 400 { btn_offset } else { FALLBACK_BTN_X },
+        bar_height,
+    };
+    log(&format!("probe_caption: btn_offset={}, bar_height={}", result.btn_offset, result.bar_height));
+    result
+}
+
+// ── Accessibility Tree Engine ───────────────────────
+
+fn role_name(ct: i32) -> &'static str {
+    match ct {
+        50000 => "Button",     50001 => "Calendar",   50002 => "CheckBox",
+        50003 => "ComboBox",   50004 => "Edit",       50005 => "Hyperlink",
+        50006 => "Image",      50007 => "ListItem",   50008 => "List",
+        50009 => "Menu",       50010 => "MenuBar",    50011 => "MenuItem",
+        50012 => "ProgressBar",50013 => "RadioButton",50014 => "ScrollBar",
+        50015 => "Slider",     50016 => "Spinner",    50017 => "StatusBar",
+        50018 => "Tab",        50019 => "TabItem",    50020 => "Text",
+        50021 => "ToolBar",    50022 => "ToolTip",    50023 => "Tree",
+        50024 => "TreeItem",   50025 => "Custom",     50026 => "Group",
+        50027 => "Thumb",      50028 => "DataGrid",   50029 => "DataItem",
+        50030 => "Document",   50031 => "SplitButton",50032 => "Window",
+        50033 => "Pane",       50034 => "Header",     50035 => "HeaderItem",
+        50036 => "Table",      50037 => "TitleBar",   50038 => "Separator",
+        _ => "Unknown",
+    }
+}
+
+/// Landmark of an element (ARIA navigation/main/search/form, or the localized
+/// type for custom landmarks like "complementary"). Empty if none. Cached (dump walk).
+unsafe fn get_landmark(elem: &IUIAutomationElement) -> String {
+    let Ok(v) = elem.GetCachedPropertyValue(UIA_LandmarkTypePropertyId) else { return String::new(); };
+    match i32::try_from(&v).unwrap_or(0) {
+        80001 => "form".to_string(),        // UIA_FormLandmarkTypeId
+        80002 => "main".to_string(),        // UIA_MainLandmarkTypeId
+        80003 => "navigation".to_string(),  // UIA_NavigationLandmarkTypeId
+        80004 => "search".to_string(),      // UIA_SearchLandmarkTypeId
+        80000 => elem.GetCachedPropertyValue(UIA_LocalizedLandmarkTypePropertyId).ok()
+            .and_then(|v| BSTR::try_from(&v).ok())
+            .map(|b| b.to_string().to_lowercase())
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| "custom".to_string()),
+        _ => String::new(),
+    }
+}
+
+/// Heading level 1-9 (UIA HeadingLevel1..9 = 80051..80059), None for non-headings. Cached.
+unsafe fn get_heading_level(elem: &IUIAutomationElement) -> Option<i32> {
+    let v = elem.GetCachedPropertyValue(UIA_HeadingLevelPropertyId).ok()?;
+    let id = i32::try_from(&v).ok()?;
+    (80051..=80059).contains(&id).then_some(id - 80050)
+}
+
+/// RangeValuePattern position as percent of min..max (ProgressBar/Spinner), None without range. Cached.
+unsafe fn get_progress(elem: &IUIAutomationElement) -> Option<f64> {
+    let (cur, min, max) = (
+        cached_f64(elem, UIA_RangeValueValuePropertyId)?,
+        cached_f64(elem, UIA_RangeValueMinimumPropertyId)?,
+        cached_f64(elem, UIA_RangeValueMaximumPropertyId)?,
+    );
+    if max <= min { return None; }
+    Some(((cur - min) / (max - min) * 100.0).clamp(0.0, 100.0))
+}
+
+/// Stored/reported in place of a password field's content.
+const PASSWORD_VALUE: &str = "(password)";
+
+/// Password fields never hand out their content — whatever the app exposes through
+/// ValuePattern (some show bullets, some the plain text) becomes PASSWORD_VALUE.
+fn mask_password(value: String, is_password: bool) -> String {
+    if is_password && !value.is_empty() { PASSWORD_VALUE.to_string() } else { value }
+}
+
+/// ValuePattern value, masked for password fields (IsPassword).
+unsafe fn get_value(elem: &IUIAutomationElement) -> String {
+    if let Ok(pat) = elem.GetCurrentPattern(UIA_ValuePatternId) {
+        if let Ok(vp) = pat.cast::<IUIAutomationValuePattern>() {
+            if let Ok(val) = vp.CurrentValue() {
+                let is_password = elem.CurrentIsPassword().map(|b| b.as_bool()).unwrap_or(false);
+                return mask_password(val.to_string(), is_password);
+            }
+        }
+    }
+    String::new()
+}
+
+// ── Dump Cache Request ──────────────────────────────
+// Every Current* getter is its own cross-process call — ~20 per element made large
+// trees (Electron, Office) slow. The walk fetches each element together with all
+// properties below in one round trip (…BuildCache) and stream_elements reads the
+// Cached* values. Pattern state comes from pattern properties (ignoreDefault →
+// "not supported" when the element lacks the pattern), so no pattern objects either.
+const DUMP_CACHED_PROPS: [UIA_PROPERTY_ID; 25] = [
+    UIA_ControlTypePropertyId, UIA_NamePropertyId, UIA_AutomationIdPropertyId, UIA_ClassNamePropertyId,
+    UIA_IsEnabledPropertyId, UIA_IsOffscreenPropertyId, UIA_BoundingRectanglePropertyId,
+    UIA_AcceleratorKeyPropertyId, UIA_LocalizedControlTypePropertyId,
+    UIA_LandmarkTypePropertyId, UIA_LocalizedLandmarkTypePropertyId, UIA_HeadingLevelPropertyId,
+    UIA_ValueValuePropertyId, UIA_IsPasswordPropertyId,
+    UIA_RangeValueValuePropertyId, UIA_RangeValueMinimumPropertyId, UIA_RangeValueMaximumPropertyId,
+    UIA_GridItemRowPropertyId, UIA_GridItemColumnPropertyId, UIA_ToggleToggleStatePropertyId,
+    UIA_ExpandCollapseExpandCollapseStatePropertyId, UIA_SelectionItemIsSelectedPropertyId,
+    UIA_FlowsToPropertyId, UIA_FlowsFromPropertyId, UIA_ControllerForPropertyId,
+];
+
+unsafe fn dump_cache_request(uia: &IUIAutomation) -> windows::core::Result<IUIAutomationCacheRequest> {
+    let cache = uia.CreateCacheRequest()?;
+    cache.SetTreeFilter(&uia.RawViewCondition()?)?; // same view as the RawViewWalker
+    for prop in DUMP_CACHED_PROPS {
+        cache.AddProperty(prop)?;
+    }
+    Ok(cache)
+}
+
+/// Cached property, None when unsupported (ignoreDefault).
+unsafe fn cached_i32(elem: &IUIAutomationElement, prop: UIA_PROPERTY_ID) -> Option<i32> {
+    elem.GetCachedPropertyValueEx(prop, true).ok().and_then(|v| i32::try_from(&v).ok())
+}
+
+unsafe fn cached_f64(elem: &IUIAutomationElement, prop: UIA_PROPERTY_ID) -> Option<f64> {
+    elem.GetCachedPropertyValueEx(prop, true).ok().and_then(|v| f64::try_from(&v).ok())
+}
+
+/// get_value from the dump cache: ValueValue, masked for password fields.
+unsafe fn cached_value(elem: &IUIAutomationElement) -> String {
+    let Some(val) = elem.GetCachedPropertyValueEx(UIA_ValueValuePropertyId, true).ok()
+        .and_then(|v| BSTR::try_from(&v).ok()) else { return String::new() };
+    let is_password = elem.CachedIsPassword().map(|b| b.as_bool()).unwrap_or(false);
+    mask_password(val.to_string(), is_password)
+}
+
+const TREE_TIMEOUT_MS: u64 = 2000;
+
+// ── SQLite DB Setup ──────────────────────────────────
+const DB_BUSY_TIMEOUT_MS: u32 = 500; // alle Verbindungen (Dump, Events, Inject, Lookup) warten gleich lang
+
+fn init_db(db_path: &str) -> Option<Connection> {
+    let conn = match Connection::open(db_path) {
+        Ok(c) => c,
+        Err(e) => { log(&format!("init_db: FAILED: {e}")); return None; }
+    };
+    // auto_vacuum=FULL muss VOR der ersten Tabelle gesetzt werden.
+    // Bei bestehender DB: einmalig VACUUM nötig um umzustellen.
+    let av: i32 = conn.query_row("PRAGMA auto_vacuum", [], |r| r.get(0)).unwrap_or(0);
+    if av != 1 {
+        let _ = conn.execute_batch("PRAGMA auto_vacuum=FULL; VACUUM;");
+    }
+    let _ = conn.execute_batch(&format!(
+        "PRAGMA journal_mode=WAL; PRAGMA synchronous=NORMAL; PRAGMA busy_timeout={};", DB_BUSY_TIMEOUT_MS));
+    let _ = conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS meta (
+            key   TEXT PRIMARY KEY,
+            value TEXT
+        );
+        CREATE TABLE IF NOT EXISTS elements (
+            id            INTEGER PRIMARY KEY,
+            parent_id     INTEGER,
+            depth         INTEGER,
+            role          TEXT NOT NULL,
+            control_type  INTEGER,
+            name          TEXT,
+            value         TEXT,
+            automation_id TEXT,
+            enabled       INTEGER DEFAULT 1,
+            offscreen     INTEGER DEFAULT 0,
+            x             INTEGER,
+            y             INTEGER,
+            w             INTEGER,
+            h             INTEGER,
+            path          TEXT,
+            grid_row      INTEGER,
+            grid_col      INTEGER,
+            selected      INTEGER DEFAULT 0,
+            accelerator   TEXT,
+            landmark      TEXT,
+            heading_level INTEGER,
+            progress      REAL,
+            localized_role TEXT,        -- LocalizedControlType (z.B. Schaltfläche), role stays English
+            class_name    TEXT,         -- UIA ClassName (Edit, Chrome_RenderWidgetHostHWND, ...)
+            toggle_state  INTEGER,      -- CheckBox: 0=off 1=on 2=indeterminate
+            expand_state  INTEGER       -- ExpandCollapse: 0=collapsed 1=expanded 2=partially (Blatt = NULL)
+        );
+        CREATE TABLE IF NOT EXISTS relations (
+            from_id INTEGER,             -- elements.id of the source
+            kind    TEXT,                -- flows_to | flows_from | controller_for
+            to_id   INTEGER,             -- elements.id of the target (NULL = outside the dump)
+            to_name TEXT, to_x INTEGER, to_y INTEGER, to_w INTEGER, to_h INTEGER
+        );
+        -- Persistent element catalog: survives the DROP/CREATE of each dump.
+        -- sig = role|automation_id|shape (roles + index among same-role siblings, no names:
+        -- a renamed element stays the same entry). what appeared since T? → WHERE first_seen > T
+        CREATE TABLE IF NOT EXISTS catalog (
+            sig           TEXT PRIMARY KEY,
+            role          TEXT,
+            name          TEXT,
+            automation_id TEXT,
+            path          TEXT,
+            first_seen    INTEGER,   -- ms since epoch
+            last_seen     INTEGER
+        );
+        CREATE INDEX IF NOT EXISTS idx_catalog_last ON catalog(last_seen);
+        CREATE INDEX IF NOT EXISTS idx_role      ON elements(role);
+        CREATE INDEX IF NOT EXISTS idx_offscreen ON elements(offscreen);
+        CREATE INDEX IF NOT EXISTS idx_visible   ON elements(offscreen, role) WHERE offscreen=0;
+        CREATE TABLE IF NOT EXISTS inject (
+            id     INTEGER PRIMARY KEY AUTOINCREMENT,
+            action TEXT DEFAULT 'text',
+            text   TEXT NOT NULL,
+            target TEXT DEFAULT '',
+            done   INTEGER DEFAULT 0,   -- 0 = pending, 1 = done, 2 = dead (retry budget exhausted)
+            retries INTEGER DEFAULT 0,
+            not_before INTEGER DEFAULT 0   -- ms since epoch; replay with recorded delays
+        );
+        CREATE TABLE IF NOT EXISTS events (
+            id            INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp     INTEGER NOT NULL,
+            event_type    TEXT NOT NULL,
+            element_name  TEXT,
+            element_role  TEXT,
+            detail        TEXT,
+            new_value     TEXT,
+            consumed      INTEGER DEFAULT 0,
+            repeats       INTEGER DEFAULT 1   -- coalesced property changes
+        );
+    ");
+    // Migrations for pre-existing DBs
+    let _ = conn.execute_batch("ALTER TABLE inject ADD COLUMN target TEXT DEFAULT '';");
+    let _ = conn.execute_batch("ALTER TABLE inject ADD COLUMN action TEXT DEFAULT 'text';");
+    let _ = conn.execute_batch("ALTER TABLE inject ADD COLUMN retries INTEGER DEFAULT 0;");
+    let _ = conn.execute_batch("ALTER TABLE inject ADD COLUMN not_before INTEGER DEFAULT 0;");
+    let _ = conn.execute_batch("ALTER TABLE events ADD COLUMN repeats INTEGER DEFAULT 1;");
+    // Clear stale actions from previous session
+    let _ = conn.execute("DELETE FROM inject WHERE done=0", []);
+    log("init_db: OK");
+    Some(conn)
+}
+
+/// Catalog: first/last seen per element signature, prune after CATALOG_TTL_MS unseen.
+/// The signature's path is structural ("Window#0 > Pane#1 > Button#0") — `path` embeds
+/// names, and a changing title or label must not turn everything below it into "new".
+fn update_catalog(conn: &Connection, now: i64) {
+    let _ = conn.execute(
+        "WITH RECURSIVE
+           seg(id, parent_id, s) AS (
+             SELECT id, parent_id, role || '#' || (ROW_NUMBER() OVER (PARTITION BY parent_id, role ORDER BY id) - 1)
+             FROM elements),
+           shape(id, spath) AS (
+             SELECT id, s FROM seg WHERE parent_id = 0
+             UNION ALL
+             SELECT seg.id, shape.spath || ' > ' || seg.s FROM seg JOIN shape ON seg.parent_id = shape.id)
+         INSERT INTO catalog(sig, role, name, automation_id, path, first_seen, last_seen)
+         SELECT e.role || '|' || COALESCE(e.automation_id,'') || '|' || shape.spath,
+                e.role, e.name, e.automation_id, e.path, ?1, ?1
+         FROM elements e JOIN shape ON shape.id = e.id WHERE 1
+         ON CONFLICT(sig) DO UPDATE SET last_seen=excluded.last_seen, name=excluded.name, path=excluded.path",
+        params![now],
+    );
+    let _ = conn.execute("DELETE FROM catalog WHERE last_seen < ?1", params![now - CATALOG_TTL_MS]);
+}
+
+/// Human-readable element path: "Window > Pane > Document > Button[Save]".
+/// Built from the parent chain during the walk, stored in the `path` column.
+fn element_path(parent_path: &str, role: &str, name: &str) -> String {
+    let seg = if name.is_empty() { role.to_string() } else { format!("{}[{}]", role, name) };
+    if parent_path.is_empty() { seg } else { format!("{} > {}", parent_path, seg) }
+}
+
+// Streaming: Direkt in DB schreiben während Tree Walk
+struct StreamCtx<'a> {
+    conn: &'a Connection,
+    count: i64,
+    batch: i32,
+    cap: i64,        // config max_elements, 0 = kein Limit
+    truncated: bool, // Cap erreicht → Walk abgebrochen
+    selected: std::collections::HashSet<(String, i32, i32, i32, i32)>, // SelectionPattern-Auswahl (name + rect)
+    modal: Option<(i64, String)>, // erster modaler Dialog im Baum (id, name)
+    strip_format: bool,           // config strip_format_chars (einmal pro Dump gelesen)
+    redact: Redact,               // config redact / <app>.redact (einmal pro Dump gelesen)
+    cache: &'a IUIAutomationCacheRequest, // siehe dump_cache_request
+}
+
+// ── Redaction (config: redact, <app>.redact) ────────
+// Edit/Document contents are user data — in a password manager or banking app nothing
+// that should sit in ds_profiles. redact=values stores REDACTED instead of their value,
+// redact=values,names also instead of their name (the text itself in many editors).
+// `<app>.redact=...` overrides the global key for one app (`<app>.redact=off` exempts it).
+// Applies to dumps and events; everything generated from the DB (.snap, .a11y) follows.
+const REDACTED: &str = "(redacted)";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Redact {
+    values: bool,
+    names: bool,
+}
+
+fn parse_redact(spec: &str) -> Redact {
+    let mut redact = Redact::default();
+    for tok in spec.split(',').map(|t| t.trim().to_lowercase()) {
+        match tok.as_str() {
+            "1" | "on" | "true" | "yes" | "value" | "values" => redact.values = true,
+            "name" | "names" => redact.names = true,
+            "all" => redact = Redact { values: true, names: true },
+            _ => {}
+        }
+    }
+    redact
+}
+
+fn redact_for(app: &str) -> Redact {
+    config_get(&format!("{}.redact", app)).or_else(|| config_get("redact"))
+        .map(|v| parse_redact(&v)).unwrap_or_default()
+}
+
+/// Roles whose name/value is content the user typed or opened.
+fn redactable_role(role: &str) -> bool {
+    matches!(role, "Edit" | "Document")
+}
+
+/// (name, value) as they may be stored for an element of `role`.
+fn redact_pair(redact: Redact, role: &str, name: String, value: String) -> (String, String) {
+    if !redactable_role(role) { return (name, value); }
+    let hide = |on: bool, s: String| if on && !s.is_empty() { REDACTED.to_string() } else { s };
+    (hide(redact.names, name), hide(redact.values, value))
+}
+
+// ── Text Normalization ──────────────────────────────
+// UIA names/values arrive as the app emits them: composed or decomposed ("é" vs "e"+U+0301),
+// sometimes with invisible format characters (zero-width space, LRM/RLM, bidi embeddings).
+// Stored names and values are NFC (Windows NormalizeString) and — unless config
+// strip_format_chars=0 — free of those characters. Incoming targets get the same treatment,
+// so a name copied out of .snap/.a11y matches again. ZWJ/ZWNJ stay: they change how emoji
+// and several scripts render.
+
+/// Invisible format characters dropped from names: ZWSP, LRM/RLM, ALM, word joiner,
+/// BOM, bidi embeddings/overrides (U+202A–E) and isolates (U+2066–9).
+fn is_format_char(c: char) -> bool {
+    matches!(c, '\u{200B}' | '\u{200E}' | '\u{200F}' | '\u{061C}' | '\u{2060}' | '\u{FEFF}'
+        | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+/// Unicode NFC. ASCII (the common case) is returned as is; on API failure the input.
+fn nfc(s: &str) -> String {
+    if s.is_ascii() { return s.to_string(); }
+    let wide: Vec<u16> = s.encode_utf16().collect();
+    unsafe {
+        let est = NormalizeString(NormalizationC, &wide, None);
+        if est <= 0 { return s.to_string(); }
+        let mut buf = vec![0u16; est as usize];
+        let n = NormalizeString(NormalizationC, &wide, Some(&mut buf));
+        if n <= 0 { return s.to_string(); }
+        String::from_utf16_lossy(&buf[..n as usize])
+    }
+}
+
+/// Name/value/target as stored and matched: format characters stripped (if `strip`), then NFC.
+fn normalize_text(s: &str, strip: bool) -> String {
+    if strip && s.contains(is_format_char) {
+        nfc(&s.chars().filter(|&c| !is_format_char(c)).collect::<String>())
+    } else {
+        nfc(s)
+    }
+}
+
+fn strip_format_enabled() -> bool {
+    config_get("strip_format_chars").as_deref() != Some("0")
+}
+
+/// Modal dialog inside the tree: Window with WindowPattern.IsModal or UIA IsDialog.
+unsafe fn is_modal_window(elem: &IUIAutomationElement) -> bool {
+    if let Ok(wp) = elem.GetCurrentPatternAs::<IUIAutomationWindowPattern>(UIA_WindowPatternId) {
+        if wp.CurrentIsModal().map(|b| b.as_bool()).unwrap_or(false) { return true; }
+    }
+    elem.GetCurrentPropertyValue(UIA_IsDialogPropertyId).ok()
+        .and_then(|v| bool::try_from(&v).ok())
+        .unwrap_or(false)
+}
+
+static LAST_MODAL: Mutex<String> = Mutex::new(String::new()); // für 'modal' Event nur bei Wechsel
+static POPOUTS: Mutex<Vec<(isize, String)>> = Mutex::new(Vec::new()); // weitere Top-Level-Fenster des Target-Prozesses (ENUM)
+static LAST_FUZZY: Mutex<Option<(String, f64)>> = Mutex::new(None); // fuzzy-aufgelöstes Ziel der laufenden Action
+static ACTION_DETAIL: Mutex<String> = Mutex::new(String::new());    // Zusatzinfo der laufenden Action → inject_result "detail"
+
+/// Containers whose SelectionPattern we read during the walk.
+fn is_selection_container(ct: UIA_CONTROLTYPE_ID) -> bool {
+    ct == UIA_ListControlTypeId || ct == UIA_TabControlTypeId
+        || ct == UIA_DataGridControlTypeId || ct == UIA_TreeControlTypeId
+}
+
+/// Roles whose ExpandCollapse state we read during the walk (tree nodes, menus,
+/// dropdowns, disclosure buttons).
+fn is_expandable_role(ct: UIA_CONTROLTYPE_ID) -> bool {
+    ct == UIA_TreeItemControlTypeId || ct == UIA_MenuItemControlTypeId
+        || ct == UIA_ComboBoxControlTypeId || ct == UIA_SplitButtonControlTypeId
+        || ct == UIA_ButtonControlTypeId || ct == UIA_DataItemControlTypeId
+}
+
+/// expand_state column → .a11y.snap annotation.
+fn expand_label(state: Option<i32>) -> Option<&'static str> {
+    match state? {
+        0 => Some("collapsed"),
+        1 => Some("expanded"),
+        2 => Some("partially expanded"),
+        _ => None,
+    }
+}
+
+/// Currently selected items of a container (SelectionPattern), empty if none/unsupported.
+unsafe fn current_selection(elem: &IUIAutomationElement) -> Vec<IUIAutomationElement> {
+    let Ok(sp) = elem.GetCurrentPatternAs::<IUIAutomationSelectionPattern>(UIA_SelectionPatternId) else {
+        return Vec::new();
+    };
+    let Ok(arr) = sp.GetCurrentSelection() else { return Vec::new(); };
+    let n = arr.Length().unwrap_or(0);
+    (0..n).filter_map(|i| arr.GetElement(i).ok()).collect()
+}
+
+/// Stream one root (window element) with its cached properties.
+unsafe fn stream_root(ctx: &mut StreamCtx, root: &IUIAutomationElement, walker: &IUIAutomationTreeWalker) {
+    match root.BuildUpdatedCache(ctx.cache) {
+        Ok(root) => stream_elements(ctx, &root, walker, 0, "", 0),
+        Err(e) => log(&format!("dump: root cache FAIL: {e}")),
+    }
+}
+
+unsafe fn stream_elements(
+    ctx: &mut StreamCtx,
+    elem: &IUIAutomationElement,
+    walker: &IUIAutomationTreeWalker,
+    parent_id: i64,
+    parent_path: &str,
+    depth: i32,
+) {
+    if depth > MAX_DEPTH || ctx.truncated { return; }
+    if ctx.cap > 0 && ctx.count >= ctx.cap {
+        ctx.truncated = true;
+        return;
+    }
+
+    // Alles aus dem Cache (ein Round-Trip pro Element, siehe dump_cache_request)
+    let ct = elem.CachedControlType().unwrap_or_default();
+    let name = elem.CachedName().ok().map(|s| normalize_text(&s.to_string(), ctx.strip_format)).unwrap_or_default();
+    let value = normalize_text(&cached_value(elem), ctx.strip_format);
+    let (name, value) = redact_pair(ctx.redact, role_name(ct.0), name, value);
+    let aid = elem.CachedAutomationId().ok().map(|s| s.to_string()).unwrap_or_default();
+    let class = elem.CachedClassName().ok().map(|s| s.to_string()).unwrap_or_default();
+    let enabled = elem.CachedIsEnabled().map(|b| b.as_bool()).unwrap_or(true);
+    let offscreen = elem.CachedIsOffscreen().map(|b| b.as_bool()).unwrap_or(false);
+    let rect = elem.CachedBoundingRectangle().unwrap_or_default();
+    let accel = elem.CachedAcceleratorKey().ok().map(|s| s.to_string()).unwrap_or_default();
+    let landmark = get_landmark(elem);
+    let heading_level = get_heading_level(elem);
+    let localized_role = elem.CachedLocalizedControlType().ok().map(|s| s.to_string()).unwrap_or_default();
+    let progress = if ct == UIA_ProgressBarControlTypeId || ct == UIA_SpinnerControlTypeId {
+        get_progress(elem)
+    } else {
+        None
+    };
+    let path = element_path(parent_path, role_name(ct.0), &name);
+    // DataItem → Zeile/Spalte im Grid (nur hier)
+    let (grid_row, grid_col) = if ct == UIA_DataItemControlTypeId {
+        (cached_i32(elem, UIA_GridItemRowPropertyId), cached_i32(elem, UIA_GridItemColumnPropertyId))
+    } else {
+        (None, None)
+    };
+
+    // Formular-Zustand: CheckBox → ToggleState, RadioButton → eigenes IsSelected
+    // (Radio-Gruppen sind selten Selection-Container)
+    let toggle_state = if ct == UIA_CheckBoxControlTypeId {
+        cached_i32(elem, UIA_ToggleToggleStatePropertyId)
+    } else {
+        None
+    };
+    // Aufgeklappt? Nur wo es vorkommt; ignoreDefault → "nicht unterstützt" statt LeafNode
+    let expand_state = if is_expandable_role(ct) {
+        cached_i32(elem, UIA_ExpandCollapseExpandCollapseStatePropertyId)
+            .filter(|s| *s != ExpandCollapseState_LeafNode.0)
+    } else {
+        None
+    };
+    let radio_selected = ct == UIA_RadioButtonControlTypeId
+        && elem.GetCachedPropertyValueEx(UIA_SelectionItemIsSelectedPropertyId, true).ok()
+            .and_then(|v| bool::try_from(&v).ok()).unwrap_or(false);
+
+    let sel_key = (name.clone(), rect.left, rect.top, rect.right, rect.bottom);
+    let selected = radio_selected || ctx.selected.contains(&sel_key);
+    // Auswahl VOR den Kindern merken → Kinder werden beim Insert markiert
+    if is_selection_container(ct) {
+        for item in current_selection(elem) {
+            let n = item.CurrentName().ok().map(|s| normalize_text(&s.to_string(), ctx.strip_format)).unwrap_or_default();
+            let r = item.CurrentBoundingRectangle().unwrap_or_default();
+            ctx.selected.insert((n, r.left, r.top, r.right, r.bottom));
+        }
+    }
+
+    ctx.count += 1;
+    let my_id = ctx.count;
+    if depth > 0 && ctx.modal.is_none() && ct == UIA_WindowControlTypeId && is_modal_window(elem) {
+        ctx.modal = Some((my_id, name.clone()));
+    }
+
+    let _ = ctx.conn.execute(
+        "INSERT INTO elements(id,parent_id,depth,role,control_type,name,value,automation_id,enabled,offscreen,x,y,w,h,path,grid_row,grid_col,selected,accelerator,landmark,heading_level,progress,localized_role,class_name,toggle_state,expand_state) VALUES(?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16,?17,?18,?19,?20,?21,?22,?23,?24,?25,?26)",
+        params![
+            my_id, parent_id, depth,
+            role_name(ct.0), ct.0,
+            if name.is_empty() { None } else { Some(&name) },
+            if value.is_empty() { None } else { Some(&value) },
+            if aid.is_empty() { None } else { Some(&aid) },
+            enabled as i32, offscreen as i32,
+            rect.left, rect.top,
+            rect.right - rect.left, rect.bottom - rect.top,
+            path, grid_row, grid_col, selected as i32,
+            if accel.is_empty() { None } else { Some(&accel) },
+            if landmark.is_empty() { None } else { Some(&landmark) },
+            heading_level, progress,
+            if localized_role.is_empty() { None } else { Some(&localized_role) },
+            if class.is_empty() { None } else { Some(&class) },
+            toggle_state, expand_state
+        ],
+    );
+
+    // Beziehungen: Lesereihenfolge (FlowsTo/From) + Steuerung (ControllerFor).
+    // Ziel hier nur als name+rect — id wird nach dem Walk per SQL aufgelöst.
+    let flows_from = elem.cast::<IUIAutomationElement2>().ok().and_then(|e2| e2.CachedFlowsFrom().ok());
+    for (kind, arr) in [
+        ("flows_to", elem.CachedFlowsTo().ok()),
+        ("flows_from", flows_from),
+        ("controller_for", elem.CachedControllerFor().ok()),
+    ] {
+        let Some(arr) = arr else { continue };
+        for i in 0..arr.Length().unwrap_or(0) {
+            let Ok(to) = arr.GetElement(i) else { continue };
+            let to_name = to.CurrentName().ok().map(|s| normalize_text(&s.to_string(), ctx.strip_format)).unwrap_or_default();
+            let r = to.CurrentBoundingRectangle().unwrap_or_default();
+            let _ = ctx.conn.execute(
+                "INSERT INTO relations(from_id,kind,to_name,to_x,to_y,to_w,to_h) VALUES(?1,?2,?3,?4,?5,?6,?7)",
+                params![my_id, kind, to_name, r.left, r.top, r.right - r.left, r.bottom - r.top],
+            );
+        }
+    }
+
+    // Periodic commit: macht bisherige Daten sofort querybar
+    ctx.batch += 1;
+    if ctx.batch >= STREAM_BATCH {
+        let _ = ctx.conn.execute_batch("COMMIT; BEGIN TRANSACTION;");
+        ctx.batch = 0;
+    }
+
+    // Kinder (depth-first = obere Layer kommen zuerst)
+    let mut child_count = 0i32;
+    if let Ok(child) = walker.GetFirstChildElementBuildCache(elem, ctx.cache) {
+        stream_elements(ctx, &child, walker, my_id, &path, depth + 1);
+        child_count += 1;
+        let mut prev = child;
+        loop {
+            if child_count >= MAX_CHILDREN || ctx.truncated { break; }
+            match walker.GetNextSiblingElementBuildCache(&prev, ctx.cache) {
+                Ok(next) => {
+                    stream_elements(ctx, &next, walker, my_id, &path, depth + 1);
+                    prev = next;
+                    child_count += 1;
+                }
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+/// Append one line to a log-style file. Rotates to `<path>.1` at `max_bytes`, so at
+/// most ~2× that is kept. `header` is written as the first line of every fresh file.
+fn append_rotating(path: &str, max_bytes: u64, header: Option<&str>, line: &str) {
+    use std::io::Write;
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if size >= max_bytes {
+        let _ = fs::rename(path, format!("{}.1", path));
+    }
+    let fresh = size == 0 || size >= max_bytes;
+    let Ok(mut f) = fs::OpenOptions::new().create(true).append(true).open(path) else { return };
+    if let (true, Some(h)) = (fresh, header) {
+        let _ = writeln!(f, "{}", h);
+    }
+    let _ = writeln!(f, "{}", line);
+}
+
+/// Append one dump measurement to metrics.csv. Rotates to metrics.csv.1 at
+/// METRICS_MAX_BYTES, so at most ~2 MB of history are kept.
+fn append_metrics(ts: u128, app: &str, rows: i64, ms: u128) {
+    append_rotating(&ds_file(METRICS_FILE), METRICS_MAX_BYTES, Some("timestamp,app,rows,ms"),
+        &format!("{},{},{},{}", ts, app.replace(',', "_"), rows, ms));
+}
+
+fn dump_tree() {
+    if TREE_BUSY.compare_exchange(false, true, SeqCst, SeqCst).is_err() {
+        return;
+    }
+
+    let target_raw = TARGET_HW.load(SeqCst);
+    let db_path = get_db_path();
+    if target_raw == 0 || db_path.is_empty() {
+        fail_dump_request("not snapped");
+        TREE_BUSY.store(false, SeqCst);
+        return;
+    }
+
+    std::thread::spawn(move || {
+        unsafe {
+            let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+            let target = HWND(target_raw as *mut _);
+            if IsWindow(target).as_bool() {
+                dump_window(target, &db_path, true);
+                // Still pending = dump_window bailed out before writing the files
+                fail_dump_request("dump failed (UIA unavailable or timeout, see log)");
+            } else {
+                fail_dump_request("window closed");
+            }
+            CoUninitialize();
+        }
+        TREE_BUSY.store(false, SeqCst);
+    });
+}
+
+/// A dump_now request that ends without a written dump → error marker, so the
+/// agent polling dump_done never waits forever. No-op when nothing is pending.
+fn fail_dump_request(reason: &str) {
+    if DUMP_NOW_PENDING.swap(false, SeqCst) {
+        log(&format!("dump_now: {}", reason));
+        write_checked(&ds_file(DUMP_DONE_FILE),
+            format!(r#"{{"status":"error","reason":"{}"}}"#, json_escape(reason)));
+    }
+}
+
+// ── Dump Backoff ─────────────────────────────────────
+// A hung or very slow target makes every dump run into the UIA connection timeout —
+// at 2 Hz that is a busy core and a log full of failures. Consecutive timeouts double
+// the TREE_TIMER interval (up to TREE_BACKOFF_MAX_MS); the first good dump restores
+// TREE_MS. The dump runs on a worker thread but SetTimer belongs to the window thread,
+// so the TREE_TIMER tick applies the wanted interval.
+const TREE_BACKOFF_MAX_MS: u32 = 16000;                 // Backoff: längstes Dump-Intervall
+static DUMP_TIMEOUTS: AtomicI32 = AtomicI32::new(0);     // Dump-Timeouts in Folge
+static TREE_INTERVAL: AtomicI32 = AtomicI32::new(TREE_MS as i32); // aktuell gesetztes TREE_TIMER-Intervall
+
+fn is_uia_timeout(e: &windows::core::Error) -> bool {
+    e.code() == HRESULT(UIA_E_TIMEOUT as i32)
+}
+
+/// TREE_TIMER interval after `timeouts` consecutive timed-out dumps.
+fn tree_backoff_ms(timeouts: i32) -> u32 {
+    (TREE_MS << timeouts.clamp(0, 8)).min(TREE_BACKOFF_MAX_MS)
+}
+
+/// Primary dump finished: count consecutive timeouts, reset on success.
+fn note_dump_outcome(timed_out: bool) {
+    if timed_out {
+        let n = DUMP_TIMEOUTS.fetch_add(1, SeqCst) + 1;
+        log(&format!("dump: timed out ({} in a row) — backing off, next dump in {}ms", n, tree_backoff_ms(n)));
+    } else if DUMP_TIMEOUTS.swap(0, SeqCst) > 0 {
+        log(&format!("dump: target responsive again — back to {}ms", TREE_MS));
+    }
+}
+
+/// TREE_TIMER tick: re-arm the timer when the wanted interval changed.
+unsafe fn apply_tree_backoff(me: HWND) {
+    let want = tree_backoff_ms(DUMP_TIMEOUTS.load(SeqCst));
+    if TREE_INTERVAL.swap(want as i32, SeqCst) != want as i32 {
+        let _ = SetTimer(me, TREE_TIMER, want, None);
+    }
+}
+
+/// Fresh snap / resume: normal cadence, no inherited backoff.
+fn reset_tree_backoff() {
+    DUMP_TIMEOUTS.store(0, SeqCst);
+    TREE_INTERVAL.store(TREE_MS as i32, SeqCst);
+}
+
+/// Omnibox match in the elements table (shared by URL reading and navigate).
+const ADDRESS_BAR_WHERE: &str = "role='Edit' \
+    AND (lower(name) LIKE '%address%' OR lower(name) LIKE '%adress%' \
+         OR lower(name) LIKE '%url%' OR lower(automation_id) LIKE '%url%')";
+
+/// Address bar value of a Chromium browser from the just-written dump.
+/// Omnibox = Edit named "Address and search bar" (localized: Adress-/Suchleiste, ...);
+/// fallback: the web content Document, whose Value is the page URL.
+fn browser_url(conn: &Connection) -> String {
+    conn.query_row(
+        &format!("SELECT value FROM elements WHERE {} AND value IS NOT NULL AND value<>'' ORDER BY id LIMIT 1",
+            ADDRESS_BAR_WHERE),
+        [], |r| r.get::<_, String>(0),
+    ).or_else(|_| conn.query_row(
+        "SELECT value FROM elements WHERE role='Document' AND value LIKE '%://%' ORDER BY id LIMIT 1",
+        [], |r| r.get::<_, String>(0),
+    )).unwrap_or_default()
+}
+
+/// Walk one window's tree into its DB and regenerate its .snap/.a11y/.a11y.snap.
+/// `primary` = the snapped target: additionally owns framework state, modal events,
+/// is_active and the dump_now marker. Observe-only targets just get their files.
+/// Caller must have initialized COM on this thread.
+unsafe fn dump_window(target: HWND, db_path: &str, primary: bool) {
+    let t0 = Instant::now();
+    let uia: IUIAutomation = match create_uia() {
+        Ok(u) => u,
+        Err(e) => {
+            log(&format!("dump[t]: CoCreate FAIL: {e}"));
+            return;
+        }
+    };
+
+    if let Ok(uia6) = uia.cast::<IUIAutomation6>() {
+        let _ = uia6.SetConnectionTimeout(TREE_TIMEOUT_MS as u32);
+    }
+
+    let root = match uia.ElementFromHandle(target) {
+        Ok(e) => e,
+        Err(e) => {
+            if primary && is_uia_timeout(&e) { note_dump_outcome(true); }
+            return;
+        }
+    };
+
+    let walker = match uia.RawViewWalker() {
+        Ok(w) => w,
+        Err(_) => return,
+    };
+    let cache = match dump_cache_request(&uia) {
+        Ok(c) => c,
+        Err(e) => {
+            log(&format!("dump: cache request FAIL: {e}"));
+            return;
+        }
+    };
+
+    // Hung target: already the first cross-process call runs into the connection timeout
+    let title = match root.CurrentName() {
+        Ok(s) => s.to_string(),
+        Err(e) if is_uia_timeout(&e) => {
+            log("dump: root does not answer (UIA timeout)");
+            if primary { note_dump_outcome(true); }
+            return;
+        }
+        Err(_) => String::new(),
+    };
+    let framework = root.CurrentFrameworkId().ok().map(|s| s.to_string()).unwrap_or_default();
+    if primary { set_framework(&framework); }
+    let mut win_rc = RECT::default();
+    let _ = GetWindowRect(target, &mut win_rc);
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+
+    // Streaming: Walk + INSERT gleichzeitig, COMMIT alle 200 Elemente
+    if let Some(conn) = init_db(db_path) {
+        // DROP + CREATE statt DELETE → keine Freelist-Bloat
+        let _ = conn.execute_batch("
+            DROP TABLE IF EXISTS elements;
+            DROP TABLE IF EXISTS meta;
+            CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT);
+            CREATE TABLE elements (
+                id INTEGER PRIMARY KEY, parent_id INTEGER, depth INTEGER,
+                role TEXT NOT NULL, control_type INTEGER,
+                name TEXT, value TEXT, automation_id TEXT,
+                enabled INTEGER DEFAULT 1, offscreen INTEGER DEFAULT 0,
+                x INTEGER, y INTEGER, w INTEGER, h INTEGER,
+                path TEXT, grid_row INTEGER, grid_col INTEGER,
+                selected INTEGER DEFAULT 0, accelerator TEXT, landmark TEXT,
+                heading_level INTEGER, progress REAL, localized_role TEXT, class_name TEXT,
+                toggle_state INTEGER, expand_state INTEGER
+            );
+            DROP TABLE IF EXISTS relations;
+            CREATE TABLE relations (
+                from_id INTEGER, kind TEXT, to_id INTEGER,
+                to_name TEXT, to_x INTEGER, to_y INTEGER, to_w INTEGER, to_h INTEGER
+            );
+        ");
+
+        // Meta
+        let _ = conn.execute(
+            "INSERT INTO meta(key,value) VALUES('window',?1),('hwnd',?2),('timestamp',?3),('x',?4),('y',?5),('w',?6),('h',?7),('framework',?8)",
+            params![title, format!("0x{:X}", target.0 as usize), ts.to_string(),
+                win_rc.left, win_rc.top,
+                win_rc.right - win_rc.left, win_rc.bottom - win_rc.top,
+                framework],
+        );
+        let protected = window_protection(target);
+        if !protected.is_empty() {
+            let _ = conn.execute("INSERT INTO meta(key,value) VALUES('protected',?1)", params![protected]);
+            log(&format!("dump: window is protected ({}) — content may be incomplete", protected));
+        }
+
+        // Stream: Walk tree + INSERT in einem Rutsch
+        let _ = conn.execute_batch("BEGIN TRANSACTION;");
+        let cap = config_get("max_elements").and_then(|v| v.parse::<i64>().ok()).unwrap_or(0);
+        let mut ctx = StreamCtx {
+            conn: &conn, count: 0, batch: 0, cap, truncated: false,
+            selected: std::collections::HashSet::new(), modal: None,
+            strip_format: strip_format_enabled(),
+            redact: redact_for(app_from_db_path(db_path)),
+            cache: &cache,
+        };
+        stream_root(&mut ctx, &root, &walker);
+        // Modal as separate top-level window (owned popup, main window disabled):
+        // not part of the target's UIA subtree → stream it as a second root.
+        let mut modal_popup = HWND::default();
+        if ctx.modal.is_none() && !ctx.truncated && !IsWindowEnabled(target).as_bool() {
+            if let Ok(popup) = GetWindow(target, GW_ENABLEDPOPUP) {
+                if !popup.0.is_null() && popup != target {
+                    if let Ok(pe) = uia.ElementFromHandle(popup) {
+                        let pname = pe.CurrentName().ok().map(|s| normalize_text(&s.to_string(), ctx.strip_format)).unwrap_or_default();
+                        ctx.modal = Some((ctx.count + 1, pname));
+                        modal_popup = popup;
+                        stream_root(&mut ctx, &pe, &walker);
+                    }
+                }
+            }
+        }
+        // Popouts (tool windows, detached panels) of the same process: further roots
+        let mut popouts = 0;
+        if primary && config_flag("include_popouts") {
+            let known: Vec<isize> = POPOUTS.lock().unwrap().iter().map(|(raw, _)| *raw).collect();
+            for raw in known {
+                let popout = HWND(raw as *mut _);
+                if ctx.truncated || popout == modal_popup || !IsWindow(popout).as_bool() { continue; }
+                if let Ok(pe) = uia.ElementFromHandle(popout) {
+                    stream_root(&mut ctx, &pe, &walker);
+                    popouts += 1;
+                }
+            }
+        }
+        if let Err(e) = conn.execute_batch("COMMIT;") {
+            warn_write_failure(db_path, &e.to_string());
+        }
+        // Relation targets → element ids (same name + rect in this dump)
+        let _ = conn.execute_batch("
+            UPDATE relations SET to_id = (
+                SELECT e.id FROM elements e
+                WHERE COALESCE(e.name,'') = relations.to_name
+                  AND e.x = relations.to_x AND e.y = relations.to_y
+                  AND e.w = relations.to_w AND e.h = relations.to_h
+                ORDER BY e.id LIMIT 1
+            );
+        ");
+        update_catalog(&conn, ts as i64);
+
+        // Consumers must be able to tell a cut-short dump from a complete one
+        let _ = conn.execute(
+            "INSERT INTO meta(key,value) VALUES('truncated',?1),('max_elements',?2)",
+            params![ctx.truncated as i32, cap],
+        );
+
+        let _ = conn.execute("INSERT INTO meta(key,value) VALUES('popouts',?1)", params![popouts]);
+
+        // Modal dialog: main window controls are dead while it is open
+        let (modal_id, modal_name) = ctx.modal.clone().unwrap_or((0, String::new()));
+        let _ = conn.execute(
+            "INSERT INTO meta(key,value) VALUES('modal_active',?1),('modal_name',?2),('modal_id',?3)",
+            params![(modal_id > 0) as i32, modal_name, modal_id],
+        );
+        if primary {
+            let mut last = LAST_MODAL.lock().unwrap();
+            if *last != modal_name {
+                if !modal_name.is_empty() {
+                    write_event("modal", &modal_name, "Window", "opened", "");
+                    log(&format!("dump: modal dialog '{}'", modal_name));
+                }
+                *last = modal_name;
+            }
+        }
+
+        // Browser: current URL from the dump (address bar, else the page Document's value)
+        let url = if framework == "Chrome" { browser_url(&conn) } else { String::new() };
+        let _ = conn.execute("INSERT INTO meta(key,value) VALUES('url',?1)", params![url]);
+        if primary { set_url(&url); }
+
+        let total_ms = t0.elapsed().as_millis();
+        // As slow as the connection timeout = the target answers, but barely → same backoff
+        if primary { note_dump_outcome(total_ms >= TREE_TIMEOUT_MS as u128); }
+        if ctx.truncated {
+            log(&format!("dump: TRUNCATED at max_elements={} ({}ms)", cap, total_ms));
+        }
+        log(&format!("dump{}: {} rows streamed, total={}ms", if primary { "" } else { "[observe]" }, ctx.count, total_ms));
+        let app = app_from_db_path(db_path);
+        append_metrics(ts, app, ctx.count, total_ms);
+
+        generate_snap(db_path);
+        generate_a11y(db_path);
+        let operable = generate_a11y_snap(db_path);
+        generate_relations(db_path);
+        if config_flag("binary_export") { generate_binary(db_path); }
+        if !primary { return; }
+        note_operable(operable, ctx.count);
+        write_active_status(db_path);
+        DUMP_GEN.fetch_add(1, SeqCst);
+
+        // On-demand dump (dump_now): completion marker AFTER all files are written
+        if DUMP_NOW_PENDING.swap(false, SeqCst) {
+            write_checked(&ds_file(DUMP_DONE_FILE), format!(
+                r#"{{"status":"ok","rows":{},"ms":{},"truncated":{},"timestamp":{}}}"#,
+                ctx.count, t0.elapsed().as_millis(), ctx.truncated, ts));
+        }
+    }
+}
+
+// ── Global WinEvent Hook — DS als Screen Reader sichtbar ──
+// NVDA wird von Browsern erkannt weil es SetWinEventHook nutzt.
+// Chrome probt: NotifyWinEvent(EVENT_SYSTEM_ALERT, hwnd, 1, 0)
+// Wenn IRGENDWER einen WinEvent Hook hat und AccessibleObjectFromWindow
+// zurückruft, sagt Chrome: "AT aktiv → Accessibility AN".
+// DS macht genau das — global, für ALLE Fenster, inkl. Popups.
+const WINEVENT_PROBE_MS: u64 = 250;   // pro hwnd höchstens 4 Probes/s
+const WINEVENT_MAP_MAX: usize = 256;  // ab dann alte Einträge wegräumen
+static WINEVENT_LAST_PROBE: Mutex<Option<HashMap<isize, Instant>>> = Mutex::new(None);
+
+unsafe extern "system" fn global_winevent_proc(
+    _hook: HWINEVENTHOOK,
+    _event: u32,
+    hwnd: HWND,
+    id_object: i32,
+    _id_child: i32,
+    _event_thread: u32,
+    _event_time: u32,
+) {
+    // Nur auf gültige Fenster reagieren
+    if hwnd.0.is_null() || !IsWindow(hwnd).as_bool() { return; }
+    // Throttle: pro Fenster max. alle WINEVENT_PROBE_MS — Alert-Stürme kosten sonst CPU
+    {
+        let mut last = WINEVENT_LAST_PROBE.lock().unwrap();
+        let map = last.get_or_insert_with(HashMap::new);
+        let now = Instant::now();
+        if let Some(t) = map.get(&(hwnd.0 as isize)) {
+            if now.duration_since(*t).as_millis() < WINEVENT_PROBE_MS as u128 { return; }
+        }
+        if map.len() >= WINEVENT_MAP_MAX {
+            map.retain(|_, t| now.duration_since(*t).as_millis() < WINEVENT_PROBE_MS as u128);
+        }
+        map.insert(hwnd.0 as isize, now);
+    }
+    // AccessibleObjectFromWindow zurückrufen — DAS ist was Chrome als AT-Präsenz erkennt
+    let mut acc: *mut c_void = std::ptr::null_mut();
+    let _ = AccessibleObjectFromWindow(
+        hwnd,
+        id_object as u32,
+        &IAccessible::IID,
+        &mut acc,
+    );
+}
+
+static AT_HOOK: AtomicIsize = AtomicIsize::new(0);          // EVENT_SYSTEM_ALERT hook handle
+static AT_HOOK_SCOPED: AtomicBool = AtomicBool::new(false);  // true = nur auf den Target-Prozess
+
+/// (Re)install the EVENT_SYSTEM_ALERT hook. pid 0 = global (startup / discovery),
+/// otherwise only that process (all its threads).
+unsafe fn install_at_hook(pid: u32) {
+    const EVENT_SYSTEM_ALERT: u32 = 0x0002;
+    const WINEVENT_OUTOFCONTEXT: u32 = 0x0000;
+    let old = AT_HOOK.swap(0, SeqCst);
+    if old != 0 {
+        let _ = UnhookWinEvent(HWINEVENTHOOK(old as *mut _));
+    }
+    let hook = SetWinEventHook(
+        EVENT_SYSTEM_ALERT,   // eventmin — Chrome's AT probe
+        EVENT_SYSTEM_ALERT,   // eventmax — nur dieses Event
+        HMODULE::default(),   // kein DLL, Callback in unserem Prozess
+        Some(global_winevent_proc),
+        pid,                  // 0 = alle Prozesse
+        0,                    // alle Threads
+        WINEVENT_OUTOFCONTEXT, // async Callback auf unserem Message Loop
+    );
+    AT_HOOK.store(hook.0 as isize, SeqCst);
+    AT_HOOK_SCOPED.store(pid != 0, SeqCst);
+}
+
+/// Config `winevent_scope=target`: while snapped, hook only the target process
+/// instead of the whole system. Default (global) keeps the activation trick for
+/// every app, including ones started later.
+unsafe fn scope_at_hook(target: Option<HWND>) {
+    match target {
+        Some(t) if config_get("winevent_scope").is_some_and(|v| v.eq_ignore_ascii_case("target")) => {
+            let mut pid = 0u32;
+            GetWindowThreadProcessId(t, Some(&mut pid));
+            if pid != 0 {
+                install_at_hook(pid);
+                log(&format!("WinEvent hook scoped to pid {}", pid));
+            }
+        }
+        None if AT_HOOK_SCOPED.load(SeqCst) => {
+            install_at_hook(0);
+            log("WinEvent hook back to global");
+        }
+        _ => {}
+    }
+}
+
+// ── Screen Reader Flag: persistent (Default) oder transient ──
+// Config `screen_reader_persist=0`: Flag nicht in die Registry schreiben
+// (nur SPIF_SENDCHANGE) und beim Beenden den vorherigen Wert zurücksetzen.
+static SCREEN_READER_PRIOR: AtomicI32 = AtomicI32::new(-1); // -1 = unbekannt
+
+fn screen_reader_transient() -> bool {
+    config_get("screen_reader_persist")
+        .is_some_and(|v| matches!(v.to_lowercase().as_str(), "0" | "false" | "no" | "off"))
+}
+
+fn screen_reader_spif() -> SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS {
+    SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(if screen_reader_transient() { 0x0002 } else { 0x0003 })
+}
+
+unsafe fn restore_screen_reader() {
+    let prior = SCREEN_READER_PRIOR.load(SeqCst);
+    if prior < 0 || !screen_reader_transient() { return; }
+    let _ = SystemParametersInfoW(
+        SPI_SETSCREENREADER, prior as u32, None,
+        SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0x0002), // SPIF_SENDCHANGE
+    );
+    log(&format!("SPI_SETSCREENREADER restored to {}", prior));
+}
+
+// ── Chromium Accessibility Trigger ───────────────────
+// Chromium prüft 3 Dinge:
+// 1. SPI_GETSCREENREADER — beim Start UND bei WM_SETTINGCHANGE
+// 2. UiaClientsAreListening() — true wenn UIA Event Handler registriert sind
+// 3. WM_GETOBJECT auf Chrome_RenderWidgetHostHWND — per-Renderer Aktivierung
+// Wir müssen ALLE DREI triggern damit es auch bei bereits laufendem Browser klappt.
+//
+// Timing ist auf Chromium auf einer schnellen Maschine getunt — konfigurierbar:
+//   a11y_settle_ms=<ms>  Wartezeit nach Phase 1+2, bevor geprobt wird
+//   a11y_retry_ms=<ms>   Pause zwischen zwei Probe-Durchgängen
+//   a11y_retries=<n>     Durchgänge nach dem ersten (mind. 1 — Render-Widgets kommen spät);
+//                        ab dem zweiten nur, solange noch kein Child-Fenster antwortet
+// Erfolg = mindestens ein CHILD-Fenster beantwortet WM_GETOBJECT mit einem eigenen Objekt
+// (≠ 0). Das Top-Level-Fenster zählt nicht: Chrome_WidgetWin_1 antwortet immer.
+const A11Y_SETTLE_MS: u64 = 300;
+const A11Y_RETRY_MS: u64 = 500;
+const A11Y_RETRIES: u32 = 1;
+const A11Y_PROBE_TIMEOUT_MS: u32 = 200; // pro Child — ein hängendes Fenster blockiert den Attach nicht
+
+/// One probe pass over the target's child windows (Chrome_RenderWidgetHostHWND, ...).
+/// Returns how many answered WM_GETOBJECT with an object of their own.
+unsafe fn probe_a11y(target: HWND) -> u32 {
+    unsafe extern "system" fn probe_child(hwnd: HWND, lp: LPARAM) -> BOOL {
+        let mut acc: *mut c_void = std::ptr::null_mut();
+        let _ = AccessibleObjectFromWindow(hwnd, 0xFFFFFFFC, &IAccessible::IID, &mut acc);
+        let mut answer = 0usize;
+        let sent = SendMessageTimeoutW(hwnd, WM_GETOBJECT, WPARAM(0), LPARAM(0xFFFFFFFC_u32 as i32 as isize),
+            SMTO_ABORTIFHUNG, A11Y_PROBE_TIMEOUT_MS, Some(&mut answer));
+        if sent.0 != 0 && answer != 0 { *(lp.0 as *mut u32) += 1; }
+        TRUE
+    }
+    let mut responding = 0u32;
+    let _ = EnumChildWindows(target, Some(probe_child), LPARAM(&mut responding as *mut u32 as isize));
+    responding
+}
+
+unsafe fn activate_accessibility(target: HWND) {
+    log("activate_a11y: full activation sequence...");
+
+    // ── Phase 1: System-Level Signal ──
+    // Screen Reader Flag setzen + persistieren
+    let _ = SystemParametersInfoW(
+        SPI_SETSCREENREADER,
+        1,
+        None,
+        screen_reader_spif(), // SPIF_UPDATEINIFILE | SPIF_SENDCHANGE (transient: nur SENDCHANGE)
+    );
+
+    // WM_SETTINGCHANGE DIREKT an Target senden (nicht auf Broadcast warten)
+    let _ = SendMessageW(
+        target,
+        WM_SETTINGCHANGE,
+        WPARAM(SPI_SETSCREENREADER.0 as usize),
+        LPARAM(0),
+    );
+
+    // ── Phase 2: UIA Event Handler registrieren ──
+    // DAS ist der Schlüssel: UiaClientsAreListening() wird true
+    // Chromium checkt das und aktiviert Accessibility für alle Renderer
+    // Reuse existing UIA instance across snaps to avoid memory leaks
+    let existing = A11Y_UIA_PTR.load(SeqCst);
+    if existing == 0 {
+        if let Ok(uia) = create_uia() {
+            let handler: IUIAutomationFocusChangedEventHandler = UiaFocusHandler.into();
+            let _ = uia.AddFocusChangedEventHandler(None, &handler);
+            log("activate_a11y: UIA FocusChanged handler registered → UiaClientsAreListening() = true");
+            // Store raw pointer — one instance for the lifetime of the process
+            let raw = Box::into_raw(Box::new(uia));
+            A11Y_UIA_PTR.store(raw as isize, SeqCst);
+        }
+    } else {
+        log("activate_a11y: reusing existing UIA instance");
+    }
+
+    let cfg = |key: &str, default: u64| config_get(key).and_then(|v| v.parse::<u64>().ok()).unwrap_or(default);
+    let settle = cfg("a11y_settle_ms", A11Y_SETTLE_MS);
+    let retry = cfg("a11y_retry_ms", A11Y_RETRY_MS);
+    let retries = cfg("a11y_retries", A11Y_RETRIES as u64) as u32;
+    log(&format!("activate_a11y: phase 1+2 signalled, settle {} ms", settle));
+
+    // Kurz warten damit Chromium die Signale verarbeiten kann
+    std::thread::sleep(std::time::Duration::from_millis(settle));
+
+    // ── Phase 3: MSAA + WM_GETOBJECT Probes ──
+    // Jetzt wo UiaClientsAreListening() true ist, werden die Probes wirksam.
+
+    // Hauptfenster proben
+    let mut acc: *mut c_void = std::ptr::null_mut();
+    let _ = AccessibleObjectFromWindow(
+        target,
+        0xFFFFFFFC, // OBJID_CLIENT
+        &IAccessible::IID,
+        &mut acc,
+    );
+
+    // Alle Child-Windows proben — insbesondere Chrome_RenderWidgetHostHWND
+    let mut responding = probe_a11y(target);
+    log(&format!("activate_a11y: phase 3 probe → {} child window(s) answer WM_GETOBJECT", responding));
+
+    // ── Phase 4: Warten + Retry ──
+    // Der zweite Durchgang läuft immer (spät erzeugte Render-Widgets), weitere nur ohne Antwort
+    let passes = retries.max(1);
+    for pass in 1..=passes {
+        if pass > 1 && responding > 0 { break; }
+        std::thread::sleep(std::time::Duration::from_millis(retry));
+        responding = probe_a11y(target);
+        log(&format!("activate_a11y: phase 4 pass {}/{} → {} child window(s) answer WM_GETOBJECT", pass, passes, responding));
+    }
+
+    if responding > 0 {
+        log(&format!("activate_a11y: done — verified, {} child window(s) expose accessibility", responding));
+    } else {
+        log("activate_a11y: NOT verified — no child window answered WM_GETOBJECT; \
+             fine for plain Win32 apps, for browsers raise a11y_settle_ms / a11y_retry_ms / a11y_retries");
+    }
+}
+
+// UIA FocusChanged Handler — die Registrierung macht UiaClientsAreListening() true.
+// Zusätzlich: merkt sich das fokussierte Top-Level-Fenster für follow_focus.
+#[windows::core::implement(IUIAutomationFocusChangedEventHandler)]
+struct UiaFocusHandler;
+
+impl IUIAutomationFocusChangedEventHandler_Impl for UiaFocusHandler_Impl {
+    fn HandleFocusChangedEvent(
+        &self,
+        _sender: Option<&IUIAutomationElement>,
+    ) -> windows::core::Result<()> {
+        unsafe { note_focus(GetForegroundWindow()); }
+        Ok(())
+    }
+}
+
+// ── Follow Focus (config: follow_focus=snap|observe) ──
+// Hands-free: DS hängt sich automatisch an das Fenster, das der User fokussiert.
+// Der FocusHandler merkt sich nur den Kandidaten; SNAP_REQ_TIMER wechselt erst,
+// wenn der Fokus FOLLOW_DEBOUNCE_MS stabil war (Alt+Tab-Durchklicken ignorieren).
+
+fn uptime_ms() -> u64 {
+    START_TIME.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+/// Record a focus change: top-level root of `hwnd`, debounce clock restarts on change.
+unsafe fn note_focus(hwnd: HWND) {
+    if hwnd.0.is_null() { return; }
+    let root = GetAncestor(hwnd, GA_ROOT);
+    let root = if root.0.is_null() { hwnd } else { root };
+    if FOLLOW_CAND.swap(root.0 as isize, SeqCst) != root.0 as isize {
+        FOLLOW_SINCE.store(uptime_ms(), SeqCst);
+    }
+}
+
+/// SNAP_REQ_TIMER: attach to the focused window once it has been stable long enough.
+unsafe fn check_follow_focus(me: HWND) {
+    let observe = match config_get("follow_focus").map(|v| v.to_lowercase()).as_deref() {
+        Some("observe") => true,
+        Some("snap" | "1" | "true" | "yes" | "on") => false,
+        _ => return,
+    };
+    // FocusHandler existiert erst nach dem ersten Snap → bis dahin Foreground pollen
+    if A11Y_UIA_PTR.load(SeqCst) == 0 {
+        note_focus(GetForegroundWindow());
+    }
+    let cand = HWND(FOLLOW_CAND.load(SeqCst) as *mut _);
+    if cand.0.is_null() || cand == me { return; }
+    if uptime_ms().saturating_sub(FOLLOW_SINCE.load(SeqCst)) < FOLLOW_DEBOUNCE_MS { return; }
+    if snapped() && tgt() == cand && OBSERVE_ONLY.load(SeqCst) == observe { return; }
+    if !IsWindow(cand).as_bool() || !IsWindowVisible(cand).as_bool() || is_shell(cand) { return; }
+    let mut buf = [0u16; 256];
+    let len = GetWindowTextW(cand, &mut buf);
+    if len == 0 || String::from_utf16_lossy(&buf[..len as usize]).trim().is_empty() { return; }
+
+    log(&format!("follow_focus: 0x{:X} (observe={})", cand.0 as usize, observe));
+    if snapped() { do_unsnap(me); }
+    DAEMON_SNAP.store(true, SeqCst);
+    if observe { do_observe(me, cand); } else { do_snap(me, cand); }
+    DAEMON_SNAP.store(false, SeqCst);
+}
+
+// ── UIA Live Event System ───────────────────────────
+// Drei Handler: Automation Events, Property Changes, Structure Changes
+// Events → SQLite `events` Tabelle → MCP liest nur Deltas
+
+/// Cached event DB connection — avoids Connection::open per event.
+static EVENT_DB: Mutex<Option<(String, Connection)>> = Mutex::new(None);
+
+// ── Event Ignore List (ds_profiles/<app>.event_ignore) ──
+// Noisy apps (live timers, media players) would flood the 500-row events buffer.
+// One rule per line, '#' = comment. A rule is one or more conditions joined by ';',
+// all must match: role=<glob>, type=<glob> (event_type), name=<glob>; a bare pattern
+// is a name pattern. Globs: * and ?, case-insensitive. Reloaded on mtime change.
+//   role=Text; name=*:??      ← clock-like texts
+//   type=property_changed; name=Elapsed*
+
+#[derive(Debug, Default, PartialEq)]
+struct EventIgnoreRule {
+    event_type: Option<String>,
+    role: Option<String>,
+    name: Option<String>,
+}
+
+fn parse_ignore_rule(line: &str) -> Option<EventIgnoreRule> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') { return None; }
+    let mut rule = EventIgnoreRule::default();
+    for cond in line.split(';').map(str::trim).filter(|c| !c.is_empty()) {
+        let (slot, pat) = match cond.split_once('=') {
+            Some((k, v)) if k.trim().eq_ignore_ascii_case("role") => (&mut rule.role, v),
+            Some((k, v)) if k.trim().eq_ignore_ascii_case("type") => (&mut rule.event_type, v),
+            Some((k, v)) if k.trim().eq_ignore_ascii_case("name") => (&mut rule.name, v),
+            _ => (&mut rule.name, cond),
+        };
+        *slot = Some(pat.trim().to_lowercase());
+    }
+    Some(rule)
+}
+
+impl EventIgnoreRule {
+    fn matches(&self, event_type: &str, name: &str, role: &str) -> bool {
+        let ok = |pat: &Option<String>, text: &str| pat.as_ref().is_none_or(|p| glob_match(p, &text.to_lowercase()));
+        ok(&self.event_type, event_type) && ok(&self.role, role) && ok(&self.name, name)
+    }
+}
+
+/// Glob with * (any run) and ? (one char). Caller lowercases both sides.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (p, t): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<(usize, usize)> = None; // (pattern pos after *, text pos)
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi + 1, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+type EventIgnoreCache = (String, Option<SystemTime>, Vec<EventIgnoreRule>);
+static EVENT_IGNORE: Mutex<Option<EventIgnoreCache>> = Mutex::new(None);
+
+/// Does the snapped app's ignore list drop this event?
+fn event_ignored(db_path: &str, event_type: &str, name: &str, role: &str) -> bool {
+    let path = db_path.replace(".db", ".event_ignore");
+    let mtime = fs::metadata(&path).and_then(|m| m.modified()).ok();
+    let mut guard = EVENT_IGNORE.lock().unwrap();
+    let stale = match &*guard {
+        Some((cached_path, cached, _)) => *cached_path != path || *cached != mtime,
+        None => true,
+    };
+    if stale {
+        let rules: Vec<EventIgnoreRule> = fs::read_to_string(&path).unwrap_or_default()
+            .lines().filter_map(parse_ignore_rule).collect();
+        if !rules.is_empty() {
+            log(&format!("events: {} ignore rule(s) from {}", rules.len(), path));
+        }
+        *guard = Some((path, mtime, rules));
+    }
+    guard.as_ref().is_some_and(|(_, _, rules)| rules.iter().any(|r| r.matches(event_type, name, role)))
+}
+
+// ── Event Durability (config: event_sync, event_checkpoint) ──
+// The events connection runs WAL + synchronous=NORMAL: fast, but a hard crash or power
+// loss can drop the last commits. For audit use, both opt-in (they cost throughput):
+//   event_sync=full          every event is fsynced before write_event returns
+//                            (applies when the connection opens, i.e. from the next snap)
+//   event_checkpoint=<secs>  fold the WAL into the DB file (TRUNCATE) every <secs> seconds
+//
+// busy_timeout alone is not enough under contention with a running dump (long write
+// transactions): a busy/locked INSERT is retried with backoff, and what still fails is
+// logged as "event DROPPED" with a running count instead of vanishing.
+static LAST_CHECKPOINT: AtomicU64 = AtomicU64::new(0);
+static EVENTS_DROPPED: AtomicU64 = AtomicU64::new(0);
+const EVENT_INSERT_RETRIES: u32 = 3;
+const EVENT_RETRY_BASE_MS: u64 = 25; // 25, 50, 100 ms (zusätzlich zum busy_timeout)
+
+fn is_db_busy(e: &rusqlite::Error) -> bool {
+    matches!(e.sqlite_error_code(), Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked))
+}
+
+fn event_sync_pragma() -> &'static str {
+    if config_get("event_sync").is_some_and(|v| v.eq_ignore_ascii_case("full")) { "FULL" } else { "NORMAL" }
+}
+
+/// ENUM tick: periodic WAL checkpoint of the cached events connection.
+fn checkpoint_events() {
+    let Some(secs) = config_get("event_checkpoint")
+        .and_then(|v| v.parse::<u64>().ok()).filter(|s| *s > 0) else { return };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+    if now.saturating_sub(LAST_CHECKPOINT.load(SeqCst)) < secs * 1000 { return; }
+    LAST_CHECKPOINT.store(now, SeqCst);
+    let Ok(guard) = EVENT_DB.lock() else { return };
+    let Some((db_path, conn)) = &*guard else { return };
+    // (busy, wal frames, checkpointed) — busy = a reader (MCP server) held the WAL, retry next round
+    match conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, i64>(1)?))) {
+        Ok((0, _)) => {}
+        Ok((_, frames)) => log(&format!("events: checkpoint of {} busy ({} WAL frames left)", app_from_db_path(db_path), frames)),
+        Err(e) => log(&format!("events: checkpoint FAIL: {}", e)),
+    }
+}
+
+/// Write a single event row to the events table.
+/// Dropped when it matches the app's ignore list (see event_ignored).
+fn write_event(event_type: &str, elem_name: &str, elem_role: &str, detail: &str, new_val: &str) {
+    let db_path = get_db_path();
+    if db_path.is_empty() { return; }
+    if event_ignored(&db_path, event_type, elem_name, elem_role) { return; }
+    let (name, value) = redact_pair(redact_for(app_from_db_path(&db_path)), elem_role,
+        elem_name.to_string(), new_val.to_string());
+    let (elem_name, new_val) = (name.as_str(), value.as_str());
+
+    let mut guard = match EVENT_DB.lock() {
+        Ok(g) => g,
+        Err(_) => return,
+    };
+
+    // Re-open connection if db path changed (new snap target) or not yet opened
+    let needs_open = match &*guard {
+        Some((cached_path, _)) => cached_path != &db_path,
+        None => true,
+    };
+    if needs_open {
+        if let Ok(conn) = Connection::open(&db_path) {
+            let _ = conn.execute_batch(&format!(
+                "PRAGMA journal_mode=WAL; PRAGMA synchronous={}; PRAGMA busy_timeout={};",
+                event_sync_pragma(), DB_BUSY_TIMEOUT_MS));
+            let _ = conn.execute_batch("
+                CREATE TABLE IF NOT EXISTS events (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT, timestamp INTEGER NOT NULL,
+                    event_type TEXT NOT NULL, element_name TEXT, element_role TEXT,
+                    detail TEXT, new_value TEXT, consumed INTEGER DEFAULT 0,
+                    repeats INTEGER DEFAULT 1
+                );
+            ");
+            let _ = conn.execute_batch("ALTER TABLE events ADD COLUMN repeats INTEGER DEFAULT 1;");
+            *guard = Some((db_path.clone(), conn));
+        } else {
+            return;
+        }
+    }
+
+    if let Some((_, conn)) = &*guard {
+        let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64;
+        let name_opt = if elem_name.is_empty() { None } else { Some(elem_name) };
+        let role_opt = if elem_role.is_empty() { None } else { Some(elem_role) };
+        let val_opt = if new_val.is_empty() { None } else { Some(new_val) };
+
+        let mut attempt = 0;
+        loop {
+            match store_event(conn, ts, event_type, name_opt, role_opt, detail, val_opt) {
+                Ok(_) => break,
+                Err(e) if is_db_busy(&e) && attempt < EVENT_INSERT_RETRIES => {
+                    std::thread::sleep(std::time::Duration::from_millis(EVENT_RETRY_BASE_MS << attempt));
+                    attempt += 1;
+                }
+                Err(e) => {
+                    let dropped = EVENTS_DROPPED.fetch_add(1, SeqCst) + 1;
+                    log(&format!("event DROPPED ({} total): {} '{}' after {} retries — {e}",
+                        dropped, event_type, elem_name, attempt));
+                    return;
+                }
+            }
+        }
+        // Prune: keep max 500 events
+        let _ = conn.execute(
+            "DELETE FROM events WHERE id NOT IN (SELECT id FROM events ORDER BY id DESC LIMIT 500)", [],
+        );
+    }
+}
+
+/// Insert one event row. Coalesce: a chatty control (progress bar, clock, live counter)
+/// would otherwise flood the 500-row window. A repeated property change of the same
+/// element replaces its latest unconsumed row — deleted and re-inserted, so the id moves
+/// forward: id order stays time order, and a reader that already fetched the old row
+/// (ds_events marks consumed by `id <= max`) still gets the new value as a new id.
+fn store_event(conn: &Connection, ts: i64, event_type: &str, name: Option<&str>, role: Option<&str>,
+               detail: &str, value: Option<&str>) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    let mut repeats = 1i64;
+    if event_type == "property" {
+        repeats += tx.query_row(
+            "DELETE FROM events WHERE consumed=0 AND id=(SELECT MAX(id) FROM events WHERE event_type='property' \
+             AND element_name IS ?1 AND element_role IS ?2 AND detail IS ?3) RETURNING COALESCE(repeats,1)",
+            params![name, role, detail], |r| r.get::<_, i64>(0),
+        ).optional()?.unwrap_or(0);
+    }
+    tx.execute(
+        "INSERT INTO events(timestamp,event_type,element_name,element_role,detail,new_value,repeats) \
+         VALUES(?1,?2,?3,?4,?5,?6,?7)",
+        params![ts, event_type, name, role, detail, value, repeats],
+    )?;
+    tx.commit()
+}
+
+/// Default gap between event-triggered dumps (config: debounce_<event>=ms).
+const EVENT_DUMP_DEBOUNCE_MS: isize = 500;
+
+/// Debounced dump_tree trigger from event handlers. `event` = the trigger
+/// (content_loaded, children_invalidated, children_bulk_added); fires only if the last
+/// event-triggered dump is older than that event's window — e.g. debounce_content_loaded=100
+/// for quick tab loads, debounce_children_bulk_added=2000 to coalesce chatty lists.
+fn event_trigger_dump(event: &str) {
+    let window = config_get(&format!("debounce_{}", event))
+        .and_then(|v| v.parse::<isize>().ok())
+        .unwrap_or(EVENT_DUMP_DEBOUNCE_MS);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as isize;
+    let last = LAST_EVENT_DUMP_MS.load(SeqCst);
+    if now - last > window {
+        LAST_EVENT_DUMP_MS.store(now, SeqCst);
+        dump_tree();
+    }
+}
+
+/// Safely extract element name from UIA callback sender.
+fn sender_name(sender: Option<&IUIAutomationElement>) -> String {
+    sender.and_then(|e| unsafe { e.CurrentName().ok() })
+        .map(|s| s.to_string()).unwrap_or_default()
+}
+
+/// Safely extract element role from UIA callback sender.
+fn sender_role(sender: Option<&IUIAutomationElement>) -> String {
+    sender.and_then(|e| unsafe { e.CurrentControlType().ok() })
+        .map(|ct| role_name(ct.0).to_string()).unwrap_or_default()
+}
+
+// ── Handler 1: Automation Events (Window opened, Menu, Content loaded) ──
+
+#[windows::core::implement(IUIAutomationEventHandler)]
+struct DsEventHandler;
+
+impl IUIAutomationEventHandler_Impl for DsEventHandler_Impl {
+    fn HandleAutomationEvent(
+        &self,
+        sender: Option<&IUIAutomationElement>,
+        eventid: UIA_EVENT_ID,
+    ) -> windows::core::Result<()> {
+        let name = sender_name(sender);
+        let role = sender_role(sender);
+        let event_name = match eventid.0 {
+            20016 => "window_opened",
+            20003 => "menu_opened",
+            20006 => "content_loaded",
+            other => { log(&format!("EVENT[auto]: unknown id={}", other)); return Ok(()); }
+        };
+        log(&format!("EVENT[auto]: {} on '{}' ({})", event_name, name, role));
+        write_event("automation", &name, &role, event_name, "");
+
+        // Content loaded = new tab ready → refresh tree (fixes tab-switch bug!)
+        if eventid.0 == 20006 {
+            event_trigger_dump(event_name);
+        }
+        Ok(())
+    }
+}
+
+// ── Handler 2: Property Changes (Name, Value, ToggleState, IsEnabled) ──
+
+#[windows::core::implement(IUIAutomationPropertyChangedEventHandler)]
+struct DsPropertyHandler;
+
+impl IUIAutomationPropertyChangedEventHandler_Impl for DsPropertyHandler_Impl {
+    fn HandlePropertyChangedEvent(
+        &self,
+        sender: Option<&IUIAutomationElement>,
+        propertyid: UIA_PROPERTY_ID,
+        newvalue: &VARIANT,
+    ) -> windows::core::Result<()> {
+        let name = sender_name(sender);
+        let role = sender_role(sender);
+        let prop_name = match propertyid.0 {
+            30005 => "Name",
+            30045 => "Value",
+            30086 => "ToggleState",
+            30010 => "IsEnabled",
+            _ => "unknown",
+        };
+        // Extract value from VARIANT (windows-rs 0.58 safe API)
+        let val_str = if let Ok(s) = BSTR::try_from(newvalue) {
+            s.to_string()
+        } else if let Ok(i) = i32::try_from(newvalue) {
+            format!("{}", i)
+        } else if let Ok(b) = bool::try_from(newvalue) {
+            if b { "true".into() } else { "false".into() }
+        } else {
+            "(unknown_type)".into()
+        };
+        // Typing into a password field fires Value changes with the typed text
+        let is_password = sender.and_then(|e| unsafe { e.CurrentIsPassword().ok() }).is_some_and(|b| b.as_bool());
+        let val_str = if propertyid.0 == 30045 { mask_password(val_str, is_password) } else { val_str };
+        // Log lands on disk too → same redaction as the events table
+        let redact = redact_for(app_from_db_path(&get_db_path()));
+        let (shown_name, shown_val) = redact_pair(redact, &role, name.clone(), val_str.clone());
+        log(&format!("EVENT[prop]: {}.{} = '{}' on '{}'", role, prop_name, shown_val, shown_name));
+        write_event("property", &name, &role, prop_name, &val_str);
+        Ok(())
+    }
+}
+
+// ── Handler 3: Structure Changes (DOM mutations) ──
+
+#[windows::core::implement(IUIAutomationStructureChangedEventHandler)]
+struct DsStructureHandler;
+
+impl IUIAutomationStructureChangedEventHandler_Impl for DsStructureHandler_Impl {
+    fn HandleStructureChangedEvent(
+        &self,
+        sender: Option<&IUIAutomationElement>,
+        changetype: StructureChangeType,
+        _runtimeid: *const SAFEARRAY,
+    ) -> windows::core::Result<()> {
+        let name = sender_name(sender);
+        let role = sender_role(sender);
+        let change_name = match changetype.0 {
+            0 => "child_added",
+            1 => "child_removed",
+            2 => "children_invalidated",
+            3 => "children_bulk_added",
+            4 => "children_bulk_removed",
+            5 => "children_reordered",
+            _ => "unknown",
+        };
+        log(&format!("EVENT[struct]: {} on '{}' ({})", change_name, name, role));
+        write_event("structure", &name, &role, change_name, "");
+
+        // Major structure changes → refresh tree (debounced)
+        if changetype.0 == 2 || changetype.0 == 3 {
+            event_trigger_dump(change_name);
+        }
+        Ok(())
+    }
+}
+
+// ── Event Handler Registration / Cleanup ────────────
+
+unsafe fn register_event_handlers(target: HWND) {
+    // A previous RemoveAllEventHandlers still running → registering now would put new
+    // handlers next to old ones that still fire (duplicate events) and race their removal.
+    // Overlay: defer to the SNAP_REQ tick. Embedding API (no timers): wait here.
+    if TEARDOWNS.load(SeqCst) > 0 {
+        if DS_HWND.load(SeqCst) != 0 && teardown_age_ms() < TEARDOWN_TIMEOUT_MS {
+            DEFERRED_REGISTER.store(target.0 as isize, SeqCst);
+            log("register_events: previous teardown still running — deferred");
+            return;
+        }
+        while TEARDOWNS.load(SeqCst) > 0 && teardown_age_ms() < TEARDOWN_TIMEOUT_MS {
+            std::thread::sleep(std::time::Duration::from_millis(TEARDOWN_POLL_MS));
+        }
+        if TEARDOWNS.load(SeqCst) > 0 {
+            // create_uia() below is a fresh instance — the hung one keeps its handlers until it returns
+            log(&format!("register_events: teardown hung for {}ms — registering on a fresh UIA instance", teardown_age_ms()));
+        }
+    }
+    DEFERRED_REGISTER.store(0, SeqCst);
+    log("register_events: starting...");
+
+    let uia: IUIAutomation = match create_uia() {
+        Ok(u) => u,
+        Err(e) => { log(&format!("register_events: CoCreate FAIL: {e}")); return; }
+    };
+
+    let root = match uia.ElementFromHandle(target) {
+        Ok(e) => e,
+        Err(e) => { log(&format!("register_events: ElementFromHandle FAIL: {e}")); return; }
+    };
+
+    let scope = TreeScope(7); // TreeScope_Subtree
+
+    // 1. Automation Events
+    let auto_handler: IUIAutomationEventHandler = DsEventHandler.into();
+    for &eid in &[20016i32, 20003, 20006] { // WindowOpened, MenuOpened, AsyncContentLoaded
+        match uia.AddAutomationEventHandler(UIA_EVENT_ID(eid), &root, scope, None, &auto_handler) {
+            Ok(_) => log(&format!("register_events: automation event {} OK", eid)),
+            Err(e) => log(&format!("register_events: automation event {} FAIL: {e}", eid)),
+        }
+    }
+
+    // 2. Property Changed Events
+    let prop_handler: IUIAutomationPropertyChangedEventHandler = DsPropertyHandler.into();
+    let prop_ids = [
+        UIA_PROPERTY_ID(30005), // Name
+        UIA_PROPERTY_ID(30045), // Value
+        UIA_PROPERTY_ID(30086), // ToggleState
+        UIA_PROPERTY_ID(30010), // IsEnabled
+    ];
+    match uia.AddPropertyChangedEventHandlerNativeArray(&root, scope, None, &prop_handler, &prop_ids) {
+        Ok(_) => log("register_events: property handler OK"),
+        Err(e) => log(&format!("register_events: property handler FAIL: {e}")),
+    }
+
+    // 3. Structure Changed Events
+    let struct_handler: IUIAutomationStructureChangedEventHandler = DsStructureHandler.into();
+    match uia.AddStructureChangedEventHandler(&root, scope, None, &struct_handler) {
+        Ok(_) => log("register_events: structure handler OK"),
+        Err(e) => log(&format!("register_events: structure handler FAIL: {e}")),
+    }
+
+    // Store UIA instance for cleanup on unsnap
+    let ptr = Box::into_raw(Box::new(uia)) as isize;
+    EVENT_UIA_PTR.store(ptr, SeqCst);
+    log("register_events: ALL handlers registered");
+}
+
+unsafe fn unregister_event_handlers() {
+    // Unsnapped while the registration was still waiting: nothing to remove
+    DEFERRED_REGISTER.store(0, SeqCst);
+    let ptr = EVENT_UIA_PTR.swap(0, SeqCst);
+    if ptr != 0 {
+        // RemoveAllEventHandlers() is a synchronous COM call that can block for
+        // 10+ seconds if the target app is slow or hung. Running it on the
+        // message-loop thread freezes the entire overlay. Spawn a background
+        // thread so unsnap completes instantly and the UI stays responsive.
+        // TEARDOWNS tracks it so the next register_event_handlers waits for it.
+        if TEARDOWNS.fetch_add(1, SeqCst) == 0 {
+            TEARDOWN_SINCE.store(now_ms_u64(), SeqCst);
+        }
+        std::thread::spawn(move || {
+            // COM needs per-thread init
+            let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+            let uia = Box::from_raw(ptr as *mut IUIAutomation);
+            let t0 = Instant::now();
+            match uia.RemoveAllEventHandlers() {
+                Ok(_) => log(&format!("unregister_events: all handlers removed ({}ms)", t0.elapsed().as_millis())),
+                Err(e) => log(&format!("unregister_events: FAIL: {e}")),
+            }
+            drop(uia); // COM Release before CoUninitialize
+            CoUninitialize();
+            TEARDOWNS.fetch_sub(1, SeqCst);
+        });
+    }
+}
+
+fn now_ms_u64() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// How long the oldest still-running teardown has been going (0 = none).
+fn teardown_age_ms() -> u64 {
+    if TEARDOWNS.load(SeqCst) == 0 { return 0; }
+    now_ms_u64().saturating_sub(TEARDOWN_SINCE.load(SeqCst))
+}
+
+/// SNAP_REQ tick: run a deferred registration once the old teardown finished
+/// (or hung past TEARDOWN_TIMEOUT_MS). Dropped if the target changed meanwhile.
+unsafe fn check_deferred_registration() {
+    let raw = DEFERRED_REGISTER.load(SeqCst);
+    if raw == 0 { return; }
+    if !snapped() || raw != TARGET_HW.load(SeqCst) {
+        DEFERRED_REGISTER.store(0, SeqCst);
+        return;
+    }
+    if TEARDOWNS.load(SeqCst) > 0 && teardown_age_ms() < TEARDOWN_TIMEOUT_MS { return; }
+    log("register_events: running deferred registration");
+    register_event_handlers(HWND(raw as *mut _));
+}
+
+/// WM_DESTROY: free the process-lifetime UIA instance from activate_accessibility.
+/// Removes its FocusChanged handler first so COM releases cleanly. Same hang risk as
+/// above → worker thread, but exit waits at most A11Y_RELEASE_TIMEOUT_MS for it.
+unsafe fn release_a11y_uia() {
+    let ptr = A11Y_UIA_PTR.swap(0, SeqCst);
+    if ptr == 0 { return; }
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+        let uia = Box::from_raw(ptr as *mut IUIAutomation);
+        let _ = uia.RemoveAllEventHandlers();
+        drop(uia);
+        CoUninitialize();
+        let _ = tx.send(());
+    });
+    match rx.recv_timeout(std::time::Duration::from_millis(A11Y_RELEASE_TIMEOUT_MS)) {
+        Ok(()) => log("a11y UIA instance released"),
+        Err(_) => log("a11y UIA release timed out — exiting anyway"),
+    }
+}
+
+// ── Element Rows ────────────────────────────────────
+// One typed view of the `elements` table for every reader (.snap, .a11y, .a11y.snap,
+// embedding API). New column → ELEMENT_COLUMNS + field + from_row, nothing else.
+
+/// One element of a dump (row of the `elements` table). NULL text → "".
+#[derive(Debug, Clone, Default)]
+pub struct Element {
+    pub id: i64,
+    pub parent_id: i64,
+    pub depth: i32,
+    pub role: String,
+    pub name: String,
+    pub value: String,
+    pub automation_id: String,
+    pub enabled: bool,
+    pub offscreen: bool,
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+    pub path: String,
+    pub selected: bool,
+    pub accelerator: String,
+    pub landmark: Option<String>,
+    pub heading_level: Option<i32>,
+    pub progress: Option<f64>,
+    pub localized_role: String,
+    pub class_name: String,
+    /// CheckBox: 0 = off, 1 = on, 2 = indeterminate.
+    pub toggle_state: Option<i32>,
+    /// ExpandCollapse: 0 = collapsed, 1 = expanded, 2 = partially expanded (None = leaf / n/a).
+    pub expand_state: Option<i32>,
+}
+
+/// Column list matching Element::from_row (same order).
+const ELEMENT_COLUMNS: &str = "id, COALESCE(parent_id,0), COALESCE(depth,0), role, COALESCE(name,''), \
+    COALESCE(value,''), COALESCE(automation_id,''), COALESCE(enabled,1), COALESCE(offscreen,0), \
+    x, y, w, h, COALESCE(path,''), COALESCE(selected,0), COALESCE(accelerator,''), \
+    landmark, heading_level, progress, COALESCE(localized_role,''), COALESCE(class_name,''), \
+    toggle_state, expand_state";
+
+impl Element {
+    fn from_row(r: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Element {
+            id: r.get(0)?, parent_id: r.get(1)?, depth: r.get(2)?,
+            role: r.get(3)?, name: r.get(4)?, value: r.get(5)?, automation_id: r.get(6)?,
+            enabled: r.get::<_, i32>(7)? != 0, offscreen: r.get::<_, i32>(8)? != 0,
+            x: r.get(9)?, y: r.get(10)?, w: r.get(11)?, h: r.get(12)?,
+            path: r.get(13)?, selected: r.get::<_, i32>(14)? != 0, accelerator: r.get(15)?,
+            landmark: r.get(16)?, heading_level: r.get(17)?, progress: r.get(18)?,
+            localized_role: r.get(19)?, class_name: r.get(20)?, toggle_state: r.get(21)?,
+            expand_state: r.get(22)?,
+        })
+    }
+}
+
+/// `SELECT <ELEMENT_COLUMNS> FROM elements <tail>` — tail = WHERE/ORDER BY clause.
+/// Query errors → empty list (generators just write fewer lines).
+fn query_elements<P: rusqlite::Params>(conn: &Connection, tail: &str, params: P) -> Vec<Element> {
+    let sql = format!("SELECT {} FROM elements {}", ELEMENT_COLUMNS, tail);
+    let Ok(mut stmt) = conn.prepare(&sql) else { return Vec::new() };
+    let rows: Vec<Element> = match stmt.query_map(params, Element::from_row) {
+        Ok(rows) => rows.flatten().collect(),
+        Err(_) => Vec::new(),
+    };
+    rows
+}
+
+// ── .snap File Generation ───────────────────────────
+
+/// Map UI control role → input tool. None = not interactive.
+fn input_tool(role: &str) -> Option<&'static str> {
+    match role {
+        "Edit" | "Document" => Some("keyboard"),
+        "Button" | "Hyperlink" | "MenuItem" | "TabItem" | "ListItem"
+        | "TreeItem" | "DataItem" | "SplitButton" => Some("click"),
+        "CheckBox" | "RadioButton" => Some("toggle"),
+        "ComboBox" => Some("select"),
+        "Slider" => Some("slide"),
+        "Spinner" => Some("spin"),
+        _ => None,
+    }
+}
+
+// Lange Namen (Chat-Nachrichten, ganze Absätze als Name) sprengen eine Snap-Zeile.
+// .snap und .a11y.snap kürzen auf snap_name_max Zeichen mit '…' — die DB behält den vollen Namen.
+const SNAP_NAME_MAX_DEFAULT: usize = 200; // Zeichen, config snap_name_max (0 = ungekürzt)
+const CLIP_MARK: char = '…';
+
+fn snap_name_max() -> usize {
+    config_get("snap_name_max").and_then(|v| v.parse().ok()).unwrap_or(SNAP_NAME_MAX_DEFAULT)
+}
+
+/// Cut a name to `max` chars (char-boundary safe), last char replaced by '…'. 0 = unlimited.
+fn clip_name(name: &str, max: usize) -> Cow<'_, str> {
+    if max == 0 || name.chars().count() <= max {
+        return Cow::Borrowed(name);
+    }
+    let mut clipped: String = name.chars().take(max - 1).collect();
+    clipped.push(CLIP_MARK);
+    Cow::Owned(clipped)
+}
+
+/// Target copied from a clipped snap line → the full name from the last dump.
+/// Only if no element carries the name verbatim and exactly one name has that prefix.
+fn unclip_target(conn: &Connection, target: &str) -> Option<String> {
+    let prefix = target.strip_suffix(CLIP_MARK).filter(|p| !p.is_empty())?;
+    let exact: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM elements WHERE name = ?1", [target], |r| r.get(0)).ok()?;
+    if exact > 0 {
+        return None;
+    }
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT name FROM elements WHERE substr(name, 1, length(?1)) = ?1 LIMIT 2").ok()?;
+    let names: Vec<String> = stmt.query_map([prefix], |r| r.get(0)).ok()?
+        .filter_map(|r| r.ok()).collect();
+    if names.len() == 1 { names.into_iter().next() } else { None }
+}
+
+// ── Percent Coordinates (config: snap_coords=percent) ──
+// Absolute pixels break as soon as the window is moved or resized. In percent mode
+// each snap line carries the element's CENTER as % of the window (meta x/y/w/h),
+// size as % too. A click target "85.0%,5.2%" is mapped back onto the live window.
+
+fn percent_coords() -> bool {
+    config_get("snap_coords").is_some_and(|v| v.eq_ignore_ascii_case("percent"))
+}
+
+/// Window rect of the dump (meta x/y/w/h). None if missing or degenerate.
+fn meta_rect(conn: &Connection) -> Option<RECT> {
+    let get = |k: &str| -> Option<i32> {
+        conn.query_row("SELECT CAST(value AS INTEGER) FROM meta WHERE key=?1", [k], |r| r.get(0)).ok()
+    };
+    let (x, y, w, h) = (get("x")?, get("y")?, get("w")?, get("h")?);
+    (w > 0 && h > 0).then_some(RECT { left: x, top: y, right: x + w, bottom: y + h })
+}
+
+/// "@ x,y (wxh)" in screen pixels, or "@ cx%,cy% (w%xh%)" relative to `win`.
+fn snap_position(e: &Element, win: Option<&RECT>) -> String {
+    let Some(win) = win else {
+        return format!("@ {},{} ({}x{})", e.x, e.y, e.w, e.h);
+    };
+    let (ww, wh) = ((win.right - win.left) as f64, (win.bottom - win.top) as f64);
+    let cx = (e.x - win.left) as f64 + e.w as f64 / 2.0;
+    let cy = (e.y - win.top) as f64 + e.h as f64 / 2.0;
+    format!("@ {:.1}%,{:.1}% ({:.1}%x{:.1}%)",
+        cx * 100.0 / ww, cy * 100.0 / wh, e.w as f64 * 100.0 / ww, e.h as f64 * 100.0 / wh)
+}
+
+/// "85%,5.2%" → (85.0, 5.2). Both parts need the '%' so "100%" zoom labels stay names.
+fn parse_percent_point(s: &str) -> Option<(f64, f64)> {
+    let (a, b) = s.trim().split_once(',')?;
+    let pct = |p: &str| p.trim().strip_suffix('%')?.trim().parse::<f64>().ok().filter(|v| (0.0..=100.0).contains(v));
+    Some((pct(a)?, pct(b)?))
+}
+
+/// Percent point → absolute screen coordinates inside `win`.
+fn percent_to_screen(px: f64, py: f64, win: &RECT) -> (i32, i32) {
+    let x = win.left + ((win.right - win.left) as f64 * px / 100.0).round() as i32;
+    let y = win.top + ((win.bottom - win.top) as f64 * py / 100.0).round() as i32;
+    (x.min(win.right - 1), y.min(win.bottom - 1))
+}
+
+/// Generate .snap file from DB — lists all interactive elements with their input tool.
+fn generate_snap(db_path: &str) {
+    let snap_path = db_path.replace(".db", ".snap");
+
+    let conn = match Connection::open(db_path) {
+        Ok(c) => c,
+        Err(_) => return,
+    };
+    let _ = conn.execute_batch("PRAGMA journal_mode=WAL;");
+
+    let title: String = conn
+        .query_row("SELECT value FROM meta WHERE key='window'", [], |r| r.get(0))
+        .unwrap_or_default();
+
+    let elements = query_elements(&conn,
+        "WHERE enabled=1 AND offscreen=0 AND name IS NOT NULL AND name != '' \
+         ORDER BY y, x", []);
+
+    let mut lines: Vec<String> = Vec::new();
+    let snap_name = snap_path.split('/').last().unwrap_or("unknown");
+    lines.push(format!("# {} — Generated by DirectShell", snap_name));
+    lines.push(format!("# Window: {}", title));
+    let win = if percent_coords() { meta_rect(&conn) } else { None };
+    if win.is_some() {
+        lines.push("# Coordinates: center in % of the window — click target \"x%,y%\"".to_string());
+    }
+    lines.push(String::new());
+
+    let name_max = snap_name_max();
+    let mut count = 0usize;
+    for e in &elements {
+        if let Some(tool) = input_tool(&e.role) {
+            let mut line = format!("[{}] \"{}\" {}",
+                tool, clip_name(&e.name, name_max), snap_position(e, win.as_ref()));
+            if !e.automation_id.is_empty() {
+                line.push_str(&format!(" id={}", e.automation_id));
+            }
+            lines.push(line);
+            count += 1;
+        }
+    }
+
+    let content = lines.join("\n");
+    write_checked(&snap_path, &content);
+    log(&format!("snap: {} interactive elements → {}", count, snap_path));
+}
+
+/// Generate <app>.relations.json — FlowsTo/FlowsFrom/ControllerFor edges of the
+/// last dump. `to` is null when the target lies outside the dumped tree.
+fn generate_relations(db_path: &str) {
+    let out_path = db_path.replace(".db", ".relations.json");
+    let Ok(conn) = Connection::open(db_path) else { return };
+    let Ok(mut stmt) = conn.prepare(
+        "SELECT r.from_id, COALESCE(e.name,''), e.role, r.kind, r.to_id, r.to_name \
+         FROM relations r JOIN elements e ON e.id = r.from_id ORDER BY r.from_id",
+    ) else { return };
+    let rows = stmt.query_map([], |r| Ok((
+        r.get::<_, i64>(0)?, r.get::<_, String>(1)?, r.get::<_, String>(2)?,
+        r.get::<_, String>(3)?, r.get::<_, Option<i64>>(4)?, r.get::<_, String>(5)?,
+    )));
+    let mut entries = Vec::new();
+    if let Ok(rows) = rows {
+        for (from, from_name, from_role, kind, to, to_name) in rows.flatten() {
+            entries.push(format!(
+                r#"{{"from":{},"from_name":"{}","from_role":"{}","kind":"{}","to":{},"to_name":"{}"}}"#,
+                from, json_escape(&from_name), from_role, kind,
+                to.map(|t| t.to_string()).unwrap_or_else(|| "null".into()), json_escape(&to_name)));
+        }
+    }
+    write_checked(&out_path, format!("{{\"relations\":{}}}", json_array(&entries, "")));
+}
+
+// ── Binary Export (<app>.elements.bin, config: binary_export=1) ──
+// Fixed-size records → mmap and index directly, no SQLite, no parsing.
+// All integers little-endian. String refs = (offset into string blob, byte length), UTF-8.
+//   Header  (40 B): "DSEL", u16 version, u16 record size, u32 count, u32 blob offset,
+//                   u32 blob length, u64 timestamp ms, (u32,u32) window title, u32 reserved
+//   Record  (96 B): u32 id, u32 parent_id, u16 depth, u8 flags (1=enabled 2=offscreen 4=selected),
+//                   u8 heading_level (0=none), i32 x,y,w,h, f32 progress (NaN=none),
+//                   8 string refs: role, name, value, automation_id, path, localized_role,
+//                   landmark, accelerator
+// Records are in id order (= tree pre-order) → binary search on id.
+
+const BIN_MAGIC: &[u8; 4] = b"DSEL";
+const BIN_VERSION: u16 = 1;
+const BIN_HEADER_SIZE: usize = 40;
+const BIN_RECORD_SIZE: usize = 96;
+
+/// Encode a dump into the .elements.bin layout above.
+fn encode_elements(window: &str, ts: u64, elements: &[Element]) -> Vec<u8> {
+    let mut blob: Vec<u8> = Vec::new();
+    let mut put_str = |s: &str| -> [u8; 8] {
+        let mut r = [0u8; 8];
+        r[..4].copy_from_slice(&(blob.len() as u32).to_le_bytes());
+        r[4..].copy_from_slice(&(s.len() as u32).to_le_bytes());
+        blob.extend_from_slice(s.as_bytes());
+        r
+    };
+    let window_ref = put_str(window);
+    let mut records = Vec::with_capacity(elements.len() * BIN_RECORD_SIZE);
+    for e in elements {
+        let flags = (e.enabled as u8) | (e.offscreen as u8) << 1 | (e.selected as u8) << 2;
+        records.extend_from_slice(&(e.id as u32).to_le_bytes());
+        records.extend_from_slice(&(e.parent_id as u32).to_le_bytes());
+        records.extend_from_slice(&(e.depth.clamp(0, u16::MAX as i32) as u16).to_le_bytes());
+        records.push(flags);
+        records.push(e.heading_level.unwrap_or(0).clamp(0, 255) as u8);
+        for v in [e.x, e.y, e.w, e.h] { records.extend_from_slice(&v.to_le_bytes()); }
+        records.extend_from_slice(&(e.progress.unwrap_or(f64::NAN) as f32).to_le_bytes());
+        for text in [&e.role, &e.name, &e.value, &e.automation_id, &e.path, &e.localized_role,
+                     e.landmark.as_deref().unwrap_or_default(), &e.accelerator] {
+            records.extend_from_slice(&put_str(text));
+        }
+    }
+
+    let blob_offset = BIN_HEADER_SIZE + records.len();
+    let mut out = Vec::with_capacity(blob_offset + blob.len());
+    out.extend_from_slice(BIN_MAGIC);
+    out.extend_from_slice(&BIN_VERSION.to_le_bytes());
+    out.extend_from_slice(&(BIN_RECORD_SIZE as u16).to_le_bytes());
+    out.extend_from_slice(&(elements.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(blob_offset as u32).to_le_bytes());
+    out.extend_from_slice(&(blob.len() as u32).to_le_bytes());
+    out.extend_from_slice(&ts.to_le_bytes());
+    out.extend_from_slice(&window_ref);
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&records);
+    out.extend_from_slice(&blob);
+    out
+}
+
+/// Write <app>.elements.bin from the last dump.
+fn generate_binary(db_path: &str) {
+    let out_path = db_path.replace(".db", ".elements.bin");
+    let Ok(conn) = Connection::open(db_path) else { return };
+    let window: String = conn
+        .query_row("SELECT value FROM meta WHERE key='window'", [], |r| r.get(0))
+        .unwrap_or_default();
+    let elements = query_elements(&conn, "ORDER BY id", []);
+    let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+    write_checked(&out_path, encode_elements(&window, ts, &elements));
+}
+
+// ── .a11y File Generation (Screen Reader View) ──────
+
+/// Generate .a11y file — DB-based. Only GetFocusedElement() is live UIA.
+/// Everything else comes from the SQLite dump that just ran.
+fn generate_a11y(db_path: &str) {
+    let a11y_path = db_path.replace(".db", ".a11y");
+
+    let conn = match Connection::open(db_path) {
+        Ok(c) => c,
+        Err(_) => return,
+    };
+    let _ = conn.execute_batch("PRAGMA journal_mode=WAL;");
+
+    let title: String = conn
+        .query_row("SELECT value FROM meta WHERE key='window'", [], |r| r.get(0))
+        .unwrap_or_default();
+
+    let mut lines: Vec<String> = Vec::new();
+    let a11y_name = a11y_path.split('/').last().unwrap_or("unknown");
+    lines.push(format!("# {} — Screen Reader View (DirectShell)", a11y_name));
+    lines.push(format!("# Window: {}", title));
+    lines.push(String::new());
+
+    // 1. Focus — single live UIA call
+    lines.push("## Focus".to_string());
+    unsafe {
+        if let Ok(uia) = create_uia() {
+            if let Ok(fe) = uia.GetFocusedElement() {
+                let fname = fe.CurrentName().ok().map(|s| s.to_string()).unwrap_or_default();
+                let fct = fe.CurrentControlType().unwrap_or_default();
+                let frole = role_name(fct.0);
+                let ftool = input_tool(frole).unwrap_or("interact");
+                let frect = fe.CurrentBoundingRectangle().unwrap_or_default();
+                // Live read → the dump's redaction has to be applied here as well
+                let (fname, fval) = redact_pair(redact_for(app_from_db_path(db_path)), frole, fname, get_value(&fe));
+                lines.push(format!("[{}] \"{}\" @ {},{} ({}x{})",
+                    ftool, fname, frect.left, frect.top,
+                    frect.right - frect.left, frect.bottom - frect.top));
+                let floc = fe.CurrentLocalizedControlType().ok().map(|s| s.to_string()).unwrap_or_default();
+                if !floc.is_empty() && !floc.eq_ignore_ascii_case(frole) {
+                    lines.push(format!("  role: \"{}\"", floc));
+                }
+                if !fval.is_empty() && fval != PASSWORD_VALUE {
+                    lines.push(format!("  value: \"{}\"", clip_name(&fval, 100)));
+                }
+            } else {
+                lines.push("(none)".to_string());
+            }
+        }
+    }
+    lines.push(String::new());
+
+    // 1b. Landmarks — high-level page map (ARIA regions, mostly web content)
+    {
+        let marks: Vec<String> = query_elements(&conn,
+            "WHERE landmark IS NOT NULL AND offscreen=0 ORDER BY y, x", [])
+            .iter()
+            .map(|e| format!("[{}] \"{}\" @ {},{} ({}x{})",
+                e.landmark.as_deref().unwrap_or_default(), e.name, e.x, e.y, e.w, e.h))
+            .collect();
+        if !marks.is_empty() {
+            lines.push("## Landmarks".to_string());
+            lines.extend(marks);
+            lines.push(String::new());
+        }
+    }
+
+    // 2. Input Targets — from DB (Edit/Document with name + value)
+    lines.push("## Input Targets".to_string());
+    for e in query_elements(&conn,
+        "WHERE enabled=1 AND offscreen=0 \
+         AND name IS NOT NULL AND name != '' \
+         AND w > 10 AND h > 10 \
+         AND role IN ('Edit', 'Document', 'ComboBox') \
+         ORDER BY y, x", []) {
+        let tool = input_tool(&e.role).unwrap_or("keyboard");
+        lines.push(format!("[{}] \"{}\" @ {},{} ({}x{})", tool, e.name, e.x, e.y, e.w, e.h));
+        // Localized control type in the user's language (only if it adds something)
+        if !e.localized_role.is_empty() && !e.localized_role.eq_ignore_ascii_case(&e.role) {
+            lines.push(format!("  role: \"{}\"", e.localized_role));
+        }
+        if !e.value.is_empty() && e.value != PASSWORD_VALUE {
+            lines.push(format!("  value: \"{}\"", clip_name(&e.value, 100)));
+        }
+    }
+    lines.push(String::new());
+
+    // 3. Content — visible elements with names (from DB, no UIA walk)
+    lines.push("## Content".to_string());
+    for e in query_elements(&conn,
+        "WHERE offscreen=0 \
+         AND name IS NOT NULL AND name != '' \
+         AND w > 20 AND h > 10 \
+         AND role IN ('Text', 'Document', 'Hyperlink', 'Image', 'ListItem', 'TreeItem', 'DataItem', 'Group') \
+         ORDER BY y, x", []) {
+        if !e.value.is_empty() && e.value != e.name && e.value != PASSWORD_VALUE {
+            lines.push(format!("{} ({})", e.name, e.value));
+        } else {
+            lines.push(e.name);
+        }
+    }
+
+    // 4. Outline — headings with their depth (#, ##, ...). Last section: runs to EOF.
+    {
+        let outline: Vec<String> = query_elements(&conn,
+            "WHERE heading_level IS NOT NULL AND name IS NOT NULL AND name != '' ORDER BY id", [])
+            .iter()
+            .map(|e| format!("{} {}", "#".repeat(e.heading_level.unwrap_or(1).clamp(1, 9) as usize), e.name))
+            .collect();
+        if !outline.is_empty() {
+            lines.push(String::new());
+            lines.push("## Outline".to_string());
+            lines.extend(outline);
+        }
+    }
+
+    let content = lines.join("\n");
+    write_checked(&a11y_path, &content);
+}
+
+// ── .a11y.snap File Generation (Operable Elements in Viewport) ──
+
+/// Generate .a11y.snap from the EXISTING DB — no extra UIA calls.
+/// Lists all interactive, visible, named elements the AI can operate.
+/// Returns the number of operable entries written.
+fn generate_a11y_snap(db_path: &str) -> u32 {
+    let snap_path = db_path.replace(".db", ".a11y.snap");
+
+    let conn = match Connection::open(db_path) {
+        Ok(c) => c,
+        Err(_) => return 0,
+    };
+    let _ = conn.execute_batch("PRAGMA journal_mode=WAL;");
+
+    let title: String = conn
+        .query_row("SELECT value FROM meta WHERE key='window'", [], |r| r.get(0))
+        .unwrap_or_default();
+
+    // Modal open → only the dialog's subtree is operable
+    let modal: Option<(i64, String)> = conn.query_row(
+        "SELECT CAST(i.value AS INTEGER), n.value FROM meta i, meta n \
+         WHERE i.key='modal_id' AND n.key='modal_name' AND CAST(i.value AS INTEGER) > 0",
+        [], |r| Ok((r.get(0)?, r.get(1)?)),
+    ).ok();
+
+    let elements = query_elements(&conn,
+        "WHERE enabled=1 AND offscreen=0 \
+         AND name IS NOT NULL AND name != '' \
+         AND w > 10 AND h > 10 \
+         AND (?1 = 0 OR id IN (WITH RECURSIVE sub(id) AS ( \
+             SELECT ?1 WHERE ?1 > 0 UNION ALL SELECT e.id FROM elements e JOIN sub ON e.parent_id = sub.id) \
+             SELECT id FROM sub)) \
+         ORDER BY y, x",
+        params![modal.as_ref().map_or(0, |m| m.0)]);
+
+    let mut lines: Vec<String> = Vec::new();
+    let fname = snap_path.split('/').last().unwrap_or("unknown");
+    lines.push(format!("# {} — Operable Elements (DirectShell)", fname));
+    lines.push(format!("# Window: {}", title));
+    lines.push(format!("# Use 'target' column in inject table to aim at an element by name (or path=...)"));
+    if let Some((_, ref name)) = modal {
+        lines.push(format!("# MODAL DIALOG OPEN: \"{}\" — only its elements are listed; close it first", name));
+    }
+    let win = if percent_coords() { meta_rect(&conn) } else { None };
+    if win.is_some() {
+        lines.push("# Coordinates: center in % of the window — click target \"x%,y%\"".to_string());
+    }
+    lines.push(String::new());
+
+    let mut idx = 0u32;
+
+    // Optional: full element path per line (config: snap_paths=1)
+    let with_paths = config_flag("snap_paths");
+    let name_max = snap_name_max();
+
+    for e in &elements {
+        if let Some(tool) = input_tool(&e.role) {
+            idx += 1;
+            let mut line = format!("[{}] [{}] \"{}\" {}",
+                idx, tool, clip_name(&e.name, name_max), snap_position(e, win.as_ref()));
+            if e.selected {
+                line.push_str(" [selected]");
+            }
+            // Don't expand what is open, don't look for children of a closed node
+            if let Some(state) = expand_label(e.expand_state) {
+                line.push_str(&format!(" [{}]", state));
+            }
+            if with_paths && !e.path.is_empty() {
+                line.push_str(&format!("  path={}", e.path));
+            }
+            lines.push(line);
+        }
+    }
+
+    lines.push(String::new());
+    lines.push(format!("# {} operable elements in viewport", idx));
+
+    let content = lines.join("\n");
+    write_checked(&snap_path, &content);
+    idx
+}
+
+// ── Empty Snap Detection ────────────────────────────
+// An empty .a11y.snap is either honest (a video player, a canvas game) or the symptom
+// of accessibility that never switched on (Electron/Chromium answering with a bare frame).
+// The difference shows in the tree size: nothing operable AND almost no rows at all is
+// suspicious. is_active reports operable=<n> + suspect_empty=1; with
+// reactivate_on_empty=1 the activation sequence runs once more per target and re-dumps.
+const EMPTY_SNAP_MAX_ROWS: i64 = 20; // bis zu so vielen Zeilen ohne Bedienelement → verdächtig
+
+static OPERABLE: AtomicI32 = AtomicI32::new(-1);              // Einträge in .a11y.snap (letzter Dump, -1 = noch keiner)
+static SUSPECT_EMPTY: AtomicBool = AtomicBool::new(false);
+static REACTIVATE_PENDING: AtomicBool = AtomicBool::new(false);
+static REACTIVATED_FOR: AtomicIsize = AtomicIsize::new(0);    // Ziel, für das schon nachaktiviert wurde
+
+fn empty_snap_suspicious(operable: u32, rows: i64) -> bool {
+    operable == 0 && rows <= EMPTY_SNAP_MAX_ROWS
+}
+
+/// Primary dump finished: remember the operable count, flag (and maybe retry) a suspicious empty snap.
+fn note_operable(operable: u32, rows: i64) {
+    let before = OPERABLE.swap(operable as i32, SeqCst);
+    let suspicious = empty_snap_suspicious(operable, rows);
+    SUSPECT_EMPTY.store(suspicious, SeqCst);
+    // Log the transition only, not every dump of an empty window
+    if operable == 0 && before != 0 {
+        if suspicious {
+            log(&format!("a11y.snap: WARNING — 0 operable elements in {} rows, accessibility is probably off", rows));
+        } else {
+            log(&format!("a11y.snap: 0 operable elements ({} rows — app seems to have no controls)", rows));
+        }
+    }
+    let target = TARGET_HW.load(SeqCst);
+    if suspicious && config_flag("reactivate_on_empty") && REACTIVATED_FOR.swap(target, SeqCst) != target {
+        REACTIVATE_PENDING.store(true, SeqCst);
+    }
+}
+
+/// SNAP_REQ tick: one more activation run + dump for a suspicious empty snap.
+unsafe fn check_empty_snap() {
+    if !REACTIVATE_PENDING.swap(false, SeqCst) || !snapped() { return; }
+    log("a11y.snap: empty and suspicious — re-running accessibility activation");
+    activate_accessibility(tgt());
+    dump_tree();
+}
+
+// ── Target Selectors (inject.target) ────────────────
+// Plain text          → element name (live UIA FindFirst, legacy)
+// path=A > B > C[x]   → exact match on the stored `path` column of the last dump
+// id=AutomationId     → AutomationId (stable across languages/layouts where the app sets it)
+// class=ClassName     → UIA ClassName (Edit, Chrome_RenderWidgetHostHWND, ...) — framework-
+//                       standard controls keep it in every language
+// //Role[...]/...     → XPath-like selector over the elements table (see below)
+
+enum Target<'a> {
+    Name(&'a str),
+    Path(&'a str),
+    XPath(&'a str),
+    Id(&'a str),
+    Class(&'a str),
+}
+
+fn parse_target(target: &str) -> Target<'_> {
+    if let Some(p) = target.strip_prefix("path=") {
+        return Target::Path(p.trim());
+    }
+    if let Some(id) = target.strip_prefix("id=") {
+        return Target::Id(id.trim());
+    }
+    if let Some(class) = target.strip_prefix("class=") {
+        return Target::Class(class.trim());
+    }
+    if target.starts_with('/') {
+        return Target::XPath(target.trim());
+    }
+    Target::Name(target)
+}
+
+// ── XPath-like Selector Engine ──────────────────────
+// Grammar (bewusst klein):
+//   selector := step+
+//   step     := ("/" | "//") role pred*
+//   role     := UIA role name ("Button", "ListItem", ...) or "*"
+//   pred     := "[" "@" attr "=" quoted "]"   attr: name | id | value | class
+//             | "[" N "]"                      1-based position among matching siblings
+//   quoted   := '...' | "..."
+// "/" = direct children, "//" = all descendants. Evaluated against the last
+// dump — live UIA is only touched afterwards if the action needs a pattern.
+// Examples: //Button[@name='Save']   //List/ListItem[3]   //Pane//Edit[@id='q']
+
+struct XStep {
+    descendant: bool,
+    role: String,
+    attrs: Vec<(String, String)>,
+    index: Option<usize>,
+}
+
+fn parse_xpath(sel: &str) -> Option<Vec<XStep>> {
+    let chars: Vec<char> = sel.chars().collect();
+    let mut i = 0;
+    let mut steps = Vec::new();
+    while i < chars.len() {
+        if chars[i] != '/' { return None; }
+        i += 1;
+        let descendant = i < chars.len() && chars[i] == '/';
+        if descendant { i += 1; }
+        let start = i;
+        while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '*') { i += 1; }
+        if i == start { return None; }
+        let role: String = chars[start..i].iter().collect();
+        let mut attrs = Vec::new();
+        let mut index = None;
+        while i < chars.len() && chars[i] == '[' {
+            i += 1;
+            if i < chars.len() && chars[i] == '@' {
+                i += 1;
+                let a = i;
+                while i < chars.len() && chars[i].is_alphanumeric() { i += 1; }
+                let attr: String = chars[a..i].iter().collect();
+                if i >= chars.len() || chars[i] != '=' { return None; }
+                i += 1;
+                let quote = *chars.get(i)?;
+                if quote != '\'' && quote != '"' { return None; }
+                i += 1;
+                let v = i;
+                while i < chars.len() && chars[i] != quote { i += 1; }
+                if i >= chars.len() { return None; }
+                attrs.push((attr.to_lowercase(), chars[v..i].iter().collect()));
+                i += 1;
+            } else {
+                let n = i;
+                while i < chars.len() && chars[i].is_ascii_digit() { i += 1; }
+                index = Some(chars[n..i].iter().collect::<String>().parse().ok()?);
+            }
+            if i >= chars.len() || chars[i] != ']' { return None; }
+            i += 1;
+        }
+        steps.push(XStep { descendant, role, attrs, index });
+    }
+    if steps.is_empty() { None } else { Some(steps) }
+}
+
+/// Row subset needed to evaluate selectors.
+struct XNode {
+    id: i64,
+    parent: i64,
+    role: String,
+    name: String,
+    aid: String,
+    value: String,
+    class: String,
+}
+
+fn xstep_matches(step: &XStep, n: &XNode) -> bool {
+    if step.role != "*" && step.role != n.role { return false; }
+    step.attrs.iter().all(|(k, v)| match k.as_str() {
+        "name" => &n.name == v,
+        "id" => &n.aid == v,
+        "value" => &n.value == v,
+        "class" => &n.class == v,
+        _ => false,
+    })
+}
+
+/// Evaluate a selector against the elements table. Returns matching ids in tree order.
+fn eval_xpath(conn: &Connection, sel: &str) -> Option<Vec<i64>> {
+    let steps = parse_xpath(sel)?;
+    let mut stmt = conn.prepare(
+        "SELECT id, parent_id, role, COALESCE(name,''), COALESCE(automation_id,''), COALESCE(value,''), \
+         COALESCE(class_name,'') FROM elements ORDER BY id",
+    ).ok()?;
+    let nodes: Vec<XNode> = stmt.query_map([], |r| Ok(XNode {
+        id: r.get(0)?, parent: r.get(1)?, role: r.get(2)?,
+        name: r.get(3)?, aid: r.get(4)?, value: r.get(5)?, class: r.get(6)?,
+    })).ok()?.flatten().collect();
+
+    let mut children: HashMap<i64, Vec<usize>> = HashMap::new();
+    for (i, n) in nodes.iter().enumerate() {
+        children.entry(n.parent).or_default().push(i);
+    }
+
+    // Context starts at the virtual root (parent_id 0 of the window element)
+    let mut context: Vec<i64> = vec![0];
+    for step in &steps {
+        let mut candidates: Vec<usize> = Vec::new();
+        for &ctx_id in &context {
+            let mut stack: Vec<usize> = children.get(&ctx_id).cloned().unwrap_or_default();
+            stack.reverse();
+            while let Some(idx) = stack.pop() {
+                candidates.push(idx);
+                if step.descendant {
+                    if let Some(kids) = children.get(&nodes[idx].id) {
+                        stack.extend(kids.iter().rev());
+                    }
+                }
+            }
+        }
+        let mut next: Vec<i64> = Vec::new();
+        for idx in candidates {
+            let n = &nodes[idx];
+            if !xstep_matches(step, n) { continue; }
+            if let Some(want) = step.index {
+                // Position among the parent's children that match the same step
+                let pos = children.get(&n.parent).map(|sib| {
+                    sib.iter().take_while(|&&s| s != idx)
+                        .filter(|&&s| xstep_matches(step, &nodes[s])).count() + 1
+                }).unwrap_or(1);
+                if pos != want { continue; }
+            }
+            next.push(n.id);
+        }
+        next.sort_unstable();
+        next.dedup();
+        context = next;
+        if context.is_empty() { break; }
+    }
+    Some(context)
+}
+
+/// Resolve a DB-backed selector against the last dump of the snapped app.
+/// Returns (name, x, y, w, h) of the first matching element in tree order.
+/// Row id of the first element matching a selector in the last dump.
+fn lookup_id(conn: &Connection, target: &Target) -> Option<i64> {
+    let first = |sql: &str, v: &str| conn.query_row(sql, params![v], |r| r.get(0)).ok();
+    match target {
+        Target::Path(p) => first("SELECT id FROM elements WHERE path=?1 ORDER BY id LIMIT 1", p),
+        Target::Name(n) => first("SELECT id FROM elements WHERE name=?1 ORDER BY offscreen, id LIMIT 1", n),
+        Target::Id(a) => first("SELECT id FROM elements WHERE automation_id=?1 ORDER BY offscreen, id LIMIT 1", a),
+        Target::Class(c) => first("SELECT id FROM elements WHERE class_name=?1 ORDER BY offscreen, id LIMIT 1", c),
+        Target::XPath(x) => {
+            let Some(ids) = eval_xpath(conn, x) else {
+                log(&format!("selector: invalid syntax '{}'", x));
+                return None;
+            };
+            if ids.len() > 1 {
+                log(&format!("selector: '{}' matched {} elements, using first", x, ids.len()));
+            }
+            ids.first().copied()
+        }
+    }
+}
+
+/// Open the current app DB for selector lookups (None when not snapped).
+fn open_lookup_db() -> Option<Connection> {
+    let db_path = get_db_path();
+    if db_path.is_empty() { return None; }
+    let conn = Connection::open(&db_path).ok()?;
+    let _ = conn.execute_batch(&format!("PRAGMA busy_timeout={};", DB_BUSY_TIMEOUT_MS));
+    Some(conn)
+}
+
+fn lookup_element(target: &Target) -> Option<(String, i32, i32, i32, i32)> {
+    let conn = open_lookup_db()?;
+    let id = lookup_id(&conn, target)?;
+    conn.query_row(
+        "SELECT name, x, y, w, h FROM elements WHERE id=?1",
+        params![id],
+        |r| Ok((
+            r.get::<_, Option<String>>(0)?.unwrap_or_default(),
+            r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?,
+        )),
+    ).ok()
+}
+
+// ── Normalized Name Matching ─────────────────────────
+// Fallback when the exact live Name lookup fails: the agent read "Einstellungen"
+// but the app now says "EINSTELLUNGEN", or "Cafe" vs "Café". Both sides are folded
+// (lowercase, diacritics stripped, whitespace collapsed) and compared against the dump;
+// if nothing is equal after folding, the closest name by edit distance is used
+// when its score clears the threshold ("Save draft" → "Save drafts").
+
+/// Case/diacritic-fold a name for tolerant comparison.
+fn fold_name(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars().flat_map(char::to_lowercase) {
+        match c {
+            'à'..='å' | 'ā' | 'ă' | 'ą' => out.push('a'),
+            'æ' => out.push_str("ae"),
+            'ç' | 'ć' | 'č' => out.push('c'),
+            'ď' | 'đ' => out.push('d'),
+            'è'..='ë' | 'ē' | 'ė' | 'ę' | 'ě' => out.push('e'),
+            'ğ' => out.push('g'),
+            'ì'..='ï' | 'ī' | 'į' | 'ı' => out.push('i'),
+            'ł' => out.push('l'),
+            'ñ' | 'ń' | 'ň' => out.push('n'),
+            'ò'..='ö' | 'ø' | 'ō' | 'ő' => out.push('o'),
+            'œ' => out.push_str("oe"),
+            'ř' => out.push('r'),
+            'ś' | 'š' | 'ş' => out.push('s'),
+            'ß' => out.push_str("ss"),
+            'ť' | 'ţ' => out.push('t'),
+            'ù'..='ü' | 'ū' | 'ů' | 'ű' | 'ų' => out.push('u'),
+            'ý' | 'ÿ' => out.push('y'),
+            'ź' | 'ż' | 'ž' => out.push('z'),
+            '\u{0300}'..='\u{036F}' => {} // combining marks (decomposed input)
+            c if is_format_char(c) => {}
+            c => out.push(c),
+        }
+    }
+    out.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Edit distance over chars (insert/delete/substitute = 1).
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let sub = prev[j] + usize::from(ca != *cb);
+            cur[j + 1] = sub.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+/// Similarity 0.0..=1.0 of two (already folded) names: 1 - distance / longer length.
+fn name_similarity(a: &str, b: &str) -> f64 {
+    let longest = a.chars().count().max(b.chars().count());
+    if longest == 0 { return 1.0; }
+    1.0 - levenshtein(a, b) as f64 / longest as f64
+}
+
+/// Best dump element for a name that has no exact live match (on-screen first).
+/// Folded equality scores 1.0; otherwise the most similar folded name wins if it
+/// reaches `fuzzy_threshold` (config, default FUZZY_THRESHOLD_DEFAULT).
+/// Returns (real name, score, x, y, w, h).
+fn lookup_fuzzy(query: &str) -> Option<(String, f64, i32, i32, i32, i32)> {
+    let want = fold_name(query);
+    if want.is_empty() { return None; }
+    let threshold = config_get("fuzzy_threshold").and_then(|v| v.parse::<f64>().ok())
+        .unwrap_or(FUZZY_THRESHOLD_DEFAULT);
+    let conn = open_lookup_db()?;
+    let mut stmt = conn.prepare(
+        "SELECT name, x, y, w, h FROM elements WHERE name IS NOT NULL AND name<>'' ORDER BY offscreen, id",
+    ).ok()?;
+    let rows: Vec<(String, i32, i32, i32, i32)> = stmt.query_map([], |r| Ok((
+        r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?,
+    ))).ok()?.flatten().collect();
+
+    let (score, i) = best_fuzzy(rows.iter().map(|(name, ..)| name.as_str()), &want, threshold)?;
+    let (name, x, y, w, h) = rows.into_iter().nth(i)?;
+    Some((name, score, x, y, w, h))
+}
+
+/// (score, index) of the best candidate for a folded query. Folded equality wins
+/// outright; otherwise the most similar name reaching `threshold`, first one on ties.
+fn best_fuzzy<'a>(names: impl IntoIterator<Item = &'a str>, want: &str, threshold: f64) -> Option<(f64, usize)> {
+    let want_len = want.chars().count();
+    let mut best: Option<(f64, usize)> = None;
+    for (i, name) in names.into_iter().enumerate() {
+        let folded = fold_name(name);
+        if folded == want { return Some((1.0, i)); }
+        // Length gap alone already below threshold → skip the O(n·m) distance
+        let len = folded.chars().count();
+        if 1.0 - (len.abs_diff(want_len) as f64 / len.max(want_len) as f64) < threshold { continue; }
+        let score = name_similarity(&folded, want);
+        if score >= threshold && best.is_none_or(|(b, _)| score > b) {
+            best = Some((score, i));
+        }
+    }
+    best
+}
+
+/// Exact FindFirst failed → same normalized name in the dump → fuzzy match → live element
+/// (real name, else by point). The live Name condition compares the raw UIA string, the
+/// dump holds normalize_text() names — a format char alone is not a fuzzy match.
+/// Only real fuzzy matches are logged + remembered for inject_result.
+unsafe fn resolve_fuzzy(uia: &IUIAutomation, root: &IUIAutomationElement, query: &str) -> Option<IUIAutomationElement> {
+    if let Some((_, x, y, w, h)) = lookup_element(&Target::Name(query)) {
+        log(&format!("lookup: '{}' found via normalized dump name", query));
+        return live_element_at(uia, x, y, w, h);
+    }
+    let (name, score, x, y, w, h) = lookup_fuzzy(query)?;
+    log(&format!("fuzzy: '{}' matched '{}' (score {:.2}) — use the exact name", query, name, score));
+    *LAST_FUZZY.lock().unwrap() = Some((name.clone(), score));
+    let live = uia.CreatePropertyCondition(UIA_NamePropertyId, &VARIANT::from(BSTR::from(name.as_str())))
+        .ok()
+        .and_then(|cond| root.FindFirst(TreeScope_Descendants, &cond).ok());
+    live.or_else(|| live_element_at(uia, x, y, w, h))
+}
+
+/// Re-acquire the live UIA element for a DB row via its on-screen center.
+/// Falls back to the element under the point even if the name drifted.
+unsafe fn live_element_at(uia: &IUIAutomation, x: i32, y: i32, w: i32, h: i32) -> Option<IUIAutomationElement> {
+    let pt = POINT { x: x + w / 2, y: y + h / 2 };
+    uia.ElementFromPoint(pt).ok()
+}
+
+// ── Injection Pipeline (External → App) ─────────────
+
+/// Forced injection strategy for the snapped app (config override).
+/// Config keys: `<app>.text=valuepattern|sendinput`, `<app>.click=invoke|sendinput`
+/// (<app> = DB name, e.g. `discord.click=sendinput`). No key → automatic choice.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Strategy { ValuePattern, Invoke, SendInput }
+
+fn strategy_override(action: &str) -> Option<Strategy> {
+    let db_path = get_db_path();
+    let app = app_from_db_path(&db_path);
+    if app.is_empty() { return None; }
+    let key = format!("{}.{}", app, action);
+    let val = config_get(&key)?;
+    match val.to_lowercase().as_str() {
+        "valuepattern" | "value" => Some(Strategy::ValuePattern),
+        "invoke" | "invokepattern" => Some(Strategy::Invoke),
+        "sendinput" => Some(Strategy::SendInput),
+        _ => { log(&format!("config: unknown strategy '{}' for {}", val, key)); None }
+    }
+}
+
+// ── Foreground Strategy (config: foreground, <app>.foreground) ──
+// SendInput goes to the foreground window, so click/type/key pull the target to the
+// front — stealing focus from whatever the user is doing right now.
+//   always   (default) activate the target and leave it in front
+//   never    never activate: UIA patterns (Invoke, SetValue) still work, anything that
+//            needs the foreground fails with foreground_required
+//   restore  activate, act, then hand the foreground back to the user's window
+// "After the action" means after the whole burst: the queue worker only restores once no
+// further action is due within FG_RESTORE_GAP_MS — click → type → submit stay in front
+// throughout instead of flickering back and forth. Restore is skipped when the foreground
+// meanwhile moved somewhere else than the target (the user switched — leave it there).
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Foreground { Always, Never, Restore }
+
+static FG_RESTORE: AtomicIsize = AtomicIsize::new(0);        // restore: Fenster, das vor der Aktion vorne war
+static FG_RESTORE_TARGET: AtomicIsize = AtomicIsize::new(0); // ... und wem wir den Vordergrund gegeben haben
+const FG_RESTORE_GAP_MS: i64 = 1000; // nächste Aktion fällig innerhalb dieser Zeit → noch nicht zurückgeben
+
+fn parse_foreground(v: &str) -> Option<Foreground> {
+    match v.to_lowercase().as_str() {
+        "always" => Some(Foreground::Always),
+        "never" => Some(Foreground::Never),
+        "restore" => Some(Foreground::Restore),
+        _ => None,
+    }
+}
+
+/// Per-app key wins over the global one; unknown values → Always (logged).
+fn foreground_strategy() -> Foreground {
+    let db_path = get_db_path();
+    let app = app_from_db_path(&db_path);
+    let val = (!app.is_empty()).then(|| config_get(&format!("{}.foreground", app))).flatten()
+        .or_else(|| config_get("foreground"));
+    let Some(val) = val else { return Foreground::Always };
+    parse_foreground(&val).unwrap_or_else(|| {
+        log(&format!("config: unknown foreground strategy '{}'", val));
+        Foreground::Always
+    })
+}
+
+/// Activate `target` for input according to the foreground strategy.
+/// restore: remembers the window that was in front (first activation of the action wins).
+unsafe fn bring_to_front(target: HWND) -> InjectResult {
+    if target.0.is_null() { return Ok(()); }
+    let fg = GetForegroundWindow();
+    match foreground_strategy() {
+        Foreground::Never if fg != target => {
+            log("foreground: action needs the foreground, strategy is 'never'");
+            Err(InjectError::ForegroundRequired)
+        }
+        Foreground::Never => Ok(()),
+        strategy => {
+            if strategy == Foreground::Restore && fg != target && !fg.0.is_null()
+                && FG_RESTORE.compare_exchange(0, fg.0 as isize, SeqCst, SeqCst).is_ok() {
+                FG_RESTORE_TARGET.store(target.0 as isize, SeqCst);
+            }
+            let _ = SetForegroundWindow(target);
+            Ok(())
+        }
+    }
+}
+
+/// End of an action burst (restore strategy): give the foreground back — unless the
+/// user has moved on to another window in the meantime, or theirs is gone.
+unsafe fn restore_foreground() {
+    let prev = HWND(FG_RESTORE.swap(0, SeqCst) as *mut _);
+    let target = HWND(FG_RESTORE_TARGET.swap(0, SeqCst) as *mut _);
+    if prev.0.is_null() { return; }
+    let fg = GetForegroundWindow();
+    // Dialogs the action opened are owned by the target and count as "still ours"
+    if fg != target && GetAncestor(fg, GA_ROOTOWNER) != target {
+        log(&format!("foreground: not restored — foreground moved to 0x{:X} meanwhile", fg.0 as usize));
+        return;
+    }
+    if !IsWindow(prev).as_bool() {
+        log("foreground: not restored — previous window is gone");
+        return;
+    }
+    // We sent the last input event, so Windows lets us hand the foreground on
+    if SetForegroundWindow(prev).as_bool() {
+        log(&format!("foreground: restored 0x{:X}", prev.0 as usize));
+    } else {
+        log(&format!("foreground: restore to 0x{:X} refused by Windows", prev.0 as usize));
+    }
+}
+
+/// Queue worker: an action due within FG_RESTORE_GAP_MS keeps the foreground with the target.
+fn more_actions_due(conn: &Connection) -> bool {
+    let horizon = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64 + FG_RESTORE_GAP_MS;
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM inject WHERE done=0 AND COALESCE(not_before,0) <= ?1)",
+        params![horizon], |r| r.get(0),
+    ).unwrap_or(false)
+}
+
+// ── Action Errors ───────────────────────────────────
+// Why an action failed. code() goes to inject_result as "error" (machine-readable),
+// Display as "reason" — the agent can tell "retry later" from "wrong selector".
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InjectError {
+    /// Not snapped / no target window.
+    NoTarget,
+    /// Target runs at higher integrity (admin) — Windows drops our input (UIPI).
+    UipiBlocked,
+    /// No app DB yet (nothing dumped) — DB-backed selectors can't resolve.
+    NoDump,
+    /// Element / grid / tab not found (in the dump or the live tree).
+    NotFound(String),
+    /// Element lacks the UIA pattern (or property) the action needs.
+    PatternMissing(&'static str),
+    /// ValuePattern present but SetValue was rejected (read-only field).
+    ReadOnly,
+    /// Malformed action argument (key combo, scroll spec, cell spec, url, ...).
+    InvalidArgument(String),
+    /// App or UI did not respond in time (wait_idle, wait_progress, confirm, hover).
+    Timeout(String),
+    /// Target lost foreground mid-typing.
+    FocusLost,
+    /// Action needs the foreground, but foreground=never forbids activating the target.
+    ForegroundRequired,
+    /// UIA / COM call failed.
+    Uia(String),
+    /// SQLite query failed.
+    Db(String),
+    /// Action ran but the app reported failure.
+    Failed(String),
+    /// Unknown action type.
+    UnknownAction(String),
+    /// Not a failure: the wait is not satisfied yet — the row is re-checked after n ms.
+    Pending(u64),
+}
+
+impl InjectError {
+    /// Stable snake_case code for inject_result's "error" field.
+    pub fn code(&self) -> &'static str {
+        match self {
+            InjectError::NoTarget => "no_target",
+            InjectError::UipiBlocked => "uipi_blocked",
+            InjectError::NoDump => "no_dump",
+            InjectError::NotFound(_) => "not_found",
+            InjectError::PatternMissing(_) => "pattern_missing",
+            InjectError::ReadOnly => "read_only",
+            InjectError::InvalidArgument(_) => "invalid_argument",
+            InjectError::Timeout(_) => "timeout",
+            InjectError::FocusLost => "focus_lost",
+            InjectError::ForegroundRequired => "foreground_required",
+            InjectError::Uia(_) => "uia_error",
+            InjectError::Db(_) => "db_error",
+            InjectError::Failed(_) => "failed",
+            InjectError::UnknownAction(_) => "unknown_action",
+            InjectError::Pending(_) => "pending",
+        }
+    }
+
+    /// Can a later attempt succeed? Transient states (not found yet, focus lost, COM/DB
+    /// hiccup) are retried up to max_retries; permanent ones go dead on the first failure —
+    /// retrying would re-type, re-invoke or re-wait for nothing.
+    pub fn retryable(&self) -> bool {
+        match self {
+            InjectError::NoTarget | InjectError::NoDump | InjectError::NotFound(_)
+            | InjectError::FocusLost | InjectError::Uia(_) | InjectError::Db(_)
+            | InjectError::Failed(_) | InjectError::Pending(_) => true,
+            InjectError::UipiBlocked | InjectError::PatternMissing(_) | InjectError::ReadOnly
+            | InjectError::InvalidArgument(_) | InjectError::Timeout(_)
+            | InjectError::ForegroundRequired | InjectError::UnknownAction(_) => false,
+        }
+    }
+}
+
+impl std::fmt::Display for InjectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InjectError::NoTarget => write!(f, "no target window"),
+            InjectError::UipiBlocked => write!(f, "target runs at higher integrity (admin) — run DirectShell as admin"),
+            InjectError::NoDump => write!(f, "no dump of the target yet"),
+            InjectError::NotFound(what) => write!(f, "not found: {}", what),
+            InjectError::PatternMissing(p) => write!(f, "element has no {} pattern", p),
+            InjectError::ReadOnly => write!(f, "value rejected (read-only)"),
+            InjectError::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+            InjectError::Timeout(msg) => write!(f, "timeout: {}", msg),
+            InjectError::FocusLost => write!(f, "target lost focus"),
+            InjectError::ForegroundRequired => write!(f, "action needs the foreground (foreground=never)"),
+            InjectError::Uia(msg) => write!(f, "UIA error: {}", msg),
+            InjectError::Db(msg) => write!(f, "DB error: {}", msg),
+            InjectError::Failed(msg) => write!(f, "{}", msg),
+            InjectError::UnknownAction(a) => write!(f, "unknown action '{}'", a),
+            InjectError::Pending(ms) => write!(f, "waiting — re-checked in {}ms", ms),
+        }
+    }
+}
+
+impl std::error::Error for InjectError {}
+
+type InjectResult = std::result::Result<(), InjectError>;
+
+/// Inject text into the target app — screen reader style.
+/// Reads .a11y.snap to know WHAT can be operated.
+/// `target_name`: element name from .a11y.snap (e.g. "Einen Prompt für Gemini eingeben")
+///   If empty: falls back to first focusable+value element (legacy).
+unsafe fn inject_text(target: HWND, text: &str, target_name: &str, strategy: Option<Strategy>) -> InjectResult {
+    let uia: IUIAutomation = match create_uia() {
+        Ok(u) => u,
+        Err(e) => { log(&format!("inject: CoCreate FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+
+    let root = match uia.ElementFromHandle(target) {
+        Ok(e) => e,
+        Err(e) => { log(&format!("inject: ElementFromHandle FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+
+    let sel = parse_target(target_name);
+    let elem = if !matches!(sel, Target::Name(_)) {
+        // DB-backed selector → coordinates from last dump → live element at that point
+        let Some((_, x, y, w, h)) = lookup_element(&sel) else {
+            log(&format!("inject: selector not found in dump: '{}'", target_name));
+            return Err(InjectError::NotFound(target_name.to_string()));
+        };
+        match live_element_at(&uia, x, y, w, h) {
+            Some(e) => e,
+            None => { log(&format!("inject: ElementFromPoint FAIL ('{}')", target_name)); return Err(InjectError::NotFound(target_name.to_string())); }
+        }
+    } else {
+        // Base conditions: focusable + accepts value
+        let cond_focus = match uia.CreatePropertyCondition(
+            UIA_IsKeyboardFocusablePropertyId, &VARIANT::from(true),
+        ) {
+            Ok(c) => c,
+            Err(e) => { log(&format!("inject: cond_focus FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+        };
+        let cond_value = match uia.CreatePropertyCondition(
+            UIA_IsValuePatternAvailablePropertyId, &VARIANT::from(true),
+        ) {
+            Ok(c) => c,
+            Err(e) => { log(&format!("inject: cond_value FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+        };
+        let base_cond = match uia.CreateAndCondition(&cond_focus, &cond_value) {
+            Ok(c) => c,
+            Err(e) => { log(&format!("inject: AndCondition FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+        };
+
+        // If target_name given: add Name condition for precision targeting
+        let cond: IUIAutomationCondition = if !target_name.is_empty() {
+            let cond_name = match uia.CreatePropertyCondition(
+                UIA_NamePropertyId, &VARIANT::from(BSTR::from(target_name)),
+            ) {
+                Ok(c) => c,
+                Err(e) => { log(&format!("inject: cond_name FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+            };
+            match uia.CreateAndCondition(&base_cond, &cond_name) {
+                Ok(c) => c.cast().unwrap(),
+                Err(e) => { log(&format!("inject: name+base FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+            }
+        } else {
+            base_cond.cast().unwrap()
+        };
+
+        match root.FindFirst(TreeScope_Descendants, &cond) {
+            Ok(e) => e,
+            Err(e) => {
+                log(&format!("inject: FindFirst FAIL (target='{}'): {e}", target_name));
+                if target_name.is_empty() { return Err(InjectError::NotFound("focusable value field".into())); }
+                match resolve_fuzzy(&uia, &root, target_name) {
+                    Some(e) => e,
+                    None => return Err(InjectError::NotFound(target_name.to_string())),
+                }
+            }
+        }
+    };
+
+    let name = elem.CurrentName().ok().map(|s| s.to_string()).unwrap_or_default();
+    let ct = elem.CurrentControlType().unwrap_or_default();
+    log(&format!("inject: target='{}' ct={}", name, ct.0));
+    if config_flag("highlight") {
+        if let Ok(rc) = elem.CurrentBoundingRectangle() {
+            debug_highlight(rc.left, rc.top, rc.right - rc.left, rc.bottom - rc.top);
+        }
+    }
+
+    // Focus it — like a screen reader navigating with Tab
+    let _ = elem.SetFocus();
+
+    // Read-back of the field (verification). Password fields never reveal their value.
+    let is_password = elem.CurrentIsPassword().map(|b| b.as_bool()).unwrap_or(false);
+    let vp = elem.GetCurrentPatternAs::<IUIAutomationValuePattern>(UIA_ValuePatternId).ok();
+    let read_value = || if is_password { None } else {
+        vp.as_ref().and_then(|vp| vp.CurrentValue().ok()).map(|s| s.to_string())
+    };
+
+    // Strategy 1: ValuePattern (direct text set)
+    // Chromium/Electron: SetValue bypasses the page's input events (React & co never
+    // see the change) → go straight to SendInput there.
+    let framework = get_framework();
+    let mut vp_error = InjectError::PatternMissing("Value");
+    let prefer_sendinput = match strategy {
+        Some(s) => s == Strategy::SendInput,
+        None => framework == "Chrome",
+    };
+    if prefer_sendinput {
+        log(&format!("inject: SendInput first (framework '{}', override {:?})", framework, strategy));
+    } else if let Some(vp) = &vp {
+        vp_error = InjectError::ReadOnly;
+        let current = vp.CurrentValue().ok()
+            .map(|s| s.to_string()).unwrap_or_default();
+        let combined = format!("{}{}", current, text);
+        let bstr = BSTR::from(combined.as_str());
+        if vp.SetValue(&bstr).is_ok() {
+            // Some controls take the call and revert (validation) — only trust what reads back.
+            // Changed but different (formatting, maxlength) = accepted; SendInput would double it.
+            match read_value() {
+                Some(after) if after == current && !text.is_empty() => {
+                    log("inject: ValuePattern OK but value reverted — falling back to SendInput");
+                    vp_error = InjectError::Failed("value reverted after SetValue".into());
+                }
+                Some(after) if after != combined => {
+                    log(&format!("inject: ValuePattern OK, value adjusted by control (len {} → {})", combined.len(), after.len()));
+                    *ACTION_DETAIL.lock().unwrap() = "value:adjusted".to_string();
+                    return Ok(());
+                }
+                _ => {
+                    log(&format!("inject: ValuePattern OK, len={}", combined.len()));
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // Strategy 2: SendInput — focus target first, then type
+    if !prefer_sendinput {
+        if strategy == Some(Strategy::ValuePattern) {
+            log("inject: ValuePattern forced by config but failed");
+            return Err(vp_error);
+        }
+        log("inject: ValuePattern failed, using SendInput");
+    }
+    let before = read_value();
+    bring_to_front(target)?;
+    for ch in text.chars() {
+        inject_char(ch);
+    }
+    log("inject: SendInput done");
+    // Value readable and still the same → maybe the keystrokes went nowhere, maybe the
+    // control updates asynchronously (web, React). Not an error: a retry would type twice.
+    if let Some(before) = before {
+        std::thread::sleep(std::time::Duration::from_millis(INJECT_VERIFY_MS));
+        if !text.is_empty() && read_value().as_ref() == Some(&before) {
+            log("inject: SendInput sent, value not changed yet — unverified");
+            *ACTION_DETAIL.lock().unwrap() = "unverified".to_string();
+        }
+    }
+    Ok(())
+}
+
+/// Map a key name to its VK code. Covers all 150+ keyboard keys.
+fn key_to_vk(name: &str) -> Option<VIRTUAL_KEY> {
+    match name.to_lowercase().as_str() {
+        // Letters
+        "a" => Some(VIRTUAL_KEY(0x41)), "b" => Some(VIRTUAL_KEY(0x42)),
+        "c" => Some(VIRTUAL_KEY(0x43)), "d" => Some(VIRTUAL_KEY(0x44)),
+        "e" => Some(VIRTUAL_KEY(0x45)), "f" => Some(VIRTUAL_KEY(0x46)),
+        "g" => Some(VIRTUAL_KEY(0x47)), "h" => Some(VIRTUAL_KEY(0x48)),
+        "i" => Some(VIRTUAL_KEY(0x49)), "j" => Some(VIRTUAL_KEY(0x4A)),
+        "k" => Some(VIRTUAL_KEY(0x4B)), "l" => Some(VIRTUAL_KEY(0x4C)),
+        "m" => Some(VIRTUAL_KEY(0x4D)), "n" => Some(VIRTUAL_KEY(0x4E)),
+        "o" => Some(VIRTUAL_KEY(0x4F)), "p" => Some(VIRTUAL_KEY(0x50)),
+        "q" => Some(VIRTUAL_KEY(0x51)), "r" => Some(VIRTUAL_KEY(0x52)),
+        "s" => Some(VIRTUAL_KEY(0x53)), "t" => Some(VIRTUAL_KEY(0x54)),
+        "u" => Some(VIRTUAL_KEY(0x55)), "v" => Some(VIRTUAL_KEY(0x56)),
+        "w" => Some(VIRTUAL_KEY(0x57)), "x" => Some(VIRTUAL_KEY(0x58)),
+        "y" => Some(VIRTUAL_KEY(0x59)), "z" => Some(VIRTUAL_KEY(0x5A)),
+        // Numbers
+        "0" => Some(VIRTUAL_KEY(0x30)), "1" => Some(VIRTUAL_KEY(0x31)),
+        "2" => Some(VIRTUAL_KEY(0x32)), "3" => Some(VIRTUAL_KEY(0x33)),
+        "4" => Some(VIRTUAL_KEY(0x34)), "5" => Some(VIRTUAL_KEY(0x35)),
+        "6" => Some(VIRTUAL_KEY(0x36)), "7" => Some(VIRTUAL_KEY(0x37)),
+        "8" => Some(VIRTUAL_KEY(0x38)), "9" => Some(VIRTUAL_KEY(0x39)),
+        // Function keys
+        "f1"  => Some(VK_F1),  "f2"  => Some(VK_F2),  "f3"  => Some(VK_F3),
+        "f4"  => Some(VK_F4),  "f5"  => Some(VK_F5),  "f6"  => Some(VK_F6),
+        "f7"  => Some(VK_F7),  "f8"  => Some(VK_F8),  "f9"  => Some(VK_F9),
+        "f10" => Some(VK_F10), "f11" => Some(VK_F11), "f12" => Some(VK_F12),
+        // Modifiers
+        "ctrl" | "control" | "strg" => Some(VK_CONTROL),  // strg/umschalt: German accelerator text
+        "alt" | "menu"     => Some(VK_MENU),
+        "shift" | "umschalt" => Some(VK_SHIFT),
+        "win" | "lwin"     => Some(VK_LWIN),
+        "rwin"             => Some(VK_RWIN),
+        // Navigation
+        "enter" | "return" => Some(VK_RETURN),
+        "tab"              => Some(VK_TAB),
+        "escape" | "esc"   => Some(VK_ESCAPE),
+        "space"            => Some(VK_SPACE),
+        "backspace" | "bs" => Some(VK_BACK),
+        "delete" | "del" | "entf" => Some(VK_DELETE),
+        "insert" | "ins" | "einfg" => Some(VK_INSERT),
+        "home"             => Some(VK_HOME),
+        "end"              => Some(VK_END),
+        "pageup" | "pgup"  => Some(VK_PRIOR),
+        "pagedown" | "pgdn"=> Some(VK_NEXT),
+        // Arrow keys
+        "up"    => Some(VK_UP),
+        "down"  => Some(VK_DOWN),
+        "left"  => Some(VK_LEFT),
+        "right" => Some(VK_RIGHT),
+        // Special keys
+        "printscreen" | "prtsc" => Some(VK_SNAPSHOT),
+        "scrolllock"            => Some(VK_SCROLL),
+        "pause" | "break"       => Some(VK_PAUSE),
+        "numlock"               => Some(VK_NUMLOCK),
+        "capslock" | "caps"     => Some(VK_CAPITAL),
+        // Punctuation / symbols
+        ";" | "semicolon"       => Some(VK_OEM_1),
+        "=" | "equals"          => Some(VK_OEM_PLUS),
+        "," | "comma"           => Some(VK_OEM_COMMA),
+        "-" | "minus"           => Some(VK_OEM_MINUS),
+        "." | "period"          => Some(VK_OEM_PERIOD),
+        "/" | "slash"           => Some(VK_OEM_2),
+        "`" | "backtick"        => Some(VK_OEM_3),
+        "[" | "lbracket"        => Some(VK_OEM_4),
+        "\\" | "backslash"      => Some(VK_OEM_5),
+        "]" | "rbracket"        => Some(VK_OEM_6),
+        "'" | "quote"           => Some(VK_OEM_7),
+        // Numpad
+        "num0" => Some(VK_NUMPAD0), "num1" => Some(VK_NUMPAD1),
+        "num2" => Some(VK_NUMPAD2), "num3" => Some(VK_NUMPAD3),
+        "num4" => Some(VK_NUMPAD4), "num5" => Some(VK_NUMPAD5),
+        "num6" => Some(VK_NUMPAD6), "num7" => Some(VK_NUMPAD7),
+        "num8" => Some(VK_NUMPAD8), "num9" => Some(VK_NUMPAD9),
+        "multiply" | "num*" => Some(VK_MULTIPLY),
+        "add"      | "num+" => Some(VK_ADD),
+        "subtract" | "num-" => Some(VK_SUBTRACT),
+        "decimal"  | "num." => Some(VK_DECIMAL),
+        "divide"   | "num/" => Some(VK_DIVIDE),
+        // Media
+        "volumeup"   => Some(VK_VOLUME_UP),
+        "volumedown" => Some(VK_VOLUME_DOWN),
+        "volumemute" => Some(VK_VOLUME_MUTE),
+        "nexttrack"  => Some(VK_MEDIA_NEXT_TRACK),
+        "prevtrack"  => Some(VK_MEDIA_PREV_TRACK),
+        "playpause"  => Some(VK_MEDIA_PLAY_PAUSE),
+        "stop"       => Some(VK_MEDIA_STOP),
+        _ => None,
+    }
+}
+
+/// Canonical key_to_vk names for everything that is not a letter or digit (vk_name).
+const NAMED_KEYS: &[&str] = &[
+    "enter", "tab", "escape", "space", "backspace", "delete", "insert",
+    "home", "end", "pageup", "pagedown", "up", "down", "left", "right",
+    "f1", "f2", "f3", "f4", "f5", "f6", "f7", "f8", "f9", "f10", "f11", "f12",
+    "printscreen", "scrolllock", "pause", "numlock", "capslock",
+    ";", "=", ",", "-", ".", "/", "`", "[", "\\", "]", "'",
+    "num0", "num1", "num2", "num3", "num4", "num5", "num6", "num7", "num8", "num9",
+    "multiply", "add", "subtract", "decimal", "divide",
+    "volumeup", "volumedown", "volumemute",
+];
+
+/// Reverse of key_to_vk: VK → the name a `key` action understands ("s", "7", "enter").
+fn vk_name(vk: VIRTUAL_KEY) -> Option<String> {
+    match vk.0 {
+        0x41..=0x5A | 0x30..=0x39 => Some((vk.0 as u8 as char).to_ascii_lowercase().to_string()),
+        _ => NAMED_KEYS.iter().find(|n| key_to_vk(n) == Some(vk)).map(|n| n.to_string()),
+    }
+}
+
+/// Extended flag needed for certain keys (arrows, ins/del/home/end/pgup/pgdn, numlock, right-ctrl/alt)
+fn is_extended_key(vk: VIRTUAL_KEY) -> bool {
+    matches!(vk, VK_UP | VK_DOWN | VK_LEFT | VK_RIGHT
+        | VK_INSERT | VK_DELETE | VK_HOME | VK_END | VK_PRIOR | VK_NEXT
+        | VK_NUMLOCK | VK_SNAPSHOT | VK_RWIN
+        | VK_DIVIDE)
+}
+
+/// Send a single VK key down+up via SendInput
+unsafe fn send_vk(vk: VIRTUAL_KEY) {
+    let ext = if is_extended_key(vk) { KEYEVENTF_EXTENDEDKEY } else { KEYBD_EVENT_FLAGS(0) };
+    let inputs = [
+        INPUT {
+            r#type: INPUT_KEYBOARD,
+            Anonymous: INPUT_0 {
+                ki: KEYBDINPUT {
+                    wVk: vk, wScan: 0,
+                    dwFlags: ext,
+                    time: 0, dwExtraInfo: 0,
+                },
+            },
+        },
+        INPUT {
+            r#type: INPUT_KEYBOARD,
+            Anonymous: INPUT_0 {
+                ki: KEYBDINPUT {
+                    wVk: vk, wScan: 0,
+                    dwFlags: ext | KEYEVENTF_KEYUP,
+                    time: 0, dwExtraInfo: 0,
+                },
+            },
+        },
+    ];
+    SendInput(&inputs, mem::size_of::<INPUT>() as i32);
+}
+
+/// Send a VK modifier key DOWN only
+unsafe fn send_vk_down(vk: VIRTUAL_KEY) {
+    let ext = if is_extended_key(vk) { KEYEVENTF_EXTENDEDKEY } else { KEYBD_EVENT_FLAGS(0) };
+    let input = [INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0 {
+            ki: KEYBDINPUT {
+                wVk: vk, wScan: 0,
+                dwFlags: ext,
+                time: 0, dwExtraInfo: 0,
+            },
+        },
+    }];
+    SendInput(&input, mem::size_of::<INPUT>() as i32);
+}
+
+/// Send a VK modifier key UP only
+unsafe fn send_vk_up(vk: VIRTUAL_KEY) {
+    let ext = if is_extended_key(vk) { KEYEVENTF_EXTENDEDKEY } else { KEYBD_EVENT_FLAGS(0) };
+    let input = [INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0 {
+            ki: KEYBDINPUT {
+                wVk: vk, wScan: 0,
+                dwFlags: ext | KEYEVENTF_KEYUP,
+                time: 0, dwExtraInfo: 0,
+            },
+        },
+    }];
+    SendInput(&input, mem::size_of::<INPUT>() as i32);
+}
+
+/// Parse a key combo like "ctrl+shift+a" into (modifiers, main key).
+/// Err(part) names the first unknown key.
+fn parse_key_combo(combo: &str) -> std::result::Result<(Vec<VIRTUAL_KEY>, Option<VIRTUAL_KEY>), String> {
+    let mut modifiers: Vec<VIRTUAL_KEY> = Vec::new();
+    let mut main_key: Option<VIRTUAL_KEY> = None;
+    for part in combo.split('+').map(|s| s.trim()) {
+        let Some(vk) = key_to_vk(part) else { return Err(part.to_string()); };
+        if matches!(vk, VK_CONTROL | VK_MENU | VK_SHIFT | VK_LWIN | VK_RWIN) {
+            modifiers.push(vk);
+        } else {
+            main_key = Some(vk);
+        }
+    }
+    Ok((modifiers, main_key))
+}
+
+/// Parse and send a key combo like "ctrl+shift+a" or "enter" or "f5"
+/// Supports any combination of modifiers + one main key.
+/// Uses SendInput (global) — used by keyboard hook where target is already focused.
+/// Returns InvalidArgument (nothing sent) if any part is not a known key.
+unsafe fn send_key_combo(combo: &str) -> InjectResult {
+    let (modifiers, main_key) = match parse_key_combo(combo) {
+        Ok(k) => k,
+        Err(part) => { log(&format!("key: unknown key '{}'", part)); return Err(InjectError::InvalidArgument(format!("unknown key '{}'", part))); }
+    };
+
+    // Press modifiers down
+    for &m in &modifiers { send_vk_down(m); }
+    // Press main key (or if only modifier, press the last modifier as key)
+    if let Some(mk) = main_key {
+        send_vk(mk);
+    }
+    // Release modifiers in reverse
+    for &m in modifiers.iter().rev() { send_vk_up(m); }
+
+    log(&format!("key: sent '{}'", combo));
+    Ok(())
+}
+
+/// One key down OR up by hardware scan code (KEYEVENTF_SCANCODE).
+/// Games using DirectInput/RawInput ignore virtual-key-only input.
+unsafe fn send_sc(vk: VIRTUAL_KEY, up: bool) {
+    let sc = MapVirtualKeyW(vk.0 as u32, MAPVK_VK_TO_VSC) as u16;
+    let mut flags = KEYEVENTF_SCANCODE;
+    if is_extended_key(vk) { flags |= KEYEVENTF_EXTENDEDKEY; }
+    if up { flags |= KEYEVENTF_KEYUP; }
+    let input = [INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0 {
+            ki: KEYBDINPUT {
+                wVk: VIRTUAL_KEY(0), wScan: sc,
+                dwFlags: flags,
+                time: 0, dwExtraInfo: 0,
+            },
+        },
+    }];
+    SendInput(&input, mem::size_of::<INPUT>() as i32);
+}
+
+/// Same combo syntax as send_key_combo, sent as scan codes.
+/// Optional "@<ms>" suffix holds the main key down (e.g. "w@500" = walk forward).
+unsafe fn send_scancode_combo(spec: &str) -> InjectResult {
+    let (combo, hold_ms) = match spec.rsplit_once('@') {
+        Some((c, ms)) => match ms.trim().parse::<u64>() {
+            Ok(ms) => (c, ms),
+            Err(_) => (spec, 0),
+        },
+        None => (spec, 0),
+    };
+    let (modifiers, main_key) = match parse_key_combo(combo) {
+        Ok(k) => k,
+        Err(part) => { log(&format!("scancode: unknown key '{}'", part)); return Err(InjectError::InvalidArgument(format!("unknown key '{}'", part))); }
+    };
+    for &m in &modifiers { send_sc(m, false); }
+    if let Some(mk) = main_key {
+        send_sc(mk, false);
+        // Games poll key state per frame — a zero-length press is often missed
+        std::thread::sleep(std::time::Duration::from_millis(hold_ms.max(20)));
+        send_sc(mk, true);
+    }
+    for &m in modifiers.iter().rev() { send_sc(m, true); }
+    log(&format!("scancode: sent '{}'", spec));
+    Ok(())
+}
+
+/// Native left click at screen coordinates via SendInput (virtual-desktop absolute).
+/// Persists the click position for the `type` action's re-focus.
+unsafe fn send_click(cx: i32, cy: i32) {
+    let screen_w = GetSystemMetrics(SM_CXVIRTUALSCREEN);
+    let screen_h = GetSystemMetrics(SM_CYVIRTUALSCREEN);
+    let screen_x = GetSystemMetrics(SM_XVIRTUALSCREEN);
+    let screen_y = GetSystemMetrics(SM_YVIRTUALSCREEN);
+    let abs_x = ((cx - screen_x) * 65535 / screen_w) as i32;
+    let abs_y = ((cy - screen_y) * 65535 / screen_h) as i32;
+    let vd_flags = MOUSEEVENTF_ABSOLUTE | MOUSEEVENTF_VIRTUALDESK | MOUSEEVENTF_MOVE;
+    let inputs = [
+        INPUT {
+            r#type: INPUT_MOUSE,
+            Anonymous: INPUT_0 {
+                mi: MOUSEINPUT {
+                    dx: abs_x, dy: abs_y, mouseData: 0,
+                    dwFlags: vd_flags | MOUSEEVENTF_LEFTDOWN,
+                    time: 0, dwExtraInfo: 0,
+                },
+            },
+        },
+        INPUT {
+            r#type: INPUT_MOUSE,
+            Anonymous: INPUT_0 {
+                mi: MOUSEINPUT {
+                    dx: abs_x, dy: abs_y, mouseData: 0,
+                    dwFlags: vd_flags | MOUSEEVENTF_LEFTUP,
+                    time: 0, dwExtraInfo: 0,
+                },
+            },
+        },
+    ];
+    SendInput(&inputs, mem::size_of::<INPUT>() as i32);
+    // Auto-persist: remember last click coordinates for re-focus before type/key
+    LAST_CLICK_X.store(abs_x, SeqCst);
+    LAST_CLICK_Y.store(abs_y, SeqCst);
+}
+
+// ── Debug Highlight (config: highlight=1) ───────────
+// Flashes a red frame around the element an action resolved to — "is it targeting the
+// right thing?" at a glance. Own thread with its own click-through layered window, so
+// the action never waits for it.
+
+const HIGHLIGHT_MS: u64 = 800;
+const HIGHLIGHT_BORDER: i32 = 3;
+
+unsafe extern "system" fn highlight_proc(hwnd: HWND, msg: u32, wp: WPARAM, lp: LPARAM) -> LRESULT {
+    DefWindowProcW(hwnd, msg, wp, lp)
+}
+
+/// Flash the screen rect x,y,w,h if highlight=1 (no-op otherwise).
+fn debug_highlight(x: i32, y: i32, w: i32, h: i32) {
+    if !config_flag("highlight") || w <= 0 || h <= 0 { return; }
+    std::thread::spawn(move || unsafe { flash_rect(x, y, w, h) });
+}
+
+unsafe fn flash_rect(x: i32, y: i32, w: i32, h: i32) {
+    static CLASS: OnceLock<bool> = OnceLock::new();
+    let Ok(hinst) = GetModuleHandleW(None) else { return };
+    let cls = w!("DirectShellHighlight");
+    let registered = *CLASS.get_or_init(|| {
+        let wc = WNDCLASSEXW {
+            cbSize: mem::size_of::<WNDCLASSEXW>() as u32,
+            lpfnWndProc: Some(highlight_proc),
+            hInstance: hinst.into(),
+            hbrBackground: CreateSolidBrush(TARGET_BOX_CLR),
+            lpszClassName: cls,
+            ..Default::default()
+        };
+        RegisterClassExW(&wc) != 0
+    });
+    if !registered { return; }
+
+    // Frame just outside the element, so the element itself stays readable
+    let b = HIGHLIGHT_BORDER;
+    let (fw, fh) = (w + 2 * b, h + 2 * b);
+    let Ok(hwnd) = CreateWindowExW(
+        WS_EX_LAYERED | WS_EX_TRANSPARENT | WS_EX_TOPMOST | WS_EX_TOOLWINDOW | WS_EX_NOACTIVATE,
+        cls, w!(""), WS_POPUP,
+        x - b, y - b, fw, fh,
+        HWND::default(), HMENU::default(), hinst, None,
+    ) else { return };
+    let _ = SetLayeredWindowAttributes(hwnd, COLORREF(0), 220, LWA_ALPHA);
+    let frame = CreateRectRgn(0, 0, fw, fh);
+    let inner = CreateRectRgn(b, b, fw - b, fh - b);
+    let _ = CombineRgn(frame, frame, inner, RGN_DIFF);
+    let _ = DeleteObject(inner);
+    SetWindowRgn(hwnd, frame, FALSE); // region now owned by the window
+    let _ = ShowWindow(hwnd, SW_SHOWNOACTIVATE);
+
+    let end = Instant::now() + std::time::Duration::from_millis(HIGHLIGHT_MS);
+    let mut msg = MSG::default();
+    while Instant::now() < end {
+        while PeekMessageW(&mut msg, hwnd, 0, 0, PM_REMOVE).as_bool() {
+            let _ = TranslateMessage(&msg);
+            DispatchMessageW(&msg);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(15));
+    }
+    let _ = DestroyWindow(hwnd);
+}
+
+/// clear: empty a field. Focus it, SetValue("") where ValuePattern takes it, else
+/// Ctrl+A + Delete. Then read back — a field that still has content is a failure.
+/// Without a readable value the result can't be checked (ACTION_DETAIL "unverified").
+unsafe fn clear_field(target_hwnd: HWND, selector: &str) -> InjectResult {
+    let uia: IUIAutomation = match create_uia() {
+        Ok(u) => u,
+        Err(e) => { log(&format!("clear: CoCreate FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+    let root = match uia.ElementFromHandle(target_hwnd) {
+        Ok(e) => e,
+        Err(e) => { log(&format!("clear: ElementFromHandle FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+    let Some(elem) = resolve_live(&uia, &root, selector) else {
+        log(&format!("clear: element not found '{}'", selector));
+        return Err(InjectError::NotFound(selector.to_string()));
+    };
+    let vp = elem.GetCurrentPatternAs::<IUIAutomationValuePattern>(UIA_ValuePatternId).ok();
+    let read_only = vp.as_ref().is_some_and(|vp| vp.CurrentIsReadOnly().is_ok_and(|b| b.as_bool()));
+    if read_only {
+        log(&format!("clear: '{}' is read-only", selector));
+        return Err(InjectError::ReadOnly);
+    }
+    bring_to_front(target_hwnd)?;
+    let _ = elem.SetFocus();
+    let read = || vp.as_ref().map(|vp| vp.CurrentValue().map(|v| v.to_string()).unwrap_or_default());
+    // SetValue accepted but reverted (validation, bound model) → keys after all
+    let mut via_value = vp.as_ref().is_some_and(|vp| vp.SetValue(&BSTR::new()).is_ok());
+    if via_value && read().is_some_and(|v| !v.is_empty()) {
+        log(&format!("clear: SetValue(\"\") on '{}' did not stick — using keys", selector));
+        via_value = false;
+    }
+    if !via_value {
+        send_key_combo("ctrl+a")?;
+        send_key_combo("delete")?;
+        std::thread::sleep(std::time::Duration::from_millis(INJECT_VERIFY_MS));
+    }
+    let how = if via_value { "valuepattern" } else { "keys" };
+    let Some(left) = read() else {
+        log(&format!("clear: '{}' cleared via {} (no value to verify)", selector, how));
+        *ACTION_DETAIL.lock().unwrap() = format!("{}:unverified", how);
+        return Ok(());
+    };
+    if !left.is_empty() {
+        log(&format!("clear: '{}' still has {} chars after {}", selector, left.chars().count(), how));
+        return Err(InjectError::Failed(format!("field not empty after clear ({} chars left)", left.chars().count())));
+    }
+    log(&format!("clear: '{}' empty ({})", selector, how));
+    *ACTION_DETAIL.lock().unwrap() = how.to_string();
+    Ok(())
+}
+
+// ── read_field (ValuePattern, else Clipboard) ────────
+// Exactly what is in a field, also for custom-drawn editors without Value/TextPattern:
+// ValuePattern if offered, otherwise focus → Ctrl+A → Ctrl+C → read the clipboard.
+// The user's clipboard is put back afterwards — only when it held nothing but text:
+// images, files or rich formats can't be restored, so then the clipboard path is not
+// taken at all. The select-all selection is collapsed again. Password fields are never copied.
+const CF_TEXT: u32 = 1;
+const CF_OEMTEXT: u32 = 7;
+const CF_UNICODETEXT: u32 = 13;
+const CF_LOCALE: u32 = 16;
+
+/// Open the clipboard, retrying briefly — another app may hold it.
+unsafe fn open_clipboard() -> bool {
+    let owner = HWND(DS_HWND.load(SeqCst) as *mut _);
+    for _ in 0..5 {
+        if OpenClipboard(owner).is_ok() { return true; }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    false
+}
+
+/// CF_UNICODETEXT of the already opened clipboard.
+unsafe fn open_clipboard_text() -> Option<String> {
+    GetClipboardData(CF_UNICODETEXT).ok().and_then(|h| {
+        let mem = HGLOBAL(h.0);
+        let ptr = GlobalLock(mem) as *const u16;
+        if ptr.is_null() { return None; }
+        let len = (0..).take_while(|&i| *ptr.add(i) != 0).count();
+        let s = String::from_utf16_lossy(std::slice::from_raw_parts(ptr, len));
+        let _ = GlobalUnlock(mem);
+        Some(s)
+    })
+}
+
+/// Current clipboard text (CF_UNICODETEXT). None = no text / clipboard busy.
+unsafe fn clipboard_text() -> Option<String> {
+    if !open_clipboard() { return None; }
+    let text = open_clipboard_text();
+    let _ = CloseClipboard();
+    text
+}
+
+/// Clipboard content that set_clipboard_text can put back exactly: Some(None) = empty,
+/// Some(Some(text)) = text only. None = busy, or other formats present (images, files).
+unsafe fn restorable_clipboard() -> Option<Option<String>> {
+    if !open_clipboard() { return None; }
+    let mut only_text = true;
+    let mut fmt = 0;
+    loop {
+        fmt = EnumClipboardFormats(fmt);
+        if fmt == 0 { break; }
+        if !matches!(fmt, CF_TEXT | CF_OEMTEXT | CF_UNICODETEXT | CF_LOCALE) { only_text = false; break; }
+    }
+    let saved = if !only_text {
+        None
+    } else if CountClipboardFormats() == 0 {
+        Some(None)
+    } else {
+        open_clipboard_text().map(Some)
+    };
+    let _ = CloseClipboard();
+    saved
+}
+
+/// Replace the clipboard with `text` (None = leave it empty).
+unsafe fn set_clipboard_text(text: Option<&str>) -> bool {
+    if !open_clipboard() { return false; }
+    let _ = EmptyClipboard();
+    let ok = match text {
+        None => true,
+        Some(t) => {
+            let wide: Vec<u16> = t.encode_utf16().chain(std::iter::once(0)).collect();
+            match GlobalAlloc(GMEM_MOVEABLE, wide.len() * 2) {
+                Ok(mem) => {
+                    let ptr = GlobalLock(mem) as *mut u16;
+                    if !ptr.is_null() {
+                        std::ptr::copy_nonoverlapping(wide.as_ptr(), ptr, wide.len());
+                        let _ = GlobalUnlock(mem);
+                    }
+                    // Ownership passes to the clipboard on success
+                    !ptr.is_null() && SetClipboardData(CF_UNICODETEXT, HANDLE(mem.0)).is_ok()
+                }
+                Err(_) => false,
+            }
+        }
+    };
+    let _ = CloseClipboard();
+    ok
+}
+
+/// read_field: full content of a field → one 'read_field' event
+/// (element_name/role = the field, detail = "valuepattern" | "clipboard", value = content).
+unsafe fn read_field(target_hwnd: HWND, selector: &str) -> InjectResult {
+    let uia: IUIAutomation = match create_uia() {
+        Ok(u) => u,
+        Err(e) => { log(&format!("read_field: CoCreate FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+    let root = match uia.ElementFromHandle(target_hwnd) {
+        Ok(e) => e,
+        Err(e) => { log(&format!("read_field: ElementFromHandle FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+    let Some(elem) = resolve_live(&uia, &root, selector) else {
+        log(&format!("read_field: element not found '{}'", selector));
+        return Err(InjectError::NotFound(selector.to_string()));
+    };
+    let name = elem.CurrentName().ok().map(|s| s.to_string()).unwrap_or_default();
+    let role = role_name(elem.CurrentControlType().unwrap_or_default().0);
+    let is_password = elem.CurrentIsPassword().map(|b| b.as_bool()).unwrap_or(false);
+
+    let (value, how) = if elem.GetCurrentPattern(UIA_ValuePatternId).is_ok() || is_password {
+        // get_value masks password fields
+        (get_value(&elem), "valuepattern")
+    } else {
+        // Only when the user's clipboard can be put back exactly
+        let Some(saved) = restorable_clipboard() else {
+            log(&format!("read_field: '{}' has no ValuePattern and the clipboard holds non-text data — not overwritten", selector));
+            return Err(InjectError::Failed("field offers no value and the clipboard holds non-text data".into()));
+        };
+        let seq = GetClipboardSequenceNumber();
+        bring_to_front(target_hwnd)?;
+        let _ = elem.SetFocus();
+        std::thread::sleep(std::time::Duration::from_millis(30));
+        send_key_combo("ctrl+a")?;
+        send_key_combo("ctrl+c")?;
+        let t0 = Instant::now();
+        while GetClipboardSequenceNumber() == seq && (t0.elapsed().as_millis() as u64) < CLIPBOARD_WAIT_MS {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        let copied = (GetClipboardSequenceNumber() != seq).then(|| clipboard_text()).flatten();
+        // Collapse the select-all again — a stray keystroke would replace the whole field
+        let _ = send_key_combo("ctrl+home");
+        if !set_clipboard_text(saved.as_deref()) {
+            log("read_field: could not restore the clipboard");
+        }
+        let Some(copied) = copied else {
+            log(&format!("read_field: Ctrl+C on '{}' copied nothing within {}ms", selector, CLIPBOARD_WAIT_MS));
+            return Err(InjectError::Failed("field offers no value and copy produced nothing".into()));
+        };
+        (copied, "clipboard")
+    };
+    write_event("read_field", &name, role, how, &value);
+    log(&format!("read_field: '{}' → {} chars via {}", selector, value.chars().count(), how));
+    *ACTION_DETAIL.lock().unwrap() = how.to_string();
+    Ok(())
+}
+
+/// Click on a UI element by name using UIA. Finds element, gets center, sends mouse click.
+/// DB-backed selectors (path=...) skip UIA entirely and click the stored bounds.
+/// "x%,y%" (percent snap mode) clicks that point of the live window rect.
+unsafe fn click_element(target_hwnd: HWND, element_name: &str, strategy: Option<Strategy>) -> InjectResult {
+    if let Some((px, py)) = parse_percent_point(element_name) {
+        let mut win = RECT::default();
+        if GetWindowRect(target_hwnd, &mut win).is_err() || win.right <= win.left || win.bottom <= win.top {
+            return Err(InjectError::Failed("window rect unavailable".into()));
+        }
+        let (cx, cy) = percent_to_screen(px, py, &win);
+        bring_to_front(target_hwnd)?;
+        std::thread::sleep(std::time::Duration::from_millis(30));
+        send_click(cx, cy);
+        log(&format!("click: SendInput {} @ {},{} (percent of window)", element_name, cx, cy));
+        return Ok(());
+    }
+    let sel = parse_target(element_name);
+    if !matches!(sel, Target::Name(_)) {
+        let Some((_, x, y, w, h)) = lookup_element(&sel) else {
+            log(&format!("click: selector not found in dump: '{}'", element_name));
+            return Err(InjectError::NotFound(element_name.to_string()));
+        };
+        debug_highlight(x, y, w, h);
+        bring_to_front(target_hwnd)?;
+        std::thread::sleep(std::time::Duration::from_millis(30));
+        let (cx, cy) = (x + w / 2, y + h / 2);
+        send_click(cx, cy);
+        log(&format!("click: SendInput '{}' @ {},{} (from dump, persisted)", element_name, cx, cy));
+        return Ok(());
+    }
+
+    let uia: IUIAutomation = match create_uia() {
+        Ok(u) => u,
+        Err(e) => { log(&format!("click: CoCreate FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+
+    let root = match uia.ElementFromHandle(target_hwnd) {
+        Ok(e) => e,
+        Err(e) => { log(&format!("click: ElementFromHandle FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+
+    let cond = match uia.CreatePropertyCondition(
+        UIA_NamePropertyId, &VARIANT::from(BSTR::from(element_name)),
+    ) {
+        Ok(c) => c,
+        Err(e) => { log(&format!("click: cond FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+
+    let elem = match root.FindFirst(TreeScope_Descendants, &cond) {
+        Ok(e) => e,
+        Err(e) => {
+            log(&format!("click: FindFirst FAIL ('{}'): {e}", element_name));
+            match resolve_fuzzy(&uia, &root, element_name) {
+                Some(e) => e,
+                None => return Err(InjectError::NotFound(element_name.to_string())),
+            }
+        }
+    };
+
+    // WPF / XAML: InvokePattern is dispatched asynchronously by the framework
+    // (no modal-dialog blocking) and needs no foreground → use it when offered.
+    // Config override (<app>.click) wins over the framework default.
+    let framework = get_framework();
+    let try_invoke = match strategy {
+        Some(s) => s == Strategy::Invoke,
+        None => matches!(framework.as_str(), "WPF" | "XAML") || foreground_strategy() == Foreground::Never,
+    };
+    if config_flag("highlight") {
+        if let Ok(rc) = elem.CurrentBoundingRectangle() {
+            debug_highlight(rc.left, rc.top, rc.right - rc.left, rc.bottom - rc.top);
+        }
+    }
+    if try_invoke {
+        if let Ok(ip) = elem.GetCurrentPatternAs::<IUIAutomationInvokePattern>(UIA_InvokePatternId) {
+            if ip.Invoke().is_ok() {
+                log(&format!("click: InvokePattern '{}' (framework {}, override {:?})", element_name, framework, strategy));
+                return Ok(());
+            }
+        }
+    }
+
+    // Native mouse click via SendInput — everything else.
+    // UIA InvokePattern is synchronous cross-process COM → deadlocks Electron apps (Discord).
+    // We only use UIA to FIND the element coordinates, then click with real mouse input.
+    // Bring target to foreground first — SendInput goes to the foreground window.
+    bring_to_front(target_hwnd)?;
+    std::thread::sleep(std::time::Duration::from_millis(30));
+    let rect = match elem.CurrentBoundingRectangle() {
+        Ok(r) => r,
+        Err(e) => { log(&format!("click: rect FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+    let cx = rect.left + (rect.right - rect.left) / 2;
+    let cy = rect.top + (rect.bottom - rect.top) / 2;
+    send_click(cx, cy);
+    log(&format!("click: SendInput '{}' @ {},{} (persisted)", element_name, cx, cy));
+    Ok(())
+}
+
+// ── Invoke (semantic "activate") ─────────────────────
+// InvokePattern is synchronous cross-process COM — a modal dialog opened by the
+// handler blocks Invoke() until it closes. So: resolve + Invoke on a worker thread
+// with its own UIA instance; the timer thread waits at most INVOKE_TIMEOUT_MS, then
+// reports success with detail "unconfirmed" (the click most likely landed).
+const INVOKE_TIMEOUT_MS: u64 = 2000;
+
+enum InvokeOutcome { Invoked, NoPattern, NotFound, Failed }
+
+/// Resolve a target selector to a live element below `root`.
+/// Name/id=/class= → live FindFirst; path=/XPath → dump coordinates → ElementFromPoint.
+unsafe fn resolve_live(uia: &IUIAutomation, root: &IUIAutomationElement, target: &str) -> Option<IUIAutomationElement> {
+    let (prop, val) = match parse_target(target) {
+        Target::Name(n) => (UIA_NamePropertyId, n),
+        Target::Id(a) => (UIA_AutomationIdPropertyId, a),
+        Target::Class(c) => (UIA_ClassNamePropertyId, c),
+        sel => {
+            let (_, x, y, w, h) = lookup_element(&sel)?;
+            return live_element_at(uia, x, y, w, h);
+        }
+    };
+    let cond = uia.CreatePropertyCondition(prop, &VARIANT::from(BSTR::from(val))).ok()?;
+    match root.FindFirst(TreeScope_Descendants, &cond) {
+        Ok(e) => Some(e),
+        Err(_) if prop == UIA_NamePropertyId => resolve_fuzzy(uia, root, val),
+        Err(_) => None,
+    }
+}
+
+/// Invoke the element's default action. Falls back to click_element when the
+/// element has no InvokePattern. A hanging Invoke() usually means it worked and opened a
+/// modal dialog → success with detail "unconfirmed" (never invoked a second time).
+unsafe fn invoke_element(target_hwnd: HWND, target: &str) -> InjectResult {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let raw = target_hwnd.0 as usize;
+    let sel = target.to_string();
+    std::thread::spawn(move || {
+        let outcome = unsafe {
+            let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+            let outcome = (|| {
+                let uia: IUIAutomation = create_uia().ok()?;
+                let root = uia.ElementFromHandle(HWND(raw as *mut _)).ok()?;
+                let elem = resolve_live(&uia, &root, &sel)?;
+                Some(match elem.GetCurrentPatternAs::<IUIAutomationInvokePattern>(UIA_InvokePatternId) {
+                    Ok(ip) => if ip.Invoke().is_ok() { InvokeOutcome::Invoked } else { InvokeOutcome::Failed },
+                    Err(_) => InvokeOutcome::NoPattern,
+                })
+            })().unwrap_or(InvokeOutcome::NotFound);
+            CoUninitialize();
+            outcome
+        };
+        let _ = tx.send(outcome);
+    });
+
+    match rx.recv_timeout(std::time::Duration::from_millis(INVOKE_TIMEOUT_MS)) {
+        Ok(InvokeOutcome::Invoked) => { log(&format!("invoke: OK '{}'", target)); Ok(()) }
+        Ok(InvokeOutcome::NoPattern) => {
+            log(&format!("invoke: no InvokePattern on '{}', falling back to click", target));
+            click_element(target_hwnd, target, strategy_override("click"))
+        }
+        Ok(InvokeOutcome::NotFound) => { log(&format!("invoke: target not found '{}'", target)); Err(InjectError::NotFound(target.to_string())) }
+        Ok(InvokeOutcome::Failed) => { log(&format!("invoke: Invoke() FAILED '{}'", target)); Err(InjectError::Failed("Invoke() failed".into())) }
+        Err(_) => {
+            // Worker stays blocked in COM until the app returns — detached, not joined.
+            // Retrying would invoke again: stacked dialogs, duplicate submits.
+            log(&format!("invoke: no return after {}ms '{}' — modal dialog? reported as unconfirmed", INVOKE_TIMEOUT_MS, target));
+            let mut detail = ACTION_DETAIL.lock().unwrap();
+            if detail.is_empty() { detail.push_str("unconfirmed"); } else { detail.push_str(" (unconfirmed)"); }
+            Ok(())
+        }
+    }
+}
+
+// ── Dismiss (close the active modal dialog) ─────────
+// Uses the modal detected by the last dump (meta modal_id). Safest first: a cancel-like or
+// close-like button → Escape. Only if the dialog is still there after Escape (checked on a
+// completed dump, deferred like confirm): the dialog's default button (MSAA
+// STATE_SYSTEM_DEFAULT) → OK. Never a default button without a name — nobody knows what it
+// does. Pressed via invoke_element (Invoke, else click).
+
+/// Rank of a dialog button for dismiss (lower = preferred). None = never pressed
+/// (e.g. "No"/"Don't save" would discard data).
+fn dismiss_rank(name: &str) -> Option<u8> {
+    let n = fold_name(&name.replace('&', ""));
+    let n = n.trim_end_matches(['.', '…']).trim();
+    match n {
+        "cancel" | "abbrechen" | "annuler" | "cancelar" | "annulla" | "annuleren" => Some(0),
+        "close" | "schliessen" | "fermer" | "cerrar" | "chiudi" | "sluiten" | "x" => Some(1),
+        "ok" | "okay" => Some(3),
+        _ => None,
+    }
+}
+
+/// Rank of a dialog button for confirm (lower = preferred). None = not affirmative.
+fn confirm_rank(name: &str) -> Option<u8> {
+    let n = fold_name(&name.replace('&', ""));
+    let n = n.trim_end_matches(['.', '…']).trim();
+    match n {
+        "save" | "speichern" | "enregistrer" | "guardar" | "salva" | "opslaan" => Some(0),
+        "yes" | "ja" | "oui" | "si" => Some(0),
+        "ok" | "okay" => Some(1),
+        _ => None,
+    }
+}
+
+/// Modal of the last dump (name) and its enabled, visible buttons. None = no modal.
+fn modal_buttons(conn: &Connection) -> Option<(String, Vec<Element>)> {
+    let (modal_id, modal_name): (i64, String) = conn.query_row(
+        "SELECT CAST(i.value AS INTEGER), n.value FROM meta i, meta n \
+         WHERE i.key='modal_id' AND n.key='modal_name' AND CAST(i.value AS INTEGER) > 0",
+        [], |r| Ok((r.get(0)?, r.get(1)?)),
+    ).ok()?;
+    let buttons = query_elements(conn,
+        "WHERE role='Button' AND enabled=1 AND offscreen=0 AND id IN (WITH RECURSIVE sub(id) AS ( \
+             SELECT ?1 UNION ALL SELECT e.id FROM elements e JOIN sub ON e.parent_id = sub.id) \
+             SELECT id FROM sub) \
+         ORDER BY id",
+        params![modal_id]);
+    Some((modal_name, buttons))
+}
+
+/// The dialog's default button (MSAA STATE_SYSTEM_DEFAULT), asked from the live tree.
+unsafe fn default_button(buttons: &[Element]) -> Option<&Element> {
+    let uia = create_uia().ok()?;
+    buttons.iter().find(|b| live_element_at(&uia, b.x, b.y, b.w, b.h)
+        .and_then(|e| e.GetCurrentPatternAs::<IUIAutomationLegacyIAccessiblePattern>(UIA_LegacyIAccessiblePatternId).ok())
+        .and_then(|lp| lp.CurrentState().ok())
+        .is_some_and(|state| state & STATE_SYSTEM_DEFAULT != 0))
+}
+
+/// Best button by `rank` (lowest wins); if nothing ranks at or below `default_rank`,
+/// the default button takes that rank.
+unsafe fn pick_button(buttons: &[Element], rank: fn(&str) -> Option<u8>, default_rank: u8) -> Option<&Element> {
+    let mut best: Option<(u8, &Element)> = None;
+    for b in buttons {
+        if let Some(r) = rank(&b.name) {
+            if best.is_none_or(|(br, _)| r < br) { best = Some((r, b)); }
+        }
+    }
+    if best.is_none_or(|(r, _)| r > default_rank) {
+        if let Some(b) = default_button(buttons) { best = Some((default_rank, b)); }
+    }
+    best.map(|(_, b)| b)
+}
+
+/// Press a dialog button via invoke_element (path when known, else name).
+unsafe fn press_button(target_hwnd: HWND, b: &Element) -> InjectResult {
+    *ACTION_DETAIL.lock().unwrap() = format!("button:{}", b.name);
+    let sel = if b.path.is_empty() { b.name.clone() } else { format!("path={}", b.path) };
+    invoke_element(target_hwnd, &sel)
+}
+
+/// dismiss: close the modal dialog of the last dump. detail (inject_result) = what was pressed.
+unsafe fn dismiss_modal(target_hwnd: HWND, id: i64) -> InjectResult {
+    let Some(conn) = open_lookup_db() else { return Err(InjectError::NoDump); };
+    if !waiting_on(id) {
+        let Some((modal_name, buttons)) = modal_buttons(&conn) else {
+            log("dismiss: no modal dialog in the last dump");
+            return Err(InjectError::NotFound("modal dialog".into()));
+        };
+        if let Some(b) = buttons.iter().filter(|b| dismiss_rank(&b.name).is_some_and(|r| r <= 1))
+            .min_by_key(|b| dismiss_rank(&b.name)) {
+            log(&format!("dismiss: '{}' → button '{}'", modal_name, b.name));
+            return press_button(target_hwnd, b);
+        }
+        log(&format!("dismiss: '{}' has no cancel/close button, sending Escape", modal_name));
+        *ACTION_DETAIL.lock().unwrap() = "key:escape".to_string();
+        bring_to_front(target_hwnd)?;
+        std::thread::sleep(std::time::Duration::from_millis(30));
+        send_vk(VK_ESCAPE);
+        with_wait(id, dump_settled);
+        return Err(InjectError::Pending(DISMISS_ESCAPE_MS));
+    }
+
+    // Re-check after Escape — on a completed dump, not a half-streamed tree
+    let (elapsed, settled) = with_wait(id, |w| (w.started.elapsed().as_millis() as u64, dump_settled(w)));
+    if !settled {
+        if elapsed >= DISMISS_WAIT_MS {
+            log(&format!("dismiss: TIMEOUT — no completed dump within {}ms after Escape", DISMISS_WAIT_MS));
+            return Err(InjectError::Timeout(format!("no dump within {}ms to check the dialog after Escape", DISMISS_WAIT_MS)));
+        }
+        return Err(InjectError::Pending(CONFIRM_POLL_MS));
+    }
+    let Some((modal_name, buttons)) = modal_buttons(&conn) else {
+        log(&format!("dismiss: dialog closed by Escape after {}ms", elapsed));
+        *ACTION_DETAIL.lock().unwrap() = "key:escape".to_string();
+        return Ok(());
+    };
+    if elapsed < DISMISS_ESCAPE_MS { return Err(InjectError::Pending(CONFIRM_POLL_MS)); }
+
+    // Escape did nothing: the default button (only a named one), then OK
+    let named: Vec<Element> = buttons.into_iter().filter(|b| !b.name.trim().is_empty()).collect();
+    match pick_button(&named, dismiss_rank, 2) {
+        Some(b) => {
+            log(&format!("dismiss: '{}' ignored Escape → button '{}'", modal_name, b.name));
+            press_button(target_hwnd, b)
+        }
+        None => {
+            log(&format!("dismiss: '{}' ignored Escape and has no safe button", modal_name));
+            Err(InjectError::NotFound(format!("dismiss button in '{}'", modal_name)))
+        }
+    }
+}
+
+// ── Confirm (wait for a dialog, press its affirmative button) ──
+// "Do X, then confirm the dialog that pops up" as one action. text = max wait in ms
+// (default CONFIRM_WAIT_MS). Dumps itself while waiting and is deferred, like wait_progress.
+// Button choice: Save/Yes → OK → the dialog's default button. Never No/Cancel.
+
+/// confirm: poll for a modal dialog and press its affirmative button.
+/// Timeout = no dialog appeared; NotFound = dialog without an affirmative button.
+unsafe fn confirm_modal(target_hwnd: HWND, id: i64, spec: &str) -> InjectResult {
+    let max: u64 = spec.trim().parse().unwrap_or(CONFIRM_WAIT_MS).min(WAIT_MAX_MS);
+    let (elapsed, settled) = with_wait(id, |w| (w.started.elapsed().as_millis() as u64, dump_settled(w)));
+    if settled {
+        let Some(conn) = open_lookup_db() else { return Err(InjectError::NoDump); };
+        if let Some((modal_name, buttons)) = modal_buttons(&conn) {
+            return match pick_button(&buttons, confirm_rank, 2) {
+                Some(b) => {
+                    log(&format!("confirm: '{}' → button '{}' after {}ms", modal_name, b.name, elapsed));
+                    press_button(target_hwnd, b)
+                }
+                None => {
+                    log(&format!("confirm: '{}' has no affirmative button", modal_name));
+                    Err(InjectError::NotFound(format!("affirmative button in '{}'", modal_name)))
+                }
+            };
+        }
+    }
+    if elapsed >= max {
+        log(&format!("confirm: TIMEOUT — no modal dialog within {}ms", max));
+        return Err(InjectError::Timeout(format!("no modal dialog within {}ms", max)));
+    }
+    Err(InjectError::Pending(CONFIRM_POLL_MS))
+}
+
+// ── Hover + Tooltip ──────────────────────────────────
+// action='hover', target = selector, text = max wait in ms (default TOOLTIP_WAIT_MS).
+// Moves the mouse onto the element and polls (deferred) for a ToolTip of the target's
+// process — top-level (tooltips_class32, WPF/XAML popups) or inside the app (Chromium) —
+// that was not already open before. Other apps' tooltips never count.
+// Its text → event 'tooltip' (element_name/role = hovered element, detail = selector).
+// Polling instead of UIA_ToolTipOpenedEventId: the hovered element is known, and the
+// poll also sees tooltips of frameworks that never raise the event.
+
+/// All visible ToolTips of the target's process with text: top-level (desktop children
+/// with the target's pid), then the app's own subtree. (text, bounding rect) each —
+/// the text is the Name, else the first named descendant.
+unsafe fn find_tooltips(uia: &IUIAutomation, root: &IUIAutomationElement, pid: u32) -> Vec<(String, RECT)> {
+    let Ok(cond) = uia.CreatePropertyCondition(UIA_ControlTypePropertyId, &VARIANT::from(UIA_ToolTipControlTypeId.0)) else { return Vec::new() };
+    let Ok(own) = uia.CreatePropertyCondition(UIA_ProcessIdPropertyId, &VARIANT::from(pid as i32)) else { return Vec::new() };
+    let Ok(own_tip) = uia.CreateAndCondition(&cond, &own) else { return Vec::new() };
+    let Ok(named) = uia.CreatePropertyCondition(UIA_NamePropertyId, &VARIANT::from(BSTR::from("")))
+        .and_then(|empty| uia.CreateNotCondition(&empty)) else { return Vec::new() };
+    let top = uia.GetRootElement().ok().and_then(|desktop| desktop.FindAll(TreeScope_Children, &own_tip).ok());
+    let inner = root.FindAll(TreeScope_Descendants, &cond).ok();
+    let mut tips = Vec::new();
+    for arr in [top, inner].into_iter().flatten() {
+        for i in 0..arr.Length().unwrap_or(0) {
+            let Ok(tip) = arr.GetElement(i) else { continue };
+            if tip.CurrentIsOffscreen().map(|b| b.as_bool()).unwrap_or(false) { continue; }
+            let mut text = tip.CurrentName().ok().map(|s| s.to_string()).unwrap_or_default();
+            if text.trim().is_empty() {
+                text = tip.FindFirst(TreeScope_Descendants, &named).ok()
+                    .and_then(|e| e.CurrentName().ok()).map(|s| s.to_string()).unwrap_or_default();
+            }
+            let text = normalize_text(text.trim(), strip_format_enabled());
+            if !text.is_empty() {
+                tips.push((text, tip.CurrentBoundingRectangle().unwrap_or_default()));
+            }
+        }
+    }
+    tips
+}
+
+/// hover: mouse onto `selector`, wait for its tooltip, write it as 'tooltip' event.
+/// Timeout = no (new) tooltip appeared within `spec` ms (clamped to WAIT_MAX_MS). Deferred:
+/// the first run moves the mouse, later checks (TOOLTIP_POLL_MS) look for the tooltip.
+unsafe fn hover_tooltip(target_hwnd: HWND, id: i64, selector: &str, spec: &str) -> InjectResult {
+    let max: u64 = spec.trim().parse().unwrap_or(TOOLTIP_WAIT_MS).min(WAIT_MAX_MS);
+    let uia: IUIAutomation = match create_uia() {
+        Ok(u) => u,
+        Err(e) => { log(&format!("hover: CoCreate FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+    let root = match uia.ElementFromHandle(target_hwnd) {
+        Ok(e) => e,
+        Err(e) => { log(&format!("hover: ElementFromHandle FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+    let mut pid = 0u32;
+    GetWindowThreadProcessId(target_hwnd, Some(&mut pid));
+
+    if waiting_on(id) {
+        let tips = find_tooltips(&uia, &root, pid);
+        return with_wait(id, |w| {
+            let elapsed = w.started.elapsed().as_millis() as u64;
+            // A tooltip open before the hover belongs to something else
+            if let Some((text, _)) = tips.iter().find(|t| !w.tooltips_before.contains(t)) {
+                let (name, role) = &w.hovered;
+                write_event("tooltip", name, role, selector, text);
+                log(&format!("hover: '{}' → tooltip '{}' after {}ms", selector, text, elapsed));
+                *ACTION_DETAIL.lock().unwrap() = format!("{} chars", text.chars().count());
+                return Ok(());
+            }
+            if elapsed >= max {
+                log(&format!("hover: TIMEOUT — no tooltip for '{}' within {}ms", selector, max));
+                return Err(InjectError::Timeout(format!("no tooltip within {}ms", max)));
+            }
+            Err(InjectError::Pending(TOOLTIP_POLL_MS))
+        });
+    }
+
+    let Some(elem) = resolve_live(&uia, &root, selector) else {
+        log(&format!("hover: element not found '{}'", selector));
+        return Err(InjectError::NotFound(selector.to_string()));
+    };
+    let rc = elem.CurrentBoundingRectangle().map_err(|e| InjectError::Uia(e.to_string()))?;
+    let name = elem.CurrentName().ok().map(|s| s.to_string()).unwrap_or_default();
+    let role = role_name(elem.CurrentControlType().unwrap_or_default().0);
+    let before = find_tooltips(&uia, &root, pid);
+
+    bring_to_front(target_hwnd)?;
+    std::thread::sleep(std::time::Duration::from_millis(30));
+    let (cx, cy) = (rc.left + (rc.right - rc.left) / 2, rc.top + (rc.bottom - rc.top) / 2);
+    // Two moves: some controls only start their hover timer on a WM_MOUSEMOVE inside them
+    let _ = SetCursorPos(cx - 1, cy - 1);
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    let _ = SetCursorPos(cx, cy);
+
+    with_wait(id, |w| {
+        w.tooltips_before = before;
+        w.hovered = (name, role);
+    });
+    Err(InjectError::Pending(TOOLTIP_POLL_MS))
+}
+
+// ── Grid Cells ───────────────────────────────────────
+// action='cell', target='row,col' (0-based, GridPattern::GetItem) optionally
+// followed by '@<selector>' for the grid itself (name / id= / path= / XPath).
+// No selector → grid around the focused element, else the first grid in the window.
+// text='read' → cell name/value as event 'cell'; anything else → click the cell.
+
+fn parse_cell(spec: &str) -> Option<(i32, i32, &str)> {
+    let (rc, grid) = spec.split_once('@').unwrap_or((spec, ""));
+    let (r, c) = rc.split_once(',')?;
+    Some((r.trim().parse().ok()?, c.trim().parse().ok()?, grid.trim()))
+}
+
+/// Find the grid: explicit selector, else the focused element's nearest grid ancestor,
+/// else the first element in the window that offers GridPattern.
+unsafe fn find_grid(uia: &IUIAutomation, root: &IUIAutomationElement, selector: &str) -> Option<IUIAutomationGridPattern> {
+    let grid_of = |e: &IUIAutomationElement| e.GetCurrentPatternAs::<IUIAutomationGridPattern>(UIA_GridPatternId).ok();
+    if !selector.is_empty() {
+        return grid_of(&resolve_live(uia, root, selector)?);
+    }
+    if let (Ok(focus), Ok(walker)) = (uia.GetFocusedElement(), uia.ControlViewWalker()) {
+        // Focus must be inside the target app, otherwise we'd grab someone else's grid
+        let same_app = focus.CurrentProcessId().ok().is_some_and(|p| root.CurrentProcessId().ok() == Some(p));
+        let mut cur = if same_app { Some(focus) } else { None };
+        while let Some(e) = cur {
+            if let Some(g) = grid_of(&e) { return Some(g); }
+            if uia.CompareElements(root, &e).map(|b| b.as_bool()).unwrap_or(true) { break; }
+            cur = walker.GetParentElement(&e).ok();
+        }
+    }
+    let cond = uia.CreatePropertyCondition(UIA_IsGridPatternAvailablePropertyId, &VARIANT::from(true)).ok()?;
+    grid_of(&root.FindFirst(TreeScope_Descendants, &cond).ok()?)
+}
+
+unsafe fn cell_action(target_hwnd: HWND, spec: &str, mode: &str) -> InjectResult {
+    let Some((row, col, grid_sel)) = parse_cell(spec) else {
+        log(&format!("cell: bad target '{}' (expected row,col[@grid])", spec));
+        return Err(InjectError::InvalidArgument(format!("bad cell '{}' (expected row,col[@grid])", spec)));
+    };
+    let uia: IUIAutomation = match create_uia() {
+        Ok(u) => u,
+        Err(e) => { log(&format!("cell: CoCreate FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+    let root = match uia.ElementFromHandle(target_hwnd) {
+        Ok(e) => e,
+        Err(e) => { log(&format!("cell: ElementFromHandle FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+    let Some(grid) = find_grid(&uia, &root, grid_sel) else {
+        log(&format!("cell: no grid found (selector='{}')", grid_sel));
+        return Err(InjectError::NotFound(if grid_sel.is_empty() { "grid".into() } else { grid_sel.to_string() }));
+    };
+    let cell = match grid.GetItem(row, col) {
+        Ok(c) => c,
+        Err(e) => { log(&format!("cell: GetItem({},{}) FAIL: {e}", row, col)); return Err(InjectError::NotFound(format!("cell {},{}", row, col))); }
+    };
+    let name = cell.CurrentName().ok().map(|s| s.to_string()).unwrap_or_default();
+    if mode.eq_ignore_ascii_case("read") {
+        let ct = cell.CurrentControlType().unwrap_or_default();
+        write_event("cell", &name, role_name(ct.0), &format!("{},{}", row, col), &get_value(&cell));
+        log(&format!("cell: read {},{} '{}'", row, col, name));
+        return Ok(());
+    }
+    let rect = match cell.CurrentBoundingRectangle() {
+        Ok(r) => r,
+        Err(e) => { log(&format!("cell: rect FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+    bring_to_front(target_hwnd)?;
+    std::thread::sleep(std::time::Duration::from_millis(30));
+    let cx = rect.left + (rect.right - rect.left) / 2;
+    let cy = rect.top + (rect.bottom - rect.top) / 2;
+    send_click(cx, cy);
+    log(&format!("cell: click {},{} '{}' @ {},{}", row, col, name, cx, cy));
+    Ok(())
+}
+
+/// Press an element's keyboard accelerator (UIA AcceleratorKey from the last dump).
+/// Works for offscreen menu commands — no coordinates involved.
+unsafe fn press_accelerator(target_hwnd: HWND, selector: &str) -> InjectResult {
+    let Some(conn) = open_lookup_db() else { return Err(InjectError::NoDump); };
+    let Some(id) = lookup_id(&conn, &parse_target(selector)) else {
+        log(&format!("accel: element not found in dump: '{}'", selector));
+        return Err(InjectError::NotFound(selector.to_string()));
+    };
+    let accel: Option<String> = conn.query_row(
+        "SELECT accelerator FROM elements WHERE id=?1", params![id], |r| r.get(0),
+    ).ok().flatten();
+    let Some(accel) = accel.filter(|a| !a.trim().is_empty()) else {
+        log(&format!("accel: '{}' has no accelerator key recorded", selector));
+        return Err(InjectError::PatternMissing("AcceleratorKey"));
+    };
+    bring_to_front(target_hwnd)?;
+    std::thread::sleep(std::time::Duration::from_millis(30));
+    log(&format!("accel: '{}' → {}", selector, accel));
+    send_key_combo(&accel.to_lowercase())
+}
+
+/// navigate: open a URL in the snapped browser — focus omnibox, select all, type, Enter.
+/// Ctrl+L focuses the omnibox in every major browser regardless of UI language;
+/// only if focus does not land in an Edit the address bar is clicked via the dump.
+/// Load result → 'navigate' event: detail "loaded" (content_loaded seen) or "timeout".
+/// Keys only go out while the target is in front and holds the focus (ForegroundRequired
+/// otherwise); the load wait is deferred (see Deferred Waits).
+unsafe fn navigate(target_hwnd: HWND, id: i64, url: &str) -> InjectResult {
+    let url = url.trim();
+    if waiting_on(id) { return navigate_loaded(id, url); }
+    if url.is_empty() { log("navigate: empty url"); return Err(InjectError::InvalidArgument("empty url".into())); }
+    bring_to_front(target_hwnd)?;
+    std::thread::sleep(std::time::Duration::from_millis(30));
+    // Ctrl+L / Ctrl+A / Enter go to whatever is in front — never into another app
+    if GetForegroundWindow() != target_hwnd {
+        log("navigate: target did not get the foreground — no keys sent");
+        return Err(InjectError::ForegroundRequired);
+    }
+
+    if send_key_combo("ctrl+l").is_err() || focus_in_target(target_hwnd) != Some(true) {
+        let name: Option<String> = open_lookup_db().and_then(|conn| conn.query_row(
+            &format!("SELECT name FROM elements WHERE {} AND name IS NOT NULL ORDER BY id LIMIT 1", ADDRESS_BAR_WHERE),
+            [], |r| r.get(0),
+        ).ok());
+        let Some(name) = name else {
+            log("navigate: address bar not focused and not found in dump");
+            return Err(InjectError::NotFound("address bar".into()));
+        };
+        log(&format!("navigate: Ctrl+L missed, clicking '{}'", name));
+        click_element(target_hwnd, &name, None)?;
+    }
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    if GetForegroundWindow() != target_hwnd || focus_in_target(target_hwnd).is_none() {
+        log("navigate: focus left the target — URL not typed");
+        return Err(InjectError::ForegroundRequired);
+    }
+    let _ = send_key_combo("ctrl+a");
+    for ch in url.chars() { inject_char(ch); }
+    send_vk(VK_RETURN);
+    log(&format!("navigate: → {}", url));
+
+    // Renderer meldet AsyncContentLoaded → content_loaded Event — deferred, no polling here
+    with_wait(id, |_| ());
+    Err(InjectError::Pending(NAVIGATE_POLL_MS))
+}
+
+/// navigate, after Enter: content_loaded since the URL went out, or give up after
+/// NAVIGATE_TIMEOUT_MS (the page may still load — reported as "timeout", not an error).
+fn navigate_loaded(id: i64, url: &str) -> InjectResult {
+    let elapsed = with_wait(id, |w| w.started.elapsed().as_millis() as i64);
+    let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64 - elapsed;
+    let loaded = open_lookup_db().and_then(|conn| conn.query_row(
+        "SELECT COUNT(*) FROM events WHERE event_type='content_loaded' AND timestamp >= ?1",
+        params![start], |r| r.get::<_, i64>(0),
+    ).ok()).unwrap_or(0) > 0;
+    if !loaded && (elapsed as u64) < NAVIGATE_TIMEOUT_MS {
+        return Err(InjectError::Pending(NAVIGATE_POLL_MS));
+    }
+    log(&format!("navigate: {} after {}ms", if loaded { "loaded" } else { "no content_loaded" }, elapsed));
+    write_event("navigate", url, "", if loaded { "loaded" } else { "timeout" }, "");
+    Ok(())
+}
+
+/// Keyboard focus inside the target's process? Some(is Edit) — the omnibox check after
+/// Ctrl+L — or None when the focused element belongs to another app.
+unsafe fn focus_in_target(target: HWND) -> Option<bool> {
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    let mut pid = 0u32;
+    GetWindowThreadProcessId(target, Some(&mut pid));
+    let focus = create_uia().ok()?.GetFocusedElement().ok()?;
+    if focus.CurrentProcessId().ok()? as u32 != pid { return None; }
+    Some(focus.CurrentControlType().is_ok_and(|ct| ct == UIA_EditControlTypeId))
+}
+
+// ── Browser Tabs ─────────────────────────────────────
+// Tab strip = first Tab control in tree order (browser chrome precedes page content,
+// so tab widgets inside the web page are not mixed in).
+
+/// TabItems of the window's first Tab container, in strip order.
+unsafe fn tab_items(uia: &IUIAutomation, root: &IUIAutomationElement) -> Vec<IUIAutomationElement> {
+    let Ok(cond_tab) = uia.CreatePropertyCondition(UIA_ControlTypePropertyId, &VARIANT::from(UIA_TabControlTypeId.0)) else { return Vec::new(); };
+    let Ok(cond_item) = uia.CreatePropertyCondition(UIA_ControlTypePropertyId, &VARIANT::from(UIA_TabItemControlTypeId.0)) else { return Vec::new(); };
+    let Ok(strip) = root.FindFirst(TreeScope_Descendants, &cond_tab) else { return Vec::new(); };
+    let Ok(arr) = strip.FindAll(TreeScope_Descendants, &cond_item) else { return Vec::new(); };
+    let n = arr.Length().unwrap_or(0);
+    (0..n).filter_map(|i| arr.GetElement(i).ok()).collect()
+}
+
+unsafe fn tab_is_selected(item: &IUIAutomationElement) -> bool {
+    item.GetCurrentPatternAs::<IUIAutomationSelectionItemPattern>(UIA_SelectionItemPatternId)
+        .and_then(|p| p.CurrentIsSelected())
+        .map(|b| b.as_bool())
+        .unwrap_or(false)
+}
+
+/// tabs: one 'tab' event per TabItem (detail = 1-based index, new_value = "selected" or "").
+unsafe fn list_tabs(target_hwnd: HWND) -> InjectResult {
+    let uia = create_uia().map_err(|e| InjectError::Uia(e.to_string()))?;
+    let root = uia.ElementFromHandle(target_hwnd).map_err(|e| InjectError::Uia(e.to_string()))?;
+    let items = tab_items(&uia, &root);
+    for (i, item) in items.iter().enumerate() {
+        let name = item.CurrentName().ok().map(|s| normalize_text(&s.to_string(), strip_format_enabled())).unwrap_or_default();
+        write_event("tab", &name, "TabItem", &(i + 1).to_string(), if tab_is_selected(item) { "selected" } else { "" });
+    }
+    if items.is_empty() {
+        write_event("tab", "", "", "", "");
+    }
+    log(&format!("tabs: {} tab(s)", items.len()));
+    Ok(())
+}
+
+/// switch_tab: select a tab by 1-based index or name (exact, then case/accent-folded).
+/// SelectionItemPattern.Select(); without the pattern → Ctrl+<index> (1-8, 9 = last).
+unsafe fn switch_tab(target_hwnd: HWND, spec: &str) -> InjectResult {
+    let spec = spec.trim();
+    let uia = create_uia().map_err(|e| InjectError::Uia(e.to_string()))?;
+    let root = uia.ElementFromHandle(target_hwnd).map_err(|e| InjectError::Uia(e.to_string()))?;
+    let items = tab_items(&uia, &root);
+    let names: Vec<String> = items.iter()
+        .map(|e| e.CurrentName().ok().map(|s| s.to_string()).unwrap_or_default())
+        .collect();
+    let idx = match spec.parse::<usize>() {
+        Ok(n) if n >= 1 && n <= items.len() => Some(n - 1),
+        Ok(_) => None,
+        Err(_) => names.iter().position(|n| n == spec)
+            .or_else(|| names.iter().position(|n| fold_name(n) == fold_name(spec))),
+    };
+    let Some(idx) = idx else {
+        log(&format!("switch_tab: no tab '{}' ({} tabs)", spec, items.len()));
+        return Err(InjectError::NotFound(format!("tab '{}'", spec)));
+    };
+    if let Ok(sp) = items[idx].GetCurrentPatternAs::<IUIAutomationSelectionItemPattern>(UIA_SelectionItemPatternId) {
+        if sp.Select().is_ok() {
+            log(&format!("switch_tab: Select() '{}' (#{})", names[idx], idx + 1));
+            return Ok(());
+        }
+    }
+    let key = if idx < 8 { idx + 1 } else if idx == items.len() - 1 { 9 } else { 0 };
+    if key == 0 {
+        log(&format!("switch_tab: '{}' (#{}) has no SelectionItemPattern and no Ctrl+N shortcut", names[idx], idx + 1));
+        return Err(InjectError::PatternMissing("SelectionItem"));
+    }
+    bring_to_front(target_hwnd)?;
+    std::thread::sleep(std::time::Duration::from_millis(30));
+    log(&format!("switch_tab: Ctrl+{} → '{}'", key, names[idx]));
+    send_key_combo(&format!("ctrl+{}", key))
+}
+
+/// UIA patterns probed by the capabilities query, with the actions they enable.
+const CAPABILITY_PATTERNS: &[(UIA_PATTERN_ID, &str, &str)] = &[
+    (UIA_InvokePatternId,         "Invoke",         "invoke"),
+    (UIA_ValuePatternId,          "Value",          "text"),
+    (UIA_TogglePatternId,         "Toggle",         ""),
+    (UIA_ExpandCollapsePatternId, "ExpandCollapse", ""),
+    (UIA_SelectionPatternId,      "Selection",      "get_selected"),
+    (UIA_SelectionItemPatternId,  "SelectionItem",  ""),
+    (UIA_ScrollPatternId,         "Scroll",         "scroll"),
+    (UIA_ScrollItemPatternId,     "ScrollItem",     ""),
+    (UIA_GridPatternId,           "Grid",           "cell"),
+    (UIA_GridItemPatternId,       "GridItem",       ""),
+    (UIA_TablePatternId,          "Table",          ""),
+    (UIA_RangeValuePatternId,     "RangeValue",     ""),
+    (UIA_TextPatternId,           "Text",           ""),
+    (UIA_WindowPatternId,         "Window",         ""),
+];
+
+/// Query action: which UIA patterns does an element support?
+/// → event 'capabilities': detail = patterns, new_value = matching action types.
+unsafe fn query_capabilities(target_hwnd: HWND, selector: &str) -> InjectResult {
+    let uia: IUIAutomation = match create_uia() {
+        Ok(u) => u,
+        Err(e) => { log(&format!("capabilities: CoCreate FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+    let root = match uia.ElementFromHandle(target_hwnd) {
+        Ok(e) => e,
+        Err(e) => { log(&format!("capabilities: ElementFromHandle FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+    let Some(elem) = resolve_live(&uia, &root, selector) else {
+        log(&format!("capabilities: element not found '{}'", selector));
+        return Err(InjectError::NotFound(selector.to_string()));
+    };
+    let mut patterns: Vec<&str> = Vec::new();
+    // click always works (SendInput at the element's center)
+    let mut actions: Vec<&str> = vec!["click"];
+    if elem.CurrentAcceleratorKey().map(|a| !a.is_empty()).unwrap_or(false) {
+        actions.push("accel");
+    }
+    for &(id, pattern, action) in CAPABILITY_PATTERNS {
+        if elem.GetCurrentPattern(id).is_ok_and(|p| !p.as_raw().is_null()) {
+            patterns.push(pattern);
+            if !action.is_empty() { actions.push(action); }
+        }
+    }
+    let name = elem.CurrentName().ok().map(|s| s.to_string()).unwrap_or_default();
+    let ct = elem.CurrentControlType().unwrap_or_default();
+    write_event("capabilities", &name, role_name(ct.0), &patterns.join(","), &actions.join(","));
+    log(&format!("capabilities: '{}' → {}", selector, patterns.join(",")));
+    Ok(())
+}
+
+/// Patterns outside CAPABILITY_PATTERNS — only reported by dump_element.
+const DUMP_EXTRA_PATTERNS: &[(UIA_PATTERN_ID, &str)] = &[
+    (UIA_DockPatternId, "Dock"), (UIA_MultipleViewPatternId, "MultipleView"),
+    (UIA_TableItemPatternId, "TableItem"), (UIA_TransformPatternId, "Transform"),
+    (UIA_ObjectModelPatternId, "ObjectModel"), (UIA_LegacyIAccessiblePatternId, "LegacyIAccessible"),
+    (UIA_ItemContainerPatternId, "ItemContainer"), (UIA_VirtualizedItemPatternId, "VirtualizedItem"),
+    (UIA_SynchronizedInputPatternId, "SynchronizedInput"), (UIA_AnnotationPatternId, "Annotation"),
+    (UIA_TextEditPatternId, "TextEdit"), (UIA_TextChildPatternId, "TextChild"),
+    (UIA_StylesPatternId, "Styles"), (UIA_SpreadsheetPatternId, "Spreadsheet"),
+    (UIA_SpreadsheetItemPatternId, "SpreadsheetItem"), (UIA_DragPatternId, "Drag"),
+    (UIA_DropTargetPatternId, "DropTarget"), (UIA_CustomNavigationPatternId, "CustomNavigation"),
+];
+
+/// Debug action: the full live property set of one element → element_dump.json
+/// (one JSON object). The detailed counterpart to the .snap summary lines; name/value
+/// follow the app's redact setting, passwords are masked.
+unsafe fn dump_element(target_hwnd: HWND, selector: &str) -> InjectResult {
+    let uia: IUIAutomation = match create_uia() {
+        Ok(u) => u,
+        Err(e) => { log(&format!("dump_element: CoCreate FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+    let root = match uia.ElementFromHandle(target_hwnd) {
+        Ok(e) => e,
+        Err(e) => { log(&format!("dump_element: ElementFromHandle FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+    let Some(elem) = resolve_live(&uia, &root, selector) else {
+        log(&format!("dump_element: element not found '{}'", selector));
+        return Err(InjectError::NotFound(selector.to_string()));
+    };
+    let text = |r: windows::core::Result<BSTR>| json_escape(&r.map(|s| s.to_string()).unwrap_or_default());
+    let flag = |r: windows::core::Result<BOOL>| r.map(|b| b.as_bool()).unwrap_or(false);
+    let ct = elem.CurrentControlType().unwrap_or_default();
+    let rc = elem.CurrentBoundingRectangle().unwrap_or_default();
+    // Same redaction as the dump (element_dump.json is just as readable as the DB)
+    let app = app_of_db(&get_db_path()).map(|(_, app)| app.to_string()).unwrap_or_default();
+    let (name, value) = redact_pair(redact_for(&app), role_name(ct.0),
+        elem.CurrentName().map(|s| s.to_string()).unwrap_or_default(), get_value(&elem));
+    let patterns: Vec<String> = CAPABILITY_PATTERNS.iter().map(|&(id, name, _)| (id, name))
+        .chain(DUMP_EXTRA_PATTERNS.iter().copied())
+        .filter(|&(id, _)| elem.GetCurrentPattern(id).is_ok_and(|p| !p.as_raw().is_null()))
+        .map(|(_, name)| format!("\"{}\"", name))
+        .collect();
+    let json = format!(
+        "{{\n  \"selector\":\"{}\",\n  \"name\":\"{}\",\n  \"value\":\"{}\",\n  \"automation_id\":\"{}\",\n  \
+         \"control_type\":{},\n  \"role\":\"{}\",\n  \"localized_role\":\"{}\",\n  \"class_name\":\"{}\",\n  \
+         \"framework_id\":\"{}\",\n  \"rect\":{{\"x\":{},\"y\":{},\"w\":{},\"h\":{}}},\n  \
+         \"enabled\":{},\n  \"offscreen\":{},\n  \"keyboard_focusable\":{},\n  \"has_focus\":{},\n  \
+         \"password\":{},\n  \"access_key\":\"{}\",\n  \"accelerator\":\"{}\",\n  \"help_text\":\"{}\",\n  \
+         \"process_id\":{},\n  \"hwnd\":{},\n  \"patterns\":[{}]\n}}",
+        json_escape(selector), json_escape(&name), json_escape(&value), text(elem.CurrentAutomationId()),
+        ct.0, role_name(ct.0), text(elem.CurrentLocalizedControlType()), text(elem.CurrentClassName()),
+        text(elem.CurrentFrameworkId()), rc.left, rc.top, rc.right - rc.left, rc.bottom - rc.top,
+        flag(elem.CurrentIsEnabled()), flag(elem.CurrentIsOffscreen()), flag(elem.CurrentIsKeyboardFocusable()),
+        flag(elem.CurrentHasKeyboardFocus()), flag(elem.CurrentIsPassword()),
+        text(elem.CurrentAccessKey()), text(elem.CurrentAcceleratorKey()), text(elem.CurrentHelpText()),
+        elem.CurrentProcessId().unwrap_or(0), elem.CurrentNativeWindowHandle().map(|h| h.0 as isize).unwrap_or(0),
+        patterns.join(","));
+    write_checked(&ds_file(ELEMENT_DUMP_FILE), json);
+    log(&format!("dump_element: '{}' → {} ({} pattern(s))", selector, ELEMENT_DUMP_FILE, patterns.len()));
+    Ok(())
+}
+
+/// Query action: selected items of a container → one 'selected' event per item
+/// (detail = container name). Empty selection → one event with empty name.
+unsafe fn get_selected(target_hwnd: HWND, container: &str) -> InjectResult {
+    let uia: IUIAutomation = match create_uia() {
+        Ok(u) => u,
+        Err(e) => { log(&format!("get_selected: CoCreate FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+    let root = match uia.ElementFromHandle(target_hwnd) {
+        Ok(e) => e,
+        Err(e) => { log(&format!("get_selected: ElementFromHandle FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+    let Some(elem) = resolve_live(&uia, &root, container) else {
+        log(&format!("get_selected: container not found '{}'", container));
+        return Err(InjectError::NotFound(container.to_string()));
+    };
+    if elem.GetCurrentPatternAs::<IUIAutomationSelectionPattern>(UIA_SelectionPatternId).is_err() {
+        log(&format!("get_selected: '{}' has no SelectionPattern", container));
+        return Err(InjectError::PatternMissing("Selection"));
+    }
+    let items = current_selection(&elem);
+    for item in &items {
+        let name = item.CurrentName().ok().map(|s| normalize_text(&s.to_string(), strip_format_enabled())).unwrap_or_default();
+        let ct = item.CurrentControlType().unwrap_or_default();
+        write_event("selected", &name, role_name(ct.0), container, &get_value(item));
+    }
+    if items.is_empty() {
+        write_event("selected", "", "", container, "");
+    }
+    log(&format!("get_selected: '{}' → {} item(s)", container, items.len()));
+    Ok(())
+}
+
+/// form_state: every CheckBox/RadioButton of the last dump with its state, as ONE
+/// 'form_state' event (new_value = JSON) — read or verify a whole form in one shot.
+/// `target` non-empty = only below that container (path prefix, e.g. "Window > Group[2]").
+fn form_state(scope: &str) -> InjectResult {
+    let Some(conn) = open_lookup_db() else { return Err(InjectError::NoDump); };
+    let escaped = scope.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    let controls: Vec<String> = query_elements(&conn,
+        "WHERE role IN ('CheckBox','RadioButton') AND (?1 = '' OR path LIKE ?2 ESCAPE '\\') ORDER BY id",
+        params![scope, format!("{}%", escaped)])
+        .iter()
+        .map(|e| {
+            let state = if e.role == "RadioButton" {
+                if e.selected { "selected" } else { "unselected" }
+            } else {
+                match e.toggle_state { Some(0) => "off", Some(1) => "on", Some(2) => "indeterminate", _ => "unknown" }
+            };
+            format!(r#"{{"name":"{}","role":"{}","state":"{}","enabled":{},"x":{},"y":{},"w":{},"h":{}}}"#,
+                json_escape(&e.name), e.role, state, e.enabled, e.x, e.y, e.w, e.h)
+        })
+        .collect();
+    let json = format!(r#"{{"count":{},"controls":[{}]}}"#, controls.len(), controls.join(","));
+    write_event("form_state", "", "", scope, &json);
+    log(&format!("form_state: {} control(s){}", controls.len(),
+        if scope.is_empty() { String::new() } else { format!(" below '{}'", scope) }));
+    Ok(())
+}
+
+/// search: substring match over name/value of the last dump (case-insensitive LIKE).
+/// Every hit → 'search_result' event (detail = "x,y,w,h", new_value = value).
+/// No hit → one empty 'search_result' so the agent knows the query ran.
+fn search_elements(query: &str) -> InjectResult {
+    if query.is_empty() { log("search: empty query"); return Err(InjectError::InvalidArgument("empty query".into())); }
+    let Some(conn) = open_lookup_db() else { return Err(InjectError::NoDump); };
+    let escaped = query.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    let pattern = format!("%{}%", escaped);
+    let mut stmt = match conn.prepare(
+        "SELECT name, role, value, x, y, w, h FROM elements
+         WHERE name LIKE ?1 ESCAPE '\\' OR value LIKE ?1 ESCAPE '\\'
+         ORDER BY id LIMIT ?2",
+    ) {
+        Ok(s) => s,
+        Err(e) => { log(&format!("search: prepare FAIL: {e}")); return Err(InjectError::Db(e.to_string())); }
+    };
+    let rows: Vec<(String, String, String, i32, i32, i32, i32)> = match stmt.query_map(
+        params![pattern, SEARCH_MAX_RESULTS],
+        |r| Ok((
+            r.get::<_, Option<String>>(0)?.unwrap_or_default(),
+            r.get::<_, Option<String>>(1)?.unwrap_or_default(),
+            r.get::<_, Option<String>>(2)?.unwrap_or_default(),
+            r.get(3)?, r.get(4)?, r.get(5)?, r.get(6)?,
+        )),
+    ) {
+        Ok(it) => it.flatten().collect(),
+        Err(e) => { log(&format!("search: query FAIL: {e}")); return Err(InjectError::Db(e.to_string())); }
+    };
+    for (name, role, value, x, y, w, h) in &rows {
+        write_event("search_result", name, role, &format!("{},{},{},{}", x, y, w, h), value);
+    }
+    if rows.is_empty() {
+        write_event("search_result", "", "", query, "");
+    }
+    log(&format!("search: '{}' → {} hit(s)", query, rows.len()));
+    Ok(())
+}
+
+/// read_region: "x,y,w,h" (screen px) clamped to the window → None if malformed,
+/// empty, or entirely outside it.
+fn clamp_region(spec: &str, win: &RECT) -> Option<RECT> {
+    let v: Vec<i32> = spec.split(',').map(|p| p.trim().parse().ok()).collect::<Option<_>>()?;
+    let [x, y, w, h] = v[..] else { return None };
+    if w <= 0 || h <= 0 { return None; }
+    let r = RECT {
+        left: x.max(win.left), top: y.max(win.top),
+        right: x.saturating_add(w).min(win.right), bottom: y.saturating_add(h).min(win.bottom),
+    };
+    (r.left < r.right && r.top < r.bottom).then_some(r)
+}
+
+/// read_region: accessible text inside a screen rectangle (e.g. found on a screenshot).
+/// Every named/valued element of the last dump intersecting it, in reading order
+/// (y, then x), one line each → a single 'region_text' event (detail = clamped rect).
+unsafe fn read_region(target: HWND, spec: &str) -> InjectResult {
+    let mut win = RECT::default();
+    let _ = GetWindowRect(target, &mut win);
+    let Some(r) = clamp_region(spec, &win) else {
+        log(&format!("read_region: '{}' is not x,y,w,h inside the window", spec));
+        return Err(InjectError::InvalidArgument(format!("expected x,y,w,h inside the window, got '{}'", spec)));
+    };
+    let Some(conn) = open_lookup_db() else { return Err(InjectError::NoDump); };
+    // depth 0 = the window itself, it intersects everything
+    let mut stmt = match conn.prepare(
+        "SELECT COALESCE(name,''), COALESCE(value,'') FROM elements
+         WHERE (COALESCE(name,'') <> '' OR COALESCE(value,'') <> '')
+           AND depth > 0 AND offscreen = 0 AND w > 0 AND h > 0
+           AND x < ?3 AND x + w > ?1 AND y < ?4 AND y + h > ?2
+         ORDER BY y, x, id",
+    ) {
+        Ok(s) => s,
+        Err(e) => { log(&format!("read_region: prepare FAIL: {e}")); return Err(InjectError::Db(e.to_string())); }
+    };
+    let lines: Vec<String> = match stmt.query_map(
+        params![r.left, r.top, r.right, r.bottom],
+        |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+    ) {
+        Ok(it) => it.flatten().map(|(name, value)| match (name.is_empty(), value.is_empty()) {
+            (_, true) => name,
+            (true, false) => value,
+            _ if name == value => name,
+            _ => format!("{}: {}", name, value),
+        }).collect(),
+        Err(e) => { log(&format!("read_region: query FAIL: {e}")); return Err(InjectError::Db(e.to_string())); }
+    };
+    let rect = format!("{},{},{},{}", r.left, r.top, r.right - r.left, r.bottom - r.top);
+    write_event("region_text", "", "", &rect, &lines.join("\n"));
+    log(&format!("read_region: {} → {} element(s)", rect, lines.len()));
+    *ACTION_DETAIL.lock().unwrap() = format!("{} element(s) in {}", lines.len(), rect);
+    Ok(())
+}
+
+/// "x,y" screen point, or "x%,y%" of `win` (percent snap mode).
+fn parse_point(spec: &str, win: &RECT) -> Option<(i32, i32)> {
+    if let Some((px, py)) = parse_percent_point(spec) {
+        return Some(percent_to_screen(px, py, win));
+    }
+    let (x, y) = spec.split_once(',')?;
+    Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
+
+/// element_at: what is under a pixel (e.g. one a vision model picked)? Live ElementFromPoint
+/// → one 'element_at' event: name/role, detail = "x,y", value = JSON with automation_id,
+/// rect and `window`: "target" (snapped app), "other" (another window there) or
+/// "desktop" (nothing but the desktop — name/role empty).
+unsafe fn element_at(target: HWND, spec: &str) -> InjectResult {
+    let mut win = RECT::default();
+    let _ = GetWindowRect(target, &mut win);
+    let Some((x, y)) = parse_point(spec, &win) else {
+        log(&format!("element_at: '{}' is not x,y", spec));
+        return Err(InjectError::InvalidArgument(format!("expected x,y (or x%,y%), got '{}'", spec)));
+    };
+    let uia: IUIAutomation = match create_uia() {
+        Ok(u) => u,
+        Err(e) => { log(&format!("element_at: CoCreate FAIL: {e}")); return Err(InjectError::Uia(e.to_string())); }
+    };
+    let pt = POINT { x, y };
+    let elem = match uia.ElementFromPoint(pt) {
+        Ok(e) => e,
+        Err(e) => {
+            log(&format!("element_at: ElementFromPoint({},{}) FAIL: {e}", x, y));
+            return Err(InjectError::NotFound(format!("no element at {},{}", x, y)));
+        }
+    };
+    let desktop = uia.GetRootElement().ok()
+        .and_then(|root| uia.CompareElements(&root, &elem).ok())
+        .is_some_and(|same| same.as_bool());
+    let window = if desktop {
+        "desktop"
+    } else if GetAncestor(WindowFromPoint(pt), GA_ROOT) == target {
+        "target"
+    } else {
+        "other"
+    };
+    let point = format!("{},{}", x, y);
+    if desktop {
+        write_event("element_at", "", "", &point, r#"{"window":"desktop"}"#);
+        log(&format!("element_at: {} → desktop", point));
+        *ACTION_DETAIL.lock().unwrap() = "desktop".to_string();
+        return Ok(());
+    }
+    let name = elem.CurrentName().ok().map(|s| normalize_text(&s.to_string(), strip_format_enabled())).unwrap_or_default();
+    let role = role_name(elem.CurrentControlType().unwrap_or_default().0);
+    let aid = elem.CurrentAutomationId().ok().map(|s| s.to_string()).unwrap_or_default();
+    let rc = elem.CurrentBoundingRectangle().unwrap_or_default();
+    write_event("element_at", &name, role, &point, &format!(
+        r#"{{"automation_id":"{}","x":{},"y":{},"w":{},"h":{},"window":"{}"}}"#,
+        json_escape(&aid), rc.left, rc.top, rc.right - rc.left, rc.bottom - rc.top, window));
+    log(&format!("element_at: {} → {} '{}' ({})", point, role, name, window));
+    *ACTION_DETAIL.lock().unwrap() = format!("{} '{}' ({})", role, name, window);
+    Ok(())
+}
+
+/// wait_idle: wait until no events were written for `quiet` ms ("300", "300@5000").
+/// The quiet window starts no earlier than the wait itself, so at least `quiet` ms pass.
+/// Timeout = the UI never settled within `max` ms (clamped to WAIT_MAX_MS). Deferred.
+fn wait_idle(conn: &Connection, id: i64, spec: &str) -> InjectResult {
+    let (quiet, max) = match spec.trim().split_once('@') {
+        Some((q, m)) => (q.trim().parse().ok(), m.trim().parse().ok()),
+        None => (spec.trim().parse().ok(), None),
+    };
+    let quiet: i64 = quiet.unwrap_or(WAIT_IDLE_QUIET_MS).clamp(0, WAIT_MAX_MS as i64);
+    let max: u64 = max.unwrap_or(WAIT_IDLE_MAX_MS).min(WAIT_MAX_MS);
+    let elapsed = with_wait(id, |w| w.started.elapsed().as_millis() as u64);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64;
+    let start = now - elapsed as i64;
+    let last: i64 = conn.query_row("SELECT COALESCE(MAX(timestamp),0) FROM events", [], |r| r.get(0))
+        .unwrap_or(0);
+    if now - last.max(start) >= quiet {
+        log(&format!("wait_idle: settled after {}ms (quiet {}ms)", elapsed, quiet));
+        return Ok(());
+    }
+    if elapsed >= max {
+        log(&format!("wait_idle: TIMEOUT after {}ms — UI still busy", max));
+        return Err(InjectError::Timeout(format!("UI still busy after {}ms", max)));
+    }
+    Err(InjectError::Pending(WAIT_IDLE_POLL_MS))
+}
+
+/// wait_progress: wait until a progress bar reaches `percent` ("100", "80@60000").
+/// Requests dumps itself (dump_tree runs on its own thread) and reads the progress column
+/// only after a completed dump. A bar that was seen and then disappears counts as
+/// finished (apps hide it when done). Deferred; `max` is clamped to WAIT_MAX_MS.
+fn wait_progress(id: i64, selector: &str, spec: &str) -> InjectResult {
+    let (want, max) = match spec.trim().split_once('@') {
+        Some((p, m)) => (p.trim().parse().ok(), m.trim().parse().ok()),
+        None => (spec.trim().parse().ok(), None),
+    };
+    let want: f64 = want.unwrap_or(100.0);
+    let max: u64 = max.unwrap_or(WAIT_PROGRESS_MAX_MS).min(WAIT_MAX_MS);
+    let target = parse_target(selector);
+    with_wait(id, |w| {
+        let elapsed = w.started.elapsed().as_millis() as u64;
+        if dump_settled(w) {
+            let Some(conn) = open_lookup_db() else { return Err(InjectError::NoDump); };
+            match lookup_id(&conn, &target) {
+                Some(row) => {
+                    w.seen = true;
+                    let pct: Option<f64> = conn.query_row(
+                        "SELECT progress FROM elements WHERE id=?1", params![row], |r| r.get(0),
+                    ).ok().flatten();
+                    if pct.is_some_and(|p| p >= want) {
+                        log(&format!("wait_progress: '{}' at {:.0}% after {}ms", selector, pct.unwrap_or(0.0), elapsed));
+                        return Ok(());
+                    }
+                }
+                None if w.seen => {
+                    log(&format!("wait_progress: '{}' disappeared — treating as finished", selector));
+                    return Ok(());
+                }
+                None => {}
+            }
+        }
+        if elapsed >= max {
+            log(&format!("wait_progress: TIMEOUT '{}' < {}% after {}ms", selector, want, max));
+            return Err(InjectError::Timeout(format!("'{}' below {}% after {}ms", selector, want, max)));
+        }
+        Err(InjectError::Pending(WAIT_PROGRESS_POLL_MS))
+    })
+}
+
+/// Parse a scroll spec: "<dir> [amount] [smooth]".
+///   amount: notches (multiplier, fractional ok: "2.5") or "<n>px" = raw wheel
+///   delta units (120 = one notch) for apps that honor pixel-precise wheel input.
+/// The total is capped at SCROLL_MAX_NOTCHES (smooth scrolling runs its whole length).
+/// Returns (horizontal, signed total delta, smooth).
+fn parse_scroll(spec: &str) -> Option<(bool, i32, bool)> {
+    let mut parts = spec.split_whitespace();
+    let (horizontal, sign) = match parts.next()?.to_lowercase().as_str() {
+        "up"    => (false, 1),   // WHEEL_DELTA = 120
+        "down"  => (false, -1),
+        "left"  => (true, -1),
+        "right" => (true, 1),
+        _ => return None,
+    };
+    let mut delta = 120;
+    let mut smooth = false;
+    for p in parts {
+        let p = p.to_lowercase();
+        if p == "smooth" {
+            smooth = true;
+        } else if let Some(px) = p.strip_suffix("px") {
+            delta = px.parse::<i32>().ok()?.clamp(1, SCROLL_MAX_NOTCHES * 120);
+        } else {
+            let notches = p.parse::<f64>().ok().filter(|n| n.is_finite())?;
+            delta = (notches.clamp(0.0, SCROLL_MAX_NOTCHES as f64) * 120.0).round().max(1.0) as i32;
+        }
+    }
+    Some((horizontal, sign * delta, smooth))
+}
+
+/// One wheel event at screen point (cx, cy).
+unsafe fn send_wheel(cx: i32, cy: i32, horizontal: bool, delta: i32) {
+    let screen_w = GetSystemMetrics(SM_CXVIRTUALSCREEN);
+    let screen_h = GetSystemMetrics(SM_CYVIRTUALSCREEN);
+    let screen_x = GetSystemMetrics(SM_XVIRTUALSCREEN);
+    let screen_y = GetSystemMetrics(SM_YVIRTUALSCREEN);
+    let abs_x = ((cx - screen_x) * 65535 / screen_w) as i32;
+    let abs_y = ((cy - screen_y) * 65535 / screen_h) as i32;
+    let vd_flags = MOUSEEVENTF_ABSOLUTE | MOUSEEVENTF_VIRTUALDESK | MOUSEEVENTF_MOVE;
+    let wheel = if horizontal { MOUSEEVENTF_HWHEEL } else { MOUSEEVENTF_WHEEL };
+    let input = [INPUT {
+        r#type: INPUT_MOUSE,
+        Anonymous: INPUT_0 {
+            mi: MOUSEINPUT {
+                dx: abs_x, dy: abs_y,
+                mouseData: delta as u32,
+                dwFlags: vd_flags | wheel,
+                time: 0, dwExtraInfo: 0,
+            },
+        },
+    }];
+    SendInput(&input, mem::size_of::<INPUT>() as i32);
+}
+
+/// Smooth scrolling: total delta split into SMOOTH_STEP-sized events, SMOOTH_DELAY_MS apart.
+const SMOOTH_STEP: i32 = 30;
+const SMOOTH_DELAY_MS: u64 = 10;
+
+/// Scroll the target window: "down", "down 3", "up 2.5 smooth", "down 40px"
+unsafe fn scroll_window(target_hwnd: HWND, spec: &str) -> InjectResult {
+    let Some((horizontal, delta, smooth)) = parse_scroll(spec) else {
+        log(&format!("scroll: bad spec '{}'", spec));
+        return Err(InjectError::InvalidArgument(format!("bad scroll spec '{}'", spec)));
+    };
+
+    // Get center of target window for scroll position
+    let mut rect = RECT::default();
+    let _ = GetWindowRect(target_hwnd, &mut rect);
+    let cx = rect.left + (rect.right - rect.left) / 2;
+    let cy = rect.top + (rect.bottom - rect.top) / 2;
+
+    if smooth {
+        let mut left = delta;
+        while left != 0 {
+            let step = left.signum() * left.abs().min(SMOOTH_STEP);
+            send_wheel(cx, cy, horizontal, step);
+            left -= step;
+            if left != 0 { std::thread::sleep(std::time::Duration::from_millis(SMOOTH_DELAY_MS)); }
+        }
+    } else {
+        send_wheel(cx, cy, horizontal, delta);
+    }
+    log(&format!("scroll: {} (delta={}, smooth={})", spec, delta, smooth));
+    Ok(())
+}
+
+// ── Window Arrangement (snap_left / snap_right / ...) ──
+// Like Win+Arrow: the target takes a half, quarter or all of the work area of the monitor
+// it is on (taskbar excluded). Overlay and saved rect move along, so do_sync sees no
+// difference between overlay and target and keeps the new position.
+
+/// Region of `work` for a snap action suffix ("left", "topright", "maximize", ...).
+fn snap_region(work: &RECT, region: &str) -> Option<RECT> {
+    let (l, t, r, b) = (work.left, work.top, work.right, work.bottom);
+    let (mx, my) = (l + (r - l) / 2, t + (b - t) / 2);
+    let (left, top, right, bottom) = match region {
+        "left"        => (l, t, mx, b),
+        "right"       => (mx, t, r, b),
+        "top"         => (l, t, r, my),
+        "bottom"      => (l, my, r, b),
+        "topleft"     => (l, t, mx, my),
+        "topright"    => (mx, t, r, my),
+        "bottomleft"  => (l, my, mx, b),
+        "bottomright" => (mx, my, r, b),
+        "maximize"    => (l, t, r, b),
+        _ => return None,
+    };
+    Some(RECT { left, top, right, bottom })
+}
+
+/// snap_<region>: move/resize the target within the work area of its monitor.
+unsafe fn snap_target(target_hwnd: HWND, region: &str) -> InjectResult {
+    let mut mi = MONITORINFO { cbSize: mem::size_of::<MONITORINFO>() as u32, ..Default::default() };
+    let monitor = MonitorFromWindow(target_hwnd, MONITOR_DEFAULTTONEAREST);
+    let work = if GetMonitorInfoW(monitor, &mut mi).as_bool() {
+        mi.rcWork
+    } else {
+        // Fallback: primary monitor's work area
+        let mut rc = RECT::default();
+        let _ = SystemParametersInfoW(SPI_GETWORKAREA, 0, Some(&mut rc as *mut RECT as *mut c_void),
+            SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0));
+        rc
+    };
+    let Some(rc) = snap_region(&work, region) else {
+        log(&format!("snap: unknown region '{}'", region));
+        return Err(InjectError::UnknownAction(format!("snap_{}", region)));
+    };
+    // A maximized/minimized window ignores SetWindowPos until restored
+    if IsZoomed(target_hwnd).as_bool() || IsIconic(target_hwnd).as_bool() {
+        let _ = ShowWindow(target_hwnd, SW_RESTORE);
+    }
+    let (w, h) = (rc.right - rc.left, rc.bottom - rc.top);
+    if let Err(e) = SetWindowPos(target_hwnd, HWND::default(), rc.left, rc.top, w, h, SWP_NOACTIVATE | SWP_NOZORDER) {
+        log(&format!("snap: SetWindowPos FAIL: {}", e));
+        return Err(InjectError::Failed(format!("SetWindowPos: {}", e)));
+    }
+    let me = HWND(DS_HWND.load(SeqCst) as *mut _);
+    if snapped() && !OBSERVE_ONLY.load(SeqCst) && !me.0.is_null() {
+        let _ = SetWindowPos(me, HWND::default(), rc.left, rc.top, w, h, SWP_NOACTIVATE | SWP_NOZORDER);
+    }
+    save(rc.left, rc.top, w, h);
+    *ACTION_DETAIL.lock().unwrap() = format!("{},{},{},{}", rc.left, rc.top, w, h);
+    log(&format!("snap: {} → ({},{}) {}x{}", region, rc.left, rc.top, w, h));
+    Ok(())
+}
+
+/// Drain limits per INJECT_TIMER tick (see batch_eligible).
+const INJECT_BATCH_MAX: usize = 10;
+const INJECT_BATCH_BUDGET_MS: u64 = 20;
+
+/// Default retry budget per action (config: max_retries). After that the row is dead (done=2);
+/// non-retryable errors (InjectError::retryable) go dead on the first failure.
+const MAX_RETRIES_DEFAULT: i64 = 5;
+
+// ── Action History (ds_profiles/<app>.actions.jsonl) ──
+// One JSON line per processed inject row — a clean audit trail of what was done to
+// the app, separate from the debug log. Texts longer than ACTION_HISTORY_TEXT_MAX
+// are cut and flagged "truncated". Rotates like metrics.csv. Input into password
+// fields and — per redact_for — Edit/Document content never lands here in plain text:
+// such lines carry "masked" and, like truncated ones, are not replayed.
+
+/// One history record. `result` = ok | retry | dead, `error` = InjectError::code().
+fn history_line(ts: u128, app: &str, action: &str, target: &str, text: &str, result: &str, error: Option<&str>) -> String {
+    let truncated = text.chars().count() > ACTION_HISTORY_TEXT_MAX;
+    let preview: String = text.chars().take(ACTION_HISTORY_TEXT_MAX).collect();
+    format!(
+        r#"{{"timestamp":{},"app":"{}","action":"{}","target":"{}","text":"{}"{},"result":"{}"{}}}"#,
+        ts, json_escape(app), json_escape(action), json_escape(target), json_escape(&preview),
+        if truncated { r#","truncated":true"# } else { "" },
+        result, error.map(|c| format!(r#","error":"{}""#, c)).unwrap_or_default())
+}
+
+/// Extra JSON field(s) appended to a history_line (`fields` without braces).
+fn history_extra(line: String, fields: &str) -> String {
+    match line.strip_suffix('}') {
+        Some(head) => format!("{},{}}}", head, fields),
+        None => line,
+    }
+}
+
+/// Target/text as they may be recorded for an action on an element of `role`: typed
+/// text into a password field → PASSWORD_VALUE, then redact_pair. Last = anything replaced.
+fn history_redact(redact: Redact, role: &str, is_password: bool, typed: bool, target: &str, text: &str) -> (String, String, bool) {
+    let value = if typed { mask_password(text.to_string(), is_password) } else { String::new() };
+    let (name, value) = redact_pair(redact, role, target.to_string(), value);
+    let recorded = if typed { value } else { text.to_string() };
+    let masked = name != target || recorded != text;
+    (name, recorded, masked)
+}
+
+/// Actions whose `text` is typed into an element (the rest carry keys, specs, selectors).
+fn is_typing_action(action: &str) -> bool {
+    matches!(action, "text" | "type" | "type_focused" | "submit")
+}
+
+/// Role and IsPassword of the element an action went to: the named target, else the
+/// focused element of the target process. ("", false) when it can't be resolved.
+unsafe fn history_target(target: HWND, target_name: &str) -> (String, bool) {
+    if target.0.is_null() { return Default::default(); }
+    let Ok(uia) = create_uia() else { return Default::default() };
+    let elem = if target_name.is_empty() {
+        let mut pid = 0u32;
+        GetWindowThreadProcessId(target, Some(&mut pid));
+        uia.GetFocusedElement().ok().filter(|f| f.CurrentProcessId().is_ok_and(|p| p as u32 == pid))
+    } else {
+        uia.ElementFromHandle(target).ok().and_then(|root| resolve_live(&uia, &root, target_name))
+    };
+    elem.map(|e| (
+        role_name(e.CurrentControlType().map(|ct| ct.0).unwrap_or(0)).to_string(),
+        e.CurrentIsPassword().is_ok_and(|b| b.as_bool()),
+    )).unwrap_or_default()
+}
+
+/// DB path → (path without ".db", app name). None = not snapped.
+fn app_of_db(db_path: &str) -> Option<(&str, &str)> {
+    let stem = db_path.strip_suffix(".db")?;
+    Some((stem, app_from_db_path(db_path)))
+}
+
+/// Append a processed action to the current app's history file.
+fn record_action(action: &str, target: &str, text: &str, result: &str, error: Option<&InjectError>) {
+    let db_path = get_db_path();
+    let Some((stem, app)) = app_of_db(&db_path) else { return };
+    let redact = redact_for(app);
+    let typed = is_typing_action(action);
+    // Resolve only when something could be masked: typed text, or target names under redact=names
+    let (role, is_password) = if typed || (redact.names && !target.is_empty()) {
+        unsafe { history_target(HWND(TARGET_HW.load(SeqCst) as *mut _), target) }
+    } else {
+        Default::default()
+    };
+    // Typed text went into some input even when it no longer resolves
+    let role = if role.is_empty() && typed { "Edit".to_string() } else { role };
+    let (target, text, masked) = history_redact(redact, &role, is_password, typed, target, text);
+    let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    let line = history_line(ts, app, action, &target, &text, result, error.map(InjectError::code));
+    let line = if masked { history_extra(line, r#""masked":true"#) } else { line };
+    append_rotating(&format!("{}.actions.jsonl", stem), ACTION_HISTORY_MAX_BYTES, None, &line);
+}
+
+/// Result of the last processed action → inject_result (status: ok | retry | dead;
+/// on failure "error" = InjectError::code(), "reason" = its message;
+/// "detail" = what the action actually did, e.g. the button dismiss pressed).
+fn write_inject_result(id: i64, action: &str, status: &str, retries: i64, error: Option<&InjectError>, reason: &str) {
+    let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    // Target resolved by fuzzy match → tell the agent which element was actually used
+    let fuzzy = match LAST_FUZZY.lock().unwrap().take() {
+        Some((name, score)) => format!(r#","fuzzy_match":"{}","score":{:.2}"#, json_escape(&name), score),
+        None => String::new(),
+    };
+    let error = error.map(|e| format!(r#","error":"{}""#, e.code())).unwrap_or_default();
+    let detail = match std::mem::take(&mut *ACTION_DETAIL.lock().unwrap()) {
+        d if d.is_empty() => String::new(),
+        d => format!(r#","detail":"{}""#, json_escape(&d)),
+    };
+    write_checked(&ds_file(INJECT_RESULT_FILE), format!(
+        r#"{{"id":{},"action":"{}","status":"{}","retries":{}{},"reason":"{}"{}{},"timestamp":{}}}"#,
+        id, json_escape(action), status, retries, error, json_escape(reason), fuzzy, detail, ts));
+}
+
+/// Process the action queue. Dispatches: text, type, type_focused, submit, clear, read_field, key, scancode, accel, click, invoke, cell, get_selected,
+/// form_state, capabilities, dump_element, scroll, search, read_region, element_at, hover, wait_idle, wait_progress, navigate, tabs, switch_tab, dismiss, confirm,
+/// snap_<left|right|top|bottom|topleft|topright|bottomleft|bottomright|maximize>.
+/// Only runs when target app has foreground focus — won't steal focus from user.
+fn process_injections() {
+    static BUSY: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+    // Re-entry guard: COM calls in click_element can pump messages,
+    // causing WM_TIMER to fire re-entrantly. This prevents double execution.
+    if BUSY.swap(true, SeqCst) { return; }
+
+    let db_path = get_db_path();
+    if db_path.is_empty() { BUSY.store(false, SeqCst); return; }
+
+    let conn = match Connection::open(&db_path) {
+        Ok(c) => c,
+        Err(_) => { BUSY.store(false, SeqCst); return; },
+    };
+    let _ = conn.execute_batch(&format!("PRAGMA journal_mode=WAL; PRAGMA busy_timeout={};", DB_BUSY_TIMEOUT_MS));
+
+    // Drain: FIFO, several actions per tick while they are batch-eligible
+    // and the time budget lasts. Everything else stays one-per-tick.
+    let t0 = Instant::now();
+    let mut prev: Option<(String, String)> = None; // (action, target) of the last batched action
+    for n in 0..INJECT_BATCH_MAX {
+        let row: Option<(i64, String, String, String, i64)> = conn
+            .query_row(
+                "SELECT id, COALESCE(action,'text'), text, COALESCE(target,''), COALESCE(not_before,0) \
+                 FROM inject WHERE done=0 ORDER BY id LIMIT 1",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?)),
+            )
+            .ok();
+        let Some((id, action, text, target_name, not_before)) = row else { break };
+        // Scheduled (timed replay): the head waits, and so does everything behind it (FIFO)
+        if not_before > SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64 { break; }
+        let eligible = batch_eligible(&action, &target_name, prev.as_ref());
+        if n > 0 && !eligible { break; }
+
+        // Claim action — if DB is locked, bail out and retry next timer tick (30ms)
+        if conn.execute("UPDATE inject SET done=1 WHERE id=?1", params![id]).is_err() { break; }
+        let ok = run_action(&conn, id, &action, &text, &target_name, true).is_ok();
+
+        if !ok || !eligible || t0.elapsed().as_millis() as u64 >= INJECT_BATCH_BUDGET_MS { break; }
+        prev = Some((action, target_name));
+    }
+    // Restore strategy: only once the burst is over, never between its steps
+    if FG_RESTORE.load(SeqCst) != 0 && !more_actions_due(&conn) {
+        unsafe { restore_foreground(); }
+    }
+    BUSY.store(false, SeqCst);
+}
+
+/// Batch-eligible actions (may share one INJECT_TIMER tick):
+///   key    — SendInput only, no COM, no message pumping
+///   scroll — SendInput only
+///   text   — only consecutive texts to the SAME target (one field, one UIA lookup pattern)
+/// Everything else (click, invoke, cell, type, submit, get_selected, ...) runs one-per-tick:
+/// click/invoke pump messages during COM calls (re-entrancy guard), type is long-running.
+fn batch_eligible(action: &str, target: &str, prev: Option<&(String, String)>) -> bool {
+    match action {
+        "key" | "scroll" => true,
+        "text" => prev.is_none_or(|(a, t)| a != "text" || t == target),
+        _ => false,
+    }
+}
+
+/// `type`: re-click the last known click position (auto-persist), then type_chars.
+unsafe fn type_refocused(target: HWND, text: &str) -> InjectResult {
+    // Auto-persist: ALWAYS re-click last known focus before typing
+    let lx = LAST_CLICK_X.load(SeqCst);
+    let ly = LAST_CLICK_Y.load(SeqCst);
+    if lx >= 0 && ly >= 0 {
+        bring_to_front(target)?;
+        std::thread::sleep(std::time::Duration::from_millis(30));
+        let vdf = MOUSEEVENTF_ABSOLUTE | MOUSEEVENTF_VIRTUALDESK | MOUSEEVENTF_MOVE;
+        let refocus = [
+            INPUT { r#type: INPUT_MOUSE, Anonymous: INPUT_0 { mi: MOUSEINPUT { dx: lx, dy: ly, mouseData: 0, dwFlags: vdf | MOUSEEVENTF_LEFTDOWN, time: 0, dwExtraInfo: 0 } } },
+            INPUT { r#type: INPUT_MOUSE, Anonymous: INPUT_0 { mi: MOUSEINPUT { dx: lx, dy: ly, mouseData: 0, dwFlags: vdf | MOUSEEVENTF_LEFTUP, time: 0, dwExtraInfo: 0 } } },
+        ];
+        SendInput(&refocus, mem::size_of::<INPUT>() as i32);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        log(&format!("type: re-focus @ abs({},{})", lx, ly));
+    }
+    type_chars(target, text)
+}
+
+/// `submit`: type + Enter in ONE handler call — no tick boundary in between, so no dump,
+/// queued action or focus-follow can slip between message and send (chat apps).
+/// Enter only goes out if the target still has the foreground after typing.
+unsafe fn submit_text(target: HWND, text: &str) -> InjectResult {
+    type_refocused(target, text)?;
+    if GetForegroundWindow() != target {
+        log("submit: focus lost after typing — Enter NOT sent");
+        return Err(InjectError::FocusLost);
+    }
+    send_vk(VK_RETURN);
+    log(&format!("submit: {} chars + Enter", text.chars().count()));
+    Ok(())
+}
+
+/// Type `text` char by char via SendInput into whatever has the focus (\t → Tab,
+/// \n → Enter). Aborts with FocusLost as soon as the target loses the foreground.
+unsafe fn type_chars(target: HWND, text: &str) -> InjectResult {
+    log(&format!("type: BEGIN SendInput {} chars", text.len()));
+    for (i, ch) in text.chars().enumerate() {
+        // Fail-safe: abort if target lost foreground focus
+        let fg = GetForegroundWindow();
+        if fg != target && !target.0.is_null() {
+            log(&format!("type: ABORT at char[{}] — focus lost (fg=0x{:X} target=0x{:X})", i, fg.0 as usize, target.0 as usize));
+            log("type: ABORTED — focus lost mid-typing");
+            return Err(InjectError::FocusLost);
+        }
+        match ch {
+            '\t' => send_vk(VK_TAB),
+            '\n' | '\r' => send_vk(VK_RETURN),
+            _ => inject_char(ch),
+        }
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+    log(&format!("type: ALL {} CHARS DONE", text.len()));
+    Ok(())
+}
+
+// ── Deferred Waits ──────────────────────────────────
+// Waiting actions (wait_idle, ...) must not sleep on the window thread — it also owns
+// the keyboard/mouse hooks, the timers and the overlay. A wait that is not satisfied yet
+// returns InjectError::Pending(ms): run_action puts the row back with not_before = now + ms
+// (FIFO — everything queued behind it waits too) and a later INJECT tick checks again.
+// Only the queue head can be waiting, so one WaitState slot carries it between checks.
+
+struct WaitState {
+    id: i64,          // inject row
+    started: Instant, // first check → timeouts
+    dump_gen: u64,    // DUMP_GEN already evaluated (see dump_settled)
+    seen: bool,       // wait_progress: the bar was there at least once
+    tooltips_before: Vec<(String, RECT)>, // hover: tooltips already open before the move
+    hovered: (String, &'static str),      // hover: name + role of the hovered element
+}
+
+static WAIT: Mutex<Option<WaitState>> = Mutex::new(None);
+
+/// Run `f` on the wait state of action `id` — created on its first check.
+fn with_wait<R>(id: i64, f: impl FnOnce(&mut WaitState) -> R) -> R {
+    let mut slot = WAIT.lock().unwrap();
+    if slot.as_ref().is_some_and(|w| w.id != id) { *slot = None; }
+    f(slot.get_or_insert_with(|| WaitState {
+        id, started: Instant::now(), dump_gen: DUMP_GEN.load(SeqCst), seen: false,
+        tooltips_before: Vec::new(), hovered: (String::new(), ""),
+    }))
+}
+
+/// Waits that read the dump: true once a primary dump has completed since the last
+/// evaluation — the elements table is whole, not mid-stream. Otherwise request one.
+fn dump_settled(w: &mut WaitState) -> bool {
+    let done = DUMP_GEN.load(SeqCst);
+    if done > w.dump_gen && !TREE_BUSY.load(SeqCst) {
+        w.dump_gen = done;
+        return true;
+    }
+    dump_tree();
+    false
+}
+
+/// Action `id` is through (ok or failed) — drop its wait state.
+fn finish_wait(id: i64) {
+    let mut slot = WAIT.lock().unwrap();
+    if slot.as_ref().is_some_and(|w| w.id == id) { *slot = None; }
+}
+
+/// Is action `id` a wait that is being re-checked (not its first run)?
+fn waiting_on(id: i64) -> bool {
+    WAIT.lock().unwrap().as_ref().is_some_and(|w| w.id == id)
+}
+
+/// Execute one claimed action and record the outcome (done / retry / dead).
+/// `queued` = claimed from the inject table by process_injections: a Pending wait goes
+/// back into the queue. Direct API calls (queued = false) re-run it themselves.
+fn run_action(conn: &Connection, id: i64, action: &str, text: &str, target_name: &str, queued: bool) -> InjectResult {
+    // Same normalization as the stored names (see normalize_text) — text itself stays verbatim
+    let target_name = &normalize_text(target_name, strip_format_enabled());
+    // Name aus einer gekürzten Snap-Zeile ("…") → voller Name aus der DB
+    let target_name = &match unclip_target(conn, target_name) {
+        Some(full) => {
+            log(&format!("action: clipped target expanded to '{}'", full));
+            full
+        }
+        None => target_name.clone(),
+    };
+    // A deferred wait being re-checked was already announced
+    if !waiting_on(id) {
+        log(&format!("action: id={} type='{}' target='{}' text='{}'",
+            id, action, target_name, clip_name(text, 50)));
+    }
+    *LAST_FUZZY.lock().unwrap() = None;
+    ACTION_DETAIL.lock().unwrap().clear();
+
+    // No auto-focus: actions work via UIA patterns and PostMessage,
+    // independent of which window the user has in foreground.
+
+    let result = unsafe {
+        let target = HWND(TARGET_HW.load(SeqCst) as *mut _);
+        if target.0.is_null() && action != "key" {
+            log("action: no target window");
+            Err(InjectError::NoTarget)
+        } else if !target.0.is_null() && uipi_blocked(target) {
+            // Input would be dropped silently — fail loudly instead of reporting success
+            log(&format!("action: id={} blocked by UIPI — target runs at higher integrity (admin). Run DirectShell as admin.", id));
+            Err(InjectError::UipiBlocked)
+        } else if secure_desktop_active() {
+            // UAC prompt / lock screen owns the input — nothing would arrive
+            log(&format!("action: id={} blocked — secure desktop active", id));
+            Err(InjectError::Failed("secure desktop active (UAC prompt or lock screen)".into()))
+        } else {
+            match action {
+                "text" => inject_text(target, text, target_name, strategy_override("text")),
+                "type" => type_refocused(target, text),
+                "submit" => submit_text(target, text),
+                "type_focused" => {
+                    // No re-click: the caret the agent (focus) or the user placed stays where it is
+                    bring_to_front(target).and_then(|_| {
+                        std::thread::sleep(std::time::Duration::from_millis(30));
+                        type_chars(target, text)
+                    })
+                },
+                "key"  => {
+                    // No re-click! Key actions must preserve selection state (ctrl+a → backspace)
+                    // Only bring window to foreground, don't click into it
+                    bring_to_front(target).and_then(|_| send_key_combo(text))
+                },
+                "click" => {
+                    log(&format!("click: BEGIN '{}'", target_name));
+                    let r = click_element(target, target_name, strategy_override("click"));
+                    log(&format!("click: END '{}' result={}", target_name, r.is_ok()));
+                    r
+                },
+                "scancode" => {
+                    // Like key, but hardware scan codes (DirectInput/RawInput apps)
+                    bring_to_front(target).and_then(|_| send_scancode_combo(text))
+                },
+                "accel" => press_accelerator(target, target_name),
+                "invoke" => invoke_element(target, target_name),
+                "cell" => cell_action(target, target_name, text),
+                "get_selected" => get_selected(target, target_name),
+                "form_state" => form_state(target_name),
+                "capabilities" => query_capabilities(target, target_name),
+                "dump_element" => dump_element(target, target_name),
+                "clear" => clear_field(target, target_name),
+                "read_field" => read_field(target, target_name),
+                "scroll" => scroll_window(target, text),
+                a if a.starts_with("snap_") => snap_target(target, &a["snap_".len()..]),
+                "wait_idle" => wait_idle(conn, id, text),
+                "navigate" => navigate(target, id, text),
+                "wait_progress" => wait_progress(id, target_name, text),
+                "tabs" => list_tabs(target),
+                "switch_tab" => switch_tab(target, if text.is_empty() { target_name } else { text }),
+                "search" => search_elements(if text.is_empty() { target_name } else { text }),
+                "read_region" => read_region(target, text),
+                "element_at" => element_at(target, text),
+                "hover" => hover_tooltip(target, id, target_name, text),
+                "dismiss" => dismiss_modal(target, id),
+                "confirm" => confirm_modal(target, id, text),
+                _ => { log(&format!("action: unknown type '{}'", action)); Err(InjectError::UnknownAction(action.to_string())) }
+            }
+        }
+    };
+
+    if let Err(InjectError::Pending(ms)) = result {
+        if queued {
+            let due = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64 + ms as i64;
+            let _ = conn.execute("UPDATE inject SET done=0, not_before=?2 WHERE id=?1", params![id, due]);
+        }
+        return result;
+    }
+    finish_wait(id);
+
+    let status = if let Err(e) = &result {
+        log(&format!("action: id={} error={} ({})", id, e.code(), e));
+        let max = config_get("max_retries").and_then(|v| v.parse::<i64>().ok()).unwrap_or(MAX_RETRIES_DEFAULT);
+        let retries: i64 = conn.query_row(
+            "UPDATE inject SET retries=COALESCE(retries,0)+1 WHERE id=?1 RETURNING retries",
+            params![id], |r| r.get(0),
+        ).unwrap_or(max);
+        if !e.retryable() {
+            // Permanent: another attempt would fail the same way (or repeat side effects)
+            let _ = conn.execute("UPDATE inject SET done=2 WHERE id=?1", params![id]);
+            log(&format!("action: DEAD id={} — {} is not retryable", id, e.code()));
+            write_inject_result(id, action, "dead", retries, Some(e), &e.to_string());
+            "dead"
+        } else if retries >= max {
+            // Dead letter: stays in the table for inspection, never blocks the queue again
+            let _ = conn.execute("UPDATE inject SET done=2 WHERE id=?1", params![id]);
+            log(&format!("action: DEAD id={} after {} attempts — giving up", id, retries));
+            write_inject_result(id, action, "dead", retries, Some(e),
+                &format!("{} — failed {} times (max_retries={})", e, retries, max));
+            "dead"
+        } else {
+            let _ = conn.execute("UPDATE inject SET done=0 WHERE id=?1", params![id]);
+            log(&format!("action: FAILED id={} — will retry ({}/{})", id, retries, max));
+            write_inject_result(id, action, "retry", retries, Some(e), &e.to_string());
+            "retry"
+        }
+    } else {
+        log(&format!("action: done id={}", id));
+        write_inject_result(id, action, "ok", 0, None, "");
+        "ok"
+    };
+    record_action(action, target_name, text, status, result.as_ref().err());
+    result
+}
+
+// ── Macro Recorder ──────────────────────────────────
+// Tray "Record Macro": the user's own keystrokes into the snapped app are collected and
+// written to ds_profiles/<app>.macro.jsonl in the action-history format (result
+// "recorded"), so `replay` plays them back like any agent session. Printable characters
+// merge into one `type_focused` step, everything else (Enter, arrows, Ctrl/Alt/Win combos)
+// becomes a `key` step. Pause/Break in the app — or the tray entry — stops; so do unsnap
+// and quiet mode.
+// Left clicks come from the mouse hook as `click` steps (see Mouse Hook below).
+
+const RECORD_STOP_VK: VIRTUAL_KEY = VK_PAUSE;
+const RECORD_TEXT_GAP_MS: u128 = 1000; // längere Tipp-Pause → neuer type_focused-Schritt (timed replay bleibt treu)
+
+struct MacroRecorder {
+    path: String,
+    app: String,
+    lines: Vec<String>, // in memory while recording — no file I/O inside the hook
+    text: String,       // pending `type_focused` step
+    text_ts: u128,
+    last_ts: u128,
+}
+
+impl MacroRecorder {
+    fn push(&mut self, ts: u128, action: &str, text: &str) {
+        self.lines.push(history_line(ts, &self.app, action, "", text, "recorded", None));
+    }
+
+    /// `click` step — the "x%,y%" point is the target, replayed through click_element.
+    fn push_click(&mut self, ts: u128, point: &str) {
+        self.lines.push(history_line(ts, &self.app, "click", point, "", "recorded", None));
+    }
+
+    /// Add the resolved element name to click step `i` — the point stays the target.
+    fn name_click(&mut self, i: usize, name: &str) {
+        if let Some(line) = self.lines.get_mut(i) {
+            *line = history_extra(std::mem::take(line), &format!(r#""element":"{}""#, json_escape(name)));
+        }
+    }
+
+    fn flush_text(&mut self) {
+        if self.text.is_empty() { return; }
+        let text = std::mem::take(&mut self.text);
+        self.push(self.text_ts, "type_focused", &text);
+    }
+}
+
+static RECORDER: Mutex<Option<MacroRecorder>> = Mutex::new(None);
+// Pause gedrückt → Stop wird im SNAP_REQ-Tick ausgeführt (Datei schreiben + Unhook nicht im Hook)
+static RECORD_STOP_PENDING: AtomicBool = AtomicBool::new(false);
+
+fn recording() -> bool {
+    RECORDER.lock().unwrap().is_some()
+}
+
+fn start_recording() {
+    let db_path = get_db_path();
+    let Some((stem, app)) = app_of_db(&db_path) else { return };
+    let path = format!("{}.macro.jsonl", stem);
+    RECORD_STOP_PENDING.store(false, SeqCst);
+    log(&format!("record: START → {} (stop: Pause)", path));
+    *RECORDER.lock().unwrap() = Some(MacroRecorder {
+        path, app: app.to_string(), lines: Vec::new(), text: String::new(), text_ts: 0, last_ts: 0,
+    });
+    unsafe { update_mouse_hook(); }
+}
+
+fn stop_recording() {
+    let Some(mut rec) = RECORDER.lock().unwrap().take() else { return };
+    // Steps of still unresolved clicks belong to this recording — keep their x%,y% target
+    for click in USER_CLICKS.lock().unwrap().iter_mut() {
+        click.step = None;
+    }
+    unsafe { update_mouse_hook(); }
+    rec.flush_text();
+    let mut content = rec.lines.join("\n");
+    content.push('\n');
+    write_checked(&rec.path, content);
+    log(&format!("record: STOP — {} step(s) → {}", rec.lines.len(), rec.path));
+}
+
+/// SNAP_REQ tick: finish a recording stopped by the hotkey inside the keyboard hook.
+fn check_record_stop() {
+    if RECORD_STOP_PENDING.swap(false, SeqCst) {
+        stop_recording();
+    }
+}
+
+/// Combo string for a `key` step: "ctrl+shift+s". None = key has no name.
+fn combo_name(ctrl: bool, alt: bool, shift: bool, win: bool, vk: VIRTUAL_KEY) -> Option<String> {
+    let mut parts: Vec<String> = [(ctrl, "ctrl"), (alt, "alt"), (shift, "shift"), (win, "win")]
+        .iter().filter(|(on, _)| *on).map(|(_, n)| n.to_string()).collect();
+    parts.push(vk_name(vk)?);
+    Some(parts.join("+"))
+}
+
+/// Keyboard hook, key down: record the keystroke. true = consumed (stop hotkey).
+unsafe fn record_key(kbd: &KBDLLHOOKSTRUCT) -> bool {
+    let vk = VIRTUAL_KEY(kbd.vkCode as u16);
+    if vk == RECORD_STOP_VK {
+        if !recording() { return false; }
+        RECORD_STOP_PENDING.store(true, SeqCst);
+        return true;
+    }
+    // Stop already requested — later keys are no longer part of the macro
+    if RECORD_STOP_PENDING.load(SeqCst) { return false; }
+    let mut guard = RECORDER.lock().unwrap();
+    let Some(rec) = guard.as_mut() else { return false };
+    if matches!(vk, VK_SHIFT | VK_CONTROL | VK_MENU | VK_LSHIFT | VK_RSHIFT | VK_LCONTROL | VK_RCONTROL
+        | VK_LMENU | VK_RMENU | VK_LWIN | VK_RWIN | VK_CAPITAL) {
+        return false;
+    }
+    let down = |k: VIRTUAL_KEY| GetAsyncKeyState(k.0 as i32) < 0;
+    let (ctrl, alt, shift) = (down(VK_CONTROL), down(VK_MENU), down(VK_SHIFT));
+    let win = down(VK_LWIN) || down(VK_RWIN);
+    let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+
+    // Plain character (Shift allowed) → part of the pending type step
+    if !ctrl && !alt && !win && !matches!(vk, VK_RETURN | VK_TAB | VK_BACK | VK_ESCAPE | VK_DELETE) {
+        let mut kb_state = [0u8; 256];
+        if shift { kb_state[0x10] = 0x80; }
+        if GetAsyncKeyState(VK_CAPITAL.0 as i32) & 1 != 0 { kb_state[0x14] = 0x01; }
+        let mut buf = [0u16; 4];
+        let n = ToUnicode(kbd.vkCode, kbd.scanCode, Some(&kb_state), &mut buf, 0x4);
+        if n > 0 {
+            if !rec.text.is_empty() && (ts - rec.last_ts > RECORD_TEXT_GAP_MS
+                || rec.text.chars().count() >= ACTION_HISTORY_TEXT_MAX) {
+                rec.flush_text();
+            }
+            if rec.text.is_empty() { rec.text_ts = ts; }
+            rec.text.extend(char::decode_utf16(buf[..n as usize].iter().copied()).flatten());
+            rec.last_ts = ts;
+            return false;
+        }
+    }
+
+    // Everything else → key step (unnamed keys are dropped)
+    rec.flush_text();
+    if let Some(combo) = combo_name(ctrl, alt, shift, win, vk) {
+        rec.push(ts, "key", &combo);
+    }
+    rec.last_ts = ts;
+    false
+}
+
+// ── Mouse Hook (config: mouse_hook=1) ───────────────
+// The only clicks DirectShell knew about were its own. With mouse_hook=1 (and always
+// while a macro is being recorded) a WH_MOUSE_LL hook notes the user's clicks into the
+// snapped target. The hook itself only queues — ElementFromPoint is cross-process COM
+// and would blow the hook timeout — the SNAP_REQ tick resolves the element and writes a
+// 'user_click' event (detail "x,y button"). Recorded left clicks become `click` steps:
+// target "x%,y%" of the window (what replay clicks); the resolved element name is added
+// as field "element" for the reader.
+
+struct UserClick {
+    x: i32,
+    y: i32,
+    button: &'static str,
+    step: Option<usize>, // index of the recorder's click step to name
+}
+
+static USER_CLICKS: Mutex<Vec<UserClick>> = Mutex::new(Vec::new());
+
+/// Point → "x%,y%" of `win` (the click target format of percent snap mode).
+fn percent_point(x: i32, y: i32, win: &RECT) -> String {
+    let (ww, wh) = ((win.right - win.left).max(1) as f64, (win.bottom - win.top).max(1) as f64);
+    format!("{:.1}%,{:.1}%", (x - win.left) as f64 * 100.0 / ww, (y - win.top) as f64 * 100.0 / wh)
+}
+
+/// Install or remove the mouse hook as needed (mouse_hook=1 or recording).
+unsafe fn update_mouse_hook() {
+    let wanted = config_flag("mouse_hook") || recording();
+    let hook = MOUSE_HOOK.load(SeqCst);
+    if wanted && hook == 0 {
+        let Ok(hinst) = GetModuleHandleW(None) else { return };
+        match SetWindowsHookExW(WH_MOUSE_LL, Some(mouse_hook_proc), hinst, 0) {
+            Ok(h) => {
+                MOUSE_HOOK.store(h.0 as isize, SeqCst);
+                log(&format!("Mouse hook installed: 0x{:X}", h.0 as usize));
+            }
+            Err(e) => log(&format!("Mouse hook install FAILED: {e}")),
+        }
+    } else if !wanted && hook != 0 {
+        MOUSE_HOOK.store(0, SeqCst);
+        let _ = UnhookWindowsHookEx(HHOOK(hook as *mut _));
+        log("Mouse hook removed");
+    }
+}
+
+/// Low-level mouse hook callback — times mouse_hook_inner for the watchdog (see check_hooks).
+unsafe extern "system" fn mouse_hook_proc(code: i32, wp: WPARAM, lp: LPARAM) -> LRESULT {
+    let start = Instant::now();
+    let result = mouse_hook_inner(code, wp, lp);
+    let ms = start.elapsed().as_millis();
+    if ms >= HOOK_SLOW_MS && !MOUSE_HOOK_SUSPECT.swap(true, SeqCst) {
+        log(&format!("Mouse hook callback took {} ms — may have been removed by Windows", ms));
+    }
+    result
+}
+
+/// Queue real (not injected) button-downs inside the snapped target.
+unsafe fn mouse_hook_inner(code: i32, wp: WPARAM, lp: LPARAM) -> LRESULT {
+    let hook = HHOOK(MOUSE_HOOK.load(SeqCst) as *mut _);
+    let button = match wp.0 as u32 {
+        WM_LBUTTONDOWN => "left",
+        WM_RBUTTONDOWN => "right",
+        WM_MBUTTONDOWN => "middle",
+        _ => return CallNextHookEx(hook, code, wp, lp),
+    };
+    let target = tgt();
+    if code < 0 || !snapped() || target.0.is_null() {
+        return CallNextHookEx(hook, code, wp, lp);
+    }
+    let ms = &*(lp.0 as *const MSLLHOOKSTRUCT);
+    // LLMHF_INJECTED = 0x1 — our own send_click
+    if ms.flags & 0x1 != 0 || GetAncestor(WindowFromPoint(ms.pt), GA_ROOT) != target {
+        return CallNextHookEx(hook, code, wp, lp);
+    }
+    let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    let mut step = None;
+    if button == "left" && !RECORD_STOP_PENDING.load(SeqCst) {
+        if let Some(rec) = RECORDER.lock().unwrap().as_mut() {
+            let mut win = RECT::default();
+            let _ = GetWindowRect(target, &mut win);
+            rec.flush_text();
+            rec.push_click(ts, &percent_point(ms.pt.x, ms.pt.y, &win));
+            rec.last_ts = ts;
+            step = Some(rec.lines.len() - 1);
+        }
+    }
+    USER_CLICKS.lock().unwrap().push(UserClick { x: ms.pt.x, y: ms.pt.y, button, step });
+    CallNextHookEx(hook, code, wp, lp)
+}
+
+/// SNAP_REQ tick: resolve queued user clicks → 'user_click' events (+ name the recorded steps).
+unsafe fn process_user_clicks() {
+    let clicks = std::mem::take(&mut *USER_CLICKS.lock().unwrap());
+    if clicks.is_empty() { return; }
+    let uia = create_uia().ok();
+    for c in clicks {
+        let elem = uia.as_ref().and_then(|u| u.ElementFromPoint(POINT { x: c.x, y: c.y }).ok());
+        let name = elem.as_ref().and_then(|e| e.CurrentName().ok())
+            .map(|n| normalize_text(&n.to_string(), strip_format_enabled())).unwrap_or_default();
+        let role = elem.as_ref().and_then(|e| e.CurrentControlType().ok())
+            .map(|ct| role_name(ct.0)).unwrap_or("");
+        write_event("user_click", &name, role, &format!("{},{} {}", c.x, c.y, c.button), "");
+        let (Some(i), false) = (c.step, name.is_empty()) else { continue };
+        if let Some(rec) = RECORDER.lock().unwrap().as_mut() {
+            let (name, _) = redact_pair(redact_for(&rec.app), role, name, String::new());
+            rec.name_click(i, &name);
+        }
+    }
+}
+
+// ── Keyboard Hook State (status.json) ───────────────
+// Whether kb_hook_proc rewrites typing right now is a decision, not just "installed":
+// only while snapped, not quiet and the target has the foreground. hook_state() is that
+// decision — the hook itself uses it — and every change lands in status.json and as a
+// green dot in the overlay's title bar, so "why is my typing transformed?" has an answer.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum HookState { NotInstalled, NotSnapped, Quiet, NotFocused, Intercepting }
+
+impl HookState {
+    fn as_str(self) -> &'static str {
+        match self {
+            HookState::NotInstalled => "not_installed",
+            HookState::NotSnapped   => "not_snapped",
+            HookState::Quiet        => "quiet",
+            HookState::NotFocused   => "target_not_focused",
+            HookState::Intercepting => "intercepting",
+        }
+    }
+}
+
+/// The hook's per-keystroke gate, minus the per-key checks (injected, Ctrl/Alt, non-printable).
+unsafe fn hook_state() -> HookState {
+    if KB_HOOK.load(SeqCst) == 0 { return HookState::NotInstalled; }
+    if !snapped() { return HookState::NotSnapped; }
+    if QUIET.load(SeqCst) { return HookState::Quiet; }
+    let target = tgt();
+    if target.0.is_null() { return HookState::NotSnapped; }
+    let fg = GetForegroundWindow();
+    if fg != target && GetAncestor(fg, GA_ROOT) != target { return HookState::NotFocused; }
+    HookState::Intercepting
+}
+
+/// Timer tick: write status.json and repaint the overlay when the hook state changed.
+/// Also carries the event-handler teardown state (a re-snap waiting for the old one).
+unsafe fn update_hook_status() {
+    let state = hook_state();
+    let teardown = TEARDOWNS.load(SeqCst) > 0;
+    let deferred = DEFERRED_REGISTER.load(SeqCst) != 0;
+    let events = teardown as i32 | (deferred as i32) << 1;
+    let hook_same = HOOK_STATE_SHOWN.swap(state as i32, SeqCst) == state as i32;
+    if EVENTS_STATE_SHOWN.swap(events, SeqCst) == events && hook_same { return; }
+    let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    write_checked(&ds_file(STATUS_FILE), format!(
+        r#"{{"timestamp":{},"hook":{{"installed":{},"intercepting":{},"state":"{}"}},"events":{{"teardown_pending":{},"teardown_ms":{},"registration_deferred":{}}}}}"#,
+        ts, state != HookState::NotInstalled, state == HookState::Intercepting, state.as_str(),
+        teardown, teardown_age_ms(), deferred));
+    if hook_same { return; }
+    let me = HWND(DS_HWND.load(SeqCst) as *mut _);
+    if !me.0.is_null() { let _ = InvalidateRect(me, None, FALSE); }
+}
+
+// ── Keyboard Hook (Input Proxy) ─────────────────────
+
+/// Keys the hook never intercepts (config hook_passthrough, whitespace-separated) — escape
+/// hatch for apps whose own IME trigger or shortcut the rewrite breaks. A single character
+/// ("^", "ß") matches the typed character, "0x.."/decimal a VK code, anything else a key
+/// name as in `key` actions ("num5", "comma"); unknown names are ignored. Example: `hook_passthrough=^ 0xDC comma`
+#[derive(Debug, Default, PartialEq)]
+struct Passthrough {
+    vks: Vec<u16>,
+    chars: Vec<char>,
+}
+
+fn parse_passthrough(spec: &str) -> Passthrough {
+    let mut pass = Passthrough::default();
+    for tok in spec.split_whitespace() {
+        let mut chars = tok.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            pass.chars.push(c);
+        } else if let Some(hex) = tok.strip_prefix("0x").or_else(|| tok.strip_prefix("0X")) {
+            pass.vks.extend(u16::from_str_radix(hex, 16).ok());
+        } else if let Ok(vk) = tok.parse::<u16>() {
+            pass.vks.push(vk);
+        } else if let Some(vk) = key_to_vk(tok) {
+            pass.vks.push(vk.0);
+        }
+    }
+    pass
+}
+
+// Geparste hook_passthrough — der Hook liest nur diesen Cache (kein Config-Zugriff pro Taste)
+static PASSTHROUGH: Mutex<Passthrough> = Mutex::new(Passthrough { vks: Vec::new(), chars: Vec::new() });
+
+/// ENUM tick (+ hook install): re-read hook_passthrough into the cache.
+fn refresh_passthrough() {
+    let pass = config_get("hook_passthrough").map(|v| parse_passthrough(&v)).unwrap_or_default();
+    *PASSTHROUGH.lock().unwrap() = pass;
+}
+
+/// Inject a single Unicode character into the focused window via SendInput
+unsafe fn inject_char(ch: char) {
+    let code = ch as u16;
+    let inputs = [
+        INPUT {
+            r#type: INPUT_KEYBOARD,
+            Anonymous: INPUT_0 {
+                ki: KEYBDINPUT {
+                    wVk: VIRTUAL_KEY(0),
+                    wScan: code,
+                    dwFlags: KEYEVENTF_UNICODE,
+                    time: 0,
+                    dwExtraInfo: 0,
+                },
+            },
+        },
+        INPUT {
+            r#type: INPUT_KEYBOARD,
+            Anonymous: INPUT_0 {
+                ki: KEYBDINPUT {
+                    wVk: VIRTUAL_KEY(0),
+                    wScan: code,
+                    dwFlags: KEYEVENTF_UNICODE | KEYEVENTF_KEYUP,
+                    time: 0,
+                    dwExtraInfo: 0,
+                },
+            },
+        },
+    ];
+    SendInput(&inputs, mem::size_of::<INPUT>() as i32);
+}
+
+// ── Hook Watchdog (keyboard + mouse) ────────────────
+// Windows removes a low-level hook WITHOUT notice once a callback exceeds
+// LowLevelHooksTimeout — the handle in KB_HOOK stays, interception is just gone.
+// There is no API to ask "is my hook still installed", so: every callback is timed,
+// a slow one marks the hook suspect, and the ENUM tick re-installs it (also if it is missing).
+// The callbacks run on the UI thread: while it is blocked (a long dump, a COM call) input
+// waits for them just the same, so a gap between ENUM ticks of HOOK_SLOW_MS beyond
+// ENUM_MS marks both hooks suspect too.
+const HOOK_SLOW_MS: u128 = 200; // unter dem Windows-Default (LowLevelHooksTimeout 300–1000 ms)
+
+static HOOK_TICK: Mutex<Option<Instant>> = Mutex::new(None); // letzter check_hooks-Lauf
+
+unsafe fn install_kb_hook() -> windows::core::Result<HHOOK> {
+    let hinst = GetModuleHandleW(None)?;
+    refresh_passthrough();
+    let hook = SetWindowsHookExW(WH_KEYBOARD_LL, Some(kb_hook_proc), hinst, 0)?;
+    KB_HOOK.store(hook.0 as isize, SeqCst);
+    Ok(hook)
+}
+
+/// ENUM tick: re-install the keyboard hook if it is missing or a callback ran too long,
+/// the mouse hook (when installed) if a callback ran too long — either after a UI-thread stall.
+unsafe fn check_hooks() {
+    let gap = HOOK_TICK.lock().unwrap().replace(Instant::now()).map_or(0, |t| t.elapsed().as_millis());
+    if gap >= ENUM_MS as u128 + HOOK_SLOW_MS {
+        log(&format!("UI thread stalled ~{} ms — hooks may have been removed by Windows", gap - ENUM_MS as u128));
+        HOOK_SUSPECT.store(true, SeqCst);
+        MOUSE_HOOK_SUSPECT.store(true, SeqCst);
+    }
+    if MOUSE_HOOK_SUSPECT.swap(false, SeqCst) {
+        let old = MOUSE_HOOK.swap(0, SeqCst);
+        if old != 0 {
+            let _ = UnhookWindowsHookEx(HHOOK(old as *mut _));
+            update_mouse_hook();
+            log(&format!("Mouse hook re-installed (was 0x{:X})", old));
+        }
+    }
+    let old = KB_HOOK.load(SeqCst);
+    let suspect = HOOK_SUSPECT.swap(false, SeqCst);
+    if old != 0 && !suspect { return; }
+    if old != 0 {
+        // Already removed by Windows → fails harmlessly
+        let _ = UnhookWindowsHookEx(HHOOK(old as *mut _));
+    }
+    match install_kb_hook() {
+        Ok(hook) => log(&format!("Keyboard hook re-installed: 0x{:X} (was 0x{:X}, {})",
+            hook.0 as usize, old, if old == 0 { "missing" } else { "callback exceeded timeout" })),
+        Err(e) => {
+            KB_HOOK.store(0, SeqCst);
+            log(&format!("Keyboard hook re-install FAILED: {e}"));
+        }
+    }
+}
+
+/// Low-level keyboard hook callback — times kb_hook_inner for the watchdog.
+unsafe extern "system" fn kb_hook_proc(code: i32, wp: WPARAM, lp: LPARAM) -> LRESULT {
+    let start = Instant::now();
+    let result = kb_hook_inner(code, wp, lp);
+    let ms = start.elapsed().as_millis();
+    if ms >= HOOK_SLOW_MS && !HOOK_SUSPECT.swap(true, SeqCst) {
+        log(&format!("Keyboard hook callback took {} ms — may have been removed by Windows", ms));
+    }
+    result
+}
+
+/// Intercepts keystrokes when snapped + target has focus.
+/// Blocks the original, transforms the character, injects the result.
+unsafe fn kb_hook_inner(code: i32, wp: WPARAM, lp: LPARAM) -> LRESULT {
+    let hook = HHOOK(KB_HOOK.load(SeqCst) as *mut _);
+
+    // Negative code = must pass through per contract
+    if code < 0 {
+        return CallNextHookEx(hook, code, wp, lp);
+    }
+
+    // Only intercept when snapped, not quiet and the target app has focus
+    if hook_state() != HookState::Intercepting {
+        return CallNextHookEx(hook, code, wp, lp);
+    }
+
+    let kbd = &*(lp.0 as *const KBDLLHOOKSTRUCT);
+
+    // Skip injected keys (our own output) — LLKHF_INJECTED = 0x10
+    if kbd.flags.0 & 0x10 != 0 {
+        return CallNextHookEx(hook, code, wp, lp);
+    }
+
+    // Macro recorder sees the user's real keystrokes (injected ones were skipped above)
+    if matches!(wp.0 as u32, WM_KEYDOWN | WM_SYSKEYDOWN) && record_key(kbd) {
+        return LRESULT(1); // stop hotkey never reaches the app
+    }
+
+    // Whitelisted keys: never touched (characters are checked after ToUnicode below)
+    if PASSTHROUGH.lock().unwrap().vks.contains(&(kbd.vkCode as u16)) {
+        return CallNextHookEx(hook, code, wp, lp);
+    }
+
+    // Preserve Ctrl/Alt shortcuts (copy, paste, undo, etc.)
+    if GetAsyncKeyState(VK_CONTROL.0 as i32) < 0 || GetAsyncKeyState(VK_MENU.0 as i32) < 0 {
+        return CallNextHookEx(hook, code, wp, lp);
+    }
+
+    let msg = wp.0 as u32;
+    let vk = kbd.vkCode;
+
+    // Non-character keys — ALWAYS pass through, no ToUnicode needed
+    let vk_key = VIRTUAL_KEY(vk as u16);
+    if matches!(vk_key,
+        VK_RETURN | VK_BACK | VK_TAB | VK_ESCAPE | VK_DELETE | VK_INSERT |
+        VK_HOME | VK_END | VK_PRIOR | VK_NEXT |
+        VK_UP | VK_DOWN | VK_LEFT | VK_RIGHT |
+        VK_F1 | VK_F2 | VK_F3 | VK_F4 | VK_F5 | VK_F6 |
+        VK_F7 | VK_F8 | VK_F9 | VK_F10 | VK_F11 | VK_F12
+    ) {
+        return CallNextHookEx(hook, code, wp, lp);
+    }
+
+    // Build keyboard state for ToUnicode
+    let mut kb_state = [0u8; 256];
+    if GetAsyncKeyState(VK_SHIFT.0 as i32) < 0 { kb_state[0x10] = 0x80; }
+    if GetAsyncKeyState(VK_LSHIFT.0 as i32) < 0 { kb_state[0xA0] = 0x80; }
+    if GetAsyncKeyState(VK_RSHIFT.0 as i32) < 0 { kb_state[0xA1] = 0x80; }
+    if GetAsyncKeyState(VK_CAPITAL.0 as i32) & 1 != 0 { kb_state[0x14] = 0x01; }
+
+    // Try converting virtual key → Unicode character
+    let mut buf = [0u16; 4];
+    // Flag 0x4 = do not modify keyboard state (preserve dead keys like ^ ´ `)
+    let n = ToUnicode(vk, kbd.scanCode, Some(&kb_state), &mut buf, 0x4);
+
+    // n <= 0 = dead key or no translation → pass through
+    if n <= 0 {
+        return CallNextHookEx(hook, code, wp, lp);
+    }
+    if char::decode_utf16(buf[..n as usize].iter().copied()).flatten().any(|c| PASSTHROUGH.lock().unwrap().chars.contains(&c)) {
+        return CallNextHookEx(hook, code, wp, lp);
+    }
+
+    // It's a printable character — intercept it
+    if msg == WM_KEYDOWN {
+        for i in 0..n as usize {
+            if let Some(ch) = char::from_u32(buf[i] as u32) {
+                inject_char(ch);
+            }
+        }
+    }
+    // Block both WM_KEYDOWN and WM_KEYUP for intercepted keys
+    LRESULT(1)
+}
+
+// ── Snap-Ziel finden ────────────────────────────────
+unsafe fn find_snap(me: HWND) -> Option<HWND> {
+    let mut rc = RECT::default();
+    let _ = GetWindowRect(me, &mut rc);
+    let pt = POINT { x: (rc.left + rc.right) / 2, y: (rc.top + rc.bottom) / 2 };
+    let _ = ShowWindow(me, SW_HIDE);
+    let hit = WindowFromPoint(pt);
+    let _ = ShowWindow(me, SW_SHOWNA);
+    if hit.0.is_null() { return None; }
+    let top = GetAncestor(hit, GA_ROOT);
+    if top.0.is_null() || top == me { return None; }
+    if !IsWindowVisible(top).as_bool() { return None; }
+    if is_shell(top) { return None; }
+    let mut trc = RECT::default();
+    let _ = GetWindowRect(top, &mut trc);
+    if snap_hit(&rc, &trc) { Some(top) } else { None }
+}
+
+// ── Snap / Unsnap ───────────────────────────────────
+unsafe fn do_snap(me: HWND, target: HWND) {
+    do_attach(me, target, false);
+}
+
+/// observe:<app> — attach for reading only: DB, dumps, events and .a11y files,
+/// but no owner change, no SetWindowPos, no overlay, no injection timer.
+unsafe fn do_observe(me: HWND, target: HWND) {
+    do_attach(me, target, true);
+}
+
+/// Window-independent part of a snap: app DB, accessibility activation, live
+/// event handlers. Shared by the overlay (do_attach) and the embedding API.
+unsafe fn attach_core(target: HWND) {
+    // Persistente App-DB: Fenstertitel → Dateiname
+    {
+        let mut buf = [0u16; 256];
+        let len = GetWindowTextW(target, &mut buf);
+        let mut title = String::from_utf16_lossy(&buf[..len as usize]);
+        // Kein Titel → accessible Name der Root (sonst landet alles in unknown.db)
+        if title.trim().is_empty() {
+            if let Some(name) = create_uia().ok().and_then(|uia| uia_root_name(&uia, target)) {
+                title = name;
+            }
+        }
+        let db_path = db_name_from_title(&title);
+        let _ = fs::create_dir_all(db_dir());
+        set_db_path(&db_path);
+        log(&format!("do_snap: app db = {}", db_path));
+    }
+
+    // MSAA-Probe: Chromium Accessibility Tree aktivieren
+    activate_accessibility(target);
+
+    // Live Event Handlers registrieren (Property/Structure/Automation)
+    register_event_handlers(target);
+    scope_at_hook(Some(target));
+}
+
+/// Counterpart of attach_core: handlers off, target state cleared. DB stays on disk.
+unsafe fn detach_core() {
+    // Event Handler deregistrieren (separate UIA Instanz)
+    unregister_event_handlers();
+    scope_at_hook(None);
+    // DB bleibt persistent! Nur Pfad leeren.
+    set_db_path("");
+    set_framework("");
+    set_url("");
+    write_active_status("");
+    IS_SNAPPED.store(false, SeqCst);
+    TARGET_HW.store(0, SeqCst);
+}
+
+unsafe fn do_attach(me: HWND, target: HWND, observe: bool) {
+    log(&format!("do_snap: me=0x{:X} target=0x{:X} observe={}", me.0 as usize, target.0 as usize, observe));
+
+    if observe {
+        OBSERVE_ONLY.store(true, SeqCst);
+        TARGET_HW.store(target.0 as isize, SeqCst);
+        IS_SNAPPED.store(true, SeqCst);
+        if IsWindowVisible(me).as_bool() { let _ = ShowWindow(me, SW_HIDE); }
+    } else {
+        let mut rc = RECT::default();
+        let _ = GetWindowRect(target, &mut rc);
+        let (x, y, w, h) = (rc.left, rc.top, rc.right - rc.left, rc.bottom - rc.top);
+        log(&format!("do_snap: target rect x={} y={} w={} h={}", x, y, w, h));
+        // Owner setzen: Windows hält owned windows IMMER über ihrem Owner
+        let _ = SetWindowLongPtrW(me, WINDOW_LONG_PTR_INDEX(-8), target.0 as isize);
+        // TOPMOST entfernen + positionieren
+        let _ = SetWindowPos(me, HWND_NOTOPMOST, x, y, w, h, SWP_NOACTIVATE);
+        TARGET_HW.store(target.0 as isize, SeqCst);
+        IS_SNAPPED.store(true, SeqCst);
+        save(x, y, w, h);
+
+        // UIA: TitleBar-Höhe + Button-Position auslesen
+        let info = probe_caption(target);
+        BTN_OFF_X.store(info.btn_offset, SeqCst);
+        DYN_TOP_H.store(info.bar_height, SeqCst);
+    }
+
+    attach_core(target);
+
+    let _ = KillTimer(me, ANIM_TIMER);
+    let _ = SetTimer(me, SYNC_TIMER, TIMER_MS, None); // observe: nur noch "Ziel weg?"-Check
+    reset_tree_backoff();
+    let _ = SetTimer(me, TREE_TIMER, TREE_MS, None);
+    if !observe {
+        let _ = SetTimer(me, INJECT_TIMER, INJECT_MS, None);
+    }
+    log("do_snap: first tree dump...");
+    dump_tree();
+    log("do_snap: COMPLETE");
+    let _ = InvalidateRect(me, None, TRUE);
+}
+
+unsafe fn do_unsnap(me: HWND) {
+    log("do_unsnap: START");
+    stop_recording();
+    let _ = KillTimer(me, SYNC_TIMER);
+    let _ = KillTimer(me, TREE_TIMER);
+    let _ = KillTimer(me, INJECT_TIMER);
+    detach_core();
+    DYN_TOP_H.store(DEFAULT_TOP_H, SeqCst);
+    // Observe hatte das Overlay versteckt → im Human-Mode wieder zeigen
+    if OBSERVE_ONLY.swap(false, SeqCst) && !AGENT_MODE.load(SeqCst) {
+        let _ = ShowWindow(me, SW_SHOWNA);
+    }
+    // Owner entfernen + TOPMOST wiederherstellen + Startgröße
+    let _ = SetWindowLongPtrW(me, WINDOW_LONG_PTR_INDEX(-8), 0);
+    let mut rc = RECT::default();
+    let _ = GetWindowRect(me, &mut rc);
+    let _ = SetWindowPos(me, HWND_TOPMOST, rc.left, rc.top, INIT_W, INIT_H, SWP_NOACTIVATE);
+    let _ = SetTimer(me, ANIM_TIMER, ANIM_MS, None);
+    log("do_unsnap: COMPLETE");
+    let _ = InvalidateRect(me, None, TRUE);
+}
+
+/// JSON-escape a string (handles backslash, quotes, and control characters)
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '"'  => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < '\x20' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// JSON array from pre-rendered items (each carrying its own indentation), one per line.
+/// Empty → `[]` — no blank line, no chance of a dangling comma. `indent` = closing bracket's.
+fn json_array(items: &[String], indent: &str) -> String {
+    if items.is_empty() {
+        "[]".to_string()
+    } else {
+        format!("[\n{}\n{}]", items.join(",\n"), indent)
+    }
+}
+
+/// Info about a visible top-level window
+struct WindowInfo {
+    hwnd: HWND,
+    raw: isize,
+    title: String,
+    app: String,
+    pid: u32,
+    elevated: bool,     // Target runs as admin
+    injectable: bool,   // SendInput/UIA can reach it (false = elevated target, DS unelevated)
+}
+
+/// Enumerate all visible top-level windows (excluding DS itself and shell windows)
+unsafe fn get_visible_windows() -> Vec<WindowInfo> {
+    let ds = HWND(DS_HWND.load(SeqCst) as *mut _);
+    let hwnds = collect_windows();
+    let mut result = Vec::new();
+    for &raw in &hwnds {
+        let hwnd = HWND(raw as *mut _);
+        if !IsWindowVisible(hwnd).as_bool() { continue; }
+        if !ds.0.is_null() && hwnd == ds { continue; }
+        if is_shell(hwnd) { continue; }
+        let mut buf = [0u16; 256];
+        let len = GetWindowTextW(hwnd, &mut buf);
+        if len == 0 { continue; }
+        let title = String::from_utf16_lossy(&buf[..len as usize]);
+        if title.trim().is_empty() { continue; }
+        let db_path = db_name_from_title(&title);
+        let app = app_from_db_path(&db_path).to_string();
+        let mut pid: u32 = 0;
+        GetWindowThreadProcessId(hwnd, Some(&mut pid));
+        let elevated = process_is_elevated(pid);
+        let injectable = !elevated || self_is_elevated();
+        result.push(WindowInfo { hwnd, raw, title, app, pid, elevated, injectable });
+    }
+    result
+}
+
+/// UIA Name of a window's root element (trimmed, None if empty).
+unsafe fn uia_root_name(uia: &IUIAutomation, hwnd: HWND) -> Option<String> {
+    let name = uia.ElementFromHandle(hwnd).ok()?.CurrentName().ok()?.to_string();
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Snap fallback when no title matches: compare the requested app against the root
+/// element's UIA Name. Some apps have an empty or generic title bar but a meaningful
+/// accessible name — empty-titled windows are only reachable this way.
+unsafe fn find_by_uia_name(requested: &str) -> Option<(HWND, bool)> {
+    let uia = create_uia().ok()?;
+    let ds = HWND(DS_HWND.load(SeqCst) as *mut _);
+    for raw in collect_windows() {
+        let hwnd = HWND(raw as *mut _);
+        if !IsWindowVisible(hwnd).as_bool() || hwnd == ds || is_shell(hwnd) { continue; }
+        let Some(name) = uia_root_name(&uia, hwnd) else { continue };
+        if app_name_from_title(&name) != requested { continue; }
+        let mut pid: u32 = 0;
+        GetWindowThreadProcessId(hwnd, Some(&mut pid));
+        log(&format!("snap_request: '{}' matched by UIA name '{}' (0x{:X})", requested, name, raw));
+        return Some((hwnd, !process_is_elevated(pid) || self_is_elevated()));
+    }
+    None
+}
+
+// ── Daemon Mode: Background Window Enumeration ──────
+unsafe extern "system" fn enum_windows_cb(hwnd: HWND, lparam: LPARAM) -> BOOL {
+    let vec = &mut *(lparam.0 as *mut Vec<isize>);
+    vec.push(hwnd.0 as isize);
+    TRUE
+}
+
+unsafe fn collect_windows() -> Vec<isize> {
+    let mut hwnds: Vec<isize> = Vec::new();
+    let _ = EnumWindows(Some(enum_windows_cb), LPARAM(&mut hwnds as *mut Vec<isize> as isize));
+    hwnds
+}
+
+/// TokenElevation of a process handle. false if the token can't be queried.
+unsafe fn token_is_elevated(process: HANDLE) -> bool {
+    let mut token = HANDLE::default();
+    if OpenProcessToken(process, TOKEN_QUERY, &mut token).is_err() { return false; }
+    let mut elev = TOKEN_ELEVATION::default();
+    let mut len = 0u32;
+    let ok = GetTokenInformation(
+        token, TokenElevation,
+        Some(&mut elev as *mut _ as *mut c_void),
+        mem::size_of::<TOKEN_ELEVATION>() as u32, &mut len,
+    );
+    let _ = CloseHandle(token);
+    ok.is_ok() && elev.TokenIsElevated != 0
+}
+
+/// Is the process behind `pid` running elevated (admin)?
+unsafe fn process_is_elevated(pid: u32) -> bool {
+    if pid == 0 { return false; }
+    let handle = match OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid) {
+        Ok(h) => h,
+        Err(_) => return false,
+    };
+    let elevated = token_is_elevated(handle);
+    let _ = CloseHandle(handle);
+    elevated
+}
+
+/// Is DirectShell itself elevated? Cached — can't change during the process lifetime.
+fn self_is_elevated() -> bool {
+    static SELF_ELEVATED: OnceLock<bool> = OnceLock::new();
+    *SELF_ELEVATED.get_or_init(|| unsafe { token_is_elevated(GetCurrentProcess()) })
+}
+
+/// Mandatory integrity level RID of a process token
+/// (0x1000 low, 0x2000 medium, 0x3000 high, 0x4000 system).
+unsafe fn token_integrity(process: HANDLE) -> Option<u32> {
+    let mut token = HANDLE::default();
+    OpenProcessToken(process, TOKEN_QUERY, &mut token).ok()?;
+    let mut len = 0u32;
+    let _ = GetTokenInformation(token, TokenIntegrityLevel, None, 0, &mut len);
+    // u64 buffer → pointer-aligned for TOKEN_MANDATORY_LABEL
+    let mut buf = vec![0u64; (len as usize).div_ceil(8).max(1)];
+    let ok = GetTokenInformation(
+        token, TokenIntegrityLevel,
+        Some(buf.as_mut_ptr() as *mut c_void), len, &mut len,
+    );
+    let _ = CloseHandle(token);
+    if ok.is_err() || (len as usize) < mem::size_of::<TOKEN_MANDATORY_LABEL>() { return None; }
+    let label = &*(buf.as_ptr() as *const TOKEN_MANDATORY_LABEL);
+    let count = *GetSidSubAuthorityCount(label.Label.Sid);
+    if count == 0 { return None; }
+    Some(*GetSidSubAuthority(label.Label.Sid, count as u32 - 1))
+}
+
+/// UIPI: Windows silently drops SendInput aimed at a window of higher integrity.
+/// Returns true if we KNOW the target is above us. Unknown → false (don't block blindly).
+unsafe fn uipi_blocked(target: HWND) -> bool {
+    static SELF_IL: OnceLock<Option<u32>> = OnceLock::new();
+    let Some(own) = *SELF_IL.get_or_init(|| token_integrity(GetCurrentProcess())) else { return false; };
+    let mut pid = 0u32;
+    GetWindowThreadProcessId(target, Some(&mut pid));
+    if pid == 0 { return false; }
+    let Ok(handle) = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid) else { return false; };
+    let target_il = token_integrity(handle);
+    let _ = CloseHandle(handle);
+    matches!(target_il, Some(il) if il > own)
+}
+
+unsafe fn get_exe_name(pid: u32) -> String {
+    if pid == 0 { return String::new(); }
+    let handle = match OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid) {
+        Ok(h) => h,
+        Err(_) => return String::new(),
+    };
+    let mut buf = [0u16; 260];
+    let mut len = buf.len() as u32;
+    let ok = QueryFullProcessImageNameW(
+        handle, PROCESS_NAME_FORMAT(0), PWSTR(buf.as_mut_ptr()), &mut len,
+    );
+    let _ = CloseHandle(handle);
+    if ok.is_ok() {
+        let path = String::from_utf16_lossy(&buf[..len as usize]);
+        path.rsplit('\\').next().unwrap_or("").to_string()
+    } else {
+        String::new()
+    }
+}
+
+/// Format version of ds_profiles/windows.json. Bump whenever a field changes.
+const WINDOWS_JSON_VERSION: u32 = 2;
+
+/// Write windows.json for AI agents.
+///
+/// Schema:
+/// ```text
+/// {
+///   "version":    <WINDOWS_JSON_VERSION>,
+///   "timestamp":  unix seconds,
+///   "windows": [
+///     {"title":str, "app":str, "exe":str, "hwnd":int, "elevated":bool, "injectable":bool,
+///      "popout":bool}
+///   ]
+/// }
+/// ```
+/// popout = another top-level window of the snapped target's process (snap_request
+/// `hwnd:<hwnd>` switches to it; config include_popouts=1 dumps it along).
+unsafe fn enum_windows_to_json() {
+    let windows = get_visible_windows();
+    let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let mut entries = Vec::new();
+
+    let target = tgt();
+    let mut target_pid: u32 = 0;
+    if snapped() && !target.0.is_null() {
+        GetWindowThreadProcessId(target, Some(&mut target_pid));
+    }
+    let is_popout = |w: &WindowInfo| target_pid != 0 && w.pid == target_pid && w.hwnd != target;
+
+    for w in &windows {
+        let exe = get_exe_name(w.pid);
+        entries.push(format!(
+            r#"    {{"title":"{}","app":"{}","exe":"{}","hwnd":{},"elevated":{},"injectable":{},"popout":{}}}"#,
+            json_escape(&w.title), json_escape(&w.app), json_escape(&exe), w.raw,
+            w.elevated, w.injectable, is_popout(w)
+        ));
+    }
+
+    write_checked(&ds_file(WINDOWS_FILE), windows_json(ts, &entries));
+    track_popouts(windows.iter().filter(|w| is_popout(w)).map(|w| (w.raw, w.title.clone())).collect());
+}
+
+/// Remember the target's popouts; opened/closed ones become 'popout' events
+/// (element_name = title, new_value = hwnd) so agents notice a secondary window.
+fn track_popouts(current: Vec<(isize, String)>) {
+    let mut known = POPOUTS.lock().unwrap();
+    if snapped() {
+        for (raw, title) in current.iter().filter(|(raw, _)| !known.iter().any(|(k, _)| k == raw)) {
+            log(&format!("popout: '{}' opened (0x{:X})", title, raw));
+            write_event("popout", title, "Window", "opened", &raw.to_string());
+        }
+        for (raw, title) in known.iter().filter(|(raw, _)| !current.iter().any(|(c, _)| c == raw)) {
+            log(&format!("popout: '{}' closed (0x{:X})", title, raw));
+            write_event("popout", title, "Window", "closed", &raw.to_string());
+        }
+    }
+    *known = current;
+}
+
+/// windows.json document around the rendered window entries.
+fn windows_json(ts: u64, entries: &[String]) -> String {
+    format!(
+        "{{\n  \"version\":{},\n  \"timestamp\":{},\n  \"windows\":{}\n}}",
+        WINDOWS_JSON_VERSION, ts, json_array(entries, "  ")
+    )
+}
+
+unsafe fn check_snap_request(me: HWND) {
+    let content = match fs::read_to_string(ds_file(SNAP_REQUEST_FILE)) {
+        Ok(c) => c,
+        Err(_) => return, // No request pending
+    };
+    let _ = fs::remove_file(ds_file(SNAP_REQUEST_FILE));
+    let requested = content.trim().to_lowercase();
+    if requested.is_empty() { return; }
+    let (requested, observe) = match requested.strip_prefix("observe:") {
+        Some(app) => (app.trim().to_string(), true),
+        None => (requested, false),
+    };
+    if let Some(app) = requested.strip_prefix("watch:") {
+        watch_request(app.trim(), true);
+        return;
+    }
+    if let Some(app) = requested.strip_prefix("unwatch:") {
+        watch_request(app.trim(), false);
+        return;
+    }
+    log(&format!("snap_request: looking for '{}'", requested));
+
+    let windows = get_visible_windows();
+    // hwnd:<n> = exactly this window (e.g. a popout from windows.json), else by app name
+    let found = match requested.strip_prefix("hwnd:") {
+        Some(raw) => raw.trim().parse::<isize>().ok().and_then(|raw| windows.iter().find(|w| w.raw == raw)),
+        None => windows.iter().find(|w| w.app == requested),
+    };
+    let found = found.map(|w| (w.hwnd, w.injectable))
+        .or_else(|| if requested.starts_with("hwnd:") { None } else { find_by_uia_name(&requested) });
+
+    match found {
+        Some((target, injectable)) => {
+            log(&format!("snap_request: found '{}' at 0x{:X}", requested, target.0 as usize));
+            // Already attached to this exact window in the same mode?
+            if snapped() && tgt() == target && OBSERVE_ONLY.load(SeqCst) == observe {
+                write_checked(&ds_file(SNAP_RESULT_FILE),
+                    format!(r#"{{"status":"ok","app":"{}"}}"#, requested));
+                return;
+            }
+            if snapped() { do_unsnap(me); }
+            DAEMON_SNAP.store(true, SeqCst);
+            if observe { do_observe(me, target); } else { do_snap(me, target); }
+            DAEMON_SNAP.store(false, SeqCst);
+
+            if observe {
+                // Read-only: no injection, so elevation does not matter
+                write_checked(&ds_file(SNAP_RESULT_FILE),
+                    format!(r#"{{"status":"ok","app":"{}","mode":"observe"}}"#, requested));
+            } else if injectable {
+                write_checked(&ds_file(SNAP_RESULT_FILE),
+                    format!(r#"{{"status":"ok","app":"{}"}}"#, requested));
+            } else {
+                // Snapped, but UIPI will swallow every click/keystroke — say so up front
+                log(&format!("snap_request: '{}' is elevated, DS is not — input will be blocked", requested));
+                write_checked(&ds_file(SNAP_RESULT_FILE), format!(
+                    r#"{{"status":"ok","app":"{}","elevated":true,"injectable":false,"reason":"Target runs as administrator but DirectShell does not. Windows (UIPI) blocks input into it. Restart DirectShell as administrator."}}"#,
+                    requested));
+            }
+        }
+        None => {
+            log(&format!("snap_request: '{}' NOT FOUND", requested));
+            write_checked(&ds_file(SNAP_RESULT_FILE),
+                format!(r#"{{"status":"error","reason":"No window matching '{}' found"}}"#, requested));
+        }
+    }
+}
+
+// ── Observe-only Targets (watch:<app> / unwatch:<app>) ──
+// Background targets next to the snapped one: own DB + .a11y files at OBSERVE_MS,
+// no overlay, no geometry, no injection. For side-by-side reading (copy A → B).
+
+unsafe fn watch_request(app: &str, add: bool) {
+    let result = if add {
+        match get_visible_windows().into_iter().find(|w| w.app == app) {
+            Some(w) => {
+                let raw = w.hwnd.0 as usize;
+                let db_path = db_name_from_title(&w.title);
+                let mut obs = OBSERVERS.lock().unwrap();
+                // One observer per DB — two windows with the same title would overwrite each other's dumps
+                if !obs.iter().any(|(_, db)| *db == db_path) {
+                    obs.push((raw, db_path));
+                    log(&format!("observe: + '{}' at 0x{:X}", app, raw));
+                }
+                format!(r#"{{"status":"ok","app":"{}","mode":"watch"}}"#, json_escape(app))
+            }
+            None => format!(r#"{{"status":"error","reason":"No window matching '{}' found"}}"#, json_escape(app)),
+        }
+    } else {
+        OBSERVERS.lock().unwrap().retain(|(_, db)| app_from_db_path(db) != app);
+        log(&format!("observe: - '{}'", app));
+        format!(r#"{{"status":"ok","app":"{}","mode":"unwatch"}}"#, json_escape(app))
+    };
+    write_checked(&ds_file(SNAP_RESULT_FILE), result);
+    write_active_status(&get_db_path());
+}
+
+/// OBSERVE_TIMER: dump all observe-only targets on one background thread.
+/// Closed windows drop out; the snapped target — and anything writing its DB — is skipped
+/// (TREE_TIMER owns it).
+fn dump_observers() {
+    if OBSERVERS.lock().unwrap().is_empty() { return; }
+    if OBSERVE_BUSY.swap(true, SeqCst) { return; }
+    std::thread::spawn(|| {
+        unsafe {
+            let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+            let list = OBSERVERS.lock().unwrap().clone();
+            let snapped_db = get_db_path();
+            let mut gone = false;
+            for (raw, db_path) in list {
+                let hwnd = HWND(raw as *mut _);
+                if !IsWindow(hwnd).as_bool() {
+                    log(&format!("observe: '{}' closed, dropping", app_from_db_path(&db_path)));
+                    OBSERVERS.lock().unwrap().retain(|(h, _)| *h != raw);
+                    gone = true;
+                    continue;
+                }
+                if raw as isize == TARGET_HW.load(SeqCst) || db_path == snapped_db { continue; }
+                dump_window(hwnd, &db_path, false);
+            }
+            if gone { write_active_status(&get_db_path()); }
+            CoUninitialize();
+        }
+        OBSERVE_BUSY.store(false, SeqCst);
+    });
+}
+
+// ── On-Demand Dump (AI-triggered) ───────────────────
+// Agent writes ds_profiles/dump_now → immediate dump_tree → dump_done marker.
+// If a dump is already running, the request stays on disk until the next poll.
+fn check_dump_request() {
+    if !std::path::Path::new(&ds_file(DUMP_NOW_FILE)).exists() { return; }
+    if TREE_BUSY.load(SeqCst) { return; }
+    let _ = fs::remove_file(ds_file(DUMP_NOW_FILE));
+    let _ = fs::remove_file(ds_file(DUMP_DONE_FILE));
+    if !snapped() || get_db_path().is_empty() {
+        write_checked(&ds_file(DUMP_DONE_FILE), r#"{"status":"error","reason":"not snapped"}"#);
+        return;
+    }
+    log("dump_now: on-demand dump requested");
+    DUMP_NOW_PENDING.store(true, SeqCst);
+    dump_tree();
+}
+
+// ── Self-Test (AI/User-triggered) ────────────────────
+// ds_profiles/selftest → battery of real subsystem checks → selftest_result.json.
+// Runs on its own thread (own COM init) so a hanging UIA call can't freeze the overlay.
+fn check_selftest_request() {
+    if !std::path::Path::new(&ds_file(SELFTEST_FILE)).exists() { return; }
+    let _ = fs::remove_file(ds_file(SELFTEST_FILE));
+    log("selftest: requested");
+    std::thread::spawn(|| unsafe { run_selftest() });
+}
+
+unsafe fn run_selftest() {
+    let mut checks: Vec<(&str, bool, String)> = Vec::new();
+
+    let hr = CoInitializeEx(None, COINIT_MULTITHREADED);
+    checks.push(("com_init", hr.is_ok(), format!("0x{:08X}", hr.0)));
+
+    match create_uia() {
+        Ok(uia) => {
+            checks.push(("uia_create", true, uia_class().into()));
+            let root = uia.GetRootElement();
+            checks.push(("uia_root", root.is_ok(), root.err().map(|e| e.to_string()).unwrap_or_default()));
+        }
+        Err(e) => checks.push(("uia_create", false, e.to_string())),
+    }
+
+    let mut on = BOOL(0);
+    let spi = SystemParametersInfoW(
+        SPI_GETSCREENREADER, 0, Some(&mut on as *mut BOOL as *mut c_void),
+        SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+    );
+    checks.push(("screen_reader_flag", spi.is_ok() && on.as_bool(),
+        if spi.is_err() { "query failed".into() } else if on.as_bool() { "on".into() } else { "off".into() }));
+
+    let hook = KB_HOOK.load(SeqCst);
+    checks.push(("keyboard_hook", hook != 0, format!("0x{:X}", hook)));
+
+    let writable = check_db_dir_writable();
+    checks.push(("profiles_writable", writable.is_ok(),
+        format!("{} {}", db_dir(), writable.err().unwrap_or_default()).trim().to_string()));
+
+    let windows = get_visible_windows();
+    checks.push(("enum_windows", !windows.is_empty(), format!("{} window(s)", windows.len())));
+
+    if hr.is_ok() { CoUninitialize(); }
+
+    let all_ok = checks.iter().all(|(_, ok, _)| *ok);
+    let entries: Vec<String> = checks.iter().map(|(name, ok, detail)| format!(
+        r#"    {{"name":"{}","ok":{},"detail":"{}"}}"#, name, ok, json_escape(detail))).collect();
+    let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    write_checked(&ds_file(SELFTEST_RESULT_FILE), format!(
+        "{{\"status\":\"{}\",\"timestamp\":{},\"checks\":{}}}",
+        if all_ok { "ok" } else { "fail" }, ts, json_array(&entries, "")));
+    log(&format!("selftest: {} ({} checks)", if all_ok { "OK" } else { "FAIL" }, checks.len()));
+}
+
+// ── Events Delta (AI-triggered) ─────────────────────
+// Agent writes a ms timestamp to ds_profiles/events_since → DS writes all
+// events with timestamp > that value to events_delta.json. Saves the agent
+// from re-reading and filtering the whole events table on every poll.
+fn check_events_since_request() {
+    let content = match fs::read_to_string(ds_file(EVENTS_SINCE_FILE)) {
+        Ok(c) => c,
+        Err(_) => return,
+    };
+    let _ = fs::remove_file(ds_file(EVENTS_SINCE_FILE));
+    let since: i64 = content.trim().parse().unwrap_or(0);
+    write_checked(&ds_file(EVENTS_DELTA_FILE), events_since_json(since));
+}
+
+/// Events of the snapped app newer than `since` (ms) as the events_delta.json document.
+fn events_since_json(since: i64) -> String {
+    let db_path = get_db_path();
+    let conn = match Connection::open(&db_path) {
+        Ok(c) if !db_path.is_empty() => c,
+        _ => return r#"{"status":"error","reason":"not snapped"}"#.to_string(),
+    };
+    let _ = conn.execute_batch(&format!("PRAGMA busy_timeout={};", DB_BUSY_TIMEOUT_MS));
+
+    let mut entries = Vec::new();
+    let mut latest = since;
+    if let Ok(mut stmt) = conn.prepare(
+        "SELECT id, timestamp, event_type, COALESCE(element_name,''), COALESCE(element_role,''), \
+         COALESCE(detail,''), COALESCE(new_value,''), COALESCE(repeats,1) \
+         FROM events WHERE timestamp > ?1 ORDER BY id",
+    ) {
+        let rows = stmt.query_map(params![since], |r| Ok((
+            r.get::<_, i64>(0)?, r.get::<_, i64>(1)?, r.get::<_, String>(2)?,
+            r.get::<_, String>(3)?, r.get::<_, String>(4)?,
+            r.get::<_, String>(5)?, r.get::<_, String>(6)?, r.get::<_, i64>(7)?,
+        )));
+        if let Ok(rows) = rows {
+            for (id, ts, etype, name, role, detail, val, repeats) in rows.flatten() {
+                latest = latest.max(ts);
+                entries.push(format!(
+                    r#"    {{"id":{},"timestamp":{},"event_type":"{}","element_name":"{}","element_role":"{}","detail":"{}","new_value":"{}","repeats":{}}}"#,
+                    id, ts, json_escape(&etype), json_escape(&name), json_escape(&role),
+                    json_escape(&detail), json_escape(&val), repeats));
+            }
+        }
+    }
+
+    format!(
+        "{{\n  \"since\":{},\n  \"latest\":{},\n  \"events\":{}\n}}",
+        since, latest, json_array(&entries, "  "))
+}
+
+// ── Action Replay (AI/User-triggered) ────────────────
+// ds_profiles/replay = "[timed:]<file>" → the file's successful actions are re-enqueued
+// into inject, in order. <file> = an <app>.actions.jsonl (relative → ds_profiles/);
+// empty = the snapped app's own history. "timed:" keeps the recorded gaps (not_before).
+// Every line must belong to the snapped app — a macro never runs against another one.
+// Lines are parsed with SQLite's json_extract, no JSON dependency needed.
+
+#[derive(Debug, PartialEq)]
+struct ReplayStep {
+    offset_ms: i64, // since the first replayed action
+    action: String,
+    target: String,
+    text: String,
+}
+
+/// Replayable steps of a history file + number of skipped lines (failed attempts,
+/// truncated texts, garbage). Err = the file belongs to another app.
+fn parse_history(conn: &Connection, content: &str, app: &str) -> std::result::Result<(Vec<ReplayStep>, usize), String> {
+    let mut steps = Vec::new();
+    let mut skipped = 0;
+    let mut first_ts: Option<i64> = None;
+    for line in content.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let row = conn.query_row(
+            "SELECT json_extract(?1,'$.timestamp'), COALESCE(json_extract(?1,'$.app'),''), \
+                    COALESCE(json_extract(?1,'$.action'),''), COALESCE(json_extract(?1,'$.target'),''), \
+                    COALESCE(json_extract(?1,'$.text'),''), COALESCE(json_extract(?1,'$.result'),''), \
+                    COALESCE(json_extract(?1,'$.truncated'),0) + COALESCE(json_extract(?1,'$.masked'),0) \
+             WHERE json_valid(?1)",
+            params![line],
+            |r| Ok((r.get::<_, Option<i64>>(0)?, r.get::<_, String>(1)?, r.get::<_, String>(2)?,
+                    r.get::<_, String>(3)?, r.get::<_, String>(4)?, r.get::<_, String>(5)?, r.get::<_, i64>(6)?)),
+        );
+        let Ok((ts, line_app, action, target, text, result, incomplete)) = row else { skipped += 1; continue };
+        if !line_app.is_empty() && line_app != app {
+            return Err(format!("history is for '{}', snapped to '{}'", line_app, app));
+        }
+        if action.is_empty() || incomplete != 0 || !matches!(result.as_str(), "ok" | "recorded") {
+            skipped += 1;
+            continue;
+        }
+        let ts = ts.unwrap_or(0);
+        let first = *first_ts.get_or_insert(ts);
+        steps.push(ReplayStep { offset_ms: (ts - first).max(0), action, target, text });
+    }
+    Ok((steps, skipped))
+}
+
+fn check_replay_request() {
+    let content = match fs::read_to_string(ds_file(REPLAY_FILE)) {
+        Ok(c) => c,
+        Err(_) => return,
+    };
+    let _ = fs::remove_file(ds_file(REPLAY_FILE));
+    let fail = |reason: &str| {
+        log(&format!("replay: {}", reason));
+        write_checked(&ds_file(REPLAY_RESULT_FILE),
+            format!(r#"{{"status":"error","reason":"{}"}}"#, json_escape(reason)));
+    };
+
+    let db_path = get_db_path();
+    let Some((stem, app)) = app_of_db(&db_path) else { return fail("not snapped") };
+    let request = content.trim();
+    let (file, timed) = match request.strip_prefix("timed:") {
+        Some(f) => (f.trim(), true),
+        None => (request, false),
+    };
+    let path = if file.is_empty() {
+        format!("{}.actions.jsonl", stem)
+    } else if std::path::Path::new(file).is_absolute() {
+        file.to_string()
+    } else {
+        ds_file(file)
+    };
+    let Ok(history) = fs::read_to_string(&path) else { return fail(&format!("cannot read '{}'", path)) };
+
+    let conn = match Connection::open(&db_path) {
+        Ok(c) => c,
+        Err(e) => return fail(&e.to_string()),
+    };
+    let _ = conn.execute_batch(&format!("PRAGMA busy_timeout={};", DB_BUSY_TIMEOUT_MS));
+    let (steps, skipped) = match parse_history(&conn, &history, app) {
+        Ok(parsed) => parsed,
+        Err(reason) => return fail(&reason),
+    };
+
+    // One transaction: the queue never sees half a macro
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64;
+    let enqueue = || -> rusqlite::Result<()> {
+        let tx = conn.unchecked_transaction()?;
+        for s in &steps {
+            tx.execute(
+                "INSERT INTO inject(action, text, target, done, not_before) VALUES(?1, ?2, ?3, 0, ?4)",
+                params![s.action, s.text, s.target, if timed { now + s.offset_ms } else { 0 }],
+            )?;
+        }
+        tx.commit()
+    };
+    if let Err(e) = enqueue() { return fail(&e.to_string()); }
+
+    log(&format!("replay: {} action(s) from '{}' enqueued ({} skipped, timed={})", steps.len(), path, skipped, timed));
+    write_checked(&ds_file(REPLAY_RESULT_FILE), format!(
+        r#"{{"status":"ok","app":"{}","file":"{}","enqueued":{},"skipped":{},"timed":{}}}"#,
+        json_escape(app), json_escape(&path), steps.len(), skipped, timed));
+}
+
+// ── Web Dashboard (config: dashboard=<port>) ─────────
+// For people who don't script against ds_profiles: GET / serves one HTML page that
+// polls the same JSON DS writes anyway (windows.json, status.json, is_active, the
+// events delta, inject_result) and POSTs actions into the inject queue.
+// Bound to 127.0.0.1 only. Host and Origin must be local too — otherwise any web page
+// could drive the desktop via DNS rebinding or a cross-site POST. Read once at startup.
+const DASHBOARD_REQUEST_MAX: usize = 64 * 1024;      // Dashboard: max. Request-Größe (Header + Body)
+const DASHBOARD_HTML: &str = r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>DirectShell</title>
+<style>
+body{font:14px system-ui,sans-serif;margin:0;background:#111;color:#ddd}
+header{padding:10px 16px;background:#1b1b1b;border-bottom:1px solid #333}
+main{display:grid;grid-template-columns:1fr 1fr;gap:16px;padding:16px}
+section{background:#1b1b1b;border:1px solid #333;padding:10px;overflow:auto;max-height:40vh}
+h2{font-size:13px;margin:0 0 8px;color:#8ab4f8}
+pre,td{font:12px ui-monospace,monospace;white-space:pre-wrap;margin:0}
+input,textarea{width:100%;box-sizing:border-box;background:#000;color:#ddd;border:1px solid #444;margin-bottom:6px}
+.snapped{color:#7fd47f}
+</style></head><body>
+<header><b>DirectShell</b> &mdash; <span id="app">…</span> &middot; hook: <span id="hook">…</span></header>
+<main>
+<section><h2>Windows</h2><table id="windows"></table></section>
+<section><h2>Inject</h2>
+<input id="action" placeholder="action (text, click, key, ...)">
+<input id="target" placeholder="target (element name or selector)">
+<textarea id="text" rows="4" placeholder="text / key combo"></textarea>
+<button id="send">Send</button><pre id="result"></pre></section>
+<section style="grid-column:span 2"><h2>Events</h2><pre id="events"></pre></section>
+</main>
+<script>
+let since = Date.now() - 60000, lines = [];
+const $ = id => document.getElementById(id);
+const text = (el, s) => { el.textContent = s; return el; };
+async function get(path) { const r = await fetch(path, {cache: "no-store"}); return r.ok ? r.text() : ""; }
+async function poll() {
+  try {
+    const active = (await get("/is_active")).split("\n");
+    const app = active[0] && active[0] !== "none" ? active[0].split(/[\\/]/).pop().replace(/\.db$/, "") : "";
+    text($("app"), app ? "snapped: " + app : "not snapped").className = app ? "snapped" : "";
+    const status = JSON.parse(await get("/status.json") || "{}");
+    text($("hook"), status.hook ? status.hook.state : "?");
+    const win = JSON.parse(await get("/windows.json") || "{}");
+    $("windows").replaceChildren(...(win.windows || []).map(w => {
+      const tr = document.createElement("tr");
+      for (const v of [w.app, w.title, w.popout ? "popout" : ""]) tr.appendChild(text(document.createElement("td"), v));
+      if (w.app === app) tr.className = "snapped";
+      return tr;
+    }));
+    const ev = JSON.parse(await get("/events?since=" + since) || "{}");
+    for (const e of ev.events || []) lines.push(new Date(e.timestamp).toLocaleTimeString() + "  " + e.event_type +
+      "  " + e.element_role + " '" + e.element_name + "'  " + e.detail + (e.new_value ? " = " + e.new_value : ""));
+    if (ev.latest) since = ev.latest;
+    lines = lines.slice(-200);
+    text($("events"), lines.slice().reverse().join("\n"));
+    text($("result"), await get("/inject_result"));
+  } catch (e) { text($("app"), "DirectShell not reachable"); }
+}
+$("send").onclick = async () => {
+  const body = JSON.stringify({action: $("action").value, target: $("target").value, text: $("text").value});
+  const r = await fetch("/inject", {method: "POST", headers: {"Content-Type": "application/json"}, body});
+  text($("result"), await r.text());
+};
+poll(); setInterval(poll, 1000);
+</script></body></html>
+"#;
+
+#[derive(Debug, Default, PartialEq)]
+struct HttpRequest {
+    method: String,
+    path: String,
+    host: String,
+    origin: Option<String>,
+    body: String,
+}
+
+/// Parse a complete HTTP/1.x request. None = incomplete (read more) or not HTTP.
+fn parse_http_request(raw: &[u8]) -> Option<HttpRequest> {
+    let end = raw.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&raw[..end]).ok()?;
+    let mut lines = head.split("\r\n");
+    let mut first = lines.next()?.split(' ');
+    let mut req = HttpRequest {
+        method: first.next()?.to_string(),
+        path: first.next()?.to_string(),
+        ..Default::default()
+    };
+    let mut len = 0;
+    for (k, v) in lines.filter_map(|l| l.split_once(':')) {
+        let v = v.trim();
+        match k.trim().to_ascii_lowercase().as_str() {
+            "host" => req.host = v.to_string(),
+            "origin" => req.origin = Some(v.to_string()),
+            "content-length" => len = v.parse().ok()?,
+            _ => {}
+        }
+    }
+    req.body = String::from_utf8_lossy(raw.get(end + 4..end + 4 + len)?).into_owned();
+    Some(req)
+}
+
+/// Host (and Origin, if the browser sent one) must name this very listener.
+fn dashboard_allowed(req: &HttpRequest, port: u16) -> bool {
+    let local = [format!("127.0.0.1:{}", port), format!("localhost:{}", port)];
+    local.contains(&req.host)
+        && req.origin.as_ref().is_none_or(|o| local.iter().any(|h| *o == format!("http://{}", h)))
+}
+
+fn start_dashboard() {
+    let Some(port) = config_get("dashboard").and_then(|v| v.parse::<u16>().ok()).filter(|p| *p != 0) else { return };
+    let listener = match std::net::TcpListener::bind(("127.0.0.1", port)) {
+        Ok(l) => l,
+        Err(e) => { log(&format!("dashboard: cannot bind 127.0.0.1:{}: {}", port, e)); return; }
+    };
+    log(&format!("dashboard: http://127.0.0.1:{}/", port));
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = stream.set_read_timeout(Some(std::time::Duration::from_secs(2)));
+            serve_dashboard(stream, port);
+        }
+    });
+}
+
+fn serve_dashboard(mut stream: std::net::TcpStream, port: u16) {
+    use std::io::{Read, Write};
+    let mut raw = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let req = loop {
+        match stream.read(&mut chunk) {
+            Ok(n) if n > 0 && raw.len() + n <= DASHBOARD_REQUEST_MAX => raw.extend_from_slice(&chunk[..n]),
+            _ => return,
+        }
+        if let Some(req) = parse_http_request(&raw) { break req; }
+    };
+    let file = |name: &str, ctype: &'static str| match fs::read_to_string(ds_file(name)) {
+        Ok(body) => ("200 OK", ctype, body),
+        Err(_) => ("404 Not Found", "text/plain", format!("{} not written yet", name)),
+    };
+    let (status, ctype, body) = if !dashboard_allowed(&req, port) {
+        log(&format!("dashboard: rejected {} {} (host '{}', origin {:?})", req.method, req.path, req.host, req.origin));
+        ("403 Forbidden", "text/plain", "forbidden".to_string())
+    } else {
+        let (path, query) = req.path.split_once('?').unwrap_or((req.path.as_str(), ""));
+        match (req.method.as_str(), path) {
+            ("GET", "/") => ("200 OK", "text/html; charset=utf-8", DASHBOARD_HTML.to_string()),
+            ("GET", "/windows.json") => file(WINDOWS_FILE, "application/json"),
+            ("GET", "/status.json") => file(STATUS_FILE, "application/json"),
+            ("GET", "/is_active") => file(ACTIVE_FILE, "text/plain; charset=utf-8"),
+            ("GET", "/inject_result") => file(INJECT_RESULT_FILE, "application/json"),
+            ("GET", "/events") => {
+                let since = query.split('&').find_map(|kv| kv.strip_prefix("since="))
+                    .and_then(|v| v.parse().ok()).unwrap_or(0);
+                ("200 OK", "application/json", events_since_json(since))
+            }
+            ("POST", "/inject") => dashboard_inject(&req.body),
+            _ => ("404 Not Found", "text/plain", "not found".to_string()),
+        }
+    };
+    let _ = write!(stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status, ctype, body.len(), body);
+}
+
+/// POST /inject {"action","target","text"} → row in the snapped app's inject queue.
+/// The outcome shows up in inject_result like for any other queued action.
+fn dashboard_inject(body: &str) -> (&'static str, &'static str, String) {
+    let error = |status, reason: &str| (status, "application/json",
+        format!(r#"{{"status":"error","reason":"{}"}}"#, json_escape(reason)));
+    let Some(conn) = open_lookup_db() else { return error("409 Conflict", "not snapped") };
+    let parsed = conn.query_row(
+        "SELECT COALESCE(json_extract(?1,'$.action'),''), COALESCE(json_extract(?1,'$.target'),''), \
+                COALESCE(json_extract(?1,'$.text'),'') WHERE json_valid(?1)",
+        params![body],
+        |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?, r.get::<_, String>(2)?)),
+    );
+    let (action, target, text) = match parsed {
+        Ok(p) if !p.0.trim().is_empty() => p,
+        _ => return error("400 Bad Request", "expected {\"action\",\"target\",\"text\"}"),
+    };
+    match conn.query_row(
+        "INSERT INTO inject(action, text, target, done) VALUES(?1, ?2, ?3, 0) RETURNING id",
+        params![action.trim(), text, target], |r| r.get::<_, i64>(0),
+    ) {
+        Ok(id) => {
+            log(&format!("dashboard: queued #{} {} '{}'", id, action.trim(), target));
+            ("200 OK", "application/json", format!(r#"{{"status":"queued","id":{}}}"#, id))
+        }
+        Err(e) => error("500 Internal Server Error", &e.to_string()),
+    }
+}
+
+// ── Overlay Mode Check ──────────────────────────────
+unsafe fn check_overlay_mode(me: HWND) {
+    let mode = fs::read_to_string(ds_file(OVERLAY_MODE_FILE)).unwrap_or_default();
+    let want_agent = mode.trim().eq_ignore_ascii_case("agent");
+    let was_agent = AGENT_MODE.load(SeqCst);
+    if want_agent != was_agent {
+        AGENT_MODE.store(want_agent, SeqCst);
+        if want_agent {
+            log("overlay_mode: switching to AGENT (hidden)");
+            if IsWindowVisible(me).as_bool() { let _ = ShowWindow(me, SW_HIDE); }
+        } else {
+            log("overlay_mode: switching to HUMAN (visible)");
+            if !IsWindowVisible(me).as_bool() && !OBSERVE_ONLY.load(SeqCst) { let _ = ShowWindow(me, SW_SHOWNA); }
+        }
+    }
+}
+
+// ── Quiet Mode ──────────────────────────────────────
+// Tray toggle or config quiet=1: DirectShell stays in the tray but fully disengages.
+// All timers except ENUM stop (no dumps, no action queue, no snap requests), UIA event
+// handlers are removed, the keyboard hook passes everything through, overlay hidden.
+// ENUM keeps running: windows.json stays fresh and it polls the config to resume.
+
+/// ENUM_TIMER tick: enter/leave quiet mode when tray flag or config changed.
+unsafe fn check_quiet(me: HWND) {
+    let want = QUIET_TRAY.load(SeqCst) || config_flag("quiet");
+    if want != QUIET.load(SeqCst) {
+        set_quiet(me, want);
+    }
+}
+
+unsafe fn set_quiet(me: HWND, quiet: bool) {
+    QUIET.store(quiet, SeqCst);
+    if quiet {
+        log("quiet: ON — automation suspended");
+        // The hook stops recording while quiet — save what was recorded so far
+        stop_recording();
+        for timer in [SYNC_TIMER, ANIM_TIMER, TREE_TIMER, INJECT_TIMER, SNAP_REQ_TIMER, OBSERVE_TIMER] {
+            let _ = KillTimer(me, timer);
+        }
+        if snapped() { unregister_event_handlers(); }
+        if IsWindowVisible(me).as_bool() { let _ = ShowWindow(me, SW_HIDE); }
+    } else {
+        log("quiet: OFF — automation resumed");
+        let _ = SetTimer(me, SNAP_REQ_TIMER, SNAP_REQ_MS, None);
+        let _ = SetTimer(me, OBSERVE_TIMER, OBSERVE_MS, None);
+        if snapped() {
+            let t = tgt();
+            register_event_handlers(t);
+            let _ = SetTimer(me, SYNC_TIMER, TIMER_MS, None);
+            reset_tree_backoff();
+            let _ = SetTimer(me, TREE_TIMER, TREE_MS, None);
+            if !OBSERVE_ONLY.load(SeqCst) {
+                let _ = SetTimer(me, INJECT_TIMER, INJECT_MS, None);
+            }
+            dump_tree();
+        } else {
+            let _ = SetTimer(me, ANIM_TIMER, ANIM_MS, None);
+        }
+        // do_sync brings the overlay back while snapped; unsnapped it has to be shown here
+        if !snapped() && !AGENT_MODE.load(SeqCst) { let _ = ShowWindow(me, SW_SHOWNA); }
+    }
+    write_active_status(&get_db_path());
+}
+
+// ── Position Sync (60fps) ───────────────────────────
+unsafe fn do_sync(me: HWND) {
+    if !snapped() { return; }
+    let t = tgt();
+    if t.0.is_null() || !IsWindow(t).as_bool() { log("do_sync: target gone, unsnapping"); do_unsnap(me); return; }
+    // Observe-only: no geometry takeover, overlay stays hidden
+    if OBSERVE_ONLY.load(SeqCst) { return; }
+    // Agent mode: overlay always hidden, but still track position for coordinate math
+    if AGENT_MODE.load(SeqCst) {
+        if IsWindowVisible(me).as_bool() { let _ = ShowWindow(me, SW_HIDE); }
+    } else if IsIconic(t).as_bool() {
+        if IsWindowVisible(me).as_bool() { let _ = ShowWindow(me, SW_HIDE); }
+        return;
+    } else if !IsWindowVisible(me).as_bool() {
+        let _ = ShowWindow(me, SW_SHOWNA);
+    }
+    let mut trc = RECT::default();
+    let _ = GetWindowRect(t, &mut trc);
+    let mut prc = RECT::default();
+    let _ = GetWindowRect(me, &mut prc);
+    let tp = (trc.left, trc.top, trc.right - trc.left, trc.bottom - trc.top);
+    let pp = (prc.left, prc.top, prc.right - prc.left, prc.bottom - prc.top);
+    let sp = saved();
+    if tp != sp {
+        // Target hat sich bewegt → DirectShell folgt (Z-Order via Owner automatisch)
+        let _ = SetWindowPos(me, HWND::default(), tp.0, tp.1, tp.2, tp.3,
+            SWP_NOACTIVATE | SWP_NOZORDER);
+        save(tp.0, tp.1, tp.2, tp.3);
+    } else if pp != sp {
+        // DirectShell hat sich bewegt → Target folgt
+        let _ = SetWindowPos(t, HWND::default(), pp.0, pp.1, pp.2, pp.3,
+            SWP_NOACTIVATE | SWP_NOZORDER);
+        save(pp.0, pp.1, pp.2, pp.3);
+    }
+}
+
+// ── Lichtreflex mit Gradient (diffus, kein harter Block) ──
+unsafe fn draw_light(hdc: HDC, w: i32, h: i32) {
+    let th = top_h();
+    let t = anim_t();
+    let wf = w as f64;
+    let sh = (h - th) as f64;
+    let perim = 2.0 * wf + 2.0 * sh;
+    if perim <= 0.0 { return; }
+    let center = t * perim;
+    let half = LIGHT_LEN / 2.0;
+
+    // 4 Kanten mit Hintergrundfarbe: (Start, Ende, BG-Farbe)
+    let edges: [(f64, f64, COLORREF, i32); 4] = [
+        (0.0, wf, TOP_CLR, 0),                  // top
+        (wf, wf + sh, SIDE_CLR, 1),             // right
+        (wf + sh, 2.0 * wf + sh, BOT_CLR, 2),  // bottom
+        (2.0 * wf + sh, perim, SIDE_CLR, 3),    // left
+    ];
+
+    // Wrap-Around: 3 Kopien des Zentrums prüfen
+    for &seg_center in &[center, center + perim, center - perim] {
+        for &(e_s, e_e, bg_clr, edge_idx) in &edges {
+            let s = (seg_center - half).max(e_s);
+            let e = (seg_center + half).min(e_e);
+            if s >= e { continue; }
+            let edge_len = e_e - e_s;
+            if edge_len <= 0.0 { continue; }
+            let seg_len = e - s;
+            let step_w = seg_len / LIGHT_STEPS as f64;
+
+            for j in 0..LIGHT_STEPS {
+                let ss = s + j as f64 * step_w;
+                let se = s + (j + 1) as f64 * step_w;
+                let mid = (ss + se) / 2.0;
+
+                // Distanz zum Zentrum → 0.0 (Mitte) bis 1.0 (Rand)
+                let dist = ((mid - seg_center) / half).abs().min(1.0);
+                // Smooth Falloff: cos²(dist * π/2)
+                let c = (dist * std::f64::consts::FRAC_PI_2).cos();
+                let intensity = c * c;
+                if intensity < 0.02 { continue; }
+
+                let clr = lerp_clr(bg_clr, HL_CLR, intensity);
+                let brush = CreateSolidBrush(clr);
+
+                let f0 = (ss - e_s) / edge_len;
+                let f1 = (se - e_s) / edge_len;
+
+                let rect = match edge_idx {
+                    0 => RECT { // Top: links → rechts
+                        left: (f0 * wf) as i32,
+                        top: 0,
+                        right: (f1 * wf) as i32 + 1,
+                        bottom: th,
+                    },
+                    1 => RECT { // Right: oben → unten
+                        left: w - SIDE_W,
+                        top: th + (f0 * sh) as i32,
+                        right: w,
+                        bottom: th + (f1 * sh) as i32 + 1,
+                    },
+                    2 => RECT { // Bottom: rechts → links
+                        left: w - (f1 * wf) as i32 - 1,
+                        top: h - SIDE_W,
+                        right: w - (f0 * wf) as i32,
+                        bottom: h,
+                    },
+                    _ => RECT { // Left: unten → oben
+                        left: 0,
+                        top: h - (f1 * sh) as i32 - 1,
+                        right: SIDE_W,
+                        bottom: h - (f0 * sh) as i32,
+                    },
+                };
+
+                FillRect(hdc, &rect, brush);
+                let _ = DeleteObject(brush);
+            }
+        }
+    }
+}
+
+// ── Close-Button (unsnapped, oben rechts) ──────────
+/// Hit-test code of the side/bottom grips (client coords lx/ly, window size w×h):
+/// edges resize, the two bottom corners resize diagonally. None = not on a grip.
+fn grip_hit(lx: i32, ly: i32, w: i32, h: i32) -> Option<u32> {
+    let (left, right, bottom) = (lx < GRIP, lx > w - GRIP, ly > h - GRIP);
+    match (left, right, bottom) {
+        (true, _, true) => Some(HTBOTTOMLEFT),
+        (_, true, true) => Some(HTBOTTOMRIGHT),
+        (true, _, _)    => Some(HTLEFT),
+        (_, true, _)    => Some(HTRIGHT),
+        (_, _, true)    => Some(HTBOTTOM),
+        _ => None,
+    }
+}
+
+fn close_area(w: i32) -> (i32, i32, i32, i32) {
+    let th = top_h();
+    let btn_h = th - 2;
+    let btn_w = (btn_h as f64 * 1.4) as i32;
+    let x = w - btn_w - 1;
+    let y = 1;
+    (x, y, x + btn_w, y + btn_h)
+}
+
+unsafe fn draw_close_btn(hdc: HDC, w: i32) {
+    let (l, t, r, b) = close_area(w);
+    let bw = r - l;
+    let bh = b - t;
+
+    // Hintergrund: leicht rötlich
+    let bg_brush = CreateSolidBrush(COLORREF(0x004040C0)); // Dezentes Rot (BGR)
+    FillRect(hdc, &RECT { left: l, top: t, right: r, bottom: b }, bg_brush);
+    let _ = DeleteObject(bg_brush);
+
+    // X-Symbol
+    let pen = CreatePen(PS_SOLID, 1, ICON_CLR);
+    let old_p = SelectObject(hdc, pen);
+    let cx = l + bw / 2;
+    let cy = t + bh / 2;
+    let cr = bh.min(bw) / 2 - 4;
+    let _ = MoveToEx(hdc, cx - cr, cy - cr, None);
+    let _ = LineTo(hdc, cx + cr + 1, cy + cr + 1);
+    let _ = MoveToEx(hdc, cx + cr, cy - cr, None);
+    let _ = LineTo(hdc, cx - cr - 1, cy + cr + 1);
+    SelectObject(hdc, old_p);
+    let _ = DeleteObject(pen);
+}
+
+// ── Unsnap-Button (snapped, neben Caption Buttons) ──
+fn btn_area(w: i32) -> (i32, i32, i32, i32) {
+    let off = BTN_OFF_X.load(SeqCst);
+    let th = top_h();
+    // Button quadratisch, so hoch wie Titlebar (minus Padding)
+    let btn_h = th - 2;
+    let btn_w = (btn_h as f64 * 1.2) as i32; // etwas breiter als hoch (wie Windows)
+    let x = w - off - btn_w - 2;
+    let y = 1;
+    (x, y, x + btn_w, y + btn_h)
+}
+
+// Windows-Style Caption Button mit ⊕ Icon
+unsafe fn draw_unsnap_icon(hdc: HDC, w: i32) {
+    let (l, t, r, b) = btn_area(w);
+    let bw = r - l;
+    let bh = b - t;
+
+    // Button-Hintergrund: leicht heller als Titlebar
+    let btn_bg = lerp_clr(TOP_CLR, HL_CLR, 0.08);
+    let bg_brush = CreateSolidBrush(btn_bg);
+    FillRect(hdc, &RECT { left: l, top: t, right: r, bottom: b }, bg_brush);
+    let _ = DeleteObject(bg_brush);
+
+    // ⊕ Icon zentriert im Button
+    let cx = l + bw / 2;
+    let cy = t + bh / 2;
+    let radius = bh.min(bw) / 2 - 4;
+    if radius < 3 { return; }
+
+    let pen = CreatePen(PS_SOLID, 1, ICON_CLR);
+    let old_p = SelectObject(hdc, pen);
+    let old_b = SelectObject(hdc, GetStockObject(NULL_BRUSH));
+
+    // Kreis
+    let _ = Ellipse(hdc, cx - radius, cy - radius, cx + radius + 1, cy + radius + 1);
+    // Fadenkreuz
+    let cr = radius - 2;
+    let _ = MoveToEx(hdc, cx - cr, cy, None);
+    let _ = LineTo(hdc, cx + cr + 1, cy);
+    let _ = MoveToEx(hdc, cx, cy - cr, None);
+    let _ = LineTo(hdc, cx, cy + cr + 1);
+
+    SelectObject(hdc, old_p);
+    SelectObject(hdc, old_b);
+    let _ = DeleteObject(pen);
+}
+
+// ── Paint mit Double Buffering ──────────────────────
+unsafe fn paint(hwnd: HWND) {
+    let mut ps = PAINTSTRUCT::default();
+    let hdc = BeginPaint(hwnd, &mut ps);
+    let mut rc = RECT::default();
+    let _ = GetClientRect(hwnd, &mut rc);
+    let w = rc.right;
+    let h = rc.bottom;
+    let th = top_h();
+
+    // Double Buffer
+    let mem_dc = CreateCompatibleDC(hdc);
+    let mem_bmp = CreateCompatibleBitmap(hdc, w, h);
+    let old_bmp = SelectObject(mem_dc, mem_bmp);
+
+    // 1. Magenta-Hintergrund
+    let bg = CreateSolidBrush(INVIS);
+    FillRect(mem_dc, &rc, bg);
+    let _ = DeleteObject(bg);
+
+    // 2. Rounded Clip (nur oben abgerundet)
+    let clip = CreateRoundRectRgn(0, 0, w + 1, h + CORNER_R * 4, CORNER_R * 2, CORNER_R * 2);
+    SelectClipRgn(mem_dc, clip);
+
+    // 3. Anthrazit-Rahmen (3D, dynamische Höhe)
+    let tbr = CreateSolidBrush(TOP_CLR);
+    let sbr = CreateSolidBrush(SIDE_CLR);
+    let bbr = CreateSolidBrush(BOT_CLR);
+    FillRect(mem_dc, &RECT { left: 0, top: 0, right: w, bottom: th }, tbr);
+    FillRect(mem_dc, &RECT { left: 0, top: th, right: SIDE_W, bottom: h - SIDE_W }, sbr);
+    FillRect(mem_dc, &RECT { left: w - SIDE_W, top: th, right: w, bottom: h - SIDE_W }, sbr);
+    FillRect(mem_dc, &RECT { left: 0, top: h - SIDE_W, right: w, bottom: h }, bbr);
+    let _ = DeleteObject(tbr);
+    let _ = DeleteObject(sbr);
+    let _ = DeleteObject(bbr);
+
+    // 4. 3D-Linien
+    let hl_pen = CreatePen(PS_SOLID, 1, HL_CLR);
+    let old = SelectObject(mem_dc, hl_pen);
+    let _ = MoveToEx(mem_dc, CORNER_R, 1, None);
+    let _ = LineTo(mem_dc, w - CORNER_R, 1);
+    SelectObject(mem_dc, old);
+    let _ = DeleteObject(hl_pen);
+
+    let sh_pen = CreatePen(PS_SOLID, 1, SH_CLR);
+    let old = SelectObject(mem_dc, sh_pen);
+    let _ = MoveToEx(mem_dc, 0, h - 1, None);
+    let _ = LineTo(mem_dc, w, h - 1);
+    SelectObject(mem_dc, old);
+    let _ = DeleteObject(sh_pen);
+
+    // 5. Lichtreflex + Close (nur wenn NICHT gesnappt)
+    if !snapped() {
+        draw_light(mem_dc, w, h);
+        draw_close_btn(mem_dc, w);
+    }
+
+    // 6. Unsnap-Icon (nur wenn gesnappt) + Hook-Punkt links daneben
+    if snapped() {
+        draw_unsnap_icon(mem_dc, w);
+        if HOOK_STATE_SHOWN.load(SeqCst) == HookState::Intercepting as i32 {
+            let (l, t, _, b) = btn_area(w);
+            let d = ((b - t) / 3).max(4);
+            let cy = t + (b - t) / 2;
+            let br = CreateSolidBrush(HOOK_CLR);
+            let old_b = SelectObject(mem_dc, br);
+            let old_p = SelectObject(mem_dc, GetStockObject(NULL_PEN));
+            let _ = Ellipse(mem_dc, l - d - 6, cy - d / 2, l - 6, cy + d / 2 + 1);
+            SelectObject(mem_dc, old_p);
+            SelectObject(mem_dc, old_b);
+            let _ = DeleteObject(br);
+        }
+    }
+
+    // Clip reset
+    SelectClipRgn(mem_dc, HRGN::default());
+    let _ = DeleteObject(clip);
+
+    // BitBlt: Buffer → Screen
+    let _ = BitBlt(hdc, 0, 0, w, h, mem_dc, 0, 0, SRCCOPY);
+
+    SelectObject(mem_dc, old_bmp);
+    let _ = DeleteObject(mem_bmp);
+    let _ = DeleteDC(mem_dc);
+    let _ = EndPaint(hwnd, &ps);
+}
+
+// ── System Tray Icon ─────────────────────────────────
+
+unsafe fn add_tray_icon(hwnd: HWND) {
+    use windows::Win32::UI::Shell::{
+        Shell_NotifyIconW, NOTIFYICONDATAW, NIM_ADD, NIF_MESSAGE, NIF_ICON, NIF_TIP,
+    };
+    let mut nid: NOTIFYICONDATAW = std::mem::zeroed();
+    nid.cbSize = std::mem::size_of::<NOTIFYICONDATAW>() as u32;
+    nid.hWnd = hwnd;
+    nid.uID = TRAY_ID;
+    nid.uFlags = NIF_MESSAGE | NIF_ICON | NIF_TIP;
+    nid.uCallbackMessage = WM_TRAYICON;
+    // Load the embedded EXE icon (resource ID 1 = main icon from winresource)
+    let hinst = GetModuleHandleW(PCWSTR::null()).unwrap_or_default();
+    let icon = LoadImageW(
+        hinst,
+        PCWSTR(1 as *const u16),  // resource ID 1
+        IMAGE_ICON,
+        16, 16,  // small icon for tray
+        LR_DEFAULTCOLOR,
+    );
+    nid.hIcon = match icon {
+        Ok(h) => HICON(h.0),
+        Err(_) => LoadIconW(HINSTANCE::default(), IDI_APPLICATION).unwrap_or_default(),
+    };
+    // Tooltip: "DirectShell"
+    let tip = "DirectShell\0";
+    let tip_wide: Vec<u16> = tip.encode_utf16().collect();
+    let copy_len = tip_wide.len().min(nid.szTip.len());
+    nid.szTip[..copy_len].copy_from_slice(&tip_wide[..copy_len]);
+    let _ = Shell_NotifyIconW(NIM_ADD, &nid);
+    log("Tray icon added");
+}
+
+unsafe fn remove_tray_icon(hwnd: HWND) {
+    use windows::Win32::UI::Shell::{Shell_NotifyIconW, NOTIFYICONDATAW, NIM_DELETE};
+    let mut nid: NOTIFYICONDATAW = std::mem::zeroed();
+    nid.cbSize = std::mem::size_of::<NOTIFYICONDATAW>() as u32;
+    nid.hWnd = hwnd;
+    nid.uID = TRAY_ID;
+    let _ = Shell_NotifyIconW(NIM_DELETE, &nid);
+}
+
+unsafe fn show_tray_menu(hwnd: HWND) {
+    use windows::Win32::UI::WindowsAndMessaging::{
+        CreatePopupMenu, InsertMenuW, TrackPopupMenu,
+        MF_STRING, MF_SEPARATOR, MF_CHECKED, MF_GRAYED, TPM_BOTTOMALIGN, TPM_LEFTALIGN, DestroyMenu,
+    };
+    let menu = CreatePopupMenu().unwrap();
+    let is_agent = AGENT_MODE.load(SeqCst);
+    let mode_label = if is_agent {
+        "Switch to Human Mode\0"
+    } else {
+        "Switch to Agent Mode\0"
+    };
+    let mode_wide: Vec<u16> = mode_label.encode_utf16().collect();
+    // Quiet via config can't be switched off from the tray → show it, but greyed out
+    let quiet_label: Vec<u16> = if config_flag("quiet") {
+        "Quiet Mode (config)\0"
+    } else {
+        "Quiet Mode\0"
+    }.encode_utf16().collect();
+    let mut quiet_flags = MF_STRING;
+    if QUIET.load(SeqCst) { quiet_flags |= MF_CHECKED; }
+    if config_flag("quiet") { quiet_flags |= MF_GRAYED; }
+    // Recording needs a snapped app (the macro file is per app) and no quiet mode
+    // (the keyboard hook only records while intercepting)
+    let record_label: Vec<u16> = if recording() {
+        "Stop Recording (Pause)\0"
+    } else {
+        "Record Macro\0"
+    }.encode_utf16().collect();
+    let mut record_flags = MF_STRING;
+    if recording() { record_flags |= MF_CHECKED; }
+    if !recording() && (!snapped() || OBSERVE_ONLY.load(SeqCst) || QUIET.load(SeqCst)) { record_flags |= MF_GRAYED; }
+    let exit_label: Vec<u16> = "Exit DirectShell\0".encode_utf16().collect();
+    let sep_label: Vec<u16> = "\0".encode_utf16().collect();
+
+    let _ = InsertMenuW(menu, 0, MF_STRING, IDM_TOGGLE_MODE as usize, PCWSTR(mode_wide.as_ptr()));
+    let _ = InsertMenuW(menu, 1, quiet_flags, IDM_TOGGLE_QUIET as usize, PCWSTR(quiet_label.as_ptr()));
+    let _ = InsertMenuW(menu, 2, record_flags, IDM_TOGGLE_RECORD as usize, PCWSTR(record_label.as_ptr()));
+    let _ = InsertMenuW(menu, 3, MF_SEPARATOR, 0, PCWSTR(sep_label.as_ptr()));
+    let _ = InsertMenuW(menu, 4, MF_STRING, IDM_EXIT as usize, PCWSTR(exit_label.as_ptr()));
+
+    // Required: SetForegroundWindow before TrackPopupMenu so menu dismisses properly
+    let _ = SetForegroundWindow(hwnd);
+    let mut pt = std::mem::zeroed();
+    let _ = GetCursorPos(&mut pt);
+    let _ = TrackPopupMenu(menu, TPM_LEFTALIGN | TPM_BOTTOMALIGN, pt.x, pt.y, 0, hwnd, None);
+    let _ = DestroyMenu(menu);
+}
+
+// ── Window Procedure ────────────────────────────────
+unsafe extern "system" fn wndproc(
+    hwnd: HWND, msg: u32, wp: WPARAM, lp: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_PAINT => {
+            paint(hwnd);
+            LRESULT(0)
+        }
+
+        WM_NCHITTEST => {
+            let x = (lp.0 & 0xFFFF) as i16 as i32;
+            let y = ((lp.0 >> 16) & 0xFFFF) as i16 as i32;
+            let mut rc = RECT::default();
+            let _ = GetWindowRect(hwnd, &mut rc);
+            let lx = x - rc.left;
+            let ly = y - rc.top;
+            let w = rc.right - rc.left;
+            let h = rc.bottom - rc.top;
+            let th = top_h();
+
+            if ly < th {
+                if snapped() {
+                    // Unsnap-Button → HTCLIENT (clickable)
+                    let (bl, bt, br, bb) = btn_area(w);
+                    if lx >= bl && lx <= br && ly >= bt && ly <= bb {
+                        return LRESULT(HTCLIENT as _);
+                    }
+                    // Target caption buttons (min/max/close) → pass through
+                    let off = BTN_OFF_X.load(SeqCst);
+                    if lx >= w - off {
+                        return LRESULT(HTTRANSPARENT as _);
+                    }
+                } else {
+                    let (cl, ct, cr, cb) = close_area(w);
+                    if lx >= cl && lx <= cr && ly >= ct && ly <= cb {
+                        return LRESULT(HTCLIENT as _);
+                    }
+                }
+                return LRESULT(HTCAPTION as _);
+            }
+            // Grips resize (the top bar moves); while snapped, WM_SIZING takes the target along
+            if let Some(ht) = grip_hit(lx, ly, w, h) {
+                return LRESULT(ht as _);
+            }
+            LRESULT(HTTRANSPARENT as _)
+        }
+
+        WM_LBUTTONDOWN => {
+            let cx = (lp.0 & 0xFFFF) as i16 as i32;
+            let cy = ((lp.0 >> 16) & 0xFFFF) as i16 as i32;
+            let mut wrc = RECT::default();
+            let _ = GetClientRect(hwnd, &mut wrc);
+            if snapped() {
+                let (bl, bt, br, bb) = btn_area(wrc.right);
+                if cx >= bl && cx <= br && cy >= bt && cy <= bb {
+                    do_unsnap(hwnd);
+                    return LRESULT(0);
+                }
+            } else {
+                let (cl, ct, cr, cb) = close_area(wrc.right);
+                if cx >= cl && cx <= cr && cy >= ct && cy <= cb {
+                    let _ = PostMessageW(hwnd, WM_CLOSE, WPARAM(0), LPARAM(0));
+                    return LRESULT(0);
+                }
+            }
+            DefWindowProcW(hwnd, msg, wp, lp)
+        }
+
+        WM_EXITSIZEMOVE => {
+            if !snapped() {
+                if let Some(t) = find_snap(hwnd) {
+                    do_snap(hwnd, t);
+                }
+            }
+            LRESULT(0)
+        }
+
+        WM_MOVING | WM_SIZING => {
+            if snapped() {
+                let new_rc = &*(lp.0 as *const RECT);
+                let t = tgt();
+                if !t.0.is_null() && IsWindow(t).as_bool() {
+                    let nw = new_rc.right - new_rc.left;
+                    let nh = new_rc.bottom - new_rc.top;
+                    let _ = SetWindowPos(t, HWND::default(),
+                        new_rc.left, new_rc.top, nw, nh,
+                        SWP_NOACTIVATE | SWP_NOZORDER);
+                    save(new_rc.left, new_rc.top, nw, nh);
+                }
+            }
+            DefWindowProcW(hwnd, msg, wp, lp)
+        }
+
+        WM_TIMER => {
+            match wp.0 {
+                SYNC_TIMER => do_sync(hwnd),
+                ANIM_TIMER => { let _ = InvalidateRect(hwnd, None, FALSE); },
+                TREE_TIMER => {
+                    dump_tree();
+                    apply_tree_backoff(hwnd);
+                },
+                INJECT_TIMER => { process_injections(); },
+                ENUM_TIMER => {
+                    enum_windows_to_json();
+                    check_quiet(hwnd);
+                    check_hooks();
+                    refresh_passthrough();
+                    check_protection();
+                    update_mouse_hook();
+                    update_hook_status();
+                    checkpoint_events();
+                },
+                OBSERVE_TIMER => { dump_observers(); },
+                SNAP_REQ_TIMER => {
+                    check_snap_request(hwnd);
+                    check_follow_focus(hwnd);
+                    check_overlay_mode(hwnd);
+                    check_dump_request();
+                    check_selftest_request();
+                    check_events_since_request();
+                    check_deferred_registration();
+                    process_user_clicks();
+                    check_record_stop();
+                    check_empty_snap();
+                    check_replay_request();
+                    update_hook_status();
+                },
+                _ => {}
+            }
+            LRESULT(0)
+        }
+
+        WM_CLOSE => {
+            log("WM_CLOSE received");
+            if snapped() {
+                let t = tgt();
+                if !t.0.is_null() && IsWindow(t).as_bool() {
+                    let _ = PostMessageW(t, WM_CLOSE, WPARAM(0), LPARAM(0));
+                }
+                do_unsnap(hwnd);
+            }
+            // DBs bleiben persistent (ds_profiles/). Nur Log aufräumen.
+            let _ = DestroyWindow(hwnd);
+            LRESULT(0)
+        }
+
+        WM_DESTROY => {
+            remove_tray_icon(hwnd);
+            restore_screen_reader();
+            release_a11y_uia();
+            let hk = KB_HOOK.swap(0, SeqCst);
+            if hk != 0 {
+                let _ = UnhookWindowsHookEx(HHOOK(hk as *mut _));
+                log("Keyboard hook removed");
+            }
+            let mh = MOUSE_HOOK.swap(0, SeqCst);
+            if mh != 0 {
+                let _ = UnhookWindowsHookEx(HHOOK(mh as *mut _));
+            }
+            PostQuitMessage(0);
+            LRESULT(0)
+        }
+
+        // System Tray icon callback
+        x if x == WM_TRAYICON => {
+            let event = (lp.0 & 0xFFFF) as u32;
+            // WM_RBUTTONUP = 0x0205, WM_LBUTTONUP = 0x0202
+            if event == 0x0205 || event == 0x0202 {
+                show_tray_menu(hwnd);
+            }
+            LRESULT(0)
+        }
+
+        // Menu command from tray popup
+        WM_COMMAND => {
+            let cmd = (wp.0 & 0xFFFF) as u16;
+            match cmd {
+                IDM_TOGGLE_MODE => {
+                    let is_agent = AGENT_MODE.load(SeqCst);
+                    let new_mode = if is_agent { "human" } else { "agent" };
+                    let _ = fs::write(ds_file(OVERLAY_MODE_FILE), new_mode);
+                    // Apply immediately
+                    AGENT_MODE.store(!is_agent, SeqCst);
+                    if is_agent {
+                        log("tray: switched to HUMAN mode");
+                        if !IsWindowVisible(hwnd).as_bool() && !OBSERVE_ONLY.load(SeqCst) && !QUIET.load(SeqCst) {
+                            let _ = ShowWindow(hwnd, SW_SHOWNA);
+                        }
+                    } else {
+                        log("tray: switched to AGENT mode");
+                        if IsWindowVisible(hwnd).as_bool() {
+                            let _ = ShowWindow(hwnd, SW_HIDE);
+                        }
+                    }
+                }
+                IDM_TOGGLE_QUIET => {
+                    let on = !QUIET_TRAY.load(SeqCst);
+                    QUIET_TRAY.store(on, SeqCst);
+                    log(&format!("tray: quiet mode {}", if on { "ON" } else { "OFF" }));
+                    // Apply immediately
+                    check_quiet(hwnd);
+                }
+                IDM_TOGGLE_RECORD => {
+                    if recording() { stop_recording(); } else { start_recording(); }
+                }
+                IDM_EXIT => {
+                    log("tray: exit requested");
+                    let _ = PostMessageW(hwnd, WM_CLOSE, WPARAM(0), LPARAM(0));
+                }
+                _ => {}
+            }
+            LRESULT(0)
+        }
+
+        _ => DefWindowProcW(hwnd, msg, wp, lp),
+    }
+}
+
+// ── Browser Shortcut Patching ────────────────────────
+// At startup: find browser .lnk files on the Desktop, ask user to add CDP + a11y flags.
+// Flags: --remote-debugging-port=9222 (localhost ONLY) + --force-renderer-accessibility
+// This replaces the old "bounce" approach — flags are baked into shortcuts permanently.
+
+const DS_FLAGS: &str = "--remote-debugging-port=9222 --remote-allow-origins=* --force-renderer-accessibility";
+const BROWSER_EXES: [&str; 6] = ["chrome.exe", "opera.exe", "msedge.exe", "brave.exe", "vivaldi.exe", "chromium.exe"];
+const SHORTCUTS_STATE: &str = "shortcuts_configured";
+const SHORTCUTS_BACKUP: &str = "shortcuts_backup.json";
+const REVERT_GUIDE: &str = "BROWSER_FLAGS_GUIDE.txt";
+
+/// Read target path + arguments from a .lnk shortcut file via COM (IShellLinkW)
+unsafe fn read_shortcut_info(lnk_path: &std::path::Path) -> Option<(String, String)> {
+    use windows::Win32::UI::Shell::IShellLinkW;
+    use windows::Win32::System::Com::IPersistFile;
+
+    // CLSID_ShellLink = {00021401-0000-0000-C000-000000000046}
+    let clsid = GUID { data1: 0x00021401, data2: 0x0000, data3: 0x0000,
+        data4: [0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46] };
+
+    let link: IShellLinkW = CoCreateInstance(&clsid, None, CLSCTX_INPROC_SERVER).ok()?;
+    let persist: IPersistFile = link.cast().ok()?;
+
+    let wide: Vec<u16> = lnk_path.to_string_lossy().encode_utf16().chain(std::iter::once(0)).collect();
+    persist.Load(PCWSTR(wide.as_ptr()), STGM(0)).ok()?; // STGM_READ = 0
+
+    let mut target_buf = [0u16; 512];
+    link.GetPath(&mut target_buf, std::ptr::null_mut(), 0).ok()?;
+    let target = String::from_utf16_lossy(&target_buf)
+        .trim_end_matches('\0').to_string();
+
+    let mut args_buf = [0u16; 4096];
+    let _ = link.GetArguments(&mut args_buf);
+    let args = String::from_utf16_lossy(&args_buf)
+        .trim_end_matches('\0').to_string();
+
+    Some((target, args))
+}
+
+/// Patch a .lnk shortcut to append DS flags to its arguments
+unsafe fn patch_browser_shortcut(lnk_path: &str, original_args: &str, flags: &str) -> bool {
+    use windows::Win32::UI::Shell::IShellLinkW;
+    use windows::Win32::System::Com::IPersistFile;
+
+    let clsid = GUID { data1: 0x00021401, data2: 0x0000, data3: 0x0000,
+        data4: [0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46] };
+
+    let link: IShellLinkW = match CoCreateInstance(&clsid, None, CLSCTX_INPROC_SERVER) {
+        Ok(l) => l, Err(_) => return false,
+    };
+    let persist: IPersistFile = match link.cast() {
+        Ok(p) => p, Err(_) => return false,
+    };
+
+    let wide_path: Vec<u16> = lnk_path.encode_utf16().chain(std::iter::once(0)).collect();
+    // STGM_READWRITE = 2
+    if persist.Load(PCWSTR(wide_path.as_ptr()), STGM(2)).is_err() { return false; }
+
+    let new_args = if original_args.is_empty() {
+        flags.to_string()
+    } else {
+        format!("{} {}", original_args, flags)
+    };
+
+    let wide_args: Vec<u16> = new_args.encode_utf16().chain(std::iter::once(0)).collect();
+    if link.SetArguments(PCWSTR(wide_args.as_ptr())).is_err() { return false; }
+
+    // Save in-place (NULL path = save to same file)
+    persist.Save(PCWSTR::null(), TRUE).is_ok()
+}
+
+/// Write the "how to revert" guide in ds_profiles/
+fn write_browser_revert_guide(patched: &[(String, String, String)]) {
+    let mut guide = String::new();
+    guide.push_str("=== DirectShell — Browser Flags Guide ===\n\n");
+    guide.push_str("DirectShell has modified the following browser shortcuts:\n\n");
+
+    for (path, name, original_args) in patched {
+        guide.push_str(&format!("  Shortcut: {}\n", name));
+        guide.push_str(&format!("  Path: {}\n", path));
+        guide.push_str(&format!("  Original arguments: {}\n",
+            if original_args.is_empty() { "(none)" } else { original_args }));
+        guide.push_str(&format!("  Added: {}\n\n", DS_FLAGS));
+    }
+
+    guide.push_str("--- What was added? ---\n\n");
+    guide.push_str("  --remote-debugging-port=9222\n");
+    guide.push_str("    Chrome DevTools Protocol (CDP) on port 9222.\n");
+    guide.push_str("    ONLY reachable from this PC (localhost/127.0.0.1).\n");
+    guide.push_str("    Allows AI agents to control the browser.\n\n");
+    guide.push_str("  --remote-allow-origins=*\n");
+    guide.push_str("    Allows local programs to connect via WebSocket.\n\n");
+    guide.push_str("  --force-renderer-accessibility\n");
+    guide.push_str("    Forces the browser to build its Accessibility Tree.\n");
+    guide.push_str("    Allows AI agents to read and interact with UI elements.\n\n");
+
+    guide.push_str("--- Revert manually ---\n\n");
+    guide.push_str("  1. Right-click the browser shortcut > Properties\n");
+    guide.push_str("  2. In the 'Target' field, remove the three flags at the end:\n");
+    guide.push_str(&format!("     {}\n", DS_FLAGS));
+    guide.push_str("  3. Click OK. Done.\n\n");
+
+    guide.push_str("--- Revert via agent ---\n\n");
+    guide.push_str(&format!("  The original arguments are saved in {}.\n", ds_file(SHORTCUTS_BACKUP)));
+    guide.push_str("  An agent can restore the shortcuts from that backup.\n\n");
+
+    guide.push_str("--- Is this safe? ---\n\n");
+    guide.push_str("  YES. Port 9222 is NOT reachable from the network.\n");
+    guide.push_str("  It is only accessible from this PC (127.0.0.1).\n");
+    guide.push_str("  It is the same port that Chrome DevTools (F12) uses.\n");
+    guide.push_str("  The accessibility flags have minimal performance impact.\n");
+
+    let _ = fs::write(ds_file(REVERT_GUIDE), guide);
+}
+
+/// Main shortcut check — runs once at startup, shows popup if unpatched browsers found
+unsafe fn check_browser_shortcuts() {
+    if std::path::Path::new(&ds_file(SHORTCUTS_STATE)).exists() { return; }
+    let _ = fs::create_dir_all(db_dir());
+
+    // Collect desktop paths
+    let home = std::env::var("USERPROFILE").unwrap_or_default();
+    if home.is_empty() { return; }
+    let mut desktops = vec![format!("{}\\Desktop", home)];
+    if let Ok(public) = std::env::var("PUBLIC") {
+        desktops.push(format!("{}\\Desktop", public));
+    }
+
+    // Scan for browser .lnk files that need patching
+    let mut to_patch: Vec<(String, String, String)> = Vec::new(); // (path, name, original_args)
+    for desktop in &desktops {
+        let Ok(entries) = fs::read_dir(desktop) else { continue; };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("lnk") { continue; }
+            if let Some((target, args)) = read_shortcut_info(&path) {
+                let target_lower = target.to_lowercase();
+                if BROWSER_EXES.iter().any(|exe| target_lower.ends_with(exe))
+                    && !args.contains("--remote-debugging-port")
+                {
+                    let name = path.file_stem().and_then(|s| s.to_str())
+                        .unwrap_or("?").to_string();
+                    to_patch.push((path.to_string_lossy().to_string(), name, args));
+                }
+            }
+        }
+    }
+
+    if to_patch.is_empty() {
+        log("shortcuts: no unpatched browser shortcuts found");
+        let _ = fs::write(ds_file(SHORTCUTS_STATE), "no_browsers");
+        return;
+    }
+
+    log(&format!("shortcuts: found {} browser shortcuts to patch", to_patch.len()));
+
+    // Build popup message
+    let names = to_patch.iter()
+        .map(|(_, n, _)| format!("  \u{2022} {}", n))
+        .collect::<Vec<_>>().join("\n");
+    let msg = format!(
+        "DirectShell found {} browser shortcut(s) on the desktop:\n\n\
+         {}\n\n\
+         May DirectShell add developer flags to these shortcuts?\n\n\
+         What will be added:\n\
+         \u{2022} CDP (port 9222) \u{2014} remote control, ONLY reachable locally\n\
+         \u{2022} Accessibility \u{2014} Accessibility Tree for AI agents\n\n\
+         No security risk \u{2014} port 9222 is exclusively\n\
+         reachable from this PC (localhost/127.0.0.1).\n\n\
+         A guide to revert these changes is saved in:\n\
+         {}\0",
+        to_patch.len(), names, ds_file(REVERT_GUIDE)
+    );
+    let title = "DirectShell \u{2014} Browser Configuration\0";
+    let wide_msg: Vec<u16> = msg.encode_utf16().collect();
+    let wide_title: Vec<u16> = title.encode_utf16().collect();
+
+    let result = MessageBoxW(
+        HWND::default(),
+        PCWSTR(wide_msg.as_ptr()),
+        PCWSTR(wide_title.as_ptr()),
+        MB_YESNO | MB_ICONQUESTION,
+    );
+
+    if result == MESSAGEBOX_RESULT(6) { // IDYES
+        // Backup original args
+        let backup: Vec<_> = to_patch.iter().map(|(p, n, a)| {
+            format!(r#"  {{"path":"{}","name":"{}","original_args":"{}"}}"#,
+                json_escape(p), json_escape(n), json_escape(a))
+        }).collect();
+        let _ = fs::write(ds_file(SHORTCUTS_BACKUP), json_array(&backup, ""));
+
+        let mut patched_ok: Vec<String> = Vec::new();
+        let mut patched_fail: Vec<String> = Vec::new();
+        for (path, name, args) in &to_patch {
+            if patch_browser_shortcut(path, args, DS_FLAGS) {
+                log(&format!("shortcuts: patched '{}'", name));
+                patched_ok.push(name.clone());
+            } else {
+                log(&format!("shortcuts: FAILED to patch '{}' (access denied?)", name));
+                patched_fail.push(name.clone());
+            }
+        }
+
+        write_browser_revert_guide(&to_patch);
+        log(&format!("shortcuts: {}/{} browser shortcuts patched", patched_ok.len(), to_patch.len()));
+
+        if patched_fail.is_empty() {
+            // All good — save state and show success
+            let _ = fs::write(ds_file(SHORTCUTS_STATE), format!("patched:{}", patched_ok.len()));
+            let done_msg = format!("{} of {} browser shortcut(s) configured.\n\n\
+                Changes will be active on next browser launch.\0",
+                patched_ok.len(), to_patch.len());
+            let wide_done: Vec<u16> = done_msg.encode_utf16().collect();
+            MessageBoxW(HWND::default(), PCWSTR(wide_done.as_ptr()),
+                PCWSTR(wide_title.as_ptr()), MB_OK | MB_ICONINFORMATION);
+        } else {
+            // Some failed — offer admin restart
+            let fail_msg = format!(
+                "{} of {} shortcut(s) configured.\n\n\
+                 Failed (admin rights needed):\n  {}\n\n\
+                 Restart DirectShell as Administrator to fix these?\n\n\
+                 If you click No, you can manually fix them:\n\
+                 Right-click shortcut > Properties > Target, append:\n\
+                 {}\0",
+                patched_ok.len(), to_patch.len(),
+                patched_fail.join("\n  "), DS_FLAGS);
+            let wide_fail: Vec<u16> = fail_msg.encode_utf16().collect();
+            let answer = MessageBoxW(HWND::default(), PCWSTR(wide_fail.as_ptr()),
+                PCWSTR(wide_title.as_ptr()), MB_YESNO | MB_ICONWARNING);
+
+            if answer == MESSAGEBOX_RESULT(6) { // IDYES — restart elevated
+                log("shortcuts: user chose admin restart");
+                // DO NOT write state file — elevated instance will re-scan
+                // Get our own exe path
+                let mut exe_buf = [0u16; 512];
+                let len = GetModuleFileNameW(HMODULE::default(), &mut exe_buf);
+                if len > 0 {
+                    let exe_path = String::from_utf16_lossy(&exe_buf[..len as usize]);
+                    let wide_runas: Vec<u16> = "runas\0".encode_utf16().collect();
+                    let wide_exe: Vec<u16> = exe_path.encode_utf16().chain(std::iter::once(0)).collect();
+                    let wide_dir: Vec<u16> = ".\0".encode_utf16().collect();
+                    use windows::Win32::UI::Shell::ShellExecuteW;
+                    use windows::Win32::UI::WindowsAndMessaging::SW_SHOWNORMAL;
+                    ShellExecuteW(
+                        HWND::default(),
+                        PCWSTR(wide_runas.as_ptr()),
+                        PCWSTR(wide_exe.as_ptr()),
+                        PCWSTR::null(),
+                        PCWSTR(wide_dir.as_ptr()),
+                        SW_SHOWNORMAL,
+                    );
+                    log("shortcuts: launched elevated instance, exiting");
+                    std::process::exit(0);
+                }
+            } else {
+                // User declined admin — save partial state
+                let _ = fs::write(ds_file(SHORTCUTS_STATE), format!("partial:{}", patched_ok.len()));
+                log("shortcuts: user declined admin restart");
+            }
+        }
+    } else {
+        let _ = fs::write(ds_file(SHORTCUTS_STATE), "declined");
+        log("shortcuts: user declined");
+    }
+}
+
+// ── Embedding API ───────────────────────────────────
+// Headless use of the engine from other Rust tools: no overlay window, no timers,
+// no file-request polling. Same globals as the app → one instance per process.
+// The app DB and the .a11y/.snap files are still written (they ARE the dump).
+
+/// A complete dump of the snapped window, elements in tree (pre-)order.
+#[derive(Debug, Clone, Default)]
+pub struct Tree {
+    pub window: String,
+    pub elements: Vec<Element>,
+}
+
+/// Embeddable DirectShell engine. `snap` → `dump` / `inject` → `unsnap`.
+/// Calls block; COM is initialized per call as needed.
+#[derive(Default)]
+pub struct DirectShell {
+    _private: (),
+}
+
+impl DirectShell {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attach to a top-level window (raw HWND value). false if it is not a window.
+    pub fn snap(&mut self, hwnd: isize) -> bool {
+        unsafe {
+            ensure_com();
+            let target = HWND(hwnd as *mut _);
+            if !IsWindow(target).as_bool() { return false; }
+            if snapped() { detach_core(); }
+            TARGET_HW.store(hwnd, SeqCst);
+            IS_SNAPPED.store(true, SeqCst);
+            attach_core(target);
+        }
+        true
+    }
+
+    /// Detach from the current target (no-op when not snapped).
+    pub fn unsnap(&mut self) {
+        if snapped() {
+            unsafe { detach_core(); }
+        }
+    }
+
+    /// Walk the target's UIA tree now (synchronously) and return it.
+    /// None when not snapped or the window is gone.
+    pub fn dump(&self) -> Option<Tree> {
+        let target = tgt();
+        let db_path = get_db_path();
+        if target.0.is_null() || db_path.is_empty() { return None; }
+        // Event-triggered dumps run on their own thread → wait for them to finish
+        while TREE_BUSY.compare_exchange(false, true, SeqCst, SeqCst).is_err() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        unsafe {
+            ensure_com();
+            if IsWindow(target).as_bool() {
+                dump_window(target, &db_path, true);
+            }
+        }
+        TREE_BUSY.store(false, SeqCst);
+        read_tree(&db_path)
+    }
+
+    /// Execute one action immediately (same actions and semantics as the `inject`
+    /// table: "text", "click", "key", ...). Recorded in the table and inject_result.
+    pub fn inject(&self, action: &str, text: &str, target: &str) -> std::result::Result<(), InjectError> {
+        let Some(conn) = open_lookup_db() else { return Err(InjectError::NoDump); };
+        let id: i64 = match conn.query_row(
+            "INSERT INTO inject(action, text, target, done) VALUES(?1, ?2, ?3, 1) RETURNING id",
+            params![action, text, target], |r| r.get(0),
+        ) {
+            Ok(id) => id,
+            Err(e) => { log(&format!("inject(api): INSERT FAIL: {e}")); return Err(InjectError::Db(e.to_string())); }
+        };
+        unsafe { ensure_com(); }
+        let mut result = run_action(&conn, id, action, text, target, false);
+        // Not on our window thread — the embedder's own thread may wait
+        while let Err(InjectError::Pending(ms)) = result {
+            std::thread::sleep(std::time::Duration::from_millis(ms));
+            result = run_action(&conn, id, action, text, target, false);
+        }
+        // Direct call = the action is the whole burst
+        unsafe { restore_foreground(); }
+        result
+    }
+}
+
+/// COM (MTA) once per calling thread — the embedder's threads are not ours to uninit.
+unsafe fn ensure_com() {
+    thread_local! { static COM_INIT: std::cell::Cell<bool> = const { std::cell::Cell::new(false) }; }
+    COM_INIT.with(|done| {
+        if !done.get() {
+            let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+            done.set(true);
+        }
+    });
+}
+
+/// Load the last dump of `db_path` into a Tree.
+fn read_tree(db_path: &str) -> Option<Tree> {
+    let conn = Connection::open(db_path).ok()?;
+    let window: String = conn
+        .query_row("SELECT value FROM meta WHERE key='window'", [], |r| r.get(0))
+        .unwrap_or_default();
+    let elements = query_elements(&conn, "ORDER BY id", []);
+    Some(Tree { window, elements })
+}
+
+/// Run the DirectShell desktop app: overlay window, tray icon, daemon timers and
+/// message loop. Returns when the window is destroyed.
+pub fn run() -> Result<()> {
+    // ── Single-Instance Guard ────────────────────────────────────────
+    // Only one DirectShell may run at a time.
+    // Window class "DirectShell" is unique — if it already exists, bail out.
+    if let Ok(existing) = unsafe { FindWindowW(w!("DirectShell"), None) } {
+        if existing != HWND::default() {
+            eprintln!("DirectShell is already running. Exiting.");
+            std::process::exit(0);
+        }
+    }
+
+    // Clear stale snap state from previous session
+    write_active_status("");
+    log("=== DirectShell START ===");
+    log(&format!("profiles: {}", db_dir()));
+    write_profiles_breadcrumb();
+
+    // Read-only / full disk: sonst tut DS still gar nichts Nützliches
+    if let Err(e) = check_db_dir_writable() {
+        log(&format!("startup: {} not writable: {}", db_dir(), e));
+        let msg = format!(
+            "DirectShell cannot write to its profile folder:\n\n  {}\n\n{}\n\n\
+             Agents will not see any app data until this is fixed\n\
+             (free up disk space or move DirectShell to a writable folder).\0",
+            db_dir(), e);
+        let wide_msg: Vec<u16> = msg.encode_utf16().collect();
+        unsafe {
+            MessageBoxW(HWND::default(), PCWSTR(wide_msg.as_ptr()),
+                w!("DirectShell \u{2014} Disk not writable"), MB_OK | MB_ICONWARNING);
+        }
+    }
+
+    unsafe {
+        let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+        log("COM initialized");
+
+        // Browser-Verknüpfungen prüfen und ggf. CDP+UIA Flags anbieten
+        check_browser_shortcuts();
+
+        // Vorherigen Wert merken — WM_DESTROY stellt ihn im transient-Modus wieder her
+        let mut prior = BOOL(0);
+        if SystemParametersInfoW(SPI_GETSCREENREADER, 0, Some(&mut prior as *mut BOOL as *mut c_void),
+            SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0)).is_ok() {
+            SCREEN_READER_PRIOR.store(prior.0, SeqCst);
+        }
+
+        // Screen Reader Flag SOFORT setzen — bevor irgendwas passiert.
+        // Apps die NACH DirectShell starten sehen das Flag von Anfang an.
+        let _ = SystemParametersInfoW(
+            SPI_SETSCREENREADER,
+            1,
+            None,
+            SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0x0002),
+        );
+        log("SPI_SETSCREENREADER = TRUE (global, at startup)");
+
+        // Global WinEvent Hook — macht DS für ALLE Apps als Screen Reader sichtbar.
+        // Chrome probt mit NotifyWinEvent(EVENT_SYSTEM_ALERT) ob jemand zuhört.
+        // Unser Hook fängt das ab + antwortet mit AccessibleObjectFromWindow.
+        // → Chrome (und jeder andere Browser) aktiviert Accessibility automatisch.
+        // Gilt für ALLE Fenster: Hauptfenster, Popups, neue Tabs — alles.
+        install_at_hook(0);
+        log("Global WinEvent hook installed — DS visible as AT to all apps");
+
+        let inst = GetModuleHandleW(None)?;
+        let hinst: HINSTANCE = inst.into();
+        let cls = w!("DirectShell");
+
+        // Load embedded icon for window class (taskbar + alt-tab)
+        let app_icon = LoadImageW(hinst, PCWSTR(1 as *const u16), IMAGE_ICON, 0, 0, LR_DEFAULTCOLOR | LR_DEFAULTSIZE);
+        let app_icon_sm = LoadImageW(hinst, PCWSTR(1 as *const u16), IMAGE_ICON, 16, 16, LR_DEFAULTCOLOR);
+        let wc = WNDCLASSEXW {
+            cbSize: mem::size_of::<WNDCLASSEXW>() as u32,
+            style: CS_HREDRAW | CS_VREDRAW,
+            lpfnWndProc: Some(wndproc),
+            hInstance: hinst,
+            hCursor: LoadCursorW(None, IDC_ARROW)?,
+            hbrBackground: CreateSolidBrush(INVIS),
+            lpszClassName: cls,
+            hIcon: HICON(app_icon.as_ref().map(|h| h.0).unwrap_or(std::ptr::null_mut())),
+            hIconSm: HICON(app_icon_sm.as_ref().map(|h| h.0).unwrap_or(std::ptr::null_mut())),
+            ..Default::default()
+        };
+        RegisterClassExW(&wc);
+
+        let hwnd = CreateWindowExW(
+            WS_EX_LAYERED | WS_EX_TOPMOST,
+            cls, w!("DirectShell"),
+            WS_POPUP | WS_VISIBLE,
+            200, 200, 500, 350,
+            HWND::default(), HMENU::default(), hinst, None,
+        )?;
+
+        SetLayeredWindowAttributes(hwnd, INVIS, ALPHA, LWA_COLORKEY | LWA_ALPHA)?;
+        log(&format!("Window created: 0x{:X}", hwnd.0 as usize));
+        DS_HWND.store(hwnd.0 as isize, SeqCst);
+        add_tray_icon(hwnd);
+
+        let _ = SetTimer(hwnd, ANIM_TIMER, ANIM_MS, None);
+
+        // Daemon Mode: Background window enumeration + snap request polling
+        let _ = fs::create_dir_all(db_dir());
+        let _ = SetTimer(hwnd, ENUM_TIMER, ENUM_MS, None);
+        let _ = SetTimer(hwnd, SNAP_REQ_TIMER, SNAP_REQ_MS, None);
+        let _ = SetTimer(hwnd, OBSERVE_TIMER, OBSERVE_MS, None);
+        log("Daemon mode: ENUM_TIMER + SNAP_REQ_TIMER + OBSERVE_TIMER started");
+        // config quiet=1 → start passive instead of waiting for the first ENUM tick
+        check_quiet(hwnd);
+        start_dashboard();
+
+        // Keyboard Hook installieren (global, low-level)
+        let hook = install_kb_hook()?;
+        log(&format!("Keyboard hook installed: 0x{:X}", hook.0 as usize));
+        update_mouse_hook();
+
+        let mut msg = MSG::default();
+        while GetMessageW(&mut msg, None, 0, 0).into() {
+            let _ = TranslateMessage(&msg);
+            DispatchMessageW(&msg);
+        }
+
+        log("=== DirectShell EXIT ===");
+        CoUninitialize();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ── app_name_from_title / db_name_from_title ────
+    #[test]
+    fn app_name_documented_examples() {
+        assert_eq!(app_name_from_title("Google Gemini – Opera"), "opera");
+        assert_eq!(app_name_from_title("GitHub Desktop"), "github_desktop");
+        assert_eq!(app_name_from_title("release – Datei-Explorer"), "datei_explorer");
+    }
+
+    #[test]
+    fn app_name_separators() {
+        assert_eq!(app_name_from_title("Inbox — Thunderbird"), "thunderbird");
+        assert_eq!(app_name_from_title("notes.txt - Editor"), "editor");
+        assert_eq!(app_name_from_title("a – b - c"), "c");
+        // Hyphen without spaces is part of the name, not a separator
+        assert_eq!(app_name_from_title("Datei-Explorer"), "datei_explorer");
+    }
+
+    #[test]
+    fn app_name_trailing_separator_uses_last_nonempty_segment() {
+        assert_eq!(app_name_from_title("Notes – "), "notes");
+        assert_eq!(app_name_from_title("Notes —"), "notes");
+        assert_eq!(app_name_from_title("Mail - "), "mail");
+    }
+
+    #[test]
+    fn app_name_fallback_unknown() {
+        assert_eq!(app_name_from_title(""), "unknown");
+        assert_eq!(app_name_from_title("   "), "unknown");
+        assert_eq!(app_name_from_title("***"), "unknown");
+        assert_eq!(app_name_from_title(" – "), "unknown");
+    }
+
+    #[test]
+    fn app_name_unicode_case_folds_to_one_db() {
+        assert_eq!(app_name_from_title("ÜBERSICHT"), "übersicht");
+        assert_eq!(app_name_from_title("ÜBERSICHT"), app_name_from_title("Übersicht"));
+    }
+
+    #[test]
+    fn app_name_strips_outer_symbols() {
+        assert_eq!(app_name_from_title("(Visual Studio Code)"), "visual_studio_code");
+        assert_eq!(app_name_from_title("Paint 3D"), "paint_3d");
+    }
+
+    // ── parse_key_combo ─────────────────────────────
+    #[test]
+    fn key_combo_modifiers_and_main_key() {
+        assert_eq!(parse_key_combo("ctrl+shift+a"), Ok((vec![VK_CONTROL, VK_SHIFT], Some(VIRTUAL_KEY(0x41)))));
+        assert_eq!(parse_key_combo("alt+f4"), Ok((vec![VK_MENU], Some(VK_F4))));
+        assert_eq!(parse_key_combo("win+d"), Ok((vec![VK_LWIN], Some(VIRTUAL_KEY(0x44)))));
+    }
+
+    #[test]
+    fn key_combo_single_keys() {
+        assert_eq!(parse_key_combo("enter"), Ok((vec![], Some(VK_RETURN))));
+        assert_eq!(parse_key_combo("f5"), Ok((vec![], Some(VK_F5))));
+        assert_eq!(parse_key_combo("esc"), Ok((vec![], Some(VK_ESCAPE))));
+    }
+
+    #[test]
+    fn key_combo_modifier_only() {
+        assert_eq!(parse_key_combo("ctrl"), Ok((vec![VK_CONTROL], None)));
+        assert_eq!(parse_key_combo("ctrl+shift"), Ok((vec![VK_CONTROL, VK_SHIFT], None)));
+    }
+
+    #[test]
+    fn key_combo_whitespace_and_case() {
+        assert_eq!(parse_key_combo(" Ctrl + A "), parse_key_combo("ctrl+a"));
+        assert_eq!(parse_key_combo("CTRL+SHIFT+ENTER"), Ok((vec![VK_CONTROL, VK_SHIFT], Some(VK_RETURN))));
+    }
+
+    #[test]
+    fn key_combo_german_accelerator_names() {
+        // AcceleratorKey text from German apps ("Strg+Umschalt+S")
+        assert_eq!(parse_key_combo("strg+umschalt+s"), parse_key_combo("ctrl+shift+s"));
+    }
+
+    #[test]
+    fn key_combo_unknown_key_is_error() {
+        assert_eq!(parse_key_combo("ctrl+foo"), Err("foo".to_string()));
+        assert_eq!(parse_key_combo("ctrl+"), Err(String::new()));
+        assert_eq!(parse_key_combo(""), Err(String::new()));
+    }
+
+    // ── overlap / snap_hit ──────────────────────────
+    fn rect(left: i32, top: i32, right: i32, bottom: i32) -> RECT {
+        RECT { left, top, right, bottom }
+    }
+
+    #[test]
+    fn overlap_disjoint_is_zero() {
+        assert_eq!(overlap(&rect(0, 0, 100, 100), &rect(200, 200, 300, 300)), 0.0);
+        // Touching edges share no area
+        assert_eq!(overlap(&rect(0, 0, 100, 100), &rect(100, 0, 200, 100)), 0.0);
+    }
+
+    #[test]
+    fn overlap_containment_is_one() {
+        assert_eq!(overlap(&rect(10, 10, 60, 60), &rect(0, 0, 100, 100)), 1.0);
+        assert_eq!(overlap(&rect(0, 0, 100, 100), &rect(0, 0, 100, 100)), 1.0);
+    }
+
+    #[test]
+    fn overlap_is_relative_to_first_rect() {
+        // Small a inside big b → 1.0; big a around small b → b's share of a
+        let (small, big) = (rect(0, 0, 10, 10), rect(0, 0, 100, 100));
+        assert_eq!(overlap(&small, &big), 1.0);
+        assert_eq!(overlap(&big, &small), 0.01);
+    }
+
+    #[test]
+    fn overlap_partial() {
+        assert_eq!(overlap(&rect(0, 0, 100, 100), &rect(50, 0, 150, 100)), 0.5);
+        assert_eq!(overlap(&rect(0, 0, 100, 100), &rect(50, 50, 150, 150)), 0.25);
+        assert_eq!(overlap(&rect(0, 0, 100, 100), &rect(-50, -50, 50, 50)), 0.25);
+    }
+
+    #[test]
+    fn overlap_zero_area_is_guarded() {
+        assert_eq!(overlap(&rect(10, 10, 10, 50), &rect(0, 0, 100, 100)), 0.0);
+        assert_eq!(overlap(&rect(10, 10, 50, 10), &rect(0, 0, 100, 100)), 0.0);
+        assert_eq!(overlap(&rect(50, 50, 10, 10), &rect(0, 0, 100, 100)), 0.0);
+        assert_eq!(overlap(&rect(0, 0, 100, 100), &rect(10, 10, 10, 10)), 0.0);
+    }
+
+    #[test]
+    fn snap_threshold_boundary() {
+        let me = rect(0, 0, 100, 100);
+        assert!(snap_hit(&me, &rect(80, 0, 300, 100)));   // exactly 0.20
+        assert!(!snap_hit(&me, &rect(81, 0, 300, 100)));  // 0.19
+        assert!(snap_hit(&me, &rect(79, 0, 300, 100)));   // 0.21
+        assert!(!snap_hit(&me, &rect(200, 0, 300, 100)));
+    }
+
+    // ── json_escape ─────────────────────────────────
+    /// Embed as a JSON string value, parse it back → must be the original text.
+    fn json_round_trip(s: &str) -> String {
+        let doc = format!(r#"{{"v":"{}"}}"#, json_escape(s));
+        let parsed: serde_json::Value = serde_json::from_str(&doc)
+            .unwrap_or_else(|e| panic!("invalid JSON for {:?}: {} — {}", s, e, doc));
+        parsed["v"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn json_escape_quotes_and_backslashes() {
+        assert_eq!(json_escape(r#"say "hi""#), r#"say \"hi\""#);
+        assert_eq!(json_escape(r"C:\Users\x"), r"C:\\Users\\x");
+        for s in [r#"""#, r"\", r#"\""#, r#"a\"b"#, r"trailing\"] {
+            assert_eq!(json_round_trip(s), s);
+        }
+    }
+
+    #[test]
+    fn json_escape_control_characters() {
+        assert_eq!(json_escape("a\nb\r\nc\td"), r"a\nb\r\nc\td");
+        assert_eq!(json_escape("\0"), r"\u0000");
+        assert_eq!(json_escape("\x1f"), r"\u001f");
+        assert_eq!(json_escape("\x08\x0c"), r"\u0008\u000c");
+        // \x20 (space) is the first character that needs no escaping
+        assert_eq!(json_escape(" "), " ");
+        let all: String = (0u8..0x20).map(char::from).collect();
+        assert_eq!(json_round_trip(&all), all);
+        for s in ["line1\r\nline2", "\n\n", "\r", "nul\0inside", "\x7f"] {
+            assert_eq!(json_round_trip(s), s);
+        }
+    }
+
+    #[test]
+    fn json_escape_non_ascii_passes_through() {
+        // Astral-plane chars (surrogate pairs in UTF-16) stay raw UTF-8 — valid JSON
+        for s in ["Schaltfläche", "日本語", "😀 👍🏽", "\u{2028}\u{2029}", "a\u{feff}b"] {
+            assert_eq!(json_escape(s), s);
+            assert_eq!(json_round_trip(s), s);
+        }
+    }
+
+    // ── json_array / windows.json ───────────────────
+    #[test]
+    fn json_array_empty_and_filled() {
+        assert_eq!(json_array(&[], "  "), "[]");
+        let items = vec!["    1".to_string(), "    2".to_string()];
+        assert_eq!(json_array(&items, "  "), "[\n    1,\n    2\n  ]");
+    }
+
+    #[test]
+    fn windows_json_zero_windows_is_valid() {
+        let doc = windows_json(1700000000, &[]);
+        let v: serde_json::Value = serde_json::from_str(&doc).unwrap();
+        assert_eq!(v["windows"], serde_json::json!([]));
+        assert_eq!(v["version"], WINDOWS_JSON_VERSION);
+        assert!(!doc.contains("[\n\n"));
+    }
+
+    #[test]
+    fn windows_json_with_entries_is_valid() {
+        let entries = vec![
+            format!(r#"    {{"title":"{}","hwnd":1}}"#, json_escape("a \"quoted\" – title")),
+            r#"    {"title":"b","hwnd":2}"#.to_string(),
+        ];
+        let v: serde_json::Value = serde_json::from_str(&windows_json(1, &entries)).unwrap();
+        let list = v["windows"].as_array().unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0]["title"], "a \"quoted\" – title");
+        assert_eq!(list[1]["hwnd"], 2);
+    }
+
+    // ── encode_elements (.elements.bin) ─────────────
+    #[test]
+    fn binary_export_layout() {
+        let u32_at = |b: &[u8], o: usize| u32::from_le_bytes(b[o..o + 4].try_into().unwrap()) as usize;
+        let elements = vec![
+            Element { id: 1, role: "Window".into(), name: "Editor".into(), enabled: true, w: 800, h: 600, ..Default::default() },
+            Element { id: 2, parent_id: 1, depth: 1, role: "Edit".into(), name: "Textfeld – ü".into(),
+                      enabled: true, selected: true, x: 10, y: 20, w: 300, h: 40,
+                      progress: Some(50.0), heading_level: Some(2), ..Default::default() },
+        ];
+        let bin = encode_elements("Editor – Notes", 42, &elements);
+        assert_eq!(&bin[..4], BIN_MAGIC);
+        assert_eq!(u32_at(&bin, 8), 2);
+        let blob = u32_at(&bin, 12);
+        assert_eq!(blob, BIN_HEADER_SIZE + 2 * BIN_RECORD_SIZE);
+        assert_eq!(bin.len(), blob + u32_at(&bin, 16));
+        let text = |r: usize| &bin[blob + u32_at(&bin, r)..blob + u32_at(&bin, r) + u32_at(&bin, r + 4)];
+        assert_eq!(text(28), "Editor – Notes".as_bytes());
+
+        let rec = BIN_HEADER_SIZE + BIN_RECORD_SIZE;
+        assert_eq!(u32_at(&bin, rec), 2);
+        assert_eq!(u32_at(&bin, rec + 4), 1);
+        assert_eq!(bin[rec + 10], 1 | 4);
+        assert_eq!(bin[rec + 11], 2);
+        assert_eq!(i32::from_le_bytes(bin[rec + 16..rec + 20].try_into().unwrap()), 20);
+        assert_eq!(f32::from_le_bytes(bin[rec + 28..rec + 32].try_into().unwrap()), 50.0);
+        assert_eq!(text(rec + 32), b"Edit");
+        assert_eq!(text(rec + 40), "Textfeld – ü".as_bytes());
+        assert!(f32::from_le_bytes(bin[BIN_HEADER_SIZE + 28..BIN_HEADER_SIZE + 32].try_into().unwrap()).is_nan());
+    }
+
+    // ── element catalog ─────────────────────────────
+    #[test]
+    fn catalog_signature_survives_renames() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("
+            CREATE TABLE elements (id INTEGER PRIMARY KEY, parent_id INTEGER, role TEXT NOT NULL,
+                                   name TEXT, automation_id TEXT, path TEXT);
+            CREATE TABLE catalog (sig TEXT PRIMARY KEY, role TEXT, name TEXT, automation_id TEXT,
+                                  path TEXT, first_seen INTEGER, last_seen INTEGER);
+            INSERT INTO elements VALUES (1, 0, 'Window', 'Inbox (3)', NULL, 'Window[Inbox (3)]'),
+                                        (2, 1, 'Button', 'Reply', 'btnReply', 'Window[Inbox (3)] > Button[Reply]'),
+                                        (3, 1, 'Button', 'Forward', NULL, 'Window[Inbox (3)] > Button[Forward]');
+        ").unwrap();
+        update_catalog(&conn, 1000);
+        conn.execute_batch("
+            UPDATE elements SET path = replace(path, '(3)', '(4)');
+            UPDATE elements SET name = 'Inbox (4)' WHERE id = 1;
+            UPDATE elements SET name = 'Weiterleiten' WHERE id = 3;
+        ").unwrap();
+        update_catalog(&conn, 2000);
+        let rows: Vec<(String, String, i64, i64)> = conn
+            .prepare("SELECT sig, name, first_seen, last_seen FROM catalog ORDER BY sig").unwrap()
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?))).unwrap()
+            .map(|r| r.unwrap()).collect();
+        assert_eq!(rows, vec![
+            ("Button|btnReply|Window#0 > Button#0".into(), "Reply".into(), 1000, 2000),
+            ("Button||Window#0 > Button#1".into(), "Weiterleiten".into(), 1000, 2000),
+            ("Window||Window#0".into(), "Inbox (4)".into(), 1000, 2000),
+        ]);
+    }
+
+    // ── action history ──────────────────────────────
+    #[test]
+    fn history_line_is_valid_json() {
+        let line = history_line(1700000000000, "editor", "text", "path=Window > Edit[\"Name\"]",
+            "a\nb\t\"c\"", "retry", Some("focus_lost"));
+        let v: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(v["timestamp"], 1700000000000u64);
+        assert_eq!(v["target"], "path=Window > Edit[\"Name\"]");
+        assert_eq!(v["text"], "a\nb\t\"c\"");
+        assert_eq!(v["result"], "retry");
+        assert_eq!(v["error"], "focus_lost");
+        assert!(v.get("truncated").is_none());
+    }
+
+    #[test]
+    fn history_line_truncates_long_text_on_char_boundary() {
+        let long = "ü".repeat(ACTION_HISTORY_TEXT_MAX + 10);
+        let v: serde_json::Value = serde_json::from_str(
+            &history_line(0, "app", "text", "", &long, "ok", None)).unwrap();
+        assert_eq!(v["text"].as_str().unwrap().chars().count(), ACTION_HISTORY_TEXT_MAX);
+        assert_eq!(v["truncated"], true);
+        assert!(v.get("error").is_none());
+    }
+
+    #[test]
+    fn history_masks_password_input_and_redacted_content() {
+        let off = Redact::default();
+        let all = Redact { values: true, names: true };
+        assert_eq!(history_redact(off, "Edit", false, true, "Name", "Bob"), ("Name".into(), "Bob".into(), false));
+        assert_eq!(history_redact(off, "Edit", true, true, "PIN", "1234"), ("PIN".into(), PASSWORD_VALUE.into(), true));
+        assert_eq!(history_redact(all, "Edit", false, true, "Notes", "secret"), (REDACTED.into(), REDACTED.into(), true));
+        // Non-typing actions keep their spec; other roles are not content
+        assert_eq!(history_redact(all, "Button", false, false, "Save", "down 3"), ("Save".into(), "down 3".into(), false));
+        let v: serde_json::Value = serde_json::from_str(
+            &history_extra(history_line(0, "app", "text", "", "x", "ok", None), r#""masked":true"#)).unwrap();
+        assert_eq!(v["masked"], true);
+    }
+
+    #[test]
+    fn parse_history_replays_successful_steps_with_offsets() {
+        let conn = Connection::open_in_memory().unwrap();
+        let history = [
+            history_line(1000, "editor", "click", "Save", "", "retry", Some("timeout")),
+            history_line(1500, "editor", "click", "Save", "", "ok", None),
+            String::from("not json"),
+            history_line(1800, "editor", "text", "Name", &"x".repeat(ACTION_HISTORY_TEXT_MAX + 1), "ok", None),
+            history_line(2250, "editor", "key", "", "ctrl+s", "recorded", None),
+            history_extra(history_line(2400, "editor", "text", "PIN", PASSWORD_VALUE, "ok", None), r#""masked":true"#),
+        ].join("\n");
+        let (steps, skipped) = parse_history(&conn, &history, "editor").unwrap();
+        assert_eq!(skipped, 4);
+        assert_eq!(steps, vec![
+            ReplayStep { offset_ms: 0, action: "click".into(), target: "Save".into(), text: String::new() },
+            ReplayStep { offset_ms: 750, action: "key".into(), target: String::new(), text: "ctrl+s".into() },
+        ]);
+        assert!(parse_history(&conn, &history, "browser").is_err());
+    }
+
+    #[test]
+    fn recorded_click_replays_its_point_as_target() {
+        let conn = Connection::open_in_memory().unwrap();
+        let mut rec = MacroRecorder {
+            path: String::new(), app: "editor".into(), lines: Vec::new(), text: String::new(), text_ts: 0, last_ts: 0,
+        };
+        let win = RECT { left: 100, top: 50, right: 900, bottom: 650 };
+        rec.push_click(1000, &percent_point(500, 350, &win));
+        rec.push(1400, "key", "ctrl+s");
+        rec.name_click(0, "Save");
+        let v: serde_json::Value = serde_json::from_str(&rec.lines[0]).unwrap();
+        assert_eq!(v["element"], "Save");
+        let (steps, skipped) = parse_history(&conn, &rec.lines.join("\n"), "editor").unwrap();
+        assert_eq!(skipped, 0);
+        assert_eq!(steps[0], ReplayStep {
+            offset_ms: 0, action: "click".into(), target: "50.0%,50.0%".into(), text: String::new() });
+        assert_eq!(parse_percent_point(&steps[0].target), Some((50.0, 50.0)));
+        assert_eq!(steps[1].target, "");
+    }
+
+    // ── target selectors / XPath ────────────────────
+    #[test]
+    fn parse_target_prefixes() {
+        assert!(matches!(parse_target("path=Window > Pane > Button[Save]"), Target::Path("Window > Pane > Button[Save]")));
+        assert!(matches!(parse_target("id= btnOpen "), Target::Id("btnOpen")));
+        assert!(matches!(parse_target("class=Edit"), Target::Class("Edit")));
+        assert!(matches!(parse_target("//Button[@name='Save']"), Target::XPath("//Button[@name='Save']")));
+        assert!(matches!(parse_target("Save"), Target::Name("Save")));
+        // Only the exact prefix counts — everything else is a name, verbatim
+        assert!(matches!(parse_target("identity"), Target::Name("identity")));
+        assert!(matches!(parse_target(" path=x"), Target::Name(" path=x")));
+    }
+
+    #[test]
+    fn parse_xpath_rejects_malformed_selectors() {
+        for bad in ["", "Button", "//", "///Button", "//Button]", "//Button[", "//Button[]", "//Button[x]",
+                    "//Button[@name=Save]", "//Button[@name='Save", "//Button[@name='Save'", "//Button[2"] {
+            assert!(parse_xpath(bad).is_none(), "{bad:?}");
+        }
+        let steps = parse_xpath(r#"/Window//ListItem[@name="Café"][2]"#).unwrap();
+        assert_eq!(steps.len(), 2);
+        assert!(!steps[0].descendant && steps[1].descendant);
+        assert_eq!(steps[1].attrs, vec![("name".to_string(), "Café".to_string())]);
+        assert_eq!(steps[1].index, Some(2));
+    }
+
+    fn xpath_tree() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE elements (id INTEGER, parent_id INTEGER, role TEXT, name TEXT,
+            automation_id TEXT, value TEXT, class_name TEXT);").unwrap();
+        for (id, parent, role, name, aid, value, class) in [
+            (1, 0, "Window", "Main", "", "", ""),
+            (2, 1, "Pane", "", "", "", ""),
+            (3, 2, "Button", "Save", "", "", ""),
+            (4, 2, "Button", "Open", "btnOpen", "", ""),
+            (5, 1, "List", "", "", "", ""),
+            (6, 5, "ListItem", "a", "", "", ""),
+            (7, 5, "ListItem", "b", "", "", ""),
+            (8, 5, "ListItem", "c", "", "", ""),
+            (9, 1, "List", "", "", "", ""),
+            (10, 9, "ListItem", "d", "", "", ""),
+            (11, 2, "Edit", "", "", "q", "Edit"),
+        ] {
+            conn.execute("INSERT INTO elements VALUES(?1,?2,?3,?4,?5,?6,?7)",
+                params![id, parent, role, name, aid, value, class]).unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn eval_xpath_child_vs_descendant_steps() {
+        let conn = xpath_tree();
+        assert_eq!(eval_xpath(&conn, "/Window"), Some(vec![1]));
+        assert_eq!(eval_xpath(&conn, "/Button"), Some(vec![]));
+        assert_eq!(eval_xpath(&conn, "//Button"), Some(vec![3, 4]));
+        assert_eq!(eval_xpath(&conn, "/Window/Button"), Some(vec![]));
+        assert_eq!(eval_xpath(&conn, "/Window//Button"), Some(vec![3, 4]));
+        assert_eq!(eval_xpath(&conn, "//List/ListItem"), Some(vec![6, 7, 8, 10]));
+        assert_eq!(eval_xpath(&conn, "/Window/*/Edit"), Some(vec![11]));
+    }
+
+    #[test]
+    fn eval_xpath_positions_are_one_based_per_parent() {
+        let conn = xpath_tree();
+        assert_eq!(eval_xpath(&conn, "//ListItem[1]"), Some(vec![6, 10]));
+        assert_eq!(eval_xpath(&conn, "//ListItem[3]"), Some(vec![8]));
+        assert_eq!(eval_xpath(&conn, "//ListItem[4]"), Some(vec![]));
+        assert_eq!(eval_xpath(&conn, "//List[2]/ListItem[1]"), Some(vec![10]));
+        // Position counts siblings matching the whole step, not all siblings
+        assert_eq!(eval_xpath(&conn, "//Button[2]"), Some(vec![4]));
+        assert_eq!(eval_xpath(&conn, "//Edit[1]"), Some(vec![11]));
+    }
+
+    #[test]
+    fn eval_xpath_attribute_predicates() {
+        let conn = xpath_tree();
+        assert_eq!(eval_xpath(&conn, "//Button[@name='Save']"), Some(vec![3]));
+        assert_eq!(eval_xpath(&conn, r#"//*[@id="btnOpen"]"#), Some(vec![4]));
+        assert_eq!(eval_xpath(&conn, "//Edit[@class='Edit'][@value='q']"), Some(vec![11]));
+        assert_eq!(eval_xpath(&conn, "//Edit[@class='Edit'][@value='x']"), Some(vec![]));
+        assert_eq!(eval_xpath(&conn, "//Button[@name='Save'][2]"), Some(vec![]));
+        assert_eq!(eval_xpath(&conn, "//Button[@role='Button']"), Some(vec![]));
+        assert_eq!(eval_xpath(&conn, "//Button[@name='Save"), None);
+    }
+
+    // ── fuzzy target matching ───────────────────────
+    #[test]
+    fn levenshtein_counts_char_edits() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("abc", ""), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("save draft", "save drafts"), 1);
+        assert_eq!(levenshtein("flaw", "lawn"), 2);
+        // Chars, not bytes: one umlaut is one substitution
+        assert_eq!(levenshtein("schon", "schön"), 1);
+        assert_eq!(name_similarity("", ""), 1.0);
+        assert_eq!(name_similarity("abcd", "abxy"), 0.5);
+    }
+
+    #[test]
+    fn fuzzy_threshold_accepts_at_boundary_and_rejects_below() {
+        let t = FUZZY_THRESHOLD_DEFAULT;
+        // 2 edits over 10 chars = 0.8 → accepted, 3 edits = 0.7 → rejected
+        assert_eq!(best_fuzzy(["abcdefghXY"], "abcdefghij", t), Some((0.8, 0)));
+        assert_eq!(best_fuzzy(["abcdefgXYZ"], "abcdefghij", t), None);
+        // Length gap alone decides: 8 of 10 chars passes, 7 does not
+        assert_eq!(best_fuzzy(["abcdefgh"], "abcdefghij", t), Some((0.8, 0)));
+        assert_eq!(best_fuzzy(["abcdefg"], "abcdefghij", t), None);
+        // Folded equality beats a closer-looking earlier candidate; best score wins, first on ties
+        assert_eq!(best_fuzzy(["save draft", "SAVE DRAFTS"], "save drafts", t), Some((1.0, 1)));
+        assert_eq!(best_fuzzy(["abcdefghXY", "abcdefghiX", "abcdefghiY"], "abcdefghij", t), Some((0.9, 1)));
+        assert_eq!(best_fuzzy(["abcdefgXYZ"], "abcdefghij", 0.7), Some((0.7, 0)));
+    }
+
+    // ── scroll spec ─────────────────────────────────
+    #[test]
+    fn parse_scroll_amounts_are_capped() {
+        assert_eq!(parse_scroll("down"), Some((false, -120, false)));
+        assert_eq!(parse_scroll("left 2.5 smooth"), Some((true, -300, true)));
+        assert_eq!(parse_scroll("up 40px"), Some((false, 40, false)));
+        assert_eq!(parse_scroll("down 1000000"), Some((false, -SCROLL_MAX_NOTCHES * 120, false)));
+        assert_eq!(parse_scroll("up 99999999px"), Some((false, SCROLL_MAX_NOTCHES * 120, false)));
+        assert_eq!(parse_scroll("down inf"), None);
+        assert_eq!(parse_scroll("sideways 2"), None);
+    }
+
+    // ── text normalization ──────────────────────────
+    #[test]
+    fn normalize_text_strips_format_chars_and_composes() {
+        assert_eq!(normalize_text("Save\u{200B}", true), "Save");
+        assert_eq!(normalize_text("\u{200E}Send\u{202C}", true), "Send");
+        assert_eq!(normalize_text("Save\u{200B}", false), "Save\u{200B}");
+        assert_eq!(normalize_text("Cafe\u{301}", true), "Caf\u{E9}");
+        // ZWJ is meaningful (emoji sequences) and stays
+        assert_eq!(normalize_text("a\u{200D}b", true), "a\u{200D}b");
+        assert_eq!(fold_name("Cancel\u{200F}"), "cancel");
+    }
+
+    // ── overlay grips ───────────────────────────────
+    #[test]
+    fn grip_hit_edges_and_corners() {
+        let (w, h) = (400, 300);
+        assert_eq!(grip_hit(2, 150, w, h), Some(HTLEFT));
+        assert_eq!(grip_hit(w - 2, 150, w, h), Some(HTRIGHT));
+        assert_eq!(grip_hit(200, h - 2, w, h), Some(HTBOTTOM));
+        assert_eq!(grip_hit(2, h - 2, w, h), Some(HTBOTTOMLEFT));
+        assert_eq!(grip_hit(w - 2, h - 2, w, h), Some(HTBOTTOMRIGHT));
+        assert_eq!(grip_hit(200, 150, w, h), None);
+    }
+
+    // ── window arrangement ──────────────────────────
+    #[test]
+    fn snap_region_halves_quarters_and_maximize() {
+        // Second monitor right of the primary, taskbar at the bottom
+        let work = RECT { left: 1920, top: 0, right: 3840, bottom: 1040 };
+        let rect = |r: RECT| (r.left, r.top, r.right, r.bottom);
+        assert_eq!(snap_region(&work, "left").map(rect), Some((1920, 0, 2880, 1040)));
+        assert_eq!(snap_region(&work, "right").map(rect), Some((2880, 0, 3840, 1040)));
+        assert_eq!(snap_region(&work, "bottomleft").map(rect), Some((1920, 520, 2880, 1040)));
+        assert_eq!(snap_region(&work, "topright").map(rect), Some((2880, 0, 3840, 520)));
+        assert_eq!(snap_region(&work, "maximize").map(rect), Some((1920, 0, 3840, 1040)));
+        assert!(snap_region(&work, "center").is_none());
+    }
+
+    // ── redaction ───────────────────────────────────
+    #[test]
+    fn redaction_hides_edit_contents_only() {
+        let redact = parse_redact("values, names");
+        assert_eq!(redact, Redact { values: true, names: true });
+        assert_eq!(parse_redact("off"), Redact::default());
+        let pair = |role, n: &str, v: &str| redact_pair(redact, role, n.into(), v.into());
+        assert_eq!(pair("Edit", "IBAN", "DE89 3704"), (REDACTED.to_string(), REDACTED.to_string()));
+        assert_eq!(pair("Document", "", ""), (String::new(), String::new()));
+        assert_eq!(pair("Button", "Senden", ""), ("Senden".to_string(), String::new()));
+        let values_only = parse_redact("values");
+        assert_eq!(redact_pair(values_only, "Edit", "IBAN".into(), "DE89".into()), ("IBAN".to_string(), REDACTED.to_string()));
+    }
+
+    #[test]
+    fn password_control_value_is_never_stored() {
+        // Password Edit that exposes its plain text via ValuePattern (some frameworks do)
+        assert_eq!(mask_password("hunter2".into(), true), PASSWORD_VALUE);
+        assert_eq!(mask_password("\u{2022}\u{2022}\u{2022}".into(), true), PASSWORD_VALUE);
+        assert_eq!(mask_password(String::new(), true), "");
+        assert_eq!(mask_password("max@example.com".into(), false), "max@example.com");
+        // redact=names on top: the marker survives, the name goes
+        let (name, value) = redact_pair(parse_redact("names"), "Edit", "Passwort".into(), mask_password("hunter2".into(), true));
+        assert_eq!((name.as_str(), value.as_str()), (REDACTED, PASSWORD_VALUE));
+    }
+
+    // ── dump backoff ────────────────────────────────
+    #[test]
+    fn tree_backoff_doubles_up_to_the_cap() {
+        assert_eq!(tree_backoff_ms(0), TREE_MS);
+        assert_eq!(tree_backoff_ms(1), TREE_MS * 2);
+        assert_eq!(tree_backoff_ms(3), TREE_MS * 8);
+        assert_eq!(tree_backoff_ms(50), TREE_BACKOFF_MAX_MS);
+    }
+
+    // ── snap name clipping ──────────────────────────
+    #[test]
+    fn long_snap_names_are_clipped_and_expanded_back() {
+        assert_eq!(clip_name("Save", 200), "Save");
+        assert_eq!(clip_name("äöüßx", 4), "äöü…");
+        assert_eq!(clip_name(&"x".repeat(500), 0).len(), 500);
+
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE elements (name TEXT);
+            INSERT INTO elements VALUES ('Grüße aus Berlin, bis morgen'), ('Open…'), ('Open Recent');").unwrap();
+        let clipped = clip_name("Grüße aus Berlin, bis morgen", 10);
+        assert_eq!(unclip_target(&conn, &clipped).as_deref(), Some("Grüße aus Berlin, bis morgen"));
+        assert_eq!(unclip_target(&conn, "Open…"), None);
+        assert_eq!(unclip_target(&conn, "Gr…").as_deref(), Some("Grüße aus Berlin, bis morgen"));
+        assert_eq!(unclip_target(&conn, "Save"), None);
+    }
+
+    // ── percent coordinates ─────────────────────────
+    #[test]
+    fn percent_points_map_back_onto_the_window() {
+        assert_eq!(parse_percent_point("85%,5.2%"), Some((85.0, 5.2)));
+        assert_eq!(parse_percent_point(" 50 % , 50% "), Some((50.0, 50.0)));
+        assert_eq!(parse_percent_point("100%"), None);
+        assert_eq!(parse_percent_point("85,5"), None);
+        assert_eq!(parse_percent_point("120%,5%"), None);
+
+        let win = RECT { left: 100, top: 50, right: 900, bottom: 650 };
+        assert_eq!(percent_to_screen(50.0, 50.0, &win), (500, 350));
+        assert_eq!(percent_to_screen(0.0, 0.0, &win), (100, 50));
+        assert_eq!(percent_to_screen(100.0, 100.0, &win), (899, 649));
+        // user_click steps: recorded as x%,y%, clicked back at the same spot
+        assert_eq!(percent_point(500, 350, &win), "50.0%,50.0%");
+        let (px, py) = parse_percent_point(&percent_point(740, 92, &win)).unwrap();
+        assert_eq!(percent_to_screen(px, py, &win), (740, 92));
+        // element_at accepts both forms
+        assert_eq!(parse_point("740, 92", &win), Some((740, 92)));
+        assert_eq!(parse_point("50%,50%", &win), Some((500, 350)));
+        assert_eq!(parse_point("740", &win), None);
+    }
+
+    // ── foreground strategy ─────────────────────────
+    #[test]
+    fn foreground_strategy_values() {
+        assert_eq!(parse_foreground("always"), Some(Foreground::Always));
+        assert_eq!(parse_foreground("Never"), Some(Foreground::Never));
+        assert_eq!(parse_foreground("restore"), Some(Foreground::Restore));
+        assert_eq!(parse_foreground("sometimes"), None);
+        assert_eq!(InjectError::ForegroundRequired.code(), "foreground_required");
+    }
+
+    #[test]
+    fn permanent_inject_errors_are_not_retried() {
+        assert!(InjectError::NotFound("Save".into()).retryable());
+        assert!(InjectError::FocusLost.retryable());
+        assert!(InjectError::Uia("0x80040201".into()).retryable());
+        assert!(!InjectError::Timeout("UI still busy after 5000ms".into()).retryable());
+        assert!(!InjectError::InvalidArgument("url".into()).retryable());
+        assert!(!InjectError::ForegroundRequired.retryable());
+        assert!(!InjectError::UipiBlocked.retryable());
+        assert!(!InjectError::UnknownAction("jump".into()).retryable());
+    }
+
+    // ── event coalescing ────────────────────────────
+    #[test]
+    fn coalesced_property_moves_to_the_newest_id() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT, timestamp INTEGER NOT NULL,
+            event_type TEXT NOT NULL, element_name TEXT, element_role TEXT,
+            detail TEXT, new_value TEXT, consumed INTEGER DEFAULT 0, repeats INTEGER DEFAULT 1);").unwrap();
+        let prop = |ts, name, val| store_event(&conn, ts, "property", Some(name), Some("Text"), "Value", Some(val)).unwrap();
+        prop(1, "Clock", "10:00");
+        prop(2, "Status", "busy");
+        // A reader fetched everything up to here but has not marked it consumed yet
+        let read_max: i64 = conn.query_row("SELECT MAX(id) FROM events", [], |r| r.get(0)).unwrap();
+        prop(3, "Clock", "10:01");
+        conn.execute("UPDATE events SET consumed=1 WHERE id <= ?1", [read_max]).unwrap();
+
+        let rows: Vec<(i64, String, String, i64)> = conn.prepare(
+            "SELECT timestamp, element_name, new_value, repeats FROM events WHERE consumed=0 ORDER BY id").unwrap()
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?))).unwrap()
+            .map(|r| r.unwrap()).collect();
+        assert_eq!(rows, vec![(3, "Clock".to_string(), "10:01".to_string(), 2)]);
+        // id order = time order
+        let ts: Vec<i64> = conn.prepare("SELECT timestamp FROM events ORDER BY id").unwrap()
+            .query_map([], |r| r.get(0)).unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(ts, vec![2, 3]);
+    }
+
+    // ── empty snap detection ────────────────────────
+    #[test]
+    fn empty_snap_is_suspicious_only_in_a_bare_tree() {
+        assert!(empty_snap_suspicious(0, 4));
+        assert!(!empty_snap_suspicious(0, 500));
+        assert!(!empty_snap_suspicious(3, 4));
+    }
+
+    // ── protected windows ───────────────────────────
+    #[test]
+    fn protection_reason_prefers_secure_desktop() {
+        assert_eq!(protection_reason(0, false), "");
+        assert_eq!(protection_reason(0x11, false), "exclude_from_capture");
+        assert_eq!(protection_reason(0x1, false), "capture_monitor_only");
+        assert_eq!(protection_reason(0x11, true), "secure_desktop");
+    }
+
+    // ── read_region ─────────────────────────────────
+    #[test]
+    fn clamp_region_to_window() {
+        let win = RECT { left: 100, top: 100, right: 900, bottom: 700 };
+        let rect = |r: RECT| (r.left, r.top, r.right, r.bottom);
+        assert_eq!(clamp_region("200, 150, 100, 50", &win).map(rect), Some((200, 150, 300, 200)));
+        assert_eq!(clamp_region("50,50,200,200", &win).map(rect), Some((100, 100, 250, 250)));
+        assert_eq!(clamp_region("800,600,500,500", &win).map(rect), Some((800, 600, 900, 700)));
+        assert!(clamp_region("950,100,10,10", &win).is_none());
+        assert!(clamp_region("200,150,0,50", &win).is_none());
+        assert!(clamp_region("200,150,100", &win).is_none());
+    }
+
+    // ── hook passthrough ────────────────────────────
+    #[test]
+    fn passthrough_chars_vk_codes_and_names() {
+        let pass = parse_passthrough("^ ß 0xDC 220 num5 comma bogus 7");
+        assert_eq!(pass.chars, vec!['^', 'ß', '7']);
+        assert_eq!(pass.vks, vec![0xDC, 220, VK_NUMPAD5.0, VK_OEM_COMMA.0]);
+        assert_eq!(parse_passthrough(""), Passthrough::default());
+    }
+
+    // ── macro recorder ──────────────────────────────
+    #[test]
+    fn vk_name_round_trips_through_key_to_vk() {
+        for name in NAMED_KEYS {
+            assert_eq!(vk_name(key_to_vk(name).unwrap()).as_deref(), Some(*name));
+        }
+        assert_eq!(vk_name(VIRTUAL_KEY(0x41)).as_deref(), Some("a"));
+        assert_eq!(vk_name(VIRTUAL_KEY(0x39)).as_deref(), Some("9"));
+        assert_eq!(vk_name(VIRTUAL_KEY(0xFF)), None);
+    }
+
+    #[test]
+    fn combo_name_orders_modifiers_like_parse_key_combo() {
+        assert_eq!(combo_name(true, false, true, false, VIRTUAL_KEY(0x53)).as_deref(), Some("ctrl+shift+s"));
+        assert_eq!(combo_name(false, false, false, false, VK_RETURN).as_deref(), Some("enter"));
+        let combo = combo_name(true, true, false, true, VK_F4).unwrap();
+        assert_eq!(parse_key_combo(&combo), Ok((vec![VK_CONTROL, VK_MENU, VK_LWIN], Some(VK_F4))));
+    }
+
+    // ── web dashboard ───────────────────────────────
+    #[test]
+    fn http_request_waits_for_body_and_checks_origin() {
+        let raw = b"POST /inject HTTP/1.1\r\nHost: 127.0.0.1:8765\r\nContent-Length: 16\r\n\r\n{\"action\":\"key\"}";
+        assert_eq!(parse_http_request(&raw[..raw.len() - 3]), None);
+        let req = parse_http_request(raw).unwrap();
+        assert_eq!((req.method.as_str(), req.path.as_str()), ("POST", "/inject"));
+        assert_eq!(req.body, r#"{"action":"key"}"#);
+        assert!(dashboard_allowed(&req, 8765));
+        assert!(!dashboard_allowed(&req, 9000));
+        let rebound = HttpRequest { host: "evil.example:8765".into(), ..Default::default() };
+        assert!(!dashboard_allowed(&rebound, 8765));
+        let cross = HttpRequest { host: "localhost:8765".into(), origin: Some("https://evil.example".into()), ..Default::default() };
+        assert!(!dashboard_allowed(&cross, 8765));
+    }
+
+    // ── event ignore list ───────────────────────────
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("elapsed*", "elapsed 00:12"));
+        assert!(glob_match("*:??", "remaining 3:07"));
+        assert!(!glob_match("*:??", "remaining 3:7"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("a*b*c", "axxbyy"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exactly"));
+    }
+
+    #[test]
+    fn ignore_rules_parse_and_match() {
+        assert_eq!(parse_ignore_rule("# comment"), None);
+        assert_eq!(parse_ignore_rule("   "), None);
+        let bare = parse_ignore_rule("Elapsed*").unwrap();
+        assert_eq!(bare.name.as_deref(), Some("elapsed*"));
+        assert!(bare.matches("property_changed", "Elapsed 0:01", "Text"));
+
+        let rule = parse_ignore_rule("role=Text; type=property_*").unwrap();
+        assert!(rule.matches("property_changed", "whatever", "text"));
+        assert!(!rule.matches("focus_changed", "whatever", "Text"));
+        assert!(!rule.matches("property_changed", "whatever", "Button"));
+    }
+}